@@ -0,0 +1,114 @@
+use crate::Spanned;
+
+/// A value given to a word or parameter, e.g. `10.5`, `#<depth>` or `[#1 * 2]`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    /// `100.2`.
+    ///
+    /// Stored as `f64` regardless of [`crate::Number`] as LinuxCNC evaluates expressions in
+    /// double precision.
+    Literal(f64),
+
+    /// `#5220`, `#<_global>` or `#<local>`.
+    Parameter(Parameter),
+
+    /// A function of one argument, e.g. `SIN[30]` or `-#1`.
+    Unary {
+        function: UnaryFunction,
+        argument: Box<Spanned<Expression>>,
+    },
+
+    /// `[#1 + 2]`.
+    Binary {
+        operator: BinaryOperator,
+        lhs: Box<Spanned<Expression>>,
+        rhs: Box<Spanned<Expression>>,
+    },
+
+    /// `ATAN[y]/[x]`.
+    Atan {
+        y: Box<Spanned<Expression>>,
+        x: Box<Spanned<Expression>>,
+    },
+
+    /// `EXISTS[#<name>]`.
+    Exists(Parameter),
+}
+
+/// A parameter reference.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Parameter {
+    /// `#5220` or `#[#1 + 100]`.
+    Numbered(Box<Spanned<Expression>>),
+
+    /// `#<_global>`. The name includes the leading underscore.
+    Global(String),
+
+    /// `#<local>`.
+    Local(String),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UnaryFunction {
+    /// `-`.
+    Negate,
+    Abs,
+    /// Arc cosine, in degrees.
+    Acos,
+    /// Arc sine, in degrees.
+    Asin,
+    /// Cosine of an angle in degrees.
+    Cos,
+    Exp,
+    /// Round down.
+    Fix,
+    /// Round up.
+    Fup,
+    /// Natural logarithm.
+    Ln,
+    Round,
+    /// Sine of an angle in degrees.
+    Sin,
+    Sqrt,
+    /// Tangent of an angle in degrees.
+    Tan,
+}
+
+/// Binary operators, in descending precedence groups.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BinaryOperator {
+    /// `**`.
+    Power,
+
+    /// `*`.
+    Multiply,
+    /// `/`.
+    Divide,
+    /// `MOD`.
+    Modulo,
+
+    /// `+`.
+    Add,
+    /// `-`.
+    Subtract,
+
+    /// `EQ`.
+    Eq,
+    /// `NE`.
+    Ne,
+    /// `GT`.
+    Gt,
+    /// `GE`.
+    Ge,
+    /// `LT`.
+    Lt,
+    /// `LE`.
+    Le,
+
+    /// `AND`.
+    And,
+    /// `OR`.
+    Or,
+    /// `XOR`.
+    Xor,
+}
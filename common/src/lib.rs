@@ -1,9 +1,24 @@
+mod expression;
+mod o_word;
+mod span;
+
+use core::fmt;
+pub use expression::*;
 use nalgebra::SVector;
+pub use o_word::*;
+pub use span::*;
 
 pub type Number = f32;
 
+/// Mathematical constants as [`Number`]s. These change along with it.
+pub mod consts {
+    pub use core::f32::consts::{PI, TAU};
+}
+
 pub type Position = SVector<Number, 9>;
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Axis {
     X,
     Y,
@@ -16,12 +31,442 @@ pub enum Axis {
     W,
 }
 
+impl Axis {
+    /// All axes in the order they're stored in a [`Position`].
+    pub const ALL: [Axis; 9] = [
+        Axis::X,
+        Axis::Y,
+        Axis::Z,
+        Axis::A,
+        Axis::B,
+        Axis::C,
+        Axis::U,
+        Axis::V,
+        Axis::W,
+    ];
+
+    /// Index of this axis in a [`Position`].
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The word letter for this axis.
+    pub fn letter(self) -> char {
+        match self {
+            Axis::X => 'X',
+            Axis::Y => 'Y',
+            Axis::Z => 'Z',
+            Axis::A => 'A',
+            Axis::B => 'B',
+            Axis::C => 'C',
+            Axis::U => 'U',
+            Axis::V => 'V',
+            Axis::W => 'W',
+        }
+    }
+
+    /// Get the axis for a word letter, e.g. `X` or `b`.
+    pub fn from_letter(letter: char) -> Option<Self> {
+        match letter.to_ascii_uppercase() {
+            'X' => Some(Axis::X),
+            'Y' => Some(Axis::Y),
+            'Z' => Some(Axis::Z),
+            'A' => Some(Axis::A),
+            'B' => Some(Axis::B),
+            'C' => Some(Axis::C),
+            'U' => Some(Axis::U),
+            'V' => Some(Axis::V),
+            'W' => Some(Axis::W),
+            _ => None,
+        }
+    }
+
+    /// Whether the axis is usually linear or rotary: `A`, `B` and `C` rotate about `X`, `Y` and
+    /// `Z`, and the others are linear.
+    pub fn kind(self) -> AxisKind {
+        match self {
+            Axis::A | Axis::B | Axis::C => AxisKind::Rotary,
+            _ => AxisKind::Linear,
+        }
+    }
+}
+
+/// How an axis moves, which decides its units: length units for a linear axis and degrees for a
+/// rotary one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AxisKind {
+    Linear,
+    Rotary,
+}
+
+// Most commands are whole blocks, so boxing them wouldn't save anything
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Command {
-    Position { axis: Axis, value: Number },
+    Position {
+        axis: Axis,
+        value: Number,
+    },
     Motion(Motion),
+    /// A whole block (line) of words, executed in RS274NGC order.
+    Block(Block),
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Motion {
     Rapid,
     Feed,
+    /// `G2`/`G3`.
+    Arc(ArcDirection),
+    /// `G73`, `G76` or `G81` to `G89`.
+    CannedCycle(CannedCycle),
+    /// `G38.2` to `G38.5`.
+    Probe(ProbeKind),
+    /// `G33`: a straight move in step with the spindle.
+    SpindleSync,
+    /// `G33.1`: rigid tapping.
+    RigidTap,
+}
+
+/// Arc direction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ArcDirection {
+    /// `G2`.
+    Clockwise,
+
+    /// `G3`.
+    CounterClockwise,
+}
+
+/// Straight probe move.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProbeKind {
+    /// `G38.2`: stop on contact, error if there isn't any.
+    Contact,
+
+    /// `G38.3`: stop on contact.
+    ContactNoError,
+
+    /// `G38.4`: stop on loss of contact, error if contact isn't lost.
+    LossOfContact,
+
+    /// `G38.5`: stop on loss of contact.
+    LossOfContactNoError,
+}
+
+impl ProbeKind {
+    /// The G code for this probe move, e.g. `38.2` for `G38.2`.
+    pub fn code(self) -> Number {
+        match self {
+            ProbeKind::Contact => 38.2,
+            ProbeKind::ContactNoError => 38.3,
+            ProbeKind::LossOfContact => 38.4,
+            ProbeKind::LossOfContactNoError => 38.5,
+        }
+    }
+
+    /// Whether the move stops on making contact rather than losing it.
+    pub fn towards(self) -> bool {
+        matches!(self, ProbeKind::Contact | ProbeKind::ContactNoError)
+    }
+
+    /// Whether it's an error for the move to finish without the probe changing state.
+    pub fn must_trip(self) -> bool {
+        matches!(self, ProbeKind::Contact | ProbeKind::LossOfContact)
+    }
+}
+
+/// Canned cycle, cancelled by `G80`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CannedCycle {
+    /// `G73`: peck drilling, breaking the chip with a small retract after each peck.
+    ChipBreak,
+
+    /// `G76`: fine boring, moving the tool off the wall before retracting.
+    FineBore,
+
+    /// `G81`: drilling.
+    Drill,
+
+    /// `G82`: drilling with a dwell at the bottom.
+    DrillDwell,
+
+    /// `G83`: peck drilling, retracting out of the hole after each peck.
+    PeckDrill,
+
+    /// `G84`: right hand tapping.
+    Tap,
+
+    /// `G85`: boring, feeding out.
+    Bore,
+
+    /// `G86`: boring with the spindle stopped before rapid out.
+    BoreSpindleStop,
+
+    /// `G87`: back boring.
+    BackBore,
+
+    /// `G88`: boring with a manual retract.
+    BoreManualRetract,
+
+    /// `G89`: boring with a dwell at the bottom, feeding out.
+    BoreDwell,
+
+    /// `G76` on a lathe: multi-pass threading.
+    Threading,
+}
+
+impl CannedCycle {
+    /// The G code for this cycle, e.g. `81.0` for `G81`.
+    pub fn code(self) -> Number {
+        match self {
+            CannedCycle::ChipBreak => 73.0,
+            CannedCycle::FineBore | CannedCycle::Threading => 76.0,
+            CannedCycle::Drill => 81.0,
+            CannedCycle::DrillDwell => 82.0,
+            CannedCycle::PeckDrill => 83.0,
+            CannedCycle::Tap => 84.0,
+            CannedCycle::Bore => 85.0,
+            CannedCycle::BoreSpindleStop => 86.0,
+            CannedCycle::BackBore => 87.0,
+            CannedCycle::BoreManualRetract => 88.0,
+            CannedCycle::BoreDwell => 89.0,
+        }
+    }
+}
+
+/// Modal group 2: plane selection.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Plane {
+    /// `G17`.
+    XY,
+
+    /// `G18`.
+    XZ,
+
+    /// `G19`.
+    YZ,
+}
+
+impl Plane {
+    /// The two axes arcs are drawn in for this plane, followed by the helical (normal) axis.
+    ///
+    /// The ordering follows RS274NGC's `first`/`second` axis convention, so for `G18` the first
+    /// axis is Z and the second is X.
+    pub fn axes(self) -> (Axis, Axis, Axis) {
+        match self {
+            Plane::XY => (Axis::X, Axis::Y, Axis::Z),
+            Plane::XZ => (Axis::Z, Axis::X, Axis::Y),
+            Plane::YZ => (Axis::Y, Axis::Z, Axis::X),
+        }
+    }
+}
+
+/// Modal group 3 (`G90`/`G91`) and the arc centre mode (`G90.1`/`G91.1`).
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DistanceMode {
+    Absolute,
+    Incremental,
+}
+
+/// Modal group 5: how the `F` word is interpreted.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FeedRateMode {
+    /// `G93`: each feed move takes `1/F` minutes.
+    InverseTime,
+
+    /// `G94`: length units (or degrees) per minute.
+    UnitsPerMinute,
+
+    /// `G95`: length units per spindle revolution.
+    UnitsPerRevolution,
+}
+
+/// Modal group 6: units.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Units {
+    /// `G20`.
+    Inch,
+
+    /// `G21`.
+    Mm,
+}
+
+/// Modal group 7: cutter radius compensation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CutterCompensation {
+    /// `G40`.
+    Off,
+
+    /// `G41` or `G41.1`: tool to the left of the programmed path.
+    Left,
+
+    /// `G42` or `G42.1`: tool to the right of the programmed path.
+    Right,
+}
+
+/// Modal group 10: where canned cycles retract to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RetractMode {
+    /// `G98`: the position before the cycle started, if it's above the R plane.
+    OldZ,
+
+    /// `G99`: the R plane.
+    RPlane,
+}
+
+/// Modal group 14: spindle speed mode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SpindleSpeedMode {
+    /// `G96`: `S` is a constant surface speed, with an optional maximum spindle speed in rpm.
+    ConstantSurfaceSpeed { max_rpm: Option<Number> },
+
+    /// `G97`: `S` is the spindle speed in rpm.
+    Rpm,
+}
+
+/// Modal group 15: lathe X axis words as diameters or radii.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DiameterMode {
+    /// `G7`.
+    Diameter,
+
+    /// `G8`.
+    Radius,
+}
+
+/// M modal group 7: spindle direction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Spindle {
+    /// `M3`.
+    Clockwise,
+
+    /// `M4`.
+    CounterClockwise,
+
+    /// `M5`.
+    Stopped,
+}
+
+/// M modal group 8: coolant. Mist and flood can both be on at once.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Coolant {
+    /// `M7`.
+    Mist,
+
+    /// `M8`.
+    Flood,
+
+    /// `M9`: turn off both mist and flood.
+    Off,
+}
+
+/// Modal group 12: work coordinate system, numbered `1` (`G54`) to `9` (`G59.3`) as in the `P`
+/// word of `G10 L2`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CoordinateSystem(u8);
+
+impl CoordinateSystem {
+    /// `G54`.
+    pub const G54: CoordinateSystem = CoordinateSystem(1);
+
+    /// Get a coordinate system by number, or `None` if it isn't between `1` and `9`.
+    pub fn new(number: u8) -> Option<Self> {
+        (1..=9).contains(&number).then_some(Self(number))
+    }
+
+    pub fn number(self) -> u8 {
+        self.0
+    }
+}
+
+impl Default for CoordinateSystem {
+    fn default() -> Self {
+        Self::G54
+    }
+}
+
+impl fmt::Display for CoordinateSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            number @ 1..=6 => write!(f, "G{}", 53 + number),
+            number => write!(f, "G59.{}", number - 6),
+        }
+    }
+}
+
+/// A single word, e.g. `G1`, `X10.5` or `Z[#1 / 2]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub letter: char,
+    pub value: Spanned<Expression>,
+    pub span: Span,
+}
+
+impl Word {
+    /// Create a word with a literal value.
+    pub fn new(letter: char, value: Number) -> Self {
+        Self {
+            letter: letter.to_ascii_uppercase(),
+            value: Spanned::unspanned(Expression::Literal(value.into())),
+            span: Span::default(),
+        }
+    }
+}
+
+/// A parameter assignment, e.g. `#1 = 10` or `#<depth> = [#1 / 2]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub parameter: Parameter,
+    pub value: Spanned<Expression>,
+    pub span: Span,
+}
+
+/// A block (line) of words.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Block {
+    /// Whether the block starts with `/` and can be skipped by the block delete switch.
+    pub block_delete: bool,
+
+    /// `N` line number.
+    pub line_number: Option<u32>,
+
+    /// Control flow statement, e.g. `o100 call [1]`.
+    pub o_word: Option<OWord>,
+
+    pub words: Vec<Word>,
+
+    /// Parameter assignments, in the order they appear.
+    pub assignments: Vec<Assignment>,
+
+    /// Comment text without delimiters, e.g. `MSG, Change tool` for `(MSG, Change tool)`.
+    pub comments: Vec<Spanned<String>>,
+
+    /// The whole block.
+    pub span: Span,
+}
+
+impl Block {
+    pub fn new(words: Vec<Word>) -> Self {
+        Self {
+            words,
+            ..Self::default()
+        }
+    }
 }
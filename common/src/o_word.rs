@@ -0,0 +1,89 @@
+use crate::{Expression, Span, Spanned};
+use core::fmt;
+
+/// An O-word control flow statement, e.g. `o100 sub` or `o<loop> while [#1 LT 10]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OWord {
+    pub name: OName,
+    pub statement: Statement,
+    pub span: Span,
+}
+
+/// The label of an O-word. Statements belonging to the same subroutine, branch or loop share a
+/// name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OName {
+    /// `o100`.
+    Number(u32),
+
+    /// `o<name>`. Stored in lower case.
+    Named(String),
+}
+
+impl fmt::Display for OName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(number) => write!(f, "o{}", number),
+            Self::Named(name) => write!(f, "o<{}>", name),
+        }
+    }
+}
+
+/// What an O-word does.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    /// Start of a subroutine definition.
+    Sub,
+
+    /// End of a subroutine definition, with an optional default return value.
+    EndSub(Option<Spanned<Expression>>),
+
+    /// Call a subroutine with up to 30 positional arguments.
+    Call(Vec<Spanned<Expression>>),
+
+    /// Return from a subroutine, optionally setting `#<_value>`.
+    Return(Option<Spanned<Expression>>),
+
+    If(Spanned<Expression>),
+    ElseIf(Spanned<Expression>),
+    Else,
+    EndIf,
+
+    /// Start of a `do`/`while` loop.
+    Do,
+
+    /// Either the start of a `while` loop or the condition at the end of a `do` loop.
+    While(Spanned<Expression>),
+    EndWhile,
+
+    /// Repeat a block of code a number of times.
+    Repeat(Spanned<Expression>),
+    EndRepeat,
+
+    Break,
+    Continue,
+}
+
+impl Statement {
+    /// The keyword for this statement, e.g. `endsub`.
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Self::Sub => "sub",
+            Self::EndSub(_) => "endsub",
+            Self::Call(_) => "call",
+            Self::Return(_) => "return",
+            Self::If(_) => "if",
+            Self::ElseIf(_) => "elseif",
+            Self::Else => "else",
+            Self::EndIf => "endif",
+            Self::Do => "do",
+            Self::While(_) => "while",
+            Self::EndWhile => "endwhile",
+            Self::Repeat(_) => "repeat",
+            Self::EndRepeat => "endrepeat",
+            Self::Break => "break",
+            Self::Continue => "continue",
+        }
+    }
+}
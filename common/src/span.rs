@@ -0,0 +1,37 @@
+/// A location in the source program.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    /// Byte offset from the start of the input.
+    pub offset: usize,
+
+    /// Length in bytes.
+    pub len: usize,
+
+    /// Line number, starting at 1.
+    pub line: u32,
+
+    /// Column, starting at 1.
+    pub column: usize,
+}
+
+/// An item with its location in the source program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<T> {
+    pub item: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(item: T, span: Span) -> Self {
+        Self { item, span }
+    }
+
+    /// Wrap an item that didn't come from a source program, e.g. one built in code.
+    pub fn unspanned(item: T) -> Self {
+        Self {
+            item,
+            span: Span::default(),
+        }
+    }
+}
@@ -0,0 +1,722 @@
+//! Arc (`G2`/`G3`) geometry.
+//!
+//! Resolves centre format (`IJK`) and radius format (`R`) arcs into an [`ArcFeed`], checking the
+//! same error conditions as the RS274NGC interpreter.
+
+use common::consts::{PI, TAU};
+use common::{ArcDirection, Axis, DistanceMode, Number, Plane, Position, Units};
+use core::fmt;
+
+type M = libm::Libm<Number>;
+
+/// Relative radius difference allowed between the start and end of a centre format arc.
+const RELATIVE_RADIUS_TOLERANCE: Number = 0.001;
+
+/// Canonical arc move, equivalent to RS274NGC's `ARC_FEED`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArcFeed {
+    /// The plane the arc is drawn in.
+    pub plane: Plane,
+
+    /// End point of the move, including helical motion along the plane's normal axis and any
+    /// other axes moving linearly alongside the arc.
+    pub end: Position,
+
+    /// Arc centre on the plane's first and second axes.
+    pub center: (Number, Number),
+
+    /// Number of turns. Positive is counter-clockwise, negative is clockwise.
+    ///
+    /// `1` or `-1` is an arc of up to one full turn. Each additional turn given by the `P` word
+    /// adds a full circle.
+    pub rotation: i32,
+}
+
+impl ArcFeed {
+    /// Arc radius measured from the given start point.
+    pub fn radius(&self, start: &Position) -> Number {
+        let (first, second, _) = self.plane.axes();
+
+        M::hypot(
+            start[first.index()] - self.center.0,
+            start[second.index()] - self.center.1,
+        )
+    }
+
+    /// Signed angle swept by the arc in radians, counter-clockwise positive.
+    pub fn sweep(&self, start: &Position) -> Number {
+        let (first, second, _) = self.plane.axes();
+
+        let start_angle = M::atan2(
+            start[second.index()] - self.center.1,
+            start[first.index()] - self.center.0,
+        );
+        let end_angle = M::atan2(
+            self.end[second.index()] - self.center.1,
+            self.end[first.index()] - self.center.0,
+        );
+
+        let extra_turns = (self.rotation.unsigned_abs() - 1) as Number * TAU;
+
+        if self.rotation > 0 {
+            let mut sweep = end_angle - start_angle;
+
+            if sweep <= Number::EPSILON {
+                sweep += TAU;
+            }
+
+            sweep + extra_turns
+        } else {
+            let mut sweep = end_angle - start_angle;
+
+            if sweep >= -Number::EPSILON {
+                sweep -= TAU;
+            }
+
+            sweep - extra_turns
+        }
+    }
+}
+
+/// Arc-related words from a single block.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ArcWords {
+    /// `I`, `J` and `K` centre offsets.
+    pub offsets: [Option<Number>; 3],
+
+    /// `R` radius. Negative values select the arc greater than 180 degrees.
+    pub radius: Option<Number>,
+
+    /// `P` number of turns.
+    pub turns: Option<Number>,
+
+    /// Whether either of the plane's two axes was given an end point in the block.
+    pub end_in_plane: bool,
+}
+
+impl ArcWords {
+    fn offset(&self, axis: Axis) -> Option<Number> {
+        match axis {
+            Axis::X => self.offsets[0],
+            Axis::Y => self.offsets[1],
+            Axis::Z => self.offsets[2],
+            _ => None,
+        }
+    }
+}
+
+/// Distance tolerance used for arc checks in the given units, as given by the RS274NGC spec.
+pub fn tolerance(units: Units) -> Number {
+    match units {
+        Units::Inch => 0.0005,
+        Units::Mm => 0.005,
+    }
+}
+
+/// Resolve an arc from the current position to `end`.
+///
+/// `end` must already be an absolute position. `center_mode` is the `G90.1`/`G91.1` arc distance
+/// mode.
+pub fn arc_feed(
+    start: &Position,
+    end: &Position,
+    direction: ArcDirection,
+    plane: Plane,
+    words: &ArcWords,
+    center_mode: DistanceMode,
+    units: Units,
+) -> Result<ArcFeed, ArcError> {
+    let rotation = turns(words.turns)? as i32;
+
+    let rotation = match direction {
+        ArcDirection::Clockwise => -rotation,
+        ArcDirection::CounterClockwise => rotation,
+    };
+
+    let (first, second, normal) = plane.axes();
+
+    let has_offsets = words.offsets.iter().any(Option::is_some);
+
+    let center = match (words.radius, has_offsets) {
+        (None, false) => return Err(ArcError::MissingRadiusAndCenter),
+        (Some(_), true) => return Err(ArcError::MixedFormat),
+        (Some(radius), false) => {
+            if !words.end_in_plane {
+                return Err(ArcError::MissingEndPoint(plane));
+            }
+
+            radius_center(start, end, direction, plane, radius, tolerance(units))?
+        }
+        (None, true) => {
+            if words.offset(normal).is_some() {
+                return Err(ArcError::OffsetInWrongPlane(plane));
+            }
+
+            let (first_offset, second_offset) = (words.offset(first), words.offset(second));
+
+            if first_offset.is_none() && second_offset.is_none() {
+                return Err(ArcError::MissingCenter(plane));
+            }
+
+            let (first_offset, second_offset) =
+                (first_offset.unwrap_or(0.0), second_offset.unwrap_or(0.0));
+
+            let center = match center_mode {
+                DistanceMode::Absolute => (first_offset, second_offset),
+                DistanceMode::Incremental => (
+                    start[first.index()] + first_offset,
+                    start[second.index()] + second_offset,
+                ),
+            };
+
+            check_center(start, end, plane, center, tolerance(units))?;
+
+            center
+        }
+    };
+
+    Ok(ArcFeed {
+        plane,
+        end: *end,
+        center,
+        rotation,
+    })
+}
+
+fn turns(p: Option<Number>) -> Result<u32, ArcError> {
+    match p {
+        None => Ok(1),
+        Some(p) if p >= 1.0 && M::fabs(p - M::round(p)) < Number::EPSILON => Ok(p as u32),
+        Some(p) => Err(ArcError::InvalidTurns(p)),
+    }
+}
+
+/// Check that the start and end points are the same distance from a centre format arc's centre.
+fn check_center(
+    start: &Position,
+    end: &Position,
+    plane: Plane,
+    center: (Number, Number),
+    tolerance: Number,
+) -> Result<(), ArcError> {
+    let (first, second, _) = plane.axes();
+
+    let start_radius = M::hypot(
+        start[first.index()] - center.0,
+        start[second.index()] - center.1,
+    );
+    let end_radius = M::hypot(
+        end[first.index()] - center.0,
+        end[second.index()] - center.1,
+    );
+
+    if start_radius < tolerance || end_radius < tolerance {
+        return Err(ArcError::ZeroRadius);
+    }
+
+    let absolute_error = M::fabs(start_radius - end_radius);
+    let relative_error = absolute_error / start_radius.max(end_radius);
+
+    if absolute_error > tolerance * 100.0
+        || (relative_error > RELATIVE_RADIUS_TOLERANCE && absolute_error > tolerance)
+    {
+        return Err(ArcError::RadiusMismatch {
+            start: start_radius,
+            end: end_radius,
+        });
+    }
+
+    Ok(())
+}
+
+/// Find the centre of a radius format arc.
+fn radius_center(
+    start: &Position,
+    end: &Position,
+    direction: ArcDirection,
+    plane: Plane,
+    radius: Number,
+    tolerance: Number,
+) -> Result<(Number, Number), ArcError> {
+    let (first, second, _) = plane.axes();
+
+    let (start_first, start_second) = (start[first.index()], start[second.index()]);
+    let (end_first, end_second) = (end[first.index()], end[second.index()]);
+
+    if start_first == end_first && start_second == end_second {
+        return Err(ArcError::StartEqualsEnd);
+    }
+
+    let abs_radius = M::fabs(radius);
+
+    let mid_first = (start_first + end_first) / 2.0;
+    let mid_second = (start_second + end_second) / 2.0;
+
+    let mut half_length = M::hypot(mid_first - end_first, mid_second - end_second);
+
+    if half_length - abs_radius > tolerance {
+        return Err(ArcError::RadiusTooSmall);
+    }
+
+    // Allow a small error for semicircles, and keep `asin` in range.
+    if half_length / abs_radius > 1.0 - 1e-6 {
+        half_length = abs_radius;
+    }
+
+    let chord_angle = M::atan2(end_second - start_second, end_first - start_first);
+
+    // Clockwise with a small arc or counter-clockwise with a large arc puts the centre on the
+    // right of the chord.
+    let theta = match (direction, radius > 0.0) {
+        (ArcDirection::Clockwise, true) | (ArcDirection::CounterClockwise, false) => {
+            chord_angle - PI / 2.0
+        }
+        _ => chord_angle + PI / 2.0,
+    };
+
+    let offset = abs_radius * M::cos(M::asin(half_length / abs_radius));
+
+    Ok((
+        mid_first + offset * M::cos(theta),
+        mid_second + offset * M::sin(theta),
+    ))
+}
+
+/// Errors found when resolving an arc.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ArcError {
+    /// Neither `R` nor any of `I`, `J` or `K` were given.
+    MissingRadiusAndCenter,
+
+    /// Both `R` and centre offsets were given.
+    MixedFormat,
+
+    /// A radius format arc has no end point in the plane.
+    MissingEndPoint(Plane),
+
+    /// A centre format arc has no offsets for either of the plane's axes.
+    MissingCenter(Plane),
+
+    /// A centre offset was given for the axis normal to the plane.
+    OffsetInWrongPlane(Plane),
+
+    /// The start or end point is on the arc centre.
+    ZeroRadius,
+
+    /// The start and end points are different distances from the centre.
+    RadiusMismatch { start: Number, end: Number },
+
+    /// A radius format arc's radius is less than half the distance to the end point.
+    RadiusTooSmall,
+
+    /// A radius format arc's start and end points are the same.
+    StartEqualsEnd,
+
+    /// `P` is not a positive integer.
+    InvalidTurns(Number),
+}
+
+impl fmt::Display for ArcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plane_name = |plane: &Plane| match plane {
+            Plane::XY => "XY",
+            Plane::XZ => "XZ",
+            Plane::YZ => "YZ",
+        };
+
+        match self {
+            Self::MissingRadiusAndCenter => f.write_str("R, I, J, and K words all missing for arc"),
+            Self::MixedFormat => f.write_str("Mixed radius-ijk format for arc"),
+            Self::MissingEndPoint(plane) => {
+                let (first, second) = match plane {
+                    Plane::XY => ('X', 'Y'),
+                    Plane::XZ => ('X', 'Z'),
+                    Plane::YZ => ('Y', 'Z'),
+                };
+
+                write!(
+                    f,
+                    "{} and {} words missing for arc in {} plane",
+                    first,
+                    second,
+                    plane_name(plane)
+                )
+            }
+            Self::MissingCenter(plane) => {
+                let (first, second) = match plane {
+                    Plane::XY => ('I', 'J'),
+                    Plane::XZ => ('I', 'K'),
+                    Plane::YZ => ('J', 'K'),
+                };
+
+                write!(
+                    f,
+                    "{} and {} words missing for center format arc in {} plane",
+                    first,
+                    second,
+                    plane_name(plane)
+                )
+            }
+            Self::OffsetInWrongPlane(plane) => {
+                let letter = match plane {
+                    Plane::XY => 'K',
+                    Plane::XZ => 'J',
+                    Plane::YZ => 'I',
+                };
+
+                write!(
+                    f,
+                    "{} word given for arc in {} plane",
+                    letter,
+                    plane_name(plane)
+                )
+            }
+            Self::ZeroRadius => f.write_str("Zero-radius arc"),
+            Self::RadiusMismatch { start, end } => write!(
+                f,
+                "Radius to end of arc differs from radius to start: start={:.4} end={:.4} difference={:.4}",
+                start,
+                end,
+                end - start
+            ),
+            Self::RadiusTooSmall => f.write_str("Arc radius too small to reach end point"),
+            Self::StartEqualsEnd => f.write_str("Current point same as end point of arc"),
+            Self::InvalidTurns(p) => write!(
+                f,
+                "P word must be a positive integer with G2 or G3, got {}",
+                p
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn pos(x: Number, y: Number, z: Number) -> Position {
+        Position::from([x, y, z, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+    }
+
+    fn ijk(i: Option<Number>, j: Option<Number>, k: Option<Number>) -> ArcWords {
+        ArcWords {
+            offsets: [i, j, k],
+            end_in_plane: true,
+            ..ArcWords::default()
+        }
+    }
+
+    fn radius(r: Number) -> ArcWords {
+        ArcWords {
+            radius: Some(r),
+            end_in_plane: true,
+            ..ArcWords::default()
+        }
+    }
+
+    fn assert_close(a: Number, b: Number) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn quarter_circle_ijk() {
+        let arc = arc_feed(
+            &pos(10.0, 0.0, 0.0),
+            &pos(0.0, 10.0, 0.0),
+            ArcDirection::CounterClockwise,
+            Plane::XY,
+            &ijk(Some(-10.0), None, None),
+            DistanceMode::Incremental,
+            Units::Mm,
+        )
+        .unwrap();
+
+        assert_eq!(arc.center, (0.0, 0.0));
+        assert_eq!(arc.rotation, 1);
+        assert_close(arc.sweep(&pos(10.0, 0.0, 0.0)), PI / 2.0);
+    }
+
+    #[test]
+    fn absolute_center() {
+        let arc = arc_feed(
+            &pos(15.0, 5.0, 0.0),
+            &pos(5.0, 15.0, 0.0),
+            ArcDirection::CounterClockwise,
+            Plane::XY,
+            &ijk(Some(5.0), Some(5.0), None),
+            DistanceMode::Absolute,
+            Units::Mm,
+        )
+        .unwrap();
+
+        assert_eq!(arc.center, (5.0, 5.0));
+    }
+
+    #[test]
+    fn radius_format() {
+        let start = pos(0.0, 0.0, 0.0);
+        let end = pos(10.0, 10.0, 0.0);
+
+        // Small arc, clockwise, centre below the chord
+        let arc = arc_feed(
+            &start,
+            &end,
+            ArcDirection::Clockwise,
+            Plane::XY,
+            &radius(10.0),
+            DistanceMode::Incremental,
+            Units::Mm,
+        )
+        .unwrap();
+
+        assert_close(arc.center.0, 10.0);
+        assert_close(arc.center.1, 0.0);
+        assert_close(arc.sweep(&start), -PI / 2.0);
+
+        // Large arc, clockwise, centre above the chord
+        let arc = arc_feed(
+            &start,
+            &end,
+            ArcDirection::Clockwise,
+            Plane::XY,
+            &radius(-10.0),
+            DistanceMode::Incremental,
+            Units::Mm,
+        )
+        .unwrap();
+
+        assert_close(arc.center.0, 0.0);
+        assert_close(arc.center.1, 10.0);
+        assert_close(arc.sweep(&start), -3.0 * PI / 2.0);
+    }
+
+    #[test]
+    fn radius_semicircle() {
+        let arc = arc_feed(
+            &pos(0.0, 0.0, 0.0),
+            &pos(20.0, 0.0, 0.0),
+            ArcDirection::Clockwise,
+            Plane::XY,
+            &radius(10.0),
+            DistanceMode::Incremental,
+            Units::Mm,
+        )
+        .unwrap();
+
+        assert_close(arc.center.0, 10.0);
+        assert_close(arc.center.1, 0.0);
+    }
+
+    #[test]
+    fn xz_plane() {
+        // G18: first axis is Z, second is X, offsets are K and I.
+        let arc = arc_feed(
+            &pos(10.0, 0.0, 0.0),
+            &pos(0.0, 0.0, 10.0),
+            ArcDirection::CounterClockwise,
+            Plane::XZ,
+            &ijk(Some(-10.0), None, None),
+            DistanceMode::Incremental,
+            Units::Mm,
+        )
+        .unwrap();
+
+        assert_eq!(arc.center, (0.0, 0.0));
+
+        assert_eq!(
+            arc_feed(
+                &pos(10.0, 0.0, 0.0),
+                &pos(0.0, 0.0, 10.0),
+                ArcDirection::CounterClockwise,
+                Plane::XZ,
+                &ijk(Some(-10.0), Some(1.0), None),
+                DistanceMode::Incremental,
+                Units::Mm,
+            ),
+            Err(ArcError::OffsetInWrongPlane(Plane::XZ))
+        );
+    }
+
+    #[test]
+    fn yz_plane() {
+        let arc = arc_feed(
+            &pos(0.0, 10.0, 0.0),
+            &pos(0.0, 0.0, 10.0),
+            ArcDirection::CounterClockwise,
+            Plane::YZ,
+            &ijk(None, Some(-10.0), None),
+            DistanceMode::Incremental,
+            Units::Mm,
+        )
+        .unwrap();
+
+        assert_eq!(arc.center, (0.0, 0.0));
+        assert_close(arc.sweep(&pos(0.0, 10.0, 0.0)), PI / 2.0);
+    }
+
+    #[test]
+    fn helical_multi_turn() {
+        let start = pos(10.0, 0.0, 0.0);
+
+        let arc = arc_feed(
+            &start,
+            &pos(10.0, 0.0, -5.0),
+            ArcDirection::Clockwise,
+            Plane::XY,
+            &ArcWords {
+                turns: Some(3.0),
+                ..ijk(Some(-10.0), Some(0.0), None)
+            },
+            DistanceMode::Incremental,
+            Units::Mm,
+        )
+        .unwrap();
+
+        assert_eq!(arc.rotation, -3);
+        assert_eq!(arc.end[2], -5.0);
+        assert_close(arc.sweep(&start), -3.0 * TAU);
+    }
+
+    #[test]
+    fn invalid_turns() {
+        let words = ArcWords {
+            turns: Some(1.5),
+            ..ijk(Some(-10.0), None, None)
+        };
+
+        assert_eq!(
+            arc_feed(
+                &pos(10.0, 0.0, 0.0),
+                &pos(0.0, 10.0, 0.0),
+                ArcDirection::Clockwise,
+                Plane::XY,
+                &words,
+                DistanceMode::Incremental,
+                Units::Mm,
+            ),
+            Err(ArcError::InvalidTurns(1.5))
+        );
+    }
+
+    #[test]
+    fn radius_mismatch() {
+        let result = arc_feed(
+            &pos(10.0, 0.0, 0.0),
+            &pos(0.0, 11.0, 0.0),
+            ArcDirection::CounterClockwise,
+            Plane::XY,
+            &ijk(Some(-10.0), None, None),
+            DistanceMode::Incremental,
+            Units::Mm,
+        );
+
+        assert_eq!(
+            result,
+            Err(ArcError::RadiusMismatch {
+                start: 10.0,
+                end: 11.0
+            })
+        );
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("Radius to end of arc differs from radius to start"));
+
+        // Within tolerance
+        assert!(arc_feed(
+            &pos(10.0, 0.0, 0.0),
+            &pos(0.0, 10.004, 0.0),
+            ArcDirection::CounterClockwise,
+            Plane::XY,
+            &ijk(Some(-10.0), None, None),
+            DistanceMode::Incremental,
+            Units::Mm,
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn format_errors() {
+        let start = pos(0.0, 0.0, 0.0);
+        let end = pos(10.0, 0.0, 0.0);
+        let arc = |words: ArcWords| {
+            arc_feed(
+                &start,
+                &end,
+                ArcDirection::Clockwise,
+                Plane::XY,
+                &words,
+                DistanceMode::Incremental,
+                Units::Mm,
+            )
+        };
+
+        assert_eq!(
+            arc(ArcWords {
+                end_in_plane: true,
+                ..ArcWords::default()
+            }),
+            Err(ArcError::MissingRadiusAndCenter)
+        );
+        assert_eq!(
+            arc(ArcWords {
+                radius: Some(5.0),
+                ..ijk(Some(5.0), None, None)
+            }),
+            Err(ArcError::MixedFormat)
+        );
+        assert_eq!(
+            arc(ArcWords {
+                end_in_plane: false,
+                ..radius(5.0)
+            }),
+            Err(ArcError::MissingEndPoint(Plane::XY))
+        );
+        assert_eq!(arc(radius(4.0)), Err(ArcError::RadiusTooSmall));
+        assert_eq!(
+            arc(ijk(None, None, Some(1.0))),
+            Err(ArcError::OffsetInWrongPlane(Plane::XY))
+        );
+        assert_eq!(
+            ArcError::MissingEndPoint(Plane::XZ).to_string(),
+            "X and Z words missing for arc in XZ plane"
+        );
+    }
+
+    #[test]
+    fn radius_start_equals_end() {
+        assert_eq!(
+            arc_feed(
+                &pos(0.0, 0.0, 0.0),
+                &pos(0.0, 0.0, 5.0),
+                ArcDirection::Clockwise,
+                Plane::XY,
+                &radius(5.0),
+                DistanceMode::Incremental,
+                Units::Mm,
+            ),
+            Err(ArcError::StartEqualsEnd)
+        );
+    }
+
+    #[test]
+    fn full_circle() {
+        let start = pos(10.0, 0.0, 0.0);
+
+        let arc = arc_feed(
+            &start,
+            &start,
+            ArcDirection::CounterClockwise,
+            Plane::XY,
+            &ijk(Some(-10.0), None, None),
+            DistanceMode::Incremental,
+            Units::Mm,
+        )
+        .unwrap();
+
+        assert_close(arc.sweep(&start), TAU);
+        assert_close(arc.radius(&start), 10.0);
+    }
+}
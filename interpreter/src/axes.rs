@@ -0,0 +1,320 @@
+//! Which axes a machine has and how they move.
+//!
+//! [`MachineAxes`] lists the axes present out of `XYZABCUVW` and configures each as linear or
+//! rotary. By default every axis is present, with `A`, `B` and `C` rotary, so positions always
+//! have all nine. Blocks using an axis word for an axis the machine doesn't have are rejected, so
+//! the other axes stay at zero and a planner only has to deal with [`MachineAxes::present`].
+//!
+//! A rotary axis can be wrapped, turning endlessly rather than between limits. In `G90` an
+//! absolute position on a wrapped axis is an angle, and the axis turns the shortest way to it,
+//! e.g. from `A350` to `A10` goes forwards 20 degrees. In `G91` it turns by the amount given, so
+//! `A720` is two full turns. Positions aren't wrapped into 0 to 360, so moves stay continuous, but
+//! [`MachineAxes::wrap`] gives them as the machine would show them.
+//!
+//! Straight moves are measured along the linear axes, or in degrees through the rotary axes if
+//! none of the linear ones move, for inverse time feed. A rotary axis can be measured as if it was
+//! linear instead, for machines that treat a rotary axis as a linear one in the CAM system.
+
+use crate::parameters::ParameterStore;
+use crate::Interpreter;
+use common::{Axis, AxisKind, Number, Position};
+use core::fmt;
+
+type M = libm::Libm<Number>;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AxisError {
+    /// A block uses a word for an axis the machine doesn't have.
+    NotConfigured(Axis),
+}
+
+impl fmt::Display for AxisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotConfigured(axis) => write!(
+                f,
+                "{} word used but the machine has no {} axis",
+                axis.letter(),
+                axis.letter()
+            ),
+        }
+    }
+}
+
+/// Configuration of one axis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AxisConfig {
+    pub kind: AxisKind,
+
+    /// A rotary axis turns endlessly, taking the shortest way to absolute positions.
+    pub wrapped: bool,
+
+    /// A rotary axis is measured with the linear axes for inverse time feed.
+    pub inverse_time_linear: bool,
+}
+
+impl AxisConfig {
+    pub fn linear() -> Self {
+        Self {
+            kind: AxisKind::Linear,
+            wrapped: false,
+            inverse_time_linear: false,
+        }
+    }
+
+    pub fn rotary() -> Self {
+        Self {
+            kind: AxisKind::Rotary,
+            ..Self::linear()
+        }
+    }
+
+    /// A rotary axis that turns endlessly.
+    pub fn wrapped() -> Self {
+        Self {
+            wrapped: true,
+            ..Self::rotary()
+        }
+    }
+
+    /// Counted with the linear axes when measuring a move.
+    fn measured_as_linear(&self) -> bool {
+        self.kind == AxisKind::Linear || self.inverse_time_linear
+    }
+}
+
+/// The axes a machine has.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineAxes {
+    axes: [Option<AxisConfig>; 9],
+}
+
+impl Default for MachineAxes {
+    /// All nine axes, with `A`, `B` and `C` rotary.
+    fn default() -> Self {
+        Self::new(&Axis::ALL)
+    }
+}
+
+impl MachineAxes {
+    /// A machine with the given axes, linear or rotary as [`Axis::kind`] says.
+    pub fn new(axes: &[Axis]) -> Self {
+        let mut config = [None; 9];
+
+        for axis in axes {
+            config[axis.index()] = Some(match axis.kind() {
+                AxisKind::Linear => AxisConfig::linear(),
+                AxisKind::Rotary => AxisConfig::rotary(),
+            });
+        }
+
+        Self { axes: config }
+    }
+
+    /// Add an axis or change its configuration.
+    pub fn with(mut self, axis: Axis, config: AxisConfig) -> Self {
+        self.axes[axis.index()] = Some(config);
+        self
+    }
+
+    pub fn config(&self, axis: Axis) -> Option<&AxisConfig> {
+        self.axes[axis.index()].as_ref()
+    }
+
+    pub fn contains(&self, axis: Axis) -> bool {
+        self.axes[axis.index()].is_some()
+    }
+
+    fn is_wrapped(&self, axis: Axis) -> bool {
+        self.axes[axis.index()].is_some_and(|config| config.wrapped)
+    }
+
+    /// The axes the machine has, in position order.
+    pub fn present(&self) -> impl Iterator<Item = Axis> + '_ {
+        Axis::ALL
+            .iter()
+            .copied()
+            .filter(move |axis| self.contains(*axis))
+    }
+
+    /// Position with wrapped axes between 0 and 360 degrees.
+    pub fn wrap(&self, position: &Position) -> Position {
+        let mut wrapped = *position;
+
+        for axis in self.present() {
+            if self.is_wrapped(axis) {
+                let angle = M::fmod(position[axis.index()], 360.0);
+
+                wrapped[axis.index()] = if angle < 0.0 { angle + 360.0 } else { angle };
+            }
+        }
+
+        wrapped
+    }
+
+    /// Where an axis ends up moving from `current` to the absolute position `target`: the nearest
+    /// angle equivalent to `target` for a wrapped axis, otherwise `target` itself.
+    pub(crate) fn absolute_target(&self, axis: Axis, current: Number, target: Number) -> Number {
+        if !self.is_wrapped(axis) {
+            return target;
+        }
+
+        let turn = M::fmod(target - current, 360.0);
+
+        current
+            + if turn > 180.0 {
+                turn - 360.0
+            } else if turn <= -180.0 {
+                turn + 360.0
+            } else {
+                turn
+            }
+    }
+
+    /// Length of a straight move: along the linear axes, or through the rotary axes if none of the
+    /// linear axes move.
+    pub fn move_length(&self, start: &Position, end: &Position) -> Number {
+        let length = |linear: bool| {
+            M::sqrt(
+                self.axes
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, config)| {
+                        config
+                            .filter(|config| config.measured_as_linear() == linear)
+                            .map(|_| end[index] - start[index])
+                    })
+                    .map(|delta| delta * delta)
+                    .sum(),
+            )
+        };
+
+        match length(true) {
+            length if length > 0.0 => length,
+            _ => length(false),
+        }
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Configure the axes the machine has.
+    pub fn set_axes(&mut self, axes: MachineAxes) {
+        self.axes = axes;
+    }
+
+    pub fn axes(&self) -> &MachineAxes {
+        &self.axes
+    }
+
+    /// Check a block's words only use axes the machine has.
+    pub(crate) fn check_axis_words(&self, words: &[(char, Number)]) -> Result<(), AxisError> {
+        match words
+            .iter()
+            .filter_map(|(letter, _)| Axis::from_letter(*letter))
+            .find(|axis| !self.axes.contains(*axis))
+        {
+            Some(axis) => Err(AxisError::NotConfigured(axis)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canon::Canon;
+    use crate::test_utils::run;
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    /// Position of the A axis, with X.
+    fn xa(x: Number, a: Number) -> Position {
+        Position::from([x, 0.0, 0.0, a, 0.0, 0.0, 0.0, 0.0, 0.0])
+    }
+
+    #[test]
+    fn missing_axes() {
+        let mut interp = Interpreter::new();
+        interp.set_axes(MachineAxes::new(&[Axis::X, Axis::Z]));
+
+        assert_eq!(
+            interp.axes().present().collect::<Vec<_>>(),
+            [Axis::X, Axis::Z]
+        );
+
+        run(&mut interp, "G0 X1 Z2").unwrap();
+
+        let error = run(&mut interp, "G1 X2 Y3 F100").unwrap_err();
+
+        assert_eq!(error, AxisError::NotConfigured(Axis::Y).into());
+        assert_eq!(
+            error.to_string(),
+            "Y word used but the machine has no Y axis"
+        );
+
+        // Not only in moves
+        assert_eq!(
+            run(&mut interp, "G28 A0"),
+            Err(AxisError::NotConfigured(Axis::A).into())
+        );
+    }
+
+    #[test]
+    fn wrapped_rotary() {
+        let mut interp = Interpreter::new();
+        interp.set_axes(MachineAxes::default().with(Axis::A, AxisConfig::wrapped()));
+
+        // Back 10 degrees from 0, then forwards through 0 to 10
+        assert_eq!(
+            run(&mut interp, "G0 A350\nG0 A10").unwrap(),
+            [
+                Canon::StraightTraverse {
+                    end: xa(0.0, -10.0)
+                },
+                Canon::StraightTraverse { end: xa(0.0, 10.0) },
+            ]
+        );
+
+        run(&mut interp, "G0 A170\nG0 A350\nG0 A-90").unwrap();
+        assert_eq!(*interp.position(), xa(0.0, 270.0));
+
+        // Incremental moves turn as far as they're told to
+        run(&mut interp, "G91 G0 A720").unwrap();
+        assert_eq!(*interp.position(), xa(0.0, 990.0));
+        assert_eq!(interp.axes().wrap(interp.position()), xa(0.0, 270.0));
+
+        // Only wrapped axes
+        assert_eq!(
+            run(&mut interp, "G90 G0 B350\nG0 B10").unwrap()[1],
+            Canon::StraightTraverse {
+                end: Position::from([0.0, 0.0, 0.0, 990.0, 10.0, 0.0, 0.0, 0.0, 0.0])
+            }
+        );
+    }
+
+    #[test]
+    fn inverse_time_length() {
+        let axes = MachineAxes::default();
+
+        assert_eq!(axes.move_length(&xa(0.0, 0.0), &xa(3.0, 4.0)), 3.0);
+        assert_eq!(axes.move_length(&xa(0.0, 0.0), &xa(0.0, 4.0)), 4.0);
+
+        let axes = axes.with(
+            Axis::A,
+            AxisConfig {
+                inverse_time_linear: true,
+                ..AxisConfig::rotary()
+            },
+        );
+
+        assert_eq!(axes.move_length(&xa(0.0, 0.0), &xa(3.0, 4.0)), 5.0);
+
+        let mut interp = Interpreter::new();
+        interp.set_axes(axes);
+
+        assert_eq!(
+            run(&mut interp, "G93 G1 X3 A4 F2").unwrap()[0],
+            Canon::SetFeedRate(10.0)
+        );
+    }
+}
@@ -0,0 +1,623 @@
+//! Collect the words in a block into the modal groups and values they set.
+
+use crate::error::InterpreterError;
+use common::{
+    ArcDirection, CannedCycle, Coolant, CoordinateSystem, CutterCompensation, DiameterMode,
+    DistanceMode, FeedRateMode, Motion, Number, Plane, ProbeKind, RetractMode, Spindle,
+    SpindleSpeedMode, Units,
+};
+
+/// Non-modal G codes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum NonModal {
+    /// `G4`: dwell for `P` seconds.
+    Dwell,
+
+    /// `G28` or `G30`: rapid through the point given by the axis words, if any, to the stored
+    /// position.
+    GoToPredefined(PredefinedPosition),
+
+    /// `G28.1` or `G30.1`: store the current position.
+    SetPredefined(PredefinedPosition),
+}
+
+/// The two stored positions, used by `G28` and `G30`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum PredefinedPosition {
+    G28,
+    G30,
+}
+
+/// `G40`, `G41`, `G42`, `G41.1` or `G42.1`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum CutterCompensationMode {
+    /// `G40`.
+    Off,
+
+    /// `G41` or `G42`: use the diameter of the tool given by `D`, or the current tool.
+    Tool(CutterCompensation),
+
+    /// `G41.1` or `G42.1`: use the diameter given by `D`.
+    Dynamic(CutterCompensation),
+}
+
+/// `G43`, `G43.1`, `G43.2` or `G49`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum ToolLengthOffset {
+    /// `G43`: use the offsets of the tool given by `H`, or the current tool.
+    Tool,
+
+    /// `G43.1`: use offsets given by the axis words.
+    Dynamic,
+
+    /// `G43.2`: add the offsets of the tool given by `H`.
+    Add,
+
+    /// `G49`.
+    Cancel,
+}
+
+/// `M6` or `M61`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum ToolCommand {
+    /// `M6`: change to the selected tool.
+    Change,
+
+    /// `M61`: set the current tool number given by `Q` without changing tools.
+    SetCurrent,
+}
+
+/// `M0`, `M1`, `M2`, `M30` or `M60`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Stop {
+    /// `M0`: pause the program.
+    Pause,
+
+    /// `M1`: pause the program if the optional stop switch is on.
+    OptionalPause,
+
+    /// `M2`: end the program.
+    End,
+
+    /// `M30`: exchange pallet shuttles and end the program, rewinding it.
+    EndRewind,
+
+    /// `M60`: exchange pallet shuttles and pause the program.
+    PalletShuttle,
+}
+
+/// `M48` to `M53`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum OverrideCommand {
+    /// `M48` or `M49`: turn the feed and spindle overrides on or off.
+    All(bool),
+
+    /// `M50`: turn the feed override on or off.
+    Feed,
+
+    /// `M51`: turn the spindle override on or off.
+    Spindle,
+
+    /// `M52`: turn adaptive feed on or off.
+    AdaptiveFeed,
+
+    /// `M53`: turn the feed stop switch on or off.
+    FeedStop,
+}
+
+/// `M62` to `M68`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum IoCommand {
+    /// `M62` to `M65`: turn digital output `P` on or off, as the next move starts or straight
+    /// away.
+    DigitalOutput { on: bool, synced: bool },
+
+    /// `M66`: wait for digital input `P` or analog input `E`.
+    WaitInput,
+
+    /// `M67` or `M68`: set analog output `E` to `Q`, as the next move starts or straight away.
+    AnalogOutput { synced: bool },
+}
+
+/// The commands given in a single block, checked for conflicts.
+#[derive(Debug, Default)]
+pub(crate) struct BlockCommands {
+    /// Modal group 0.
+    pub non_modal: Option<NonModal>,
+
+    /// Modal group 1. `Some(None)` is `G80`.
+    pub motion: Option<Option<Motion>>,
+
+    /// Modal group 2.
+    pub plane: Option<Plane>,
+
+    /// Modal group 3.
+    pub distance_mode: Option<DistanceMode>,
+
+    /// Modal group 4 (LinuxCNC's arc IJK distance mode).
+    pub arc_distance_mode: Option<DistanceMode>,
+
+    /// Modal group 5.
+    pub feed_rate_mode: Option<FeedRateMode>,
+
+    /// Modal group 6.
+    pub units: Option<Units>,
+
+    /// Modal group 7.
+    pub cutter_compensation: Option<CutterCompensationMode>,
+
+    /// Modal group 12.
+    pub coordinate_system: Option<CoordinateSystem>,
+
+    /// Modal group 10.
+    pub retract_mode: Option<RetractMode>,
+
+    /// Modal group 14.
+    pub spindle_speed_mode: Option<SpindleSpeedMode>,
+
+    /// Modal group 15.
+    pub diameter_mode: Option<DiameterMode>,
+
+    /// Modal group 8.
+    pub tool_length_offset: Option<ToolLengthOffset>,
+
+    /// M modal group 4.
+    pub stop: Option<Stop>,
+
+    /// M modal group 5.
+    pub io: Option<IoCommand>,
+
+    /// M modal group 6.
+    pub tool_change: Option<ToolCommand>,
+
+    /// M modal group 7.
+    pub spindle: Option<Spindle>,
+
+    /// M modal group 8.
+    pub coolant: Option<Coolant>,
+
+    /// M modal group 9.
+    pub overrides: Option<OverrideCommand>,
+
+    /// Values of every non-`G`/`M` word, indexed by letter.
+    values: [Option<Number>; 26],
+}
+
+impl BlockCommands {
+    /// Collect a block's words, given as letters and their evaluated values.
+    pub fn from_words(words: &[(char, Number)]) -> Result<Self, InterpreterError> {
+        let mut commands = Self::default();
+
+        for &(letter, value) in words.iter() {
+            match letter {
+                'G' => commands.g(value)?,
+                'M' => commands.m(value)?,
+                letter @ 'A'..='Z' => {
+                    let slot = &mut commands.values[letter as usize - 'A' as usize];
+
+                    if slot.is_some() {
+                        return Err(InterpreterError::DuplicateWord(letter));
+                    }
+
+                    *slot = Some(value);
+                }
+                letter => return Err(InterpreterError::UnknownWord(letter)),
+            }
+        }
+
+        // `G43.1` and `G43.2` use the axis words as offsets, and `G28` and `G30` as a point to
+        // move through
+        let users = [
+            commands.axis_words_are_offsets(),
+            matches!(commands.non_modal, Some(NonModal::GoToPredefined(_))),
+            commands.motion.is_some(),
+        ];
+
+        if users.iter().filter(|used| **used).count() > 1 {
+            return Err(InterpreterError::AxisCommandConflict);
+        }
+
+        Ok(commands)
+    }
+
+    /// Whether the axis words in the block are used by a command other than motion.
+    pub fn axis_words_used(&self) -> bool {
+        self.axis_words_are_offsets() || matches!(self.non_modal, Some(NonModal::GoToPredefined(_)))
+    }
+
+    /// Whether the axis words in the block are `G43.1` or `G43.2` offsets rather than positions.
+    pub fn axis_words_are_offsets(&self) -> bool {
+        matches!(
+            self.tool_length_offset,
+            Some(ToolLengthOffset::Dynamic | ToolLengthOffset::Add)
+        )
+    }
+
+    /// Get the value of a non-`G`/`M` word.
+    pub fn value(&self, letter: char) -> Option<Number> {
+        self.values[letter as usize - 'A' as usize]
+    }
+
+    /// Halve the `X` word, turning a lathe diameter into a radius.
+    pub fn diameter_to_radius(&mut self) {
+        if let Some(x) = &mut self.values['X' as usize - 'A' as usize] {
+            *x /= 2.0;
+        }
+    }
+
+    fn g(&mut self, value: Number) -> Result<(), InterpreterError> {
+        let conflict = InterpreterError::ModalGroupConflict(value);
+
+        // G codes are matched in tenths so `G90.1` is `901`.
+        match (value * 10.0).round() as i32 {
+            0 => set(&mut self.motion, Some(Motion::Rapid), conflict),
+            40 => set(&mut self.non_modal, NonModal::Dwell, conflict),
+            280 => self.predefined(NonModal::GoToPredefined(PredefinedPosition::G28), conflict),
+            281 => self.predefined(NonModal::SetPredefined(PredefinedPosition::G28), conflict),
+            300 => self.predefined(NonModal::GoToPredefined(PredefinedPosition::G30), conflict),
+            301 => self.predefined(NonModal::SetPredefined(PredefinedPosition::G30), conflict),
+            10 => set(&mut self.motion, Some(Motion::Feed), conflict),
+            20 => set(
+                &mut self.motion,
+                Some(Motion::Arc(ArcDirection::Clockwise)),
+                conflict,
+            ),
+            30 => set(
+                &mut self.motion,
+                Some(Motion::Arc(ArcDirection::CounterClockwise)),
+                conflict,
+            ),
+            330 => set(&mut self.motion, Some(Motion::SpindleSync), conflict),
+            331 => set(&mut self.motion, Some(Motion::RigidTap), conflict),
+            382 => self.probe(ProbeKind::Contact, conflict),
+            383 => self.probe(ProbeKind::ContactNoError, conflict),
+            384 => self.probe(ProbeKind::LossOfContact, conflict),
+            385 => self.probe(ProbeKind::LossOfContactNoError, conflict),
+            800 => set(&mut self.motion, None, conflict),
+            730 => self.canned_cycle(CannedCycle::ChipBreak, conflict),
+            760 => self.canned_cycle(CannedCycle::FineBore, conflict),
+            810 => self.canned_cycle(CannedCycle::Drill, conflict),
+            820 => self.canned_cycle(CannedCycle::DrillDwell, conflict),
+            830 => self.canned_cycle(CannedCycle::PeckDrill, conflict),
+            840 => self.canned_cycle(CannedCycle::Tap, conflict),
+            850 => self.canned_cycle(CannedCycle::Bore, conflict),
+            860 => self.canned_cycle(CannedCycle::BoreSpindleStop, conflict),
+            870 => self.canned_cycle(CannedCycle::BackBore, conflict),
+            880 => self.canned_cycle(CannedCycle::BoreManualRetract, conflict),
+            890 => self.canned_cycle(CannedCycle::BoreDwell, conflict),
+            70 => set(&mut self.diameter_mode, DiameterMode::Diameter, conflict),
+            80 => set(&mut self.diameter_mode, DiameterMode::Radius, conflict),
+            170 => set(&mut self.plane, Plane::XY, conflict),
+            180 => set(&mut self.plane, Plane::XZ, conflict),
+            190 => set(&mut self.plane, Plane::YZ, conflict),
+            200 => set(&mut self.units, Units::Inch, conflict),
+            210 => set(&mut self.units, Units::Mm, conflict),
+            400 => set(
+                &mut self.cutter_compensation,
+                CutterCompensationMode::Off,
+                conflict,
+            ),
+            410 => set(
+                &mut self.cutter_compensation,
+                CutterCompensationMode::Tool(CutterCompensation::Left),
+                conflict,
+            ),
+            420 => set(
+                &mut self.cutter_compensation,
+                CutterCompensationMode::Tool(CutterCompensation::Right),
+                conflict,
+            ),
+            411 => set(
+                &mut self.cutter_compensation,
+                CutterCompensationMode::Dynamic(CutterCompensation::Left),
+                conflict,
+            ),
+            421 => set(
+                &mut self.cutter_compensation,
+                CutterCompensationMode::Dynamic(CutterCompensation::Right),
+                conflict,
+            ),
+            430 => set(
+                &mut self.tool_length_offset,
+                ToolLengthOffset::Tool,
+                conflict,
+            ),
+            431 => set(
+                &mut self.tool_length_offset,
+                ToolLengthOffset::Dynamic,
+                conflict,
+            ),
+            432 => set(
+                &mut self.tool_length_offset,
+                ToolLengthOffset::Add,
+                conflict,
+            ),
+            490 => set(
+                &mut self.tool_length_offset,
+                ToolLengthOffset::Cancel,
+                conflict,
+            ),
+            960 => set(
+                &mut self.spindle_speed_mode,
+                // The maximum speed is filled in from the `D` word when the block is executed
+                SpindleSpeedMode::ConstantSurfaceSpeed { max_rpm: None },
+                conflict,
+            ),
+            970 => set(
+                &mut self.spindle_speed_mode,
+                SpindleSpeedMode::Rpm,
+                conflict,
+            ),
+            930 => set(
+                &mut self.feed_rate_mode,
+                FeedRateMode::InverseTime,
+                conflict,
+            ),
+            940 => set(
+                &mut self.feed_rate_mode,
+                FeedRateMode::UnitsPerMinute,
+                conflict,
+            ),
+            950 => set(
+                &mut self.feed_rate_mode,
+                FeedRateMode::UnitsPerRevolution,
+                conflict,
+            ),
+            980 => set(&mut self.retract_mode, RetractMode::OldZ, conflict),
+            990 => set(&mut self.retract_mode, RetractMode::RPlane, conflict),
+            900 => set(&mut self.distance_mode, DistanceMode::Absolute, conflict),
+            910 => set(&mut self.distance_mode, DistanceMode::Incremental, conflict),
+            901 => set(
+                &mut self.arc_distance_mode,
+                DistanceMode::Absolute,
+                conflict,
+            ),
+            911 => set(
+                &mut self.arc_distance_mode,
+                DistanceMode::Incremental,
+                conflict,
+            ),
+            code @ 540..=590 if code % 10 == 0 => set(
+                &mut self.coordinate_system,
+                coordinate_system(code / 10 - 53),
+                conflict,
+            ),
+            code @ 591..=593 => set(
+                &mut self.coordinate_system,
+                coordinate_system(code - 584),
+                conflict,
+            ),
+            _ => Err(InterpreterError::UnknownGCode(value)),
+        }
+    }
+
+    fn predefined(
+        &mut self,
+        non_modal: NonModal,
+        conflict: InterpreterError,
+    ) -> Result<(), InterpreterError> {
+        set(&mut self.non_modal, non_modal, conflict)
+    }
+
+    fn canned_cycle(
+        &mut self,
+        cycle: CannedCycle,
+        conflict: InterpreterError,
+    ) -> Result<(), InterpreterError> {
+        set(&mut self.motion, Some(Motion::CannedCycle(cycle)), conflict)
+    }
+
+    fn probe(
+        &mut self,
+        kind: ProbeKind,
+        conflict: InterpreterError,
+    ) -> Result<(), InterpreterError> {
+        set(&mut self.motion, Some(Motion::Probe(kind)), conflict)
+    }
+
+    fn digital_output(
+        &mut self,
+        on: bool,
+        synced: bool,
+        conflict: InterpreterError,
+    ) -> Result<(), InterpreterError> {
+        set(
+            &mut self.io,
+            IoCommand::DigitalOutput { on, synced },
+            conflict,
+        )
+    }
+
+    fn m(&mut self, value: Number) -> Result<(), InterpreterError> {
+        let conflict = InterpreterError::MModalGroupConflict(value);
+
+        match (value * 10.0).round() as i32 {
+            0 => set(&mut self.stop, Stop::Pause, conflict),
+            10 => set(&mut self.stop, Stop::OptionalPause, conflict),
+            20 => set(&mut self.stop, Stop::End, conflict),
+            300 => set(&mut self.stop, Stop::EndRewind, conflict),
+            600 => set(&mut self.stop, Stop::PalletShuttle, conflict),
+            30 => set(&mut self.spindle, Spindle::Clockwise, conflict),
+            40 => set(&mut self.spindle, Spindle::CounterClockwise, conflict),
+            50 => set(&mut self.spindle, Spindle::Stopped, conflict),
+            60 => set(&mut self.tool_change, ToolCommand::Change, conflict),
+            610 => set(&mut self.tool_change, ToolCommand::SetCurrent, conflict),
+            70 => set(&mut self.coolant, Coolant::Mist, conflict),
+            80 => set(&mut self.coolant, Coolant::Flood, conflict),
+            90 => set(&mut self.coolant, Coolant::Off, conflict),
+            480 => set(&mut self.overrides, OverrideCommand::All(true), conflict),
+            490 => set(&mut self.overrides, OverrideCommand::All(false), conflict),
+            500 => set(&mut self.overrides, OverrideCommand::Feed, conflict),
+            510 => set(&mut self.overrides, OverrideCommand::Spindle, conflict),
+            520 => set(&mut self.overrides, OverrideCommand::AdaptiveFeed, conflict),
+            530 => set(&mut self.overrides, OverrideCommand::FeedStop, conflict),
+            620 => self.digital_output(true, true, conflict),
+            630 => self.digital_output(false, true, conflict),
+            640 => self.digital_output(true, false, conflict),
+            650 => self.digital_output(false, false, conflict),
+            660 => set(&mut self.io, IoCommand::WaitInput, conflict),
+            670 => set(
+                &mut self.io,
+                IoCommand::AnalogOutput { synced: true },
+                conflict,
+            ),
+            680 => set(
+                &mut self.io,
+                IoCommand::AnalogOutput { synced: false },
+                conflict,
+            ),
+            _ => Err(InterpreterError::UnknownMCode(value)),
+        }
+    }
+}
+
+fn set<T>(
+    group: &mut Option<T>,
+    item: T,
+    conflict: InterpreterError,
+) -> Result<(), InterpreterError> {
+    if group.is_some() {
+        return Err(conflict);
+    }
+
+    *group = Some(item);
+
+    Ok(())
+}
+
+fn coordinate_system(number: i32) -> CoordinateSystem {
+    CoordinateSystem::new(number as u8).expect("G54 to G59.3 are coordinate systems 1 to 9")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modal_conflict() {
+        assert_eq!(
+            BlockCommands::from_words(&[('G', 0.0), ('G', 2.0)]).unwrap_err(),
+            InterpreterError::ModalGroupConflict(2.0)
+        );
+        assert_eq!(
+            BlockCommands::from_words(&[('G', 81.0), ('G', 80.0)]).unwrap_err(),
+            InterpreterError::ModalGroupConflict(80.0)
+        );
+        assert_eq!(
+            BlockCommands::from_words(&[('G', 93.0), ('G', 95.0)]).unwrap_err(),
+            InterpreterError::ModalGroupConflict(95.0)
+        );
+    }
+
+    #[test]
+    fn duplicate_word() {
+        assert_eq!(
+            BlockCommands::from_words(&[('X', 0.0), ('X', 2.0)]).unwrap_err(),
+            InterpreterError::DuplicateWord('X')
+        );
+    }
+
+    #[test]
+    fn decimal_codes() {
+        let commands = BlockCommands::from_words(&[('G', 91.1), ('G', 90.0)]).unwrap();
+
+        assert_eq!(commands.arc_distance_mode, Some(DistanceMode::Incremental));
+        assert_eq!(commands.distance_mode, Some(DistanceMode::Absolute));
+
+        let commands = BlockCommands::from_words(&[('G', 59.2)]).unwrap();
+
+        assert_eq!(commands.coordinate_system, CoordinateSystem::new(8));
+        let commands = BlockCommands::from_words(&[('G', 42.1)]).unwrap();
+
+        assert_eq!(
+            commands.cutter_compensation,
+            Some(CutterCompensationMode::Dynamic(CutterCompensation::Right))
+        );
+        assert_eq!(
+            BlockCommands::from_words(&[('G', 54.1)]).unwrap_err(),
+            InterpreterError::UnknownGCode(54.1)
+        );
+    }
+
+    #[test]
+    fn axis_command_conflict() {
+        assert_eq!(
+            BlockCommands::from_words(&[('G', 43.1), ('G', 1.0), ('Z', 1.0)]).unwrap_err(),
+            InterpreterError::AxisCommandConflict
+        );
+        assert!(BlockCommands::from_words(&[('G', 43.1), ('Z', 1.0)])
+            .unwrap()
+            .axis_words_used());
+        assert_eq!(
+            BlockCommands::from_words(&[('G', 28.0), ('G', 0.0), ('Z', 1.0)]).unwrap_err(),
+            InterpreterError::AxisCommandConflict
+        );
+        assert_eq!(
+            BlockCommands::from_words(&[('G', 30.0), ('G', 43.1), ('Z', 1.0)]).unwrap_err(),
+            InterpreterError::AxisCommandConflict
+        );
+        assert_eq!(
+            BlockCommands::from_words(&[('G', 4.0), ('G', 30.1)]).unwrap_err(),
+            InterpreterError::ModalGroupConflict(30.1)
+        );
+    }
+
+    #[test]
+    fn lathe_codes() {
+        let mut commands =
+            BlockCommands::from_words(&[('G', 7.0), ('G', 96.0), ('G', 4.0), ('X', 3.0)]).unwrap();
+
+        assert_eq!(commands.diameter_mode, Some(DiameterMode::Diameter));
+        assert_eq!(
+            commands.spindle_speed_mode,
+            Some(SpindleSpeedMode::ConstantSurfaceSpeed { max_rpm: None })
+        );
+        assert_eq!(commands.non_modal, Some(NonModal::Dwell));
+
+        commands.diameter_to_radius();
+
+        assert_eq!(commands.value('X'), Some(1.5));
+        assert_eq!(
+            BlockCommands::from_words(&[('G', 7.0), ('G', 8.0)]).unwrap_err(),
+            InterpreterError::ModalGroupConflict(8.0)
+        );
+    }
+
+    #[test]
+    fn m_codes() {
+        let commands = BlockCommands::from_words(&[('M', 6.0), ('M', 4.0), ('M', 8.0)]).unwrap();
+
+        assert_eq!(commands.tool_change, Some(ToolCommand::Change));
+        assert_eq!(commands.spindle, Some(Spindle::CounterClockwise));
+        assert_eq!(commands.coolant, Some(Coolant::Flood));
+
+        assert_eq!(
+            BlockCommands::from_words(&[('M', 3.0), ('M', 5.0)]).unwrap_err(),
+            InterpreterError::MModalGroupConflict(5.0)
+        );
+        assert_eq!(
+            BlockCommands::from_words(&[('M', 6.0), ('M', 61.0)]).unwrap_err(),
+            InterpreterError::MModalGroupConflict(61.0)
+        );
+
+        assert_eq!(
+            BlockCommands::from_words(&[('M', 49.0), ('M', 52.0)]).unwrap_err(),
+            InterpreterError::MModalGroupConflict(52.0)
+        );
+        assert_eq!(
+            BlockCommands::from_words(&[('M', 63.0)]).unwrap().io,
+            Some(IoCommand::DigitalOutput {
+                on: false,
+                synced: true
+            })
+        );
+        assert_eq!(
+            BlockCommands::from_words(&[('M', 62.0), ('M', 68.0)]).unwrap_err(),
+            InterpreterError::MModalGroupConflict(68.0)
+        );
+        assert_eq!(
+            BlockCommands::from_words(&[('M', 62.5)]).unwrap_err(),
+            InterpreterError::UnknownMCode(62.5)
+        );
+    }
+}
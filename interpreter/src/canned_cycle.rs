@@ -0,0 +1,696 @@
+//! Canned cycles, `G73`, `G76` and `G81` to `G89`, cancelled by `G80`.
+//!
+//! Each block in a canned cycle is expanded into rapids, feeds, dwells and spindle commands as
+//! described in section 3.5.16 of the RS274NGC spec. Cycles work in the selected plane: holes are
+//! positioned with the axis words for the plane and drilled along the axis normal to it, so for
+//! `G17` that's X and Y, then Z. Below, "Z" means the normal axis.
+//!
+//! The `R` word gives the retract plane and is kept for later blocks. The Z word gives the bottom
+//! of the hole and is kept while the same cycle stays active. The `P` dwell and `Q` peck depth are
+//! kept too. In incremental mode X and Y are increments from the current position, R is from the Z
+//! position at the start of the block and Z is from R. `L` repeats the cycle, moving by the X and
+//! Y increments each time in incremental mode or drilling the same hole again in absolute mode.
+//!
+//! If Z starts below R it first moves up to R. For each hole the tool then moves across to the
+//! hole and down to R, runs the cycle, and retracts to the clear Z: where Z started with `G98` if
+//! that's above R, otherwise R with `G99`.
+//!
+//! As in LinuxCNC, `G83` retracts to R between pecks rather than to the clear Z, and `G73` and
+//! `G83` come back down to 0.010 inch (0.254mm) above the previous peck. `G76` is a Fanuc style
+//! fine boring cycle: after an optional `P` dwell at the bottom of the hole it orients the
+//! spindle, moves the tool off the wall by `I` and `J` (the offset words for the plane's axes, as
+//! for `G87`) and retracts. On a lathe `G76` is a threading cycle instead, see [`crate::lathe`].
+
+use crate::block::BlockCommands;
+use crate::canon::Canon;
+use crate::parameters::ParameterStore;
+use crate::{Interpreter, InterpreterError};
+use common::{Axis, CannedCycle, DistanceMode, FeedRateMode, Number, RetractMode, Spindle, Units};
+use core::fmt;
+
+/// How far above the previous peck `G73` and `G83` come back down to, in inches.
+const PECK_CLEARANCE: Number = 0.010;
+
+/// Words kept from one canned cycle block to the next, as programmed.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CycleWords {
+    /// `R`: the retract plane.
+    pub r: Option<Number>,
+
+    /// The word for the axis normal to the plane: the bottom of the hole. Only kept while the same
+    /// cycle stays active.
+    pub bottom: Option<Number>,
+
+    /// `P`: dwell time in seconds.
+    pub p: Option<Number>,
+
+    /// `Q`: peck depth for `G73` and `G83`.
+    pub q: Option<Number>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CannedCycleError {
+    /// A block with a canned cycle G code doesn't have any of the cycle's axis words.
+    MissingAxes,
+
+    /// Axis words other than the plane's axes and the normal axis can't be used.
+    OtherAxis(char),
+
+    /// Canned cycles can't be used with cutter radius compensation on.
+    CutterComp,
+
+    /// Canned cycles can't be used in `G93` inverse time feed mode.
+    InverseTime,
+
+    /// The retract plane is below the bottom of the hole, on the given axis.
+    RBelowBottom(char),
+
+    /// `L` isn't a positive integer.
+    BadRepeat,
+
+    /// `G73` or `G83` peck depth isn't positive.
+    BadPeck,
+
+    /// The cycle needs the spindle turning.
+    SpindleNotTurning(CannedCycle),
+
+    /// `G84` needs the spindle turning clockwise.
+    SpindleNotClockwise,
+
+    /// `G80` with axis words, which would otherwise be ignored.
+    AxesWithG80,
+}
+
+impl fmt::Display for CannedCycleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingAxes => write!(f, "All axes missing with canned cycle"),
+            Self::OtherAxis(letter) => write!(f, "Cannot put {} word in canned cycle", letter),
+            Self::CutterComp => write!(f, "Cannot use canned cycles with cutter radius comp"),
+            Self::InverseTime => write!(f, "Cannot use canned cycles with inverse time feed"),
+            Self::RBelowBottom(letter) => {
+                write!(f, "R less than {} in canned cycle", letter)
+            }
+            Self::BadRepeat => write!(f, "L word must be a positive integer in canned cycle"),
+            Self::BadPeck => write!(f, "Q word must be greater than zero in canned cycle"),
+            Self::SpindleNotTurning(cycle) => {
+                write!(f, "Spindle not turning in G{}", cycle.code())
+            }
+            Self::SpindleNotClockwise => write!(f, "Spindle not turning clockwise in G84"),
+            Self::AxesWithG80 => write!(f, "Cannot use axis values with G80"),
+        }
+    }
+}
+
+/// Letter of the word giving an offset along `axis`, for `G76` and `G87`.
+fn offset_letter(axis: Axis) -> char {
+    match axis {
+        Axis::X => 'I',
+        Axis::Y => 'J',
+        _ => 'K',
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Run a block in a canned cycle.
+    pub(crate) fn canned_cycle(
+        &mut self,
+        cycle: CannedCycle,
+        commands: &BlockCommands,
+    ) -> Result<(), InterpreterError> {
+        if cycle == CannedCycle::Threading {
+            return self.threading(commands);
+        }
+
+        let (first, second, normal) = self.modal_groups.plane.axes();
+
+        if let Some(axis) = Axis::ALL
+            .iter()
+            .filter(|axis| ![first, second, normal].contains(axis))
+            .find(|axis| commands.value(axis.letter()).is_some())
+        {
+            return Err(CannedCycleError::OtherAxis(axis.letter()).into());
+        }
+
+        if [first, second, normal]
+            .iter()
+            .all(|axis| commands.value(axis.letter()).is_none())
+        {
+            // A block without axis words doesn't drill a hole unless it starts the cycle
+            return match commands.motion {
+                Some(_) => Err(CannedCycleError::MissingAxes.into()),
+                None => Ok(()),
+            };
+        }
+
+        if self.cutter_comp.is_some() {
+            return Err(CannedCycleError::CutterComp.into());
+        }
+
+        if self.modal_groups.feed_rate_mode == FeedRateMode::InverseTime {
+            return Err(CannedCycleError::InverseTime.into());
+        }
+
+        let mut words = self.modal_groups.cycle_words;
+
+        words.r = commands.value('R').or(words.r);
+        words.bottom = commands.value(normal.letter()).or(words.bottom);
+        words.p = commands.value('P').or(words.p);
+        words.q = commands.value('Q').or(words.q);
+
+        let r = words.r.ok_or(InterpreterError::MissingWord('R'))?;
+        let bottom = words
+            .bottom
+            .ok_or(InterpreterError::MissingWord(normal.letter()))?;
+
+        if words.p.is_some_and(|p| p < 0.0) {
+            return Err(InterpreterError::NegativeValue('P'));
+        }
+
+        let repeats = match commands.value('L') {
+            None => 1,
+            Some(l) if l >= 1.0 && l == (l as u32) as Number => l as u32,
+            Some(_) => return Err(CannedCycleError::BadRepeat.into()),
+        };
+
+        let incremental = self.modal_groups.distance_mode == DistanceMode::Incremental;
+        let start = self.position[normal.index()];
+
+        let (r, bottom) = if incremental {
+            (start + r, start + r + bottom)
+        } else {
+            (r, bottom)
+        };
+
+        if r < bottom {
+            return Err(CannedCycleError::RBelowBottom(normal.letter()).into());
+        }
+
+        let clear = match self.modal_groups.retract_mode {
+            RetractMode::OldZ if start > r => start,
+            _ => r,
+        };
+
+        let spindle = self.modal_groups.spindle;
+
+        if matches!(cycle, CannedCycle::ChipBreak | CannedCycle::PeckDrill)
+            && words.q.ok_or(InterpreterError::MissingWord('Q'))? <= 0.0
+        {
+            return Err(CannedCycleError::BadPeck.into());
+        }
+
+        match cycle {
+            CannedCycle::Tap if spindle != Spindle::Clockwise => {
+                return Err(CannedCycleError::SpindleNotClockwise.into());
+            }
+            CannedCycle::Tap if self.modal_groups.spindle_speed <= 0.0 => {
+                return Err(CannedCycleError::SpindleNotTurning(cycle).into());
+            }
+            CannedCycle::FineBore
+            | CannedCycle::BoreSpindleStop
+            | CannedCycle::BackBore
+            | CannedCycle::BoreManualRetract
+                if spindle == Spindle::Stopped =>
+            {
+                return Err(CannedCycleError::SpindleNotTurning(cycle).into());
+            }
+            _ => {}
+        }
+
+        if matches!(
+            cycle,
+            CannedCycle::DrillDwell
+                | CannedCycle::BoreSpindleStop
+                | CannedCycle::BoreManualRetract
+                | CannedCycle::BoreDwell
+        ) && words.p.is_none()
+        {
+            return Err(InterpreterError::MissingWord('P'));
+        }
+
+        // Back boring height, from the bottom of the hole in incremental mode
+        let back_bore = match commands.value(offset_letter(normal)) {
+            Some(k) if incremental => Some(bottom + k),
+            k => k,
+        };
+
+        if cycle == CannedCycle::BackBore && back_bore.is_none() {
+            return Err(InterpreterError::MissingWord(offset_letter(normal)));
+        }
+
+        self.modal_groups.cycle_words = words;
+
+        if self.modal_groups.feed_rate_mode == FeedRateMode::UnitsPerRevolution {
+            let rate = self.feed_per_revolution()?;
+
+            self.emit(Canon::SetFeedRate(rate));
+        }
+
+        let shift = (
+            commands.value(offset_letter(first)).unwrap_or(0.0),
+            commands.value(offset_letter(second)).unwrap_or(0.0),
+        );
+
+        let mut hole = (self.position[first.index()], self.position[second.index()]);
+
+        if start < r {
+            self.cycle_move(false, &[(normal, r)]);
+        }
+
+        for _ in 0..repeats {
+            for (position, axis) in [(&mut hole.0, first), (&mut hole.1, second)] {
+                if let Some(value) = commands.value(axis.letter()) {
+                    *position = if incremental {
+                        *position + value
+                    } else {
+                        value
+                    };
+                }
+            }
+
+            self.cycle_move(false, &[(first, hole.0), (second, hole.1)]);
+
+            if self.position[normal.index()] != r {
+                self.cycle_move(false, &[(normal, r)]);
+            }
+
+            let shifted = [(first, hole.0 + shift.0), (second, hole.1 + shift.1)];
+            let back = [(first, hole.0), (second, hole.1)];
+
+            match cycle {
+                CannedCycle::ChipBreak | CannedCycle::PeckDrill => {
+                    let q = words.q.unwrap_or_default();
+                    let clearance = match self.modal_groups.units {
+                        Units::Inch => PECK_CLEARANCE,
+                        Units::Mm => PECK_CLEARANCE * 25.4,
+                    };
+
+                    let mut depth = r - q;
+
+                    while depth > bottom {
+                        self.cycle_move(true, &[(normal, depth)]);
+
+                        if cycle == CannedCycle::PeckDrill {
+                            self.cycle_move(false, &[(normal, r)]);
+                        }
+
+                        self.cycle_move(false, &[(normal, depth + clearance)]);
+
+                        depth -= q;
+                    }
+
+                    self.cycle_move(true, &[(normal, bottom)]);
+                    self.cycle_move(false, &[(normal, clear)]);
+                }
+                CannedCycle::FineBore => {
+                    self.cycle_move(true, &[(normal, bottom)]);
+
+                    if let Some(p) = words.p.filter(|p| *p > 0.0) {
+                        self.emit(Canon::Dwell(p));
+                    }
+
+                    self.emit(Canon::OrientSpindle(0.0));
+                    self.cycle_move(false, &shifted);
+                    self.cycle_move(false, &[(normal, clear)]);
+                    self.cycle_move(false, &back);
+                    self.emit(Canon::Spindle(spindle));
+                }
+                CannedCycle::Drill | CannedCycle::DrillDwell => {
+                    self.cycle_move(true, &[(normal, bottom)]);
+
+                    if cycle == CannedCycle::DrillDwell {
+                        self.emit(Canon::Dwell(words.p.unwrap_or_default()));
+                    }
+
+                    self.cycle_move(false, &[(normal, clear)]);
+                }
+                CannedCycle::Tap => {
+                    let pitch = match self.modal_groups.feed_rate_mode {
+                        FeedRateMode::UnitsPerRevolution => self.feed_rate,
+                        _ => self.feed_rate / self.modal_groups.spindle_speed,
+                    };
+
+                    self.emit(Canon::StartSpeedFeedSync(pitch));
+                    self.cycle_move(true, &[(normal, bottom)]);
+                    self.emit(Canon::Spindle(Spindle::Stopped));
+                    self.emit(Canon::Spindle(Spindle::CounterClockwise));
+                    self.cycle_move(true, &[(normal, clear)]);
+                    self.emit(Canon::StopSpeedFeedSync);
+                    self.emit(Canon::Spindle(Spindle::Stopped));
+                    self.emit(Canon::Spindle(Spindle::Clockwise));
+                }
+                CannedCycle::Bore | CannedCycle::BoreDwell => {
+                    self.cycle_move(true, &[(normal, bottom)]);
+
+                    if cycle == CannedCycle::BoreDwell {
+                        self.emit(Canon::Dwell(words.p.unwrap_or_default()));
+                    }
+
+                    self.cycle_move(true, &[(normal, clear)]);
+                }
+                CannedCycle::BoreSpindleStop => {
+                    self.cycle_move(true, &[(normal, bottom)]);
+                    self.emit(Canon::Dwell(words.p.unwrap_or_default()));
+                    self.emit(Canon::Spindle(Spindle::Stopped));
+                    self.cycle_move(false, &[(normal, clear)]);
+                    self.emit(Canon::Spindle(spindle));
+                }
+                CannedCycle::BackBore => {
+                    self.cycle_move(false, &shifted);
+                    self.emit(Canon::OrientSpindle(0.0));
+                    self.cycle_move(false, &[(normal, bottom)]);
+                    self.cycle_move(false, &back);
+                    self.emit(Canon::Spindle(spindle));
+                    self.cycle_move(true, &[(normal, back_bore.unwrap_or(bottom))]);
+                    self.emit(Canon::OrientSpindle(0.0));
+                    self.cycle_move(false, &shifted);
+                    self.cycle_move(false, &[(normal, clear)]);
+                    self.cycle_move(false, &back);
+                    self.emit(Canon::Spindle(spindle));
+                }
+                CannedCycle::Threading => unreachable!("threading doesn't drill holes"),
+                CannedCycle::BoreManualRetract => {
+                    self.cycle_move(true, &[(normal, bottom)]);
+                    self.emit(Canon::Dwell(words.p.unwrap_or_default()));
+                    self.emit(Canon::Spindle(Spindle::Stopped));
+                    self.program_stop(Canon::ProgramStop);
+
+                    // The operator may jog the tool out by hand while paused; on resuming it goes
+                    // to the clear plane either way
+                    self.cycle_move(false, &[(normal, clear)]);
+                    self.emit(Canon::Spindle(spindle));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move the given axes to absolute program positions, at rapid or feed rate.
+    pub(crate) fn cycle_move(&mut self, feed: bool, moves: &[(Axis, Number)]) {
+        let mut end = self.position;
+
+        for (axis, value) in moves {
+            end[axis.index()] = *value;
+        }
+
+        let machine = end + self.program_offset();
+
+        self.emit(if feed {
+            Canon::StraightFeed { end: machine }
+        } else {
+            Canon::StraightTraverse { end: machine }
+        });
+
+        self.position = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mdi::ProgramState;
+    use crate::test_utils::run;
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+    use common::Position;
+
+    type M = libm::Libm<Number>;
+
+    /// Moves as `'R'` for rapid or `'F'` for feed followed by the rounded X, Y and Z end point.
+    fn moves(canon: &[Canon]) -> Vec<(char, Number, Number, Number)> {
+        let round = |value: Number| M::round(value * 1000.0) / 1000.0;
+
+        canon
+            .iter()
+            .filter_map(|canon| match canon {
+                Canon::StraightTraverse { end } => Some(('R', end)),
+                Canon::StraightFeed { end } => Some(('F', end)),
+                _ => None,
+            })
+            .map(|(kind, end)| (kind, round(end[0]), round(end[1]), round(end[2])))
+            .collect()
+    }
+
+    #[test]
+    fn drill_absolute() {
+        let mut interp = Interpreter::new();
+
+        // Example 1 from the RS274NGC spec
+        run(&mut interp, "G0 X1 Y2 Z3").unwrap();
+
+        let canon = run(&mut interp, "G17 G81 G90 G98 X4 Y5 Z1.5 R2.8 F100").unwrap();
+
+        assert_eq!(
+            moves(&canon),
+            [
+                ('R', 4.0, 5.0, 3.0),
+                ('R', 4.0, 5.0, 2.8),
+                ('F', 4.0, 5.0, 1.5),
+                ('R', 4.0, 5.0, 3.0),
+            ]
+        );
+
+        // R and Z are kept, and G99 retracts to R
+        let canon = run(&mut interp, "G99 X6").unwrap();
+
+        assert_eq!(
+            moves(&canon),
+            [
+                ('R', 6.0, 5.0, 3.0),
+                ('R', 6.0, 5.0, 2.8),
+                ('F', 6.0, 5.0, 1.5),
+                ('R', 6.0, 5.0, 2.8),
+            ]
+        );
+        assert_eq!(interp.named_parameter("_motion_mode"), Some(810.0));
+        assert_eq!(interp.named_parameter("_retract_r_plane"), Some(1.0));
+
+        // Blocks without axis words don't drill
+        assert_eq!(run(&mut interp, "F200").unwrap().len(), 1);
+
+        run(&mut interp, "G80").unwrap();
+
+        assert_eq!(interp.modal_groups().motion(), None);
+        assert_eq!(interp.named_parameter("_motion_mode"), Some(800.0));
+    }
+
+    #[test]
+    fn drill_incremental_repeats() {
+        let mut interp = Interpreter::new();
+
+        // Example 2 from the RS274NGC spec
+        run(&mut interp, "G0 X1 Y2 Z3").unwrap();
+
+        let canon = run(&mut interp, "G17 G81 G91 G98 X4 Y5 Z-0.6 R1.8 L3 F100").unwrap();
+
+        assert_eq!(
+            moves(&canon),
+            [
+                ('R', 1.0, 2.0, 4.8),
+                ('R', 5.0, 7.0, 4.8),
+                ('F', 5.0, 7.0, 4.2),
+                ('R', 5.0, 7.0, 4.8),
+                ('R', 9.0, 12.0, 4.8),
+                ('F', 9.0, 12.0, 4.2),
+                ('R', 9.0, 12.0, 4.8),
+                ('R', 13.0, 17.0, 4.8),
+                ('F', 13.0, 17.0, 4.2),
+                ('R', 13.0, 17.0, 4.8),
+            ]
+        );
+        assert_eq!(
+            *interp.position(),
+            Position::from([13.0, 17.0, 4.8, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+        );
+    }
+
+    #[test]
+    fn peck_drilling() {
+        let mut interp = Interpreter::new();
+
+        let canon = run(&mut interp, "G0 Z10\nG99 G83 X0 Y0 R1 Z-5 Q2 F100").unwrap();
+
+        assert_eq!(
+            moves(&canon)[1..],
+            [
+                ('R', 0.0, 0.0, 10.0),
+                ('R', 0.0, 0.0, 1.0),
+                ('F', 0.0, 0.0, -1.0),
+                ('R', 0.0, 0.0, 1.0),
+                ('R', 0.0, 0.0, -0.746),
+                ('F', 0.0, 0.0, -3.0),
+                ('R', 0.0, 0.0, 1.0),
+                ('R', 0.0, 0.0, -2.746),
+                ('F', 0.0, 0.0, -5.0),
+                ('R', 0.0, 0.0, 1.0),
+            ]
+        );
+
+        // Q is kept, but Z isn't once the cycle changes
+        assert_eq!(
+            run(&mut interp, "G73 X1"),
+            Err(InterpreterError::MissingWord('Z'))
+        );
+
+        let canon = run(&mut interp, "G20 G73 X1 Z-0.1").unwrap();
+
+        assert_eq!(
+            moves(&canon),
+            [
+                ('R', 1.0, 0.0, 1.0),
+                ('F', 1.0, 0.0, -0.1),
+                ('R', 1.0, 0.0, 1.0),
+            ]
+        );
+
+        let canon = run(&mut interp, "X2 Z-4 Q2").unwrap();
+
+        assert_eq!(
+            moves(&canon),
+            [
+                ('R', 2.0, 0.0, 1.0),
+                ('F', 2.0, 0.0, -1.0),
+                ('R', 2.0, 0.0, -0.99),
+                ('F', 2.0, 0.0, -3.0),
+                ('R', 2.0, 0.0, -2.99),
+                ('F', 2.0, 0.0, -4.0),
+                ('R', 2.0, 0.0, 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn spindle_cycles() {
+        let mut interp = Interpreter::new();
+        interp.start_program().unwrap();
+
+        let canon = run(&mut interp, "S500 M3\nG0 Z5\nG84 X1 R2 Z-3 F100").unwrap();
+        let end = |z: Number| Position::from([1.0, 0.0, z, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+
+        assert_eq!(
+            canon[canon.len() - 9..],
+            [
+                Canon::StraightTraverse { end: end(2.0) },
+                Canon::StartSpeedFeedSync(0.2),
+                Canon::StraightFeed { end: end(-3.0) },
+                Canon::Spindle(Spindle::Stopped),
+                Canon::Spindle(Spindle::CounterClockwise),
+                Canon::StraightFeed { end: end(5.0) },
+                Canon::StopSpeedFeedSync,
+                Canon::Spindle(Spindle::Stopped),
+                Canon::Spindle(Spindle::Clockwise),
+            ]
+        );
+
+        let canon = run(&mut interp, "G86 X1 R2 Z-3 P0.5").unwrap();
+
+        assert_eq!(
+            canon,
+            [
+                Canon::StraightTraverse { end: end(5.0) },
+                Canon::StraightTraverse { end: end(2.0) },
+                Canon::StraightFeed { end: end(-3.0) },
+                Canon::Dwell(0.5),
+                Canon::Spindle(Spindle::Stopped),
+                Canon::StraightTraverse { end: end(5.0) },
+                Canon::Spindle(Spindle::Clockwise),
+            ]
+        );
+
+        // Back bore up from Z-3 to Z-1, moving off centre by I0.5 to pass through the hole
+        let canon = run(&mut interp, "G87 X1 R2 Z-3 I0.5 K-1").unwrap();
+        let shifted = |z: Number| Position::from([1.5, 0.0, z, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+
+        assert_eq!(
+            canon,
+            [
+                Canon::StraightTraverse { end: end(5.0) },
+                Canon::StraightTraverse { end: end(2.0) },
+                Canon::StraightTraverse { end: shifted(2.0) },
+                Canon::OrientSpindle(0.0),
+                Canon::StraightTraverse { end: shifted(-3.0) },
+                Canon::StraightTraverse { end: end(-3.0) },
+                Canon::Spindle(Spindle::Clockwise),
+                Canon::StraightFeed { end: end(-1.0) },
+                Canon::OrientSpindle(0.0),
+                Canon::StraightTraverse { end: shifted(-1.0) },
+                Canon::StraightTraverse { end: shifted(5.0) },
+                Canon::StraightTraverse { end: end(5.0) },
+                Canon::Spindle(Spindle::Clockwise),
+            ]
+        );
+
+        let canon = run(&mut interp, "G88 X1 R2 Z-3 P1").unwrap();
+
+        assert_eq!(
+            canon[2..],
+            [
+                Canon::StraightFeed { end: end(-3.0) },
+                Canon::Dwell(1.0),
+                Canon::Spindle(Spindle::Stopped),
+                Canon::ProgramStop,
+                Canon::StraightTraverse { end: end(5.0) },
+                Canon::Spindle(Spindle::Clockwise),
+            ]
+        );
+        assert_eq!(interp.program_state(), ProgramState::Paused);
+        interp.resume().unwrap();
+
+        let canon = run(&mut interp, "G76 X1 R2 Z-3 P0 I-0.1").unwrap();
+
+        assert_eq!(
+            canon[3..],
+            [
+                Canon::OrientSpindle(0.0),
+                Canon::StraightTraverse {
+                    end: Position::from([0.9, 0.0, -3.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+                },
+                Canon::StraightTraverse {
+                    end: Position::from([0.9, 0.0, 5.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+                },
+                Canon::StraightTraverse { end: end(5.0) },
+                Canon::Spindle(Spindle::Clockwise),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut interp = Interpreter::new();
+
+        let mut error = |program: &str| run(&mut interp, program).unwrap_err();
+
+        assert_eq!(
+            error("G81 R1 Z2 X0"),
+            CannedCycleError::RBelowBottom('Z').into()
+        );
+        assert_eq!(error("G81 R1"), CannedCycleError::MissingAxes.into());
+        assert_eq!(error("G81 X0 Z-1"), InterpreterError::MissingWord('R'));
+        assert_eq!(
+            error("G81 X0 R1 Z-1 A1"),
+            CannedCycleError::OtherAxis('A').into()
+        );
+        assert_eq!(
+            error("G81 X0 R1 Z-1 L1.5"),
+            CannedCycleError::BadRepeat.into()
+        );
+        assert_eq!(error("G83 X0 R1 Z-1"), InterpreterError::MissingWord('Q'));
+        assert_eq!(error("G83 X0 R1 Z-1 Q0"), CannedCycleError::BadPeck.into());
+        assert_eq!(error("G82 X0 R1 Z-1"), InterpreterError::MissingWord('P'));
+        assert_eq!(
+            error("G86 X0 R1 Z-1 P1"),
+            CannedCycleError::SpindleNotTurning(CannedCycle::BoreSpindleStop).into()
+        );
+        assert_eq!(
+            error("M4\nG84 X0 R1 Z-1").to_string(),
+            "Spindle not turning clockwise in G84"
+        );
+        assert_eq!(error("G80 X1"), CannedCycleError::AxesWithG80.into());
+        assert_eq!(
+            error("G41.1 D1\nG81 X0 R1 Z-1"),
+            CannedCycleError::CutterComp.into()
+        );
+    }
+}
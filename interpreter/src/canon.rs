@@ -0,0 +1,114 @@
+//! Canonical machining commands produced by the interpreter.
+//!
+//! Positions are in machine coordinates, with work and tool length offsets applied.
+
+use crate::arc::ArcFeed;
+use crate::io::Output;
+use crate::overrides::OverrideSwitches;
+use common::{Coolant, CoordinateSystem, Number, Position, ProbeKind, Spindle, Units};
+
+/// A canonical machining command, in the spirit of RS274NGC's canonical machining functions.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Canon {
+    /// `USE_LENGTH_UNITS`.
+    SetUnits(Units),
+
+    /// `SET_FEED_RATE`.
+    SetFeedRate(Number),
+
+    /// `SET_SPINDLE_SPEED`.
+    SetSpindleSpeed(Number),
+
+    /// `G96` constant surface speed: turn the spindle at
+    /// [`lathe::spindle_rpm`](crate::lathe::spindle_rpm) for the X distance from `center`, until
+    /// the next `SET_SPINDLE_SPEED`. `surface_speed` is in length units per minute and `center`
+    /// is the machine X position of the spindle axis.
+    ConstantSurfaceSpeed {
+        surface_speed: Number,
+        max_rpm: Option<Number>,
+        center: Number,
+    },
+
+    /// `START_SPINDLE_CLOCKWISE`, `START_SPINDLE_COUNTERCLOCKWISE` or `STOP_SPINDLE_TURNING`.
+    Spindle(Spindle),
+
+    /// `ORIENT_SPINDLE`: stop the spindle at an angle in degrees.
+    OrientSpindle(Number),
+
+    /// `START_SPEED_FEED_SYNCH`: feed in step with spindle rotation, moving the given distance
+    /// per revolution.
+    StartSpeedFeedSync(Number),
+
+    /// `STOP_SPEED_FEED_SYNCH`.
+    StopSpeedFeedSync,
+
+    /// `MIST_ON`, `FLOOD_ON`, or `MIST_OFF` and `FLOOD_OFF` together.
+    Coolant(Coolant),
+
+    /// `SELECT_TOOL`: prepare a tool for the next change.
+    SelectTool(u32),
+
+    /// `CHANGE_TOOL`.
+    ChangeTool(u32),
+
+    /// `CHANGE_TOOL_NUMBER`: `M61` set the number of the tool already in the spindle.
+    SetToolNumber(u32),
+
+    /// `USE_TOOL_LENGTH_OFFSET`. Positions in later commands already include the offset.
+    UseToolLengthOffset(Position),
+
+    /// Switch to a work coordinate system, `G54` to `G59.3`. Positions in later commands already
+    /// include its offset.
+    SelectCoordinateSystem(CoordinateSystem),
+
+    /// `STRAIGHT_TRAVERSE`: a rapid move.
+    StraightTraverse { end: Position },
+
+    /// `STRAIGHT_FEED`: a linear move at the current feed rate.
+    StraightFeed { end: Position },
+
+    /// `ARC_FEED`: a circular or helical move at the current feed rate.
+    ArcFeed(ArcFeed),
+
+    /// `RIGID_TAP`: feed to `end` in step with the spindle, moving `pitch` per revolution, then
+    /// reverse the spindle and feed back to the start in step with it.
+    RigidTap { end: Position, pitch: Number },
+
+    /// `STRAIGHT_PROBE`: a probe move towards `end` at the current feed rate, which the
+    /// [`Probe`](crate::probe::Probe) has already carried out and found to stop at `stopped`.
+    StraightProbe {
+        kind: ProbeKind,
+        end: Position,
+        stopped: Position,
+    },
+
+    /// `ENABLE_FEED_OVERRIDE`, `DISABLE_SPEED_OVERRIDE` and so on: the overrides that apply to
+    /// the commands after this one.
+    Overrides(OverrideSwitches),
+
+    /// `SET_MOTION_OUTPUT_BIT` or `SET_MOTION_OUTPUT_VALUE`: set an output as the move that
+    /// follows starts.
+    SyncedOutput(Output),
+
+    /// `SET_AUX_OUTPUT_BIT` or `SET_AUX_OUTPUT_VALUE`: set an output straight away. The
+    /// [`IoBackend`](crate::io::IoBackend) has already set it, if there is one.
+    Output(Output),
+
+    /// `DWELL`: wait for a number of seconds.
+    Dwell(Number),
+
+    /// `PROGRAM_STOP`: wait for the operator to resume the program.
+    ProgramStop,
+
+    /// `OPTIONAL_PROGRAM_STOP`: `M1` with the optional stop switch on. Wait for the operator to
+    /// resume the program.
+    OptionalProgramStop,
+
+    /// `PALLET_SHUTTLE`: exchange pallet shuttles.
+    PalletShuttle,
+
+    /// `PROGRAM_END`: the program has ended and the machine is back in its end of program state.
+    /// `rewind` is set for `M30`, after which the program starts again from its first block.
+    ProgramEnd { rewind: bool },
+}
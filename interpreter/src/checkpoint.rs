@@ -0,0 +1,171 @@
+//! Undoing a block that fails part way through.
+//!
+//! A block's assignments and modal changes only stand if the whole block succeeds. Before a block
+//! runs, the interpreter state it can change is saved, and each parameter is saved before the
+//! block assigns it. If the block fails, everything is put back and the canonical commands it made
+//! are dropped, so fixing the error and running the block again starts from the same state.
+//!
+//! Anything already asked of the machine can't be undone. That covers tool changes, probe moves and
+//! `M66` waits, along with the parameters they set. Parameters written by a remapped code's
+//! subroutine also stay set.
+
+use crate::cutter_comp::CutterComp;
+use crate::io::Output;
+use crate::parameters::ParameterStore;
+use crate::state_change::StateChangeMark;
+use crate::sync::SyncPoint;
+use crate::{Interpreter, ModalGroupState};
+use alloc::string::String;
+use alloc::vec::Vec;
+use common::{Number, Position};
+
+/// A parameter's value before a block assigned it.
+enum Saved {
+    Numbered(usize, f64),
+
+    /// `None` if the parameter wasn't set.
+    Named(String, Option<f64>),
+}
+
+/// Interpreter state from before a block.
+pub(crate) struct Checkpoint {
+    modal_groups: ModalGroupState,
+    position: Position,
+    feed_rate: Number,
+    output: usize,
+    synced_outputs: Vec<Output>,
+    cutter_comp: Option<CutterComp>,
+    pending_sync: Option<SyncPoint>,
+    state_changes: Option<StateChangeMark>,
+    parameters: Vec<Saved>,
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    pub(crate) fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            modal_groups: self.modal_groups.clone(),
+            position: self.position,
+            feed_rate: self.feed_rate,
+            output: self.output.len(),
+            synced_outputs: self.synced_outputs.clone(),
+            cutter_comp: self.cutter_comp.clone(),
+            pending_sync: self.pending_sync.clone(),
+            state_changes: self.state_change_mark(),
+            parameters: Vec::new(),
+        }
+    }
+
+    /// Save a numbered parameter before it's assigned.
+    pub(crate) fn save_numbered(&self, checkpoint: &mut Checkpoint, index: usize) {
+        if let Ok(value) = self.parameters.numbered(index) {
+            checkpoint.parameters.push(Saved::Numbered(index, value));
+        }
+    }
+
+    /// Save a named parameter before it's assigned.
+    pub(crate) fn save_named(&self, checkpoint: &mut Checkpoint, name: &str) {
+        let value = if name.starts_with('_') {
+            self.parameters.global(name)
+        } else {
+            self.parameters.local(name)
+        };
+
+        checkpoint.parameters.push(Saved::Named(name.into(), value));
+    }
+
+    /// Put back the state saved in `checkpoint`, dropping any commands made since.
+    pub(crate) fn roll_back(&mut self, checkpoint: Checkpoint) {
+        // Last first, so a parameter assigned twice ends up with its value from before both
+        for saved in checkpoint.parameters.into_iter().rev() {
+            match saved {
+                Saved::Numbered(index, value) => {
+                    // It was read from the same index, so it's in range
+                    let _ = self.parameters.set_numbered(index, value);
+                }
+                Saved::Named(name, value) => {
+                    let global = name.starts_with('_');
+
+                    match value {
+                        Some(value) if global => {
+                            let _ = self.parameters.set_global(&name, value);
+                        }
+                        Some(value) => {
+                            let _ = self.parameters.set_local(&name, value);
+                        }
+                        None if global => self.parameters.remove_global(&name),
+                        None => self.parameters.remove_local(&name),
+                    }
+                }
+            }
+        }
+
+        self.modal_groups = checkpoint.modal_groups;
+        self.position = checkpoint.position;
+        self.feed_rate = checkpoint.feed_rate;
+        self.output.truncate(checkpoint.output);
+        self.synced_outputs = checkpoint.synced_outputs;
+        self.cutter_comp = checkpoint.cutter_comp;
+        self.pending_sync = checkpoint.pending_sync;
+        self.reset_state_changes(checkpoint.state_changes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::canon::Canon;
+    use crate::feed::FeedError;
+    use crate::test_utils::run;
+    use crate::Interpreter;
+    use common::{FeedRateMode, Position, Spindle};
+
+    #[test]
+    fn failed_block() {
+        let mut interp = Interpreter::new();
+
+        run(&mut interp, "#1 = 2 #<_depth> = 3 G1 X1 F100").unwrap();
+
+        assert_eq!(
+            run(&mut interp, "#1 = 5 #<_depth> = 6 #<b> = 7 G95 G1 X2 F0.1"),
+            Err(FeedError::SpindleNotTurning.into())
+        );
+
+        assert_eq!(interp.numbered_parameter(1), Ok(2.0));
+        assert_eq!(interp.named_parameter("_depth"), Some(3.0));
+        assert_eq!(interp.named_parameter("b"), None);
+        assert_eq!(
+            interp.modal_groups().feed_rate_mode(),
+            FeedRateMode::UnitsPerMinute
+        );
+        assert_eq!(interp.feed_rate(), 100.0);
+        assert_eq!(interp.position()[0], 1.0);
+
+        // Fixing the block starts from the same state
+        assert!(run(&mut interp, "S1000 M3\n#1 = [#1 + 1] G1 X2").is_ok());
+        assert_eq!(interp.numbered_parameter(1), Ok(3.0));
+        assert_eq!(interp.position()[0], 2.0);
+    }
+
+    #[test]
+    fn nothing_emitted() {
+        let mut interp = Interpreter::new();
+
+        // The spindle starts and the output is queued before the move fails
+        assert_eq!(
+            run(&mut interp, "S1000 M3 M62 P1 G1 X1"),
+            Err(FeedError::ZeroFeedLine.into())
+        );
+        assert_eq!(interp.modal_groups().spindle(), Spindle::Stopped);
+
+        assert_eq!(
+            run(&mut interp, "G1 X1 F100").unwrap(),
+            [
+                Canon::SetFeedRate(100.0),
+                Canon::StraightFeed {
+                    end: Position::from_column_slice(&[
+                        1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0
+                    ])
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,1312 @@
+//! O-word control flow: subroutines, branches and loops.
+//!
+//! Programs are kept in memory as lists of blocks so loops and subroutine calls can jump
+//! backwards. The main program is built up from blocks as they're popped from the command queue,
+//! so a loop body is executed as it arrives and replayed once its end is seen. Its blocks are
+//! dropped once they can't be run again, so a long program streamed through the queue doesn't
+//! take more and more memory: only loops that are still running and subroutines defined in it are
+//! kept. Subroutines are either defined in the main program, added with
+//! [`Interpreter::add_program`] or, with the `std` feature, loaded from `<name>.ngc` in a search
+//! path on first call.
+
+use crate::expression::{evaluate, ExpressionError, ExpressionErrorKind};
+use crate::mdi::ProgramState;
+use crate::parameters::{ParameterError, ParameterStore};
+use crate::remap::{Code, RemapCall, Remapped};
+use crate::snapshot::SnapshotError;
+use crate::{ErrorLocation, Interpreter, InterpreterError};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use common::{Block, Expression, Number, OName, OWord, Span, Spanned, Statement};
+use core::fmt;
+use core::ops::Range;
+
+/// Maximum subroutine call depth, as LinuxCNC's `INTERP_SUB_ROUTINE_LEVELS`.
+pub const MAX_CALL_DEPTH: usize = 10;
+
+/// Number of positional subroutine arguments, `#1`-`#30`.
+pub const SUBROUTINE_PARAMETERS: usize = 30;
+
+/// A program held in memory.
+#[derive(Debug, Clone)]
+pub(crate) struct Program {
+    /// File name, or empty for the main program.
+    pub name: String,
+
+    /// Blocks by line. Lines of the main program are dropped once they can't be run again.
+    pub blocks: BTreeMap<usize, Block>,
+}
+
+/// A subroutine defined in the main program.
+#[derive(Debug, Clone)]
+struct Definition {
+    name: OName,
+
+    /// Lines from the `sub` to the `endsub`, which is `usize::MAX` until it's received.
+    lines: Range<usize>,
+}
+
+/// The next block to execute.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub(crate) struct Cursor {
+    /// Index into [`ControlFlow::programs`].
+    pub program: usize,
+
+    /// Index of the block in the program.
+    pub line: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum LoopKind {
+    While,
+    Do,
+    Repeat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Loop {
+    name: OName,
+    kind: LoopKind,
+
+    /// Block to jump back to. For `while` loops this is the `while` itself so the condition is
+    /// re-evaluated, otherwise it's the first block of the loop body.
+    start: usize,
+
+    /// Iterations left in a `repeat` loop, including the current one.
+    remaining: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum SkipUntil {
+    /// The next `elseif`, `else` or `endif` of an `if` whose condition was false.
+    Branch,
+
+    /// The `endif` of an `if` that has already run a branch.
+    EndIf,
+
+    /// Past the end of a loop.
+    EndLoop,
+
+    /// The condition at the end of a `do` loop, which is then evaluated.
+    DoCondition,
+
+    /// The end of a subroutine definition.
+    EndSub,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Skip {
+    name: OName,
+    until: SkipUntil,
+}
+
+/// Where a subroutine was called from.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CallSite {
+    /// The subroutine that was called.
+    pub subroutine: OName,
+
+    /// File the call was made from, or empty for the main program.
+    pub program: String,
+
+    /// Line of the call in `program`.
+    pub line: u32,
+}
+
+impl fmt::Display for CallSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} called from ", self.subroutine)?;
+
+        if !self.program.is_empty() {
+            write!(f, "{} ", self.program)?;
+        }
+
+        write!(f, "line {}", self.line)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Frame {
+    call_site: CallSite,
+    return_to: Cursor,
+
+    /// Caller's values of `#1`-`#30`.
+    saved: [f64; SUBROUTINE_PARAMETERS],
+
+    /// Caller's open loops.
+    loops: Vec<Loop>,
+
+    /// The remapped code the subroutine was called for. Remaps finish within their block, so
+    /// these are never in a snapshot.
+    remap: Option<RemapCall>,
+}
+
+/// Programs, call stack and loop state.
+#[derive(Debug, Clone)]
+pub(crate) struct ControlFlow {
+    /// The main program is always first.
+    programs: Vec<Program>,
+
+    /// Number of main program blocks received, which is the line of the next one.
+    received: usize,
+
+    /// Main program lines before this have been dropped, unless they're in a definition.
+    dropped: usize,
+    definitions: Vec<Definition>,
+    cursor: Cursor,
+    subroutines: BTreeMap<OName, Cursor>,
+    frames: Vec<Frame>,
+    loops: Vec<Loop>,
+    skip: Option<Skip>,
+    #[cfg(feature = "std")]
+    search_path: Vec<std::path::PathBuf>,
+}
+
+impl Default for ControlFlow {
+    fn default() -> Self {
+        Self {
+            programs: alloc::vec![Program {
+                name: String::new(),
+                blocks: BTreeMap::new(),
+            }],
+            received: 0,
+            dropped: 0,
+            definitions: Vec::new(),
+            cursor: Cursor::default(),
+            subroutines: BTreeMap::new(),
+            frames: Vec::new(),
+            loops: Vec::new(),
+            skip: None,
+            #[cfg(feature = "std")]
+            search_path: Vec::new(),
+        }
+    }
+}
+
+impl ControlFlow {
+    /// Append a block to the end of the main program, dropping blocks that can't be run again.
+    pub fn push_block(&mut self, block: Block) {
+        let line = self.received;
+
+        match &block.o_word {
+            Some(OWord {
+                name,
+                statement: Statement::Sub,
+                ..
+            }) if self.defining().is_none() => self.definitions.push(Definition {
+                name: name.clone(),
+                lines: line..usize::MAX,
+            }),
+            Some(OWord {
+                name,
+                statement: Statement::EndSub(_),
+                ..
+            }) => {
+                if let Some(definition) = self.defining().filter(|d| d.name == *name) {
+                    definition.lines.end = line + 1;
+                }
+            }
+            _ => (),
+        }
+
+        self.programs[0].blocks.insert(line, block);
+        self.received += 1;
+
+        self.drop_finished_blocks();
+    }
+
+    /// The main program subroutine definition whose end hasn't been received yet.
+    fn defining(&mut self) -> Option<&mut Definition> {
+        self.definitions
+            .last_mut()
+            .filter(|definition| definition.lines.end == usize::MAX)
+    }
+
+    /// The first line of the main program that can still be run: the next one to run or the
+    /// start of a loop that's still running, either in the main program or in a subroutine's
+    /// caller.
+    fn first_needed_line(&self) -> usize {
+        core::iter::once((self.cursor, &self.loops))
+            .chain(
+                self.frames
+                    .iter()
+                    .map(|frame| (frame.return_to, &frame.loops)),
+            )
+            .filter(|(cursor, _)| cursor.program == 0)
+            .flat_map(|(cursor, loops)| {
+                core::iter::once(cursor.line).chain(loops.iter().map(|current| current.start))
+            })
+            .fold(self.received, usize::min)
+    }
+
+    /// Drop main program blocks that can't be run again. Subroutine definitions are kept, as
+    /// they can be called at any time.
+    fn drop_finished_blocks(&mut self) {
+        let needed = self.first_needed_line();
+
+        for line in self.dropped..needed {
+            if !self
+                .definitions
+                .iter()
+                .any(|definition| definition.lines.contains(&line))
+            {
+                self.programs[0].blocks.remove(&line);
+            }
+        }
+
+        self.dropped = self.dropped.max(needed);
+    }
+
+    /// Forget the blocks of the main program, so the next one starts from its first block.
+    fn clear_main_blocks(&mut self) {
+        self.programs[0].blocks.clear();
+        self.received = 0;
+        self.dropped = 0;
+        self.definitions.clear();
+    }
+
+    /// Forget the main program so the next one starts from its first block. Subroutines defined
+    /// in other programs are kept.
+    pub fn clear_main_program(&mut self) {
+        self.clear_main_blocks();
+        self.cursor = Cursor::default();
+        self.subroutines.retain(|_, cursor| cursor.program != 0);
+        self.loops.clear();
+        self.skip = None;
+    }
+
+    /// Whether blocks are being skipped, e.g. in a branch that isn't taken.
+    pub fn skipping(&self) -> bool {
+        self.skip.is_some()
+    }
+
+    /// Current subroutine call depth. The main program is at depth `0`.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Number of subroutines on the call stack that were called for a remapped code.
+    pub fn remap_depth(&self) -> usize {
+        self.frames
+            .iter()
+            .filter(|frame| frame.remap.is_some())
+            .count()
+    }
+
+    /// Whether a subroutine called for `code` is on the call stack.
+    pub fn remapping(&self, code: Code) -> bool {
+        self.frames
+            .iter()
+            .any(|frame| frame.remap.as_ref().map(|call| call.code) == Some(code))
+    }
+
+    fn skip(&mut self, name: &OName, until: SkipUntil) {
+        self.skip = Some(Skip {
+            name: name.clone(),
+            until,
+        });
+    }
+
+    /// The innermost open loop, which must have the given name.
+    fn current_loop(&mut self, o_word: &OWord) -> Result<&mut Loop, ControlFlowError> {
+        match self.loops.last_mut() {
+            Some(current) if current.name == o_word.name => Ok(current),
+            _ => Err(ControlFlowError::unexpected(o_word)),
+        }
+    }
+
+    /// Move to the next iteration of a `repeat` loop, returning `false` if the loop has finished.
+    fn next_iteration(&mut self, o_word: &OWord) -> Result<bool, ControlFlowError> {
+        let current = self.current_loop(o_word)?;
+
+        if current.kind != LoopKind::Repeat {
+            return Err(ControlFlowError::unexpected(o_word));
+        }
+
+        current.remaining -= 1;
+
+        if current.remaining > 0 {
+            self.cursor.line = current.start;
+
+            Ok(true)
+        } else {
+            self.loops.pop();
+
+            Ok(false)
+        }
+    }
+
+    /// Add a program, registering any subroutines it defines.
+    fn add_program(&mut self, name: &str, blocks: Vec<Block>) {
+        let program = self.programs.len();
+
+        for (line, block) in blocks.iter().enumerate() {
+            if let Some(OWord {
+                name,
+                statement: Statement::Sub,
+                ..
+            }) = &block.o_word
+            {
+                self.subroutines.insert(
+                    name.clone(),
+                    Cursor {
+                        program,
+                        line: line + 1,
+                    },
+                );
+            }
+        }
+
+        self.programs.push(Program {
+            name: name.into(),
+            blocks: blocks.into_iter().enumerate().collect(),
+        });
+    }
+
+    /// Load `<name>.ngc` from the first directory in the search path that has it.
+    #[cfg(feature = "std")]
+    fn load_subroutine(&mut self, name: &OName) -> Result<(), ControlFlowError> {
+        if let OName::Named(name) = name {
+            self.load_file(&alloc::format!("{}.ngc", name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Load a file from the first directory in the search path that has it, returning `false` if
+    /// none do.
+    #[cfg(feature = "std")]
+    fn load_file(&mut self, file_name: &str) -> Result<bool, ControlFlowError> {
+        let path = match self
+            .search_path
+            .iter()
+            .map(|dir| dir.join(file_name))
+            .find(|path| path.is_file())
+        {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+
+        let contents = std::fs::read_to_string(&path).map_err(|e| ControlFlowError::Io {
+            file: file_name.into(),
+            kind: e.kind(),
+        })?;
+
+        let blocks = parser::parse_program(&contents).map_err(|error| ControlFlowError::Parse {
+            file: file_name.into(),
+            error,
+        })?;
+
+        self.add_program(file_name, blocks);
+
+        Ok(true)
+    }
+
+    fn program_cursor(&self, cursor: Cursor) -> ProgramCursor {
+        ProgramCursor {
+            program: self.programs[cursor.program].name.clone(),
+            block: cursor.line,
+        }
+    }
+
+    /// Find a program by name, loading it from the search path if it isn't already loaded.
+    fn resolve(&mut self, cursor: &ProgramCursor) -> Result<Cursor, InterpreterError> {
+        let find = |flow: &Self| {
+            flow.programs
+                .iter()
+                .position(|program| program.name == cursor.program)
+        };
+
+        #[cfg(feature = "std")]
+        if find(self).is_none() {
+            self.load_file(&cursor.program)?;
+        }
+
+        let program =
+            find(self).ok_or_else(|| SnapshotError::UnknownProgram(cursor.program.clone()))?;
+
+        Ok(Cursor {
+            program,
+            line: cursor.block,
+        })
+    }
+
+    pub fn snapshot(&self) -> ControlFlowSnapshot {
+        ControlFlowSnapshot {
+            cursor: self.program_cursor(self.cursor),
+            subroutines: self
+                .subroutines
+                .iter()
+                .map(|(name, cursor)| (name.clone(), self.program_cursor(*cursor)))
+                .collect(),
+            frames: self
+                .frames
+                .iter()
+                .map(|frame| FrameSnapshot {
+                    call_site: frame.call_site.clone(),
+                    return_to: self.program_cursor(frame.return_to),
+                    saved: frame.saved,
+                    loops: frame.loops.clone(),
+                })
+                .collect(),
+            loops: self.loops.clone(),
+            skip: self.skip.clone(),
+        }
+    }
+
+    /// Restore state from a snapshot. The main program is cleared, ready to be queued again.
+    pub fn restore(&mut self, snapshot: ControlFlowSnapshot) -> Result<(), InterpreterError> {
+        self.clear_main_blocks();
+
+        self.cursor = self.resolve(&snapshot.cursor)?;
+
+        self.subroutines.clear();
+
+        for (name, cursor) in snapshot.subroutines.iter() {
+            let cursor = self.resolve(cursor)?;
+
+            self.subroutines.insert(name.clone(), cursor);
+        }
+
+        self.frames.clear();
+
+        for frame in snapshot.frames {
+            let return_to = self.resolve(&frame.return_to)?;
+
+            self.frames.push(Frame {
+                call_site: frame.call_site,
+                return_to,
+                saved: frame.saved,
+                loops: frame.loops,
+                remap: None,
+            });
+        }
+
+        self.loops = snapshot.loops;
+        self.skip = snapshot.skip;
+
+        Ok(())
+    }
+}
+
+/// A position in a program, identified by name rather than load order.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProgramCursor {
+    /// File name, or empty for the main program.
+    pub program: String,
+
+    /// Index of the next block to execute. For programs parsed with [`parser::parse_program`]
+    /// this is the zero-based line number.
+    pub block: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct FrameSnapshot {
+    call_site: CallSite,
+    return_to: ProgramCursor,
+    saved: [f64; SUBROUTINE_PARAMETERS],
+    loops: Vec<Loop>,
+}
+
+/// Program position, call stack and loop state.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ControlFlowSnapshot {
+    /// The next block to execute.
+    pub cursor: ProgramCursor,
+
+    subroutines: Vec<(OName, ProgramCursor)>,
+    frames: Vec<FrameSnapshot>,
+    loops: Vec<Loop>,
+    skip: Option<Skip>,
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Add a program whose subroutines can be called by name, e.g. the contents of a subroutine
+    /// file on a machine without a filesystem.
+    pub fn add_program(&mut self, name: &str, blocks: Vec<Block>) {
+        self.control_flow.add_program(name, blocks);
+    }
+
+    /// Add a directory to search for `<name>.ngc` when `o<name> call` calls a subroutine that
+    /// hasn't been defined yet. Directories are searched in the order they're added.
+    #[cfg(feature = "std")]
+    pub fn add_subroutine_path(&mut self, path: impl Into<std::path::PathBuf>) {
+        self.control_flow.search_path.push(path.into());
+    }
+
+    /// Current subroutine calls, innermost first.
+    pub fn call_stack(&self) -> impl Iterator<Item = &CallSite> {
+        self.control_flow
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| &frame.call_site)
+    }
+
+    /// Execute blocks until the end of the main program received so far, or until the program
+    /// is paused, stops at a sync point or fills the output.
+    pub(crate) fn run(&mut self) -> Result<(), InterpreterError> {
+        while self.waiting.is_none()
+            && self.program_state != ProgramState::Paused
+            && !self.output_full()
+        {
+            // Wait for more of the main program to be queued
+            let block = match self.next_block() {
+                Ok(Some(block)) => block,
+                Ok(None) => return Ok(()),
+                Err(e) => return Err(self.locate(e, None)),
+            };
+
+            // Read the block again once the machine has caught up
+            if self.sync_before(&block) {
+                self.control_flow.cursor.line -= 1;
+
+                return Ok(());
+            }
+
+            let result = self.step(&block);
+
+            if let Some(point) = self.pending_sync.take() {
+                if result.is_ok() {
+                    self.sync_after(point, &block);
+                }
+            }
+
+            result.map_err(|e| self.locate(e, Some(&block)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Execute blocks until the innermost subroutine returns, e.g. one called for a remapped
+    /// code that has to finish before the rest of its block.
+    pub(crate) fn run_subroutine(&mut self) -> Result<(), InterpreterError> {
+        let depth = self.control_flow.depth();
+
+        while self.control_flow.depth() >= depth {
+            match self.next_block() {
+                Ok(Some(block)) => self
+                    .step(&block)
+                    .map_err(|e| self.locate(e, Some(&block)))?,
+                Ok(None) => return Err(self.locate(self.missing_end_sub().into(), None)),
+                Err(e) => return Err(self.locate(e, None)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move the cursor past the next block and return it, or `None` at the end of the main
+    /// program received so far.
+    fn next_block(&mut self) -> Result<Option<Block>, InterpreterError> {
+        let cursor = self.control_flow.cursor;
+
+        let block = match self.control_flow.programs[cursor.program]
+            .blocks
+            .get(&cursor.line)
+        {
+            Some(block) => block.clone(),
+            None if cursor.program == 0 => return Ok(None),
+            None => return Err(self.missing_end_sub().into()),
+        };
+
+        self.control_flow.cursor.line += 1;
+
+        Ok(Some(block))
+    }
+
+    fn missing_end_sub(&self) -> ControlFlowError {
+        ControlFlowError::MissingEndSub(
+            self.call_stack()
+                .next()
+                .map(|call| call.subroutine.clone())
+                .unwrap_or(OName::Number(0)),
+        )
+    }
+
+    /// Add the location of an error raised by a block, or by the program it's in if `None`. An
+    /// error raised in a subroutine called by the block already has its location.
+    pub(crate) fn locate(
+        &self,
+        error: InterpreterError,
+        block: Option<&Block>,
+    ) -> InterpreterError {
+        if error.location().is_some() {
+            return error;
+        }
+
+        let word = match (&error, block) {
+            (InterpreterError::Expression(e), _) => Some(e.span),
+            (InterpreterError::ControlFlow(_), Some(block)) => {
+                block.o_word.as_ref().map(|o_word| o_word.span)
+            }
+            (error, Some(block)) => error.word().and_then(|(letter, value)| {
+                block
+                    .words
+                    .iter()
+                    .rev()
+                    .filter(|word| word.letter == letter)
+                    .find(|word| match value {
+                        Some(value) => {
+                            evaluate(&word.value, self).ok().map(|v| v as Number) == Some(value)
+                        }
+                        None => true,
+                    })
+                    .map(|word| word.span)
+            }),
+            (_, None) => None,
+        };
+
+        InterpreterError::Located {
+            error: alloc::boxed::Box::new(error),
+            location: ErrorLocation {
+                block: block.map(|block| block.span).unwrap_or_default(),
+                word,
+                call_stack: self.call_stack().cloned().collect(),
+            },
+        }
+    }
+
+    fn step(&mut self, block: &Block) -> Result<(), InterpreterError> {
+        if self.skip_block(block)? {
+            return Ok(());
+        }
+
+        let result = match &block.o_word {
+            Some(o_word) => self.o_word(o_word),
+            None => self.execute_block(block),
+        };
+
+        self.record_changes(block.span);
+
+        result
+    }
+
+    /// Whether a block is skipped because it's in a branch or loop that isn't running, or in a
+    /// subroutine definition.
+    fn skip_block(&mut self, block: &Block) -> Result<bool, InterpreterError> {
+        let until = match (&self.control_flow.skip, &block.o_word) {
+            (None, _) => return Ok(false),
+            (Some(skip), Some(o_word)) if skip.name == o_word.name => skip.until,
+            (Some(_), _) => return Ok(true),
+        };
+
+        // Checked above
+        let statement = &block.o_word.as_ref().unwrap().statement;
+
+        let stop = match (until, statement) {
+            (SkipUntil::Branch, Statement::ElseIf(condition)) => self.condition(condition)?,
+            (SkipUntil::Branch, Statement::Else)
+            | (SkipUntil::Branch, Statement::EndIf)
+            | (SkipUntil::EndIf, Statement::EndIf)
+            | (SkipUntil::EndLoop, Statement::EndWhile)
+            | (SkipUntil::EndLoop, Statement::EndRepeat)
+            | (SkipUntil::EndLoop, Statement::While(_))
+            | (SkipUntil::EndSub, Statement::EndSub(_)) => true,
+            (SkipUntil::DoCondition, Statement::While(_)) => {
+                self.control_flow.skip = None;
+
+                // Evaluate the condition
+                return Ok(false);
+            }
+            _ => false,
+        };
+
+        if stop {
+            self.control_flow.skip = None;
+        }
+
+        Ok(true)
+    }
+
+    fn condition(&self, condition: &Spanned<Expression>) -> Result<bool, InterpreterError> {
+        Ok(evaluate(condition, self)? != 0.0)
+    }
+
+    fn o_word(&mut self, o_word: &OWord) -> Result<(), InterpreterError> {
+        let name = &o_word.name;
+        let flow = &mut self.control_flow;
+
+        match &o_word.statement {
+            Statement::Sub => {
+                flow.subroutines.insert(name.clone(), flow.cursor);
+                flow.skip(name, SkipUntil::EndSub);
+            }
+            Statement::EndSub(value) | Statement::Return(value) => {
+                self.return_from(o_word, value.as_ref())?
+            }
+            Statement::Call(arguments) => self.call(o_word, arguments)?,
+            Statement::If(condition) => {
+                if !self.condition(condition)? {
+                    self.control_flow.skip(name, SkipUntil::Branch);
+                }
+            }
+            // A previous branch ran
+            Statement::ElseIf(_) | Statement::Else => flow.skip(name, SkipUntil::EndIf),
+            Statement::EndIf => (),
+            Statement::Do => flow.loops.push(Loop {
+                name: name.clone(),
+                kind: LoopKind::Do,
+                start: flow.cursor.line,
+                remaining: 0,
+            }),
+            Statement::While(condition) => {
+                let condition = self.condition(condition)?;
+                let flow = &mut self.control_flow;
+
+                let is_do = matches!(
+                    flow.loops.last(),
+                    Some(Loop { name: loop_name, kind: LoopKind::Do, .. }) if loop_name == name
+                );
+
+                match (is_do, condition) {
+                    (true, true) => flow.cursor.line = flow.current_loop(o_word)?.start,
+                    (true, false) => {
+                        flow.loops.pop();
+                    }
+                    (false, true) => flow.loops.push(Loop {
+                        name: name.clone(),
+                        kind: LoopKind::While,
+                        start: flow.cursor.line - 1,
+                        remaining: 0,
+                    }),
+                    (false, false) => flow.skip(name, SkipUntil::EndLoop),
+                }
+            }
+            Statement::EndWhile => {
+                if flow.current_loop(o_word)?.kind != LoopKind::While {
+                    return Err(ControlFlowError::unexpected(o_word).into());
+                }
+
+                // Jump back to re-evaluate the condition, which starts the loop again
+                flow.cursor.line = flow.loops.pop().map_or(0, |current| current.start);
+            }
+            Statement::Repeat(count) => {
+                let count = libm::round(evaluate(count, self)?);
+                let flow = &mut self.control_flow;
+
+                if count >= 1.0 {
+                    flow.loops.push(Loop {
+                        name: name.clone(),
+                        kind: LoopKind::Repeat,
+                        start: flow.cursor.line,
+                        remaining: count as u32,
+                    });
+                } else {
+                    flow.skip(name, SkipUntil::EndLoop);
+                }
+            }
+            Statement::EndRepeat => {
+                flow.next_iteration(o_word)?;
+            }
+            Statement::Break => {
+                flow.current_loop(o_word)?;
+                flow.loops.pop();
+                flow.skip(name, SkipUntil::EndLoop);
+            }
+            Statement::Continue => match flow.current_loop(o_word)?.kind {
+                LoopKind::While => {
+                    flow.cursor.line = flow.loops.pop().map_or(0, |current| current.start);
+                }
+                LoopKind::Do => flow.skip(name, SkipUntil::DoCondition),
+                LoopKind::Repeat => {
+                    if !flow.next_iteration(o_word)? {
+                        flow.skip(name, SkipUntil::EndLoop);
+                    }
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn call(
+        &mut self,
+        o_word: &OWord,
+        arguments: &[Spanned<Expression>],
+    ) -> Result<(), InterpreterError> {
+        if arguments.len() > SUBROUTINE_PARAMETERS {
+            return Err(ControlFlowError::TooManyArguments(o_word.name.clone()).into());
+        }
+
+        // Arguments are evaluated in the caller's scope
+        let mut values = [0.0; SUBROUTINE_PARAMETERS];
+
+        for (value, argument) in values.iter_mut().zip(arguments) {
+            *value = evaluate(argument, self)?;
+        }
+
+        self.call_subroutine(&o_word.name, &values, o_word.span, None)
+    }
+
+    /// Call a subroutine with values for `#1`-`#30`, continuing from its first block. `remap` is
+    /// the remapped code it's called for, if any.
+    pub(crate) fn call_subroutine(
+        &mut self,
+        name: &OName,
+        values: &[f64; SUBROUTINE_PARAMETERS],
+        span: Span,
+        remap: Option<RemapCall>,
+    ) -> Result<(), InterpreterError> {
+        if self.control_flow.depth() >= MAX_CALL_DEPTH {
+            return Err(ControlFlowError::CallDepthExceeded.into());
+        }
+
+        #[cfg(feature = "std")]
+        if !self.control_flow.subroutines.contains_key(name) {
+            self.control_flow.load_subroutine(name)?;
+        }
+
+        let start = *self
+            .control_flow
+            .subroutines
+            .get(name)
+            .ok_or_else(|| ControlFlowError::UnknownSubroutine(name.clone()))?;
+
+        let parameter_error = |e: ParameterError| ExpressionError {
+            kind: ExpressionErrorKind::Parameter(e),
+            span,
+        };
+
+        let mut saved = [0.0; SUBROUTINE_PARAMETERS];
+
+        for (index, (saved, value)) in saved.iter_mut().zip(values.iter()).enumerate() {
+            *saved = self
+                .parameters
+                .numbered(index + 1)
+                .map_err(parameter_error)?;
+
+            self.parameters
+                .set_numbered(index + 1, *value)
+                .map_err(parameter_error)?;
+        }
+
+        self.parameters.push_scope().map_err(parameter_error)?;
+        self.set_return_value(None).map_err(parameter_error)?;
+
+        let flow = &mut self.control_flow;
+
+        flow.frames.push(Frame {
+            call_site: CallSite {
+                subroutine: name.clone(),
+                program: flow.programs[flow.cursor.program].name.clone(),
+                line: span.line,
+            },
+            return_to: flow.cursor,
+            saved,
+            loops: core::mem::take(&mut flow.loops),
+            remap,
+        });
+
+        flow.cursor = start;
+
+        Ok(())
+    }
+
+    fn return_from(
+        &mut self,
+        o_word: &OWord,
+        value: Option<&Spanned<Expression>>,
+    ) -> Result<(), InterpreterError> {
+        // `return` may be labelled with the name of a branch or loop in the subroutine, but
+        // `endsub` must match the subroutine
+        match (self.control_flow.frames.last(), &o_word.statement) {
+            (Some(_), Statement::Return(_)) => (),
+            (Some(frame), Statement::EndSub(_)) if frame.call_site.subroutine == o_word.name => (),
+            _ => return Err(ControlFlowError::unexpected(o_word).into()),
+        }
+
+        let value = value.map(|value| evaluate(value, self)).transpose()?;
+
+        let parameter_error = |e: ParameterError| ExpressionError {
+            kind: ExpressionErrorKind::Parameter(e),
+            span: o_word.span,
+        };
+
+        if value.is_some() {
+            self.set_return_value(value).map_err(parameter_error)?;
+        }
+
+        // The epilog of a remap runs while the subroutine's locals are still in scope
+        let remap = self
+            .control_flow
+            .frames
+            .last()
+            .and_then(|frame| frame.remap.clone());
+
+        let action = match &remap {
+            Some(call) => self.remap_epilog(call)?,
+            None => Remapped::Done,
+        };
+
+        self.pop_frame(o_word.span)?;
+
+        match (remap, action) {
+            (Some(call), Remapped::BuiltIn) => self.built_in(&call),
+            _ => Ok(()),
+        }
+    }
+
+    /// Leave every subroutine and loop at the end of the program and forget the main program, so
+    /// nothing after `M2` or `M30` runs.
+    pub(crate) fn end_control_flow(&mut self, span: Span) -> Result<(), InterpreterError> {
+        while self.control_flow.depth() > 0 {
+            self.pop_frame(span)?;
+        }
+
+        self.control_flow.clear_main_program();
+
+        Ok(())
+    }
+
+    /// Leave the innermost subroutine, restoring the caller's parameters and loops.
+    pub(crate) fn pop_frame(&mut self, span: Span) -> Result<(), InterpreterError> {
+        let frame = match self.control_flow.frames.pop() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        for (index, saved) in frame.saved.iter().enumerate() {
+            self.parameters
+                .set_numbered(index + 1, *saved)
+                .map_err(|e| ExpressionError {
+                    kind: ExpressionErrorKind::Parameter(e),
+                    span,
+                })?;
+        }
+
+        self.parameters.pop_scope();
+
+        let flow = &mut self.control_flow;
+
+        flow.loops = frame.loops;
+        flow.cursor = frame.return_to;
+        flow.skip = None;
+
+        Ok(())
+    }
+
+    /// Set `#<_value>` and `#<_value_returned>`.
+    fn set_return_value(&mut self, value: Option<f64>) -> Result<(), ParameterError> {
+        self.parameters.set_global("_value", value.unwrap_or(0.0))?;
+        self.parameters
+            .set_global("_value_returned", if value.is_some() { 1.0 } else { 0.0 })
+    }
+}
+
+/// Errors from O-word statements.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlFlowError {
+    /// No subroutine with this name has been defined or could be loaded.
+    UnknownSubroutine(OName),
+
+    /// More than [`SUBROUTINE_PARAMETERS`] arguments were given to a call.
+    TooManyArguments(OName),
+
+    /// More than [`MAX_CALL_DEPTH`] nested calls.
+    CallDepthExceeded,
+
+    /// A statement that doesn't match the innermost open subroutine or loop, e.g. an `endwhile`
+    /// with no `while`.
+    Unexpected { name: OName, keyword: &'static str },
+
+    /// A subroutine file ended before the subroutine's `endsub`.
+    MissingEndSub(OName),
+
+    /// A subroutine file couldn't be parsed.
+    Parse {
+        file: String,
+        error: parser::ParseError,
+    },
+
+    /// A subroutine file couldn't be read.
+    #[cfg(feature = "std")]
+    Io {
+        file: String,
+        kind: std::io::ErrorKind,
+    },
+}
+
+impl ControlFlowError {
+    fn unexpected(o_word: &OWord) -> Self {
+        Self::Unexpected {
+            name: o_word.name.clone(),
+            keyword: o_word.statement.keyword(),
+        }
+    }
+}
+
+impl fmt::Display for ControlFlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownSubroutine(name) => write!(f, "Unknown subroutine {}", name),
+            Self::TooManyArguments(name) => write!(
+                f,
+                "Too many arguments to {}, at most {} are allowed",
+                name, SUBROUTINE_PARAMETERS
+            ),
+            Self::CallDepthExceeded => write!(
+                f,
+                "Too many nested subroutine calls, at most {} are allowed",
+                MAX_CALL_DEPTH
+            ),
+            Self::Unexpected { name, keyword } => write!(f, "Unexpected {} {}", name, keyword),
+            Self::MissingEndSub(name) => write!(f, "{} sub has no endsub", name),
+            Self::Parse { file, error } => write!(f, "{} in {}", error, file),
+            #[cfg(feature = "std")]
+            Self::Io { file, kind } => write!(f, "Unable to open {}: {:?}", file, kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canon::Canon;
+    use crate::test_utils::run_located as run;
+    use alloc::string::ToString;
+
+    fn x_moves(canon: &[Canon]) -> Vec<f32> {
+        canon
+            .iter()
+            .filter_map(|canon| match canon {
+                Canon::StraightTraverse { end } => Some(end[0]),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recursion() {
+        let mut interp = Interpreter::new();
+
+        run(
+            &mut interp,
+            "o<factorial> sub
+            o<factorialif> if [[#1] EQ 0]
+            o<factorial>      return [1]
+            o<factorialif> else
+            o<factorial>      call [[#1] - 1]
+            o<factorial>      return  [#<_value> * #1]
+            o<factorialif> endif
+            o<factorial> endsub
+            #1 = 7
+            o<factorial> call [5]",
+        )
+        .unwrap();
+
+        assert_eq!(interp.named_parameter("_value"), Some(120.0));
+        // Positional parameters are restored on return
+        assert_eq!(interp.numbered_parameter(1), Ok(7.0));
+        assert_eq!(interp.call_stack().count(), 0);
+    }
+
+    #[test]
+    fn return_values() {
+        let mut interp = Interpreter::new();
+
+        let program = "o1000 sub
+            o1010 if [#1 GT 0]
+            o1010    return [123*[#1]]
+            o1010 endif
+            o1020 if [#1 LT 0]
+            o1020    return
+            o1020 endif
+            o1000 endsub [4712]";
+
+        run(&mut interp, program).unwrap();
+
+        for (argument, value, returned) in [(0.0, 4712.0, 1.0), (2.0, 246.0, 1.0), (-1.0, 0.0, 0.0)]
+        {
+            run(&mut interp, &alloc::format!("o1000 call [{}]", argument)).unwrap();
+
+            assert_eq!(interp.named_parameter("_value"), Some(value));
+            assert_eq!(interp.named_parameter("_value_returned"), Some(returned));
+        }
+    }
+
+    #[test]
+    fn local_scope() {
+        let mut interp = Interpreter::new();
+
+        run(
+            &mut interp,
+            "#<depth> = 1
+            o<inner> sub
+                #<depth> = 2
+                #<_seen> = EXISTS[#<depth>]
+                #<_level> = #<_call_level>
+            o<inner> endsub
+            o<inner> call",
+        )
+        .unwrap();
+
+        assert_eq!(interp.named_parameter("depth"), Some(1.0));
+        assert_eq!(interp.named_parameter("_seen"), Some(1.0));
+        assert_eq!(interp.named_parameter("_level"), Some(1.0));
+        assert_eq!(interp.named_parameter("_call_level"), Some(0.0));
+    }
+
+    #[test]
+    fn branches_and_loops() {
+        let mut interp = Interpreter::new();
+
+        let canon = run(
+            &mut interp,
+            "#1 = 0
+            o1 while [#1 LT 3]
+                G0 X#1
+                #1 = [#1 + 1]
+            o1 endwhile
+            o2 repeat [2]
+                o3 if [#1 EQ 3]
+                    G0 X10
+                o3 elseif [#1 EQ 4]
+                    G0 X11
+                o3 else
+                    G0 X12
+                o3 endif
+                #1 = [#1 + 1]
+            o2 endrepeat
+            o4 do
+                #1 = [#1 + 1]
+                o5 if [#1 EQ 6]
+                    o4 continue
+                o5 endif
+                G0 X#1
+                o6 if [#1 GE 8]
+                    o4 break
+                o6 endif
+            o4 while [1]
+            o7 while [0]
+                G0 X100
+            o7 endwhile
+            G0 X20",
+        )
+        .unwrap();
+
+        assert_eq!(x_moves(&canon), [0.0, 1.0, 2.0, 10.0, 11.0, 7.0, 8.0, 20.0]);
+    }
+
+    #[test]
+    fn errors() {
+        let mut interp = Interpreter::new();
+
+        assert_eq!(
+            run(&mut interp, "o<missing> call").map_err(InterpreterError::into_cause),
+            Err(ControlFlowError::UnknownSubroutine(OName::Named("missing".into())).into())
+        );
+        assert_eq!(
+            run(&mut interp, "o1 endwhile").unwrap_err().to_string(),
+            "Unexpected o1 endwhile at line 1, column 1"
+        );
+
+        let mut interp = Interpreter::new();
+
+        let error = run(
+            &mut interp,
+            "o<forever> sub\no<forever> call\no<forever> endsub\no<forever> call",
+        )
+        .unwrap_err();
+
+        let call_stack = &error.location().unwrap().call_stack;
+
+        assert_eq!(*error.cause(), ControlFlowError::CallDepthExceeded.into());
+        assert_eq!(call_stack.len(), MAX_CALL_DEPTH);
+        assert_eq!(call_stack[0].line, 2);
+        assert_eq!(call_stack[MAX_CALL_DEPTH - 1].line, 4);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn subroutine_files() {
+        use common::Position;
+
+        let test_files = concat!(env!("CARGO_MANIFEST_DIR"), "/../test_files/linuxcnc");
+
+        let mut interp = Interpreter::new();
+        interp.add_subroutine_path(alloc::format!("{}/remap-subroutines", test_files));
+        interp.add_subroutine_path(alloc::format!("{}/ngcgui_lib", test_files));
+
+        let canon = run(&mut interp, "o<simp> call [.6] [0.4] [100]").unwrap();
+
+        assert_eq!(
+            canon[..2],
+            [
+                Canon::StraightTraverse {
+                    end: Position::from([0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+                },
+                Canon::SetFeedRate(100.0),
+            ]
+        );
+        assert_eq!(canon.len(), 4);
+        assert_eq!(interp.named_parameter("feedrate"), None);
+
+        // `change.ngc` expects a remap to set `#<pocket>`
+        let error = run(&mut interp, "\n\no<change> call").unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Named parameter #<pocket> not defined at line 6, column 19\n    \
+                in o<change> called from line 3"
+        );
+
+        let mut numbers = Interpreter::new();
+        numbers.add_program(
+            "subs",
+            parser::parse_program("o100 sub\nG0 X5\no100 endsub\n").unwrap(),
+        );
+
+        assert_eq!(x_moves(&run(&mut numbers, "o100 call").unwrap()), [5.0]);
+    }
+
+    #[test]
+    fn streamed_blocks_are_dropped() {
+        let mut interp = Interpreter::new();
+        interp.start_program().unwrap();
+
+        let stored = |interp: &Interpreter| interp.control_flow.programs[0].blocks.len();
+
+        run(
+            &mut interp,
+            "o<square> sub
+                G0 X[#1 * #1]
+            o<square> endsub",
+        )
+        .unwrap();
+
+        for line in 0..1000 {
+            run(&mut interp, &alloc::format!("G0 Y{}", line)).unwrap();
+        }
+
+        // Only the definition and the last block are kept
+        assert_eq!(stored(&interp), 4);
+
+        // The definition can still be called, and a loop is kept until it's finished
+        let canon = run(
+            &mut interp,
+            "#2 = 1
+            o1 while [#2 LE 3]
+                o<square> call [#2]
+                #2 = [#2 + 1]
+            o1 endwhile",
+        )
+        .unwrap();
+
+        assert_eq!(x_moves(&canon), [1.0, 4.0, 9.0]);
+
+        run(&mut interp, "G0 Z1").unwrap();
+
+        assert_eq!(stored(&interp), 4);
+        assert_eq!(interp.snapshot().cursor().block, 1009);
+    }
+}
@@ -0,0 +1,971 @@
+//! Cutter radius compensation, `G40`, `G41`, `G42`, `G41.1` and `G42.1`.
+//!
+//! With compensation on, the tool centre follows a path offset from the programmed one by the
+//! tool radius, to the left (`G41`) or right (`G42`) of the direction of travel in the selected
+//! plane. Each move is held back until the next one arrives so the corner between them can be
+//! worked out. Convex corners get an arc around the programmed corner. At concave corners both
+//! moves are cut short where their offset paths meet, which is an error if either would have to
+//! run backwards as the tool would then gouge the part. Canonical commands produced while a move
+//! is held back are held with it so they stay in order, and moves along axes outside the plane
+//! are made from wherever the held move ends up ending.
+//!
+//! The first move in the plane after compensation is turned on is the entry move. A straight
+//! entry moves along the line that brings the edge of the tool tangent to its programmed end
+//! point, and mustn't make a concave corner with the move after it. An arc entry moves straight
+//! out to the start of the offset arc first. After `G40`, the next move goes straight to its
+//! programmed end point from wherever the tool is.
+//!
+//! As in LinuxCNC, compensation works in the XY and XZ planes. In the XZ plane, left and right
+//! are as seen with X across and Z up, the opposite of how arcs are seen, so lathe programs
+//! mirrored from XY programs keep the same `G41`/`G42`.
+//!
+//! Lathe tools are touched off at their imaginary tip rather than the centre of the nose radius,
+//! so in the XZ plane the compensated path is moved from the nose centre to the tip given by the
+//! tool's orientation (`Q` in the tool table, or `L` with `G41.1`/`G42.1`). With X up and Z to the
+//! right, the orientations put the tip in these directions from the nose centre:
+//!
+//! ```text
+//! 2 6 1
+//! 7 9 5
+//! 3 8 4
+//! ```
+//!
+//! Orientations `0` and `9` control the nose centre itself.
+
+use crate::arc::{self, ArcFeed};
+use crate::block::{BlockCommands, CutterCompensationMode};
+use crate::canon::Canon;
+use crate::parameters::ParameterStore;
+use crate::{Interpreter, InterpreterError};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use common::consts::TAU;
+use common::{CutterCompensation, Number, Plane, Position};
+use core::fmt;
+
+type M = libm::Libm<Number>;
+
+/// A point in the compensation plane, on the plane's first and second axes.
+type Point = (Number, Number);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Path {
+    Traverse,
+    Feed,
+    Arc { center: Point, rotation: i32 },
+}
+
+/// An offset move waiting for the next move to settle where it ends.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Segment {
+    path: Path,
+
+    /// Start of the offset move.
+    start: Point,
+
+    /// End of the offset move, including axes outside the plane.
+    end: Position,
+
+    /// Programmed end point, which is the corner with the next move.
+    corner: Point,
+
+    /// Direction of travel at the end of the move.
+    direction: Point,
+
+    entry: bool,
+}
+
+impl Segment {
+    fn canon(&self, plane: Plane) -> Canon {
+        match self.path {
+            Path::Traverse => Canon::StraightTraverse { end: self.end },
+            Path::Feed => Canon::StraightFeed { end: self.end },
+            Path::Arc { center, rotation } => Canon::ArcFeed(ArcFeed {
+                plane,
+                end: self.end,
+                center,
+                rotation,
+            }),
+        }
+    }
+}
+
+/// How one offset move joins the next.
+enum Join {
+    /// The moves already meet, or are close enough that they can be joined without a corner.
+    Tangent,
+
+    /// The tool goes round the outside of the corner.
+    Convex,
+
+    /// Both moves end where their offset paths meet.
+    Concave(Point),
+}
+
+/// Offsets moves by the tool radius while cutter compensation is on.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct CutterComp {
+    side: CutterCompensation,
+    radius: Number,
+    plane: Plane,
+    tolerance: Number,
+
+    /// Offset from the nose centre to the control point of a lathe tool.
+    tip: Point,
+    pending: Option<Segment>,
+
+    /// Commands produced after the pending move.
+    held: Vec<Canon>,
+}
+
+impl CutterComp {
+    /// Start compensating on the given side, with `tolerance` the distance below which points
+    /// are treated as the same.
+    pub fn new(
+        side: CutterCompensation,
+        radius: Number,
+        orientation: u8,
+        plane: Plane,
+        tolerance: Number,
+    ) -> Self {
+        // Points are taken on the plane's first and second axes, Z then X for the XZ plane
+        let side = match (plane, side) {
+            (Plane::XZ, CutterCompensation::Left) => CutterCompensation::Right,
+            (Plane::XZ, CutterCompensation::Right) => CutterCompensation::Left,
+            (_, side) => side,
+        };
+
+        // Directions on X and Z
+        let (x, z) = match orientation {
+            1 => (1.0, 1.0),
+            2 => (1.0, -1.0),
+            3 => (-1.0, -1.0),
+            4 => (-1.0, 1.0),
+            5 => (0.0, 1.0),
+            6 => (1.0, 0.0),
+            7 => (0.0, -1.0),
+            8 => (-1.0, 0.0),
+            _ => (0.0, 0.0),
+        };
+
+        let tip = match plane {
+            Plane::XZ => (z * radius, x * radius),
+            _ => (0.0, 0.0),
+        };
+
+        Self {
+            side,
+            radius,
+            plane,
+            tolerance,
+            tip,
+            pending: None,
+            held: Vec::new(),
+        }
+    }
+
+    /// Whether a move is being held back, so other commands must be held behind it.
+    pub fn holding(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    pub fn hold(&mut self, canon: Canon) {
+        self.held.push(canon);
+    }
+
+    /// Compensate a move from `start`, both given in machine coordinates without compensation,
+    /// outputting any moves whose end is now settled.
+    pub fn motion(
+        &mut self,
+        start: &Position,
+        canon: Canon,
+        output: &mut VecDeque<Canon>,
+    ) -> Result<(), CutterCompError> {
+        let (path, end) = match &canon {
+            Canon::StraightTraverse { end } => (Path::Traverse, *end),
+            Canon::StraightFeed { end } => (Path::Feed, *end),
+            Canon::ArcFeed(arc) => (
+                Path::Arc {
+                    center: arc.center,
+                    rotation: arc.rotation,
+                },
+                arc.end,
+            ),
+            _ => {
+                self.output(canon, output);
+
+                return Ok(());
+            }
+        };
+
+        let from = self.point(start);
+        let to = self.point(&end);
+
+        // Moves along axes outside the plane don't change the offset path
+        if !matches!(path, Path::Arc { .. }) && distance(from, to) <= self.tolerance {
+            self.output(canon, output);
+
+            return Ok(());
+        }
+
+        let (start_direction, end_direction) = match path {
+            Path::Arc { center, rotation } => {
+                let inside = dot(
+                    self.normal(tangent(center, from, rotation)),
+                    sub(from, center),
+                ) < 0.0;
+
+                if inside && distance(from, center) - self.radius <= self.tolerance {
+                    return Err(CutterCompError::ToolTooLarge);
+                }
+
+                (
+                    tangent(center, from, rotation),
+                    tangent(center, to, rotation),
+                )
+            }
+            _ => {
+                let direction = unit(sub(to, from));
+
+                (direction, direction)
+            }
+        };
+
+        let mut segment = Segment {
+            path,
+            start: self.offset(from, start_direction),
+            end: self.with_point(&end, self.offset(to, end_direction)),
+            corner: to,
+            direction: end_direction,
+            entry: false,
+        };
+
+        let previous = match &self.pending {
+            Some(previous) => previous,
+            None => return self.entry(start, segment, output),
+        };
+
+        let join = self.join(previous, &segment, start_direction)?;
+        let (previous_end, previous_corner) = (previous.end, previous.corner);
+
+        let corner = match join {
+            Join::Tangent => {
+                segment.start = self.point(&previous_end);
+
+                None
+            }
+            Join::Convex => Some(Canon::ArcFeed(ArcFeed {
+                plane: self.plane,
+                end: self.with_point(&previous_end, segment.start),
+                center: previous_corner,
+                rotation: match self.side {
+                    CutterCompensation::Right => 1,
+                    _ => -1,
+                },
+            })),
+            Join::Concave(meet) => {
+                let end = self.with_point(&previous_end, meet);
+
+                if let Some(previous) = &mut self.pending {
+                    previous.end = end;
+                }
+
+                segment.start = meet;
+
+                None
+            }
+        };
+
+        self.release(output);
+        output.extend(corner.map(|corner| self.at_tip(corner)));
+        self.pending = Some(segment);
+
+        Ok(())
+    }
+
+    /// Output the pending move, ending square to its programmed end point. Used when
+    /// compensation is turned off.
+    pub fn finish(&mut self, output: &mut VecDeque<Canon>) {
+        self.release(output);
+    }
+
+    fn output(&mut self, canon: Canon, output: &mut VecDeque<Canon>) {
+        if self.holding() {
+            self.hold(canon);
+        } else {
+            output.push_back(canon);
+        }
+    }
+
+    /// Output the pending move followed by everything held behind it.
+    fn release(&mut self, output: &mut VecDeque<Canon>) {
+        let segment = match self.pending.take() {
+            Some(segment) => segment,
+            None => return,
+        };
+
+        let end = self.point(&segment.end);
+
+        output.push_back(self.at_tip(segment.canon(self.plane)));
+
+        for mut canon in core::mem::take(&mut self.held) {
+            if let Canon::StraightTraverse { end: held_end }
+            | Canon::StraightFeed { end: held_end } = &mut canon
+            {
+                *held_end = self.with_point(held_end, end);
+            }
+
+            output.push_back(self.at_tip(canon));
+        }
+    }
+
+    fn entry(
+        &mut self,
+        start: &Position,
+        mut segment: Segment,
+        output: &mut VecDeque<Canon>,
+    ) -> Result<(), CutterCompError> {
+        let from = self.point(start);
+
+        match segment.path {
+            Path::Arc { .. } => {
+                if distance(from, segment.start) > self.tolerance {
+                    output.push_back(self.at_tip(Canon::StraightFeed {
+                        end: self.with_point(start, segment.start),
+                    }));
+                }
+            }
+            _ => {
+                let length = distance(from, segment.corner);
+
+                if length <= self.radius {
+                    return Err(CutterCompError::EntryTooShort);
+                }
+
+                let chord = sub(segment.corner, from);
+                let angle = M::atan2(chord.1, chord.0);
+                let turn = M::asin(self.radius / length);
+
+                let angle = match self.side {
+                    CutterCompensation::Right => angle - turn,
+                    _ => angle + turn,
+                };
+
+                segment.direction = (M::cos(angle), M::sin(angle));
+                segment.start = from;
+                segment.end =
+                    self.with_point(&segment.end, self.offset(segment.corner, segment.direction));
+                segment.entry = true;
+            }
+        }
+
+        self.pending = Some(segment);
+
+        Ok(())
+    }
+
+    fn join(
+        &self,
+        previous: &Segment,
+        next: &Segment,
+        next_direction: Point,
+    ) -> Result<Join, CutterCompError> {
+        if distance(self.point(&previous.end), next.start) <= self.tolerance {
+            return Ok(Join::Tangent);
+        }
+
+        let turn = cross(previous.direction, next_direction);
+
+        let convex = if M::fabs(turn) <= 1e-6 {
+            // Reversing direction takes the tool round the end of the path
+            dot(previous.direction, next_direction) < 0.0
+        } else {
+            match self.side {
+                CutterCompensation::Right => turn > 0.0,
+                _ => turn < 0.0,
+            }
+        };
+
+        if convex {
+            return Ok(Join::Convex);
+        }
+
+        if previous.entry {
+            return Err(CutterCompError::ConcaveEntry);
+        }
+
+        self.intersection(previous, next, next_direction)
+            .filter(|meet| self.contains(previous, *meet) && self.contains(next, *meet))
+            .map(Join::Concave)
+            .ok_or(CutterCompError::Gouge)
+    }
+
+    /// Where the offset paths of two moves cross, nearest the end of the first.
+    fn intersection(
+        &self,
+        previous: &Segment,
+        next: &Segment,
+        next_direction: Point,
+    ) -> Option<Point> {
+        let near = self.point(&previous.end);
+
+        let points = match (previous.path, next.path) {
+            (
+                Path::Arc { center, .. },
+                Path::Arc {
+                    center: next_center,
+                    ..
+                },
+            ) => circle_circle(
+                center,
+                distance(center, near),
+                next_center,
+                distance(next_center, next.start),
+            ),
+            (Path::Arc { center, .. }, _) => {
+                line_circle(next.start, next_direction, center, distance(center, near))
+            }
+            (_, Path::Arc { center, .. }) => line_circle(
+                near,
+                previous.direction,
+                center,
+                distance(center, next.start),
+            ),
+            _ => [
+                line_line(near, previous.direction, next.start, next_direction),
+                None,
+            ],
+        };
+
+        points.iter().flatten().copied().min_by(|a, b| {
+            distance(*a, near)
+                .partial_cmp(&distance(*b, near))
+                .unwrap_or(core::cmp::Ordering::Equal)
+        })
+    }
+
+    /// Whether a point on a move's offset path lies between its start and end.
+    fn contains(&self, segment: &Segment, point: Point) -> bool {
+        let end = self.point(&segment.end);
+
+        match segment.path {
+            Path::Arc { center, rotation } => {
+                if rotation.abs() > 1 {
+                    return true;
+                }
+
+                let margin = self.tolerance / distance(center, end);
+
+                let mut total = swept(center, segment.start, end, rotation);
+
+                if total <= margin {
+                    total = TAU;
+                }
+
+                let along = swept(center, segment.start, point, rotation);
+
+                along <= total + margin || along >= TAU - margin
+            }
+            _ => {
+                let length = distance(segment.start, end);
+                let along = dot(sub(point, segment.start), segment.direction);
+
+                along >= -self.tolerance && along <= length + self.tolerance
+            }
+        }
+    }
+
+    /// Unit vector pointing from the path to the side the tool is on.
+    fn normal(&self, direction: Point) -> Point {
+        match self.side {
+            CutterCompensation::Right => (direction.1, -direction.0),
+            _ => (-direction.1, direction.0),
+        }
+    }
+
+    fn offset(&self, point: Point, direction: Point) -> Point {
+        let normal = self.normal(direction);
+
+        (
+            point.0 + normal.0 * self.radius,
+            point.1 + normal.1 * self.radius,
+        )
+    }
+
+    /// Move an offset move from the nose centre to the control point of the tool.
+    fn at_tip(&self, mut canon: Canon) -> Canon {
+        match &mut canon {
+            Canon::StraightTraverse { end } | Canon::StraightFeed { end } => {
+                *end = self.with_point(end, add(self.point(end), self.tip));
+            }
+            Canon::ArcFeed(arc) => {
+                arc.end = self.with_point(&arc.end, add(self.point(&arc.end), self.tip));
+                arc.center = add(arc.center, self.tip);
+            }
+            _ => {}
+        }
+
+        canon
+    }
+
+    fn point(&self, position: &Position) -> Point {
+        let (first, second, _) = self.plane.axes();
+
+        (position[first.index()], position[second.index()])
+    }
+
+    fn with_point(&self, position: &Position, point: Point) -> Position {
+        let (first, second, _) = self.plane.axes();
+
+        let mut position = *position;
+        position[first.index()] = point.0;
+        position[second.index()] = point.1;
+
+        position
+    }
+}
+
+fn add(a: Point, b: Point) -> Point {
+    (a.0 + b.0, a.1 + b.1)
+}
+
+fn sub(a: Point, b: Point) -> Point {
+    (a.0 - b.0, a.1 - b.1)
+}
+
+fn dot(a: Point, b: Point) -> Number {
+    a.0 * b.0 + a.1 * b.1
+}
+
+fn cross(a: Point, b: Point) -> Number {
+    a.0 * b.1 - a.1 * b.0
+}
+
+fn distance(a: Point, b: Point) -> Number {
+    M::hypot(a.0 - b.0, a.1 - b.1)
+}
+
+fn unit(a: Point) -> Point {
+    let length = M::hypot(a.0, a.1);
+
+    (a.0 / length, a.1 / length)
+}
+
+/// Direction of travel at a point on an arc.
+fn tangent(center: Point, point: Point, rotation: i32) -> Point {
+    let radial = unit(sub(point, center));
+
+    if rotation > 0 {
+        (-radial.1, radial.0)
+    } else {
+        (radial.1, -radial.0)
+    }
+}
+
+/// Angle swept going round an arc from one point to another, from `0` to a full turn.
+fn swept(center: Point, from: Point, to: Point, rotation: i32) -> Number {
+    let from = sub(from, center);
+    let to = sub(to, center);
+
+    let mut angle = M::atan2(to.1, to.0) - M::atan2(from.1, from.0);
+
+    if rotation < 0 {
+        angle = -angle;
+    }
+
+    if angle < 0.0 {
+        angle += TAU;
+    }
+
+    angle
+}
+
+fn line_line(p: Point, u: Point, q: Point, v: Point) -> Option<Point> {
+    let denominator = cross(u, v);
+
+    if M::fabs(denominator) <= 1e-6 {
+        return None;
+    }
+
+    let t = cross(sub(q, p), v) / denominator;
+
+    Some((p.0 + u.0 * t, p.1 + u.1 * t))
+}
+
+fn line_circle(p: Point, u: Point, center: Point, radius: Number) -> [Option<Point>; 2] {
+    let f = sub(p, center);
+    let b = dot(f, u);
+    let discriminant = b * b - (dot(f, f) - radius * radius);
+
+    if discriminant < 0.0 {
+        return [None, None];
+    }
+
+    let root = M::sqrt(discriminant);
+
+    [-b - root, -b + root].map(|t| Some((p.0 + u.0 * t, p.1 + u.1 * t)))
+}
+
+fn circle_circle(c1: Point, r1: Number, c2: Point, r2: Number) -> [Option<Point>; 2] {
+    let d = distance(c1, c2);
+
+    if d <= Number::EPSILON || d > r1 + r2 || d < M::fabs(r1 - r2) {
+        return [None, None];
+    }
+
+    let a = (r1 * r1 - r2 * r2 + d * d) / (2.0 * d);
+    let h = M::sqrt((r1 * r1 - a * a).max(0.0));
+    let u = unit(sub(c2, c1));
+    let mid = (c1.0 + u.0 * a, c1.1 + u.1 * a);
+
+    [
+        Some((mid.0 - u.1 * h, mid.1 + u.0 * h)),
+        Some((mid.0 + u.1 * h, mid.1 - u.0 * h)),
+    ]
+}
+
+/// Errors in cutter compensation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CutterCompError {
+    /// A straight entry move isn't longer than the tool radius.
+    EntryTooShort,
+
+    /// The entry move makes a concave corner with the move after it.
+    ConcaveEntry,
+
+    /// The offset moves either side of a concave corner don't meet, so the tool would gouge the
+    /// part.
+    Gouge,
+
+    /// The tool radius isn't less than the radius of an arc the tool is inside of.
+    ToolTooLarge,
+
+    /// `G41` or `G42` while compensation is already on.
+    AlreadyOn,
+
+    /// The plane was changed while compensation is on.
+    PlaneChange,
+
+    /// Compensation can't be used in the YZ plane.
+    YzPlane,
+
+    /// `L` tool orientation isn't `0` to `9`.
+    BadOrientation,
+
+    /// `G28` or `G30` while compensation is on.
+    PredefinedPosition,
+}
+
+impl fmt::Display for CutterCompError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EntryTooShort => write!(
+                f,
+                "Length of cutter compensation entry move is not greater than the tool radius"
+            ),
+            Self::ConcaveEntry => {
+                write!(f, "Concave corner after cutter compensation entry move")
+            }
+            Self::Gouge => write!(f, "Cutter gouging with cutter radius comp"),
+            Self::ToolTooLarge => {
+                write!(
+                    f,
+                    "Tool radius not less than arc radius with cutter radius comp"
+                )
+            }
+            Self::AlreadyOn => write!(f, "Cannot turn cutter radius comp on when already on"),
+            Self::PlaneChange => write!(f, "Cannot change planes with cutter radius comp on"),
+            Self::YzPlane => write!(f, "Cannot use yz-plane with cutter radius comp"),
+            Self::BadOrientation => write!(f, "Tool orientation must be an integer from 0 to 9"),
+            Self::PredefinedPosition => write!(f, "Cannot use G28 or G30 with cutter radius comp"),
+        }
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// `G40`, `G41`, `G42`, `G41.1` or `G42.1`.
+    pub(crate) fn cutter_compensation(
+        &mut self,
+        mode: CutterCompensationMode,
+        commands: &BlockCommands,
+    ) -> Result<(), InterpreterError> {
+        let (side, diameter, orientation) = match mode {
+            CutterCompensationMode::Off => {
+                if let Some(mut comp) = self.cutter_comp.take() {
+                    if !self.dry_run {
+                        comp.finish(&mut self.output);
+                    }
+                }
+
+                self.modal_groups.cutter_compensation = CutterCompensation::Off;
+                self.modal_groups.cutter_radius = 0.0;
+                self.modal_groups.cutter_orientation = 0;
+
+                return Ok(());
+            }
+            CutterCompensationMode::Tool(side) => {
+                let number = match commands.value('D') {
+                    Some(value) => self.tool_number('D', value)?,
+                    None => self.modal_groups.tool,
+                };
+
+                let tool = self.tool_table.get(number);
+
+                (
+                    side,
+                    tool.map_or(0.0, |tool| tool.diameter),
+                    tool.map_or(0, |tool| tool.orientation),
+                )
+            }
+            CutterCompensationMode::Dynamic(side) => {
+                let diameter = commands
+                    .value('D')
+                    .ok_or(InterpreterError::MissingWord('D'))?;
+
+                if diameter < 0.0 {
+                    return Err(InterpreterError::NegativeValue('D'));
+                }
+
+                let orientation = match commands.value('L') {
+                    None => 0,
+                    Some(l) if (0.0..=9.0).contains(&l) && l == (l as u8) as Number => l as u8,
+                    Some(_) => return Err(CutterCompError::BadOrientation.into()),
+                };
+
+                (side, diameter, orientation)
+            }
+        };
+
+        if self.cutter_comp.is_some() {
+            return Err(CutterCompError::AlreadyOn.into());
+        }
+
+        if self.modal_groups.plane == Plane::YZ {
+            return Err(CutterCompError::YzPlane.into());
+        }
+
+        self.modal_groups.cutter_compensation = side;
+        self.modal_groups.cutter_radius = diameter / 2.0;
+        self.modal_groups.cutter_orientation = orientation;
+        self.reset_cutter_comp();
+
+        Ok(())
+    }
+
+    /// Start compensating afresh from the modal state, without any held moves, so the next move
+    /// is an entry move.
+    pub(crate) fn reset_cutter_comp(&mut self) {
+        self.cutter_comp = match self.modal_groups.cutter_compensation {
+            CutterCompensation::Off => None,
+            side => Some(CutterComp::new(
+                side,
+                self.modal_groups.cutter_radius,
+                self.modal_groups.cutter_orientation,
+                self.modal_groups.plane,
+                arc::tolerance(self.modal_groups.units),
+            )),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::run;
+    use crate::tools::tool_file;
+    use alloc::string::ToString;
+
+    /// End points of moves in the XY plane, rounded to avoid float noise.
+    fn ends(canon: &[Canon]) -> Vec<(Number, Number)> {
+        let round = |value: Number| M::round(value * 1000.0) / 1000.0;
+
+        canon
+            .iter()
+            .filter_map(|canon| match canon {
+                Canon::StraightTraverse { end } | Canon::StraightFeed { end } => Some(end),
+                Canon::ArcFeed(arc) => Some(&arc.end),
+                _ => None,
+            })
+            .map(|end| (round(end[0]), round(end[1])))
+            .collect()
+    }
+
+    fn interpreter() -> Interpreter {
+        let mut interp = Interpreter::new();
+        interp.set_tool_table(tool_file::parse("T1 D2\nT4 D1\n").unwrap());
+
+        interp
+    }
+
+    #[test]
+    fn corners() {
+        let mut interp = interpreter();
+
+        // Round the outside of a square, then across a concave corner
+        let canon = run(
+            &mut interp,
+            "G0 X0 Y-5\nG41 D1 G1 X0 Y0 F100\nY10\nX10\nY20\nX20\nZ-1 M8\nG40\nG0 X30",
+        )
+        .unwrap();
+
+        assert_eq!(
+            ends(&canon),
+            [
+                (0.0, -5.0),
+                // Entry tangent to the tool at X0 Y0
+                (-0.98, -0.2),
+                // Convex corners
+                (-1.0, 0.0),
+                (-1.0, 10.0),
+                (0.0, 11.0),
+                // Concave corner
+                (9.0, 11.0),
+                (9.0, 20.0),
+                (10.0, 21.0),
+                (20.0, 21.0),
+                (20.0, 21.0),
+                // Exit
+                (30.0, 20.0)
+            ]
+        );
+        assert!(canon
+            .iter()
+            .any(|canon| matches!(canon, Canon::ArcFeed(arc) if arc.center == (0.0, 0.0))));
+
+        // Coolant comes on and Z moves once the held move has ended
+        let tail = &canon[canon.len() - 4..];
+
+        assert_eq!(tail[1], Canon::Coolant(common::Coolant::Flood));
+        assert!(matches!(tail[2], Canon::StraightFeed { end } if end[2] == -1.0));
+        assert_eq!(
+            interp.modal_groups().cutter_compensation(),
+            CutterCompensation::Off
+        );
+    }
+
+    #[test]
+    fn arcs() {
+        let mut interp = interpreter();
+
+        // Outside then inside a full circle, entering with the arc
+        let canon = run(
+            &mut interp,
+            "G0 X10 Y0\nG42.1 D4 G3 I-10 F100\nG40\nG41.1 D4 G3 I-10\nG40",
+        )
+        .unwrap();
+
+        assert_eq!(
+            ends(&canon),
+            [
+                (10.0, 0.0),
+                (12.0, 0.0),
+                (12.0, 0.0),
+                (8.0, 0.0),
+                (8.0, 0.0)
+            ]
+        );
+        assert!(canon
+            .iter()
+            .filter_map(|canon| match canon {
+                Canon::ArcFeed(arc) => Some(arc),
+                _ => None,
+            })
+            .all(|arc| arc.center == (0.0, 0.0) && arc.rotation == 1));
+
+        assert_eq!(
+            run(&mut interp, "G0 X1 Y0\nG41.1 D4 G3 I-1").unwrap_err(),
+            CutterCompError::ToolTooLarge.into()
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut interp = interpreter();
+
+        assert_eq!(
+            run(&mut interp, "G41 D1\nG1 X0.5 F100").unwrap_err(),
+            CutterCompError::EntryTooShort.into()
+        );
+        assert_eq!(
+            run(&mut interp, "G42").unwrap_err(),
+            CutterCompError::AlreadyOn.into()
+        );
+        assert_eq!(
+            run(&mut interp, "G18").unwrap_err(),
+            CutterCompError::PlaneChange.into()
+        );
+        assert_eq!(
+            run(&mut interpreter(), "G19 G41.1 D1").unwrap_err(),
+            CutterCompError::YzPlane.into()
+        );
+
+        // A slot narrower than the tool
+        let mut interp = interpreter();
+
+        assert_eq!(
+            run(&mut interp, "G0 X-5 Y5\nG41 D1 G1 X0 Y5 F100\nY0\nX1\nY5").unwrap_err(),
+            CutterCompError::Gouge.into()
+        );
+
+        assert_eq!(
+            run(&mut interpreter(), "G41.1 G1 X1").unwrap_err(),
+            InterpreterError::MissingWord('D')
+        );
+        assert_eq!(
+            run(&mut interpreter(), "G41 D7").unwrap_err(),
+            InterpreterError::UnknownTool(7)
+        );
+    }
+
+    /// Run a LinuxCNC example, leaving out path blending as it isn't supported.
+    fn run_example(program: &str) -> Result<Vec<Canon>, InterpreterError> {
+        let mut interp = Interpreter::new();
+        interp.set_tool_table(tool_file::parse("T1 D0.1\nT4 D1\n").unwrap());
+
+        run(&mut interp, &program.replace("g64", ""))
+    }
+
+    #[test]
+    fn linuxcnc_examples() {
+        let canon = run_example(include_str!("../../test_files/linuxcnc/comp.ngc")).unwrap();
+
+        // The tool is on the inside of the second arc with G42, in both planes
+        assert!(ends(&canon).contains(&(-0.7, 1.5)));
+        assert!(canon.iter().any(|canon| matches!(
+            canon,
+            Canon::ArcFeed(arc) if arc.plane == Plane::XZ
+                && M::fabs(arc.end[0] + 0.7) < 1e-4
+                && M::fabs(arc.end[2] - 1.5) < 1e-4
+        )));
+
+        for example in [
+            include_str!("../../test_files/linuxcnc/comp311.ngc"),
+            include_str!("../../test_files/linuxcnc/comp311_2.ngc"),
+            include_str!("../../test_files/linuxcnc/comp-g1.ngc"),
+        ] {
+            let canon = run_example(example).unwrap();
+
+            // The sides of the part are offset outwards
+            assert!(ends(&canon).contains(&(3.5, -1.0)));
+            assert!(ends(&canon).contains(&(2.0, -2.5)));
+        }
+
+        let example = include_str!("../../test_files/linuxcnc/comp-g1.ngc");
+
+        assert_eq!(
+            ends(&run_example(example).unwrap()).last(),
+            Some(&(3.0, 3.5))
+        );
+
+        // The example's suggested change makes a concave entry
+        assert_eq!(
+            run_example(&example.replace("X0 Y3.5", "X0 Y4"))
+                .unwrap_err()
+                .to_string(),
+            "Concave corner after cutter compensation entry move"
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use crate::arc::ArcError;
+use crate::axes::AxisError;
+use crate::canned_cycle::CannedCycleError;
+use crate::control_flow::{CallSite, ControlFlowError};
+use crate::cutter_comp::CutterCompError;
+use crate::expression::ExpressionError;
+use crate::feed::FeedError;
+use crate::io::IoError;
+use crate::lathe::LatheError;
+use crate::mdi::MdiError;
+use crate::parameters::var_file::VarFileError;
+use crate::probe::ProbeError;
+use crate::remap::RemapError;
+use crate::snapshot::SnapshotError;
+use crate::spindle_sync::SpindleSyncError;
+use crate::sync::SyncError;
+use crate::tools::ToolChangeError;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use common::{Number, Span};
+use core::fmt;
+
+/// Errors produced when executing a block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterpreterError {
+    /// Two G codes from the same modal group in one block.
+    ModalGroupConflict(Number),
+
+    /// Two M codes from the same modal group in one block.
+    MModalGroupConflict(Number),
+
+    /// The same word letter appears twice in one block.
+    DuplicateWord(char),
+
+    /// Unsupported word letter.
+    UnknownWord(char),
+
+    /// A word which can't be negative, e.g. `S` or `T`, has a negative value.
+    NegativeValue(char),
+
+    /// A word needed by a command in the block is missing, e.g. `Q` for `M61`.
+    MissingWord(char),
+
+    /// Axis words are used by both a motion and another command in the same block.
+    AxisCommandConflict,
+
+    /// A tool that isn't in the tool table was selected.
+    UnknownTool(u32),
+
+    /// The tool changer failed an `M6`.
+    ToolChange(ToolChangeError),
+
+    /// Unsupported G code.
+    UnknownGCode(Number),
+
+    /// Unsupported M code.
+    UnknownMCode(Number),
+
+    /// Arc geometry error.
+    Arc(ArcError),
+
+    /// Cutter radius compensation error.
+    CutterComp(CutterCompError),
+
+    /// Bad feed rate for the feed rate mode.
+    Feed(FeedError),
+
+    /// Bad canned cycle block.
+    CannedCycle(CannedCycleError),
+
+    /// Bad probe move, or the probe didn't trip.
+    Probe(ProbeError),
+
+    /// Bad `M62` to `M68` block.
+    Io(IoError),
+
+    /// Bad `G33` or `G33.1` block.
+    SpindleSync(SpindleSyncError),
+
+    /// Bad lathe cycle block.
+    Lathe(LatheError),
+
+    /// A word for an axis the machine doesn't have.
+    Axis(AxisError),
+
+    /// An expression couldn't be evaluated or a parameter couldn't be set.
+    Expression(ExpressionError),
+
+    /// Bad O-word statement or subroutine call.
+    ControlFlow(ControlFlowError),
+
+    /// A remapped code couldn't be run.
+    Remap(RemapError),
+
+    /// Bad feedback from the machine at a sync point.
+    Sync(SyncError),
+
+    /// An error raised by a block in a program, with where it happened.
+    Located {
+        error: Box<InterpreterError>,
+        location: ErrorLocation,
+    },
+
+    /// The output is full. Read it with [`next_canon`](crate::Interpreter::next_canon) and pop
+    /// again; nothing was run.
+    OutputFull,
+
+    /// A program can't be restarted at this line because it doesn't have one.
+    NoSuchLine(usize),
+
+    /// An MDI command was rejected.
+    Mdi(MdiError),
+
+    /// Persistent parameters couldn't be loaded or saved.
+    VarFile(VarFileError),
+
+    /// A snapshot couldn't be restored.
+    Snapshot(SnapshotError),
+}
+
+impl InterpreterError {
+    /// Where in the program the error happened, if it was raised by a block.
+    pub fn location(&self) -> Option<&ErrorLocation> {
+        match self {
+            Self::Located { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    /// The error without its location.
+    pub fn cause(&self) -> &InterpreterError {
+        match self {
+            Self::Located { error, .. } => error,
+            error => error,
+        }
+    }
+
+    /// Take the error out of its location.
+    pub fn into_cause(self) -> InterpreterError {
+        match self {
+            Self::Located { error, .. } => *error,
+            error => error,
+        }
+    }
+
+    /// The letter, and value if it's a `G` or `M` code or tool number, of the word that caused the
+    /// error, if it can be pinned down to one.
+    pub(crate) fn word(&self) -> Option<(char, Option<Number>)> {
+        match *self {
+            Self::ModalGroupConflict(code) | Self::UnknownGCode(code) => Some(('G', Some(code))),
+            Self::MModalGroupConflict(code) | Self::UnknownMCode(code) => Some(('M', Some(code))),
+            Self::DuplicateWord(letter)
+            | Self::UnknownWord(letter)
+            | Self::NegativeValue(letter) => Some((letter, None)),
+            Self::UnknownTool(tool) => Some(('T', Some(tool as Number))),
+            _ => None,
+        }
+    }
+}
+
+/// Where an error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorLocation {
+    /// The block that raised the error.
+    pub block: Span,
+
+    /// The word in the block that caused the error, if it's known. For an expression error it's
+    /// the expression.
+    pub word: Option<Span>,
+
+    /// Subroutine calls that led to the block, innermost first. Empty in the main program.
+    pub call_stack: Vec<CallSite>,
+}
+
+impl From<ArcError> for InterpreterError {
+    fn from(e: ArcError) -> Self {
+        Self::Arc(e)
+    }
+}
+
+impl From<CutterCompError> for InterpreterError {
+    fn from(e: CutterCompError) -> Self {
+        Self::CutterComp(e)
+    }
+}
+
+impl From<FeedError> for InterpreterError {
+    fn from(e: FeedError) -> Self {
+        Self::Feed(e)
+    }
+}
+
+impl From<CannedCycleError> for InterpreterError {
+    fn from(e: CannedCycleError) -> Self {
+        Self::CannedCycle(e)
+    }
+}
+
+impl From<ProbeError> for InterpreterError {
+    fn from(e: ProbeError) -> Self {
+        Self::Probe(e)
+    }
+}
+
+impl From<IoError> for InterpreterError {
+    fn from(e: IoError) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<SpindleSyncError> for InterpreterError {
+    fn from(e: SpindleSyncError) -> Self {
+        Self::SpindleSync(e)
+    }
+}
+
+impl From<AxisError> for InterpreterError {
+    fn from(e: AxisError) -> Self {
+        Self::Axis(e)
+    }
+}
+
+impl From<LatheError> for InterpreterError {
+    fn from(e: LatheError) -> Self {
+        Self::Lathe(e)
+    }
+}
+
+impl From<ExpressionError> for InterpreterError {
+    fn from(e: ExpressionError) -> Self {
+        Self::Expression(e)
+    }
+}
+
+impl From<ControlFlowError> for InterpreterError {
+    fn from(e: ControlFlowError) -> Self {
+        Self::ControlFlow(e)
+    }
+}
+
+impl From<RemapError> for InterpreterError {
+    fn from(e: RemapError) -> Self {
+        Self::Remap(e)
+    }
+}
+
+impl From<SyncError> for InterpreterError {
+    fn from(e: SyncError) -> Self {
+        Self::Sync(e)
+    }
+}
+
+impl From<VarFileError> for InterpreterError {
+    fn from(e: VarFileError) -> Self {
+        Self::VarFile(e)
+    }
+}
+
+impl From<ToolChangeError> for InterpreterError {
+    fn from(e: ToolChangeError) -> Self {
+        Self::ToolChange(e)
+    }
+}
+
+impl From<MdiError> for InterpreterError {
+    fn from(e: MdiError) -> Self {
+        Self::Mdi(e)
+    }
+}
+
+impl From<SnapshotError> for InterpreterError {
+    fn from(e: SnapshotError) -> Self {
+        Self::Snapshot(e)
+    }
+}
+
+impl fmt::Display for InterpreterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ModalGroupConflict(code) => {
+                write!(f, "Two G codes used from same modal group (G{})", code)
+            }
+            Self::MModalGroupConflict(code) => {
+                write!(f, "Two M codes used from same modal group (M{})", code)
+            }
+            Self::DuplicateWord(letter) => write!(f, "Multiple {} words on one line", letter),
+            Self::UnknownWord(letter) => write!(f, "Bad character used: {}", letter),
+            Self::NegativeValue(letter) => write!(f, "Negative {} word used", letter),
+            Self::MissingWord(letter) => write!(f, "Missing {} word", letter),
+            Self::AxisCommandConflict => {
+                write!(f, "Cannot use two G codes that both use axis values")
+            }
+            Self::UnknownTool(tool) => write!(f, "Tool T{} is not in the tool table", tool),
+            Self::ToolChange(e) => e.fmt(f),
+            Self::UnknownGCode(code) => write!(f, "Unknown g code used: G{}", code),
+            Self::UnknownMCode(code) => write!(f, "Unknown m code used: M{}", code),
+            Self::Arc(e) => e.fmt(f),
+            Self::CutterComp(e) => e.fmt(f),
+            Self::Feed(e) => e.fmt(f),
+            Self::CannedCycle(e) => e.fmt(f),
+            Self::Probe(e) => e.fmt(f),
+            Self::Io(e) => e.fmt(f),
+            Self::SpindleSync(e) => e.fmt(f),
+            Self::Lathe(e) => e.fmt(f),
+            Self::Axis(e) => e.fmt(f),
+            Self::Expression(e) => e.fmt(f),
+            Self::ControlFlow(e) => e.fmt(f),
+            Self::Remap(e) => e.fmt(f),
+            Self::Sync(e) => e.fmt(f),
+            Self::Located { error, location } => {
+                error.fmt(f)?;
+
+                let span = location.word.unwrap_or(location.block);
+
+                // Expression errors give their own location, and blocks built in code have none
+                if !matches!(**error, Self::Expression(_)) && span.line > 0 {
+                    write!(f, " at line {}, column {}", span.line, span.column)?;
+                }
+
+                for call in &location.call_stack {
+                    write!(f, "\n    in {}", call)?;
+                }
+
+                Ok(())
+            }
+            Self::OutputFull => write!(f, "Output is full, read it before running more"),
+            Self::NoSuchLine(line) => write!(f, "Program has no line {}", line),
+            Self::Mdi(e) => e.fmt(f),
+            Self::VarFile(e) => e.fmt(f),
+            Self::Snapshot(e) => e.fmt(f),
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Evaluate expressions with RS274NGC semantics.
+//!
+//! Evaluation is always done in `f64`, as LinuxCNC does, whatever [`common::Number`] is. Values
+//! are converted to `Number` only once a word's value is known.
+
+use crate::parameters::{ParameterError, ParameterStore, MAX_NUMBERED};
+use crate::Interpreter;
+use alloc::string::String;
+use common::{BinaryOperator, Expression, Parameter, Span, Spanned, UnaryFunction};
+use core::f64::consts::PI;
+use core::fmt;
+
+type M = libm::Libm<f64>;
+
+/// Two values closer than this compare equal with `EQ` and `NE`, as LinuxCNC's
+/// `TOLERANCE_EQUAL`.
+pub const EQUAL_TOLERANCE: f64 = 0.0001;
+
+/// Read access to parameters during evaluation.
+pub(crate) trait ParameterLookup {
+    /// Get a numbered parameter, including read-only system parameters.
+    fn numbered(&self, index: usize) -> Result<f64, ParameterError>;
+
+    /// Get a named parameter, or `None` if it isn't set.
+    fn named(&self, name: &str) -> Option<f64>;
+}
+
+impl<P: ParameterStore, const N: usize> ParameterLookup for Interpreter<P, N> {
+    fn numbered(&self, index: usize) -> Result<f64, ParameterError> {
+        self.numbered_parameter(index)
+    }
+
+    fn named(&self, name: &str) -> Option<f64> {
+        self.named_parameter(name)
+    }
+}
+
+/// A parameter to be assigned, with any index expression already evaluated.
+pub(crate) enum Target<'a> {
+    Numbered(usize),
+    Named(&'a str),
+}
+
+impl<'a> Target<'a> {
+    pub fn new(
+        parameter: &'a Parameter,
+        parameters: &impl ParameterLookup,
+    ) -> Result<Self, ExpressionError> {
+        Ok(match parameter {
+            Parameter::Numbered(index) => Self::Numbered(parameter_index(index, parameters)?),
+            Parameter::Global(name) | Parameter::Local(name) => Self::Named(name),
+        })
+    }
+}
+
+/// Evaluate an expression.
+pub(crate) fn evaluate(
+    expression: &Spanned<Expression>,
+    parameters: &impl ParameterLookup,
+) -> Result<f64, ExpressionError> {
+    let error = |kind| ExpressionError {
+        kind,
+        span: expression.span,
+    };
+
+    match &expression.item {
+        Expression::Literal(value) => Ok(*value),
+        Expression::Parameter(Parameter::Numbered(index)) => {
+            let index = parameter_index(index, parameters)?;
+
+            parameters
+                .numbered(index)
+                .map_err(|e| error(ExpressionErrorKind::Parameter(e)))
+        }
+        Expression::Parameter(Parameter::Global(name))
+        | Expression::Parameter(Parameter::Local(name)) => parameters
+            .named(name)
+            .ok_or_else(|| error(ExpressionErrorKind::UndefinedParameter(name.clone()))),
+        Expression::Exists(Parameter::Numbered(index)) => {
+            let index = parameter_index(index, parameters)?;
+
+            Ok(truth(parameters.numbered(index).is_ok()))
+        }
+        Expression::Exists(Parameter::Global(name))
+        | Expression::Exists(Parameter::Local(name)) => Ok(truth(parameters.named(name).is_some())),
+        Expression::Unary { function, argument } => {
+            unary(*function, evaluate(argument, parameters)?).map_err(error)
+        }
+        Expression::Binary { operator, lhs, rhs } => binary(
+            *operator,
+            evaluate(lhs, parameters)?,
+            evaluate(rhs, parameters)?,
+        )
+        .map_err(error),
+        Expression::Atan { y, x } => Ok(degrees(M::atan2(
+            evaluate(y, parameters)?,
+            evaluate(x, parameters)?,
+        ))),
+    }
+}
+
+/// Evaluate the index of a numbered parameter, rounding to the nearest integer.
+pub(crate) fn parameter_index(
+    index: &Spanned<Expression>,
+    parameters: &impl ParameterLookup,
+) -> Result<usize, ExpressionError> {
+    let value = M::round(evaluate(index, parameters)?);
+
+    if value < 1.0 || value > MAX_NUMBERED as f64 {
+        Err(ExpressionError {
+            kind: ExpressionErrorKind::Parameter(ParameterError::OutOfRange(
+                value.max(0.0) as usize
+            )),
+            span: index.span,
+        })
+    } else {
+        Ok(value as usize)
+    }
+}
+
+fn truth(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn degrees(radians: f64) -> f64 {
+    radians * 180.0 / PI
+}
+
+fn radians(degrees: f64) -> f64 {
+    degrees * PI / 180.0
+}
+
+fn unary(function: UnaryFunction, value: f64) -> Result<f64, ExpressionErrorKind> {
+    Ok(match function {
+        UnaryFunction::Negate => -value,
+        UnaryFunction::Abs => M::fabs(value),
+        UnaryFunction::Acos if !(-1.0..=1.0).contains(&value) => {
+            return Err(ExpressionErrorKind::AcosOutOfRange)
+        }
+        UnaryFunction::Acos => degrees(M::acos(value)),
+        UnaryFunction::Asin if !(-1.0..=1.0).contains(&value) => {
+            return Err(ExpressionErrorKind::AsinOutOfRange)
+        }
+        UnaryFunction::Asin => degrees(M::asin(value)),
+        UnaryFunction::Cos => M::cos(radians(value)),
+        UnaryFunction::Exp => M::exp(value),
+        UnaryFunction::Fix => M::floor(value),
+        UnaryFunction::Fup => M::ceil(value),
+        UnaryFunction::Ln if value <= 0.0 => return Err(ExpressionErrorKind::LnNotPositive),
+        UnaryFunction::Ln => M::log(value),
+        // Halves round away from zero
+        UnaryFunction::Round => M::round(value),
+        UnaryFunction::Sin => M::sin(radians(value)),
+        UnaryFunction::Sqrt if value < 0.0 => return Err(ExpressionErrorKind::SqrtNegative),
+        UnaryFunction::Sqrt => M::sqrt(value),
+        UnaryFunction::Tan => M::tan(radians(value)),
+    })
+}
+
+fn binary(operator: BinaryOperator, lhs: f64, rhs: f64) -> Result<f64, ExpressionErrorKind> {
+    let is_true = |value: f64| value != 0.0;
+
+    Ok(match operator {
+        BinaryOperator::Power if lhs < 0.0 && M::floor(rhs) != rhs => {
+            return Err(ExpressionErrorKind::NegativeToNonIntegerPower)
+        }
+        BinaryOperator::Power => M::pow(lhs, rhs),
+        BinaryOperator::Multiply => lhs * rhs,
+        BinaryOperator::Divide | BinaryOperator::Modulo if rhs == 0.0 => {
+            return Err(ExpressionErrorKind::DivideByZero)
+        }
+        BinaryOperator::Divide => lhs / rhs,
+        // Negative results wrap into `0..|rhs|`, as in LinuxCNC
+        BinaryOperator::Modulo => {
+            let result = M::fmod(lhs, rhs);
+
+            if result < 0.0 {
+                result + M::fabs(rhs)
+            } else {
+                result
+            }
+        }
+        BinaryOperator::Add => lhs + rhs,
+        BinaryOperator::Subtract => lhs - rhs,
+        BinaryOperator::Eq => truth(M::fabs(lhs - rhs) < EQUAL_TOLERANCE),
+        BinaryOperator::Ne => truth(M::fabs(lhs - rhs) >= EQUAL_TOLERANCE),
+        BinaryOperator::Gt => truth(lhs > rhs),
+        BinaryOperator::Ge => truth(lhs >= rhs),
+        BinaryOperator::Lt => truth(lhs < rhs),
+        BinaryOperator::Le => truth(lhs <= rhs),
+        BinaryOperator::And => truth(is_true(lhs) && is_true(rhs)),
+        BinaryOperator::Or => truth(is_true(lhs) || is_true(rhs)),
+        BinaryOperator::Xor => truth(is_true(lhs) != is_true(rhs)),
+    })
+}
+
+/// An expression couldn't be evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpressionError {
+    pub kind: ExpressionErrorKind,
+
+    /// The part of the expression that failed.
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpressionErrorKind {
+    /// Division or `MOD` by zero.
+    DivideByZero,
+
+    /// `ACOS` argument outside `-1..=1`.
+    AcosOutOfRange,
+
+    /// `ASIN` argument outside `-1..=1`.
+    AsinOutOfRange,
+
+    /// `LN` of zero or a negative number.
+    LnNotPositive,
+
+    /// `SQRT` of a negative number.
+    SqrtNegative,
+
+    /// A negative number raised to a fractional power.
+    NegativeToNonIntegerPower,
+
+    /// A named parameter was read before being set.
+    UndefinedParameter(String),
+
+    /// A parameter couldn't be read or written.
+    Parameter(ParameterError),
+}
+
+impl fmt::Display for ExpressionErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DivideByZero => f.write_str("Attempt to divide by zero"),
+            Self::AcosOutOfRange => f.write_str("Argument to acos out of range"),
+            Self::AsinOutOfRange => f.write_str("Argument to asin out of range"),
+            Self::LnNotPositive => f.write_str("Zero or negative argument to ln"),
+            Self::SqrtNegative => f.write_str("Negative argument to sqrt"),
+            Self::NegativeToNonIntegerPower => {
+                f.write_str("Attempt to raise negative to non-integer power")
+            }
+            Self::UndefinedParameter(name) => write!(f, "Named parameter #<{}> not defined", name),
+            Self::Parameter(e) => e.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)?;
+
+        // Expressions built in code have no location
+        if self.span.line > 0 {
+            write!(
+                f,
+                " at line {}, column {}",
+                self.span.line, self.span.column
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    struct Parameters;
+
+    impl ParameterLookup for Parameters {
+        fn numbered(&self, index: usize) -> Result<f64, ParameterError> {
+            Ok(index as f64 * 10.0)
+        }
+
+        fn named(&self, name: &str) -> Option<f64> {
+            (name == "_set").then_some(5.0)
+        }
+    }
+
+    fn eval(expression: &str) -> Result<f64, ExpressionError> {
+        let block = parser::parse_block(&format!("X{}", expression)).unwrap();
+
+        evaluate(&block.words[0].value, &Parameters)
+    }
+
+    fn kind(expression: &str) -> ExpressionErrorKind {
+        eval(expression).unwrap_err().kind
+    }
+
+    fn assert_close(expression: &str, expected: f64) {
+        let value = eval(expression).unwrap();
+
+        assert!(
+            (value - expected).abs() < 1e-9,
+            "{} = {}, expected {}",
+            expression,
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_close("[1 + 2 * 3 ** 2 - 4]", 15.0);
+        assert_close("[10 / 4]", 2.5);
+        assert_close("[-7 MOD 3]", 2.0);
+        assert_close("[7 MOD 3]", 1.0);
+        assert_close("[2 ** 0.5 ** 2]", 2.0);
+        assert_close("-[1 - 3]", 2.0);
+    }
+
+    #[test]
+    fn degrees() {
+        assert_close("SIN[30]", 0.5);
+        assert_close("COS[60]", 0.5);
+        assert_close("TAN[45]", 1.0);
+        assert_close("ASIN[1]", 90.0);
+        assert_close("ACOS[0]", 90.0);
+        assert_close("ATAN[1]/[-1]", 135.0);
+    }
+
+    #[test]
+    fn rounding() {
+        assert_close("ROUND[2.5]", 3.0);
+        assert_close("ROUND[-2.5]", -3.0);
+        assert_close("FIX[-2.5]", -3.0);
+        assert_close("FUP[-2.5]", -2.0);
+        assert_close("FIX[2.5]", 2.0);
+        assert_close("FUP[2.1]", 3.0);
+    }
+
+    #[test]
+    fn logic() {
+        assert_close("[1 EQ 1.00001]", 1.0);
+        assert_close("[1 NE 1.00001]", 0.0);
+        assert_close("[1 EQ 1.001]", 0.0);
+        assert_close("[1 LT 2 AND 2 GE 2]", 1.0);
+        assert_close("[0 OR 0.5]", 1.0);
+        assert_close("[1 XOR 2]", 0.0);
+    }
+
+    #[test]
+    fn parameters() {
+        assert_close("#2", 20.0);
+        assert_close("#[1 + 0.2]", 10.0);
+        assert_close("##1", 100.0);
+        assert_close("#<_set>", 5.0);
+        assert_close("EXISTS[#<_set>]", 1.0);
+        assert_close("EXISTS[#<_unset>]", 0.0);
+
+        assert_eq!(
+            kind("#<_unset>"),
+            ExpressionErrorKind::UndefinedParameter("_unset".into())
+        );
+        assert_eq!(
+            kind("#0"),
+            ExpressionErrorKind::Parameter(ParameterError::OutOfRange(0))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(kind("[1 / 0]"), ExpressionErrorKind::DivideByZero);
+        assert_eq!(kind("[1 MOD 0]"), ExpressionErrorKind::DivideByZero);
+        assert_eq!(kind("ACOS[2]"), ExpressionErrorKind::AcosOutOfRange);
+        assert_eq!(kind("ASIN[-1.5]"), ExpressionErrorKind::AsinOutOfRange);
+        assert_eq!(kind("LN[0]"), ExpressionErrorKind::LnNotPositive);
+        assert_eq!(kind("SQRT[-1]"), ExpressionErrorKind::SqrtNegative);
+        assert_eq!(
+            kind("[-8 ** 0.5]"),
+            ExpressionErrorKind::NegativeToNonIntegerPower
+        );
+        assert_close("[-2 ** 3]", -8.0);
+
+        // The error points at the failing sub-expression
+        let error = eval("[2 + [1 / [3 - 3]]]").unwrap_err();
+
+        assert_eq!(error.span.offset, 6);
+        assert_eq!(error.span.len, 13);
+        assert_eq!(
+            format!("{}", error),
+            "Attempt to divide by zero at line 1, column 7"
+        );
+    }
+}
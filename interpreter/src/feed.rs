@@ -0,0 +1,255 @@
+//! Feed rate modes, `G93`, `G94` and `G95`.
+//!
+//! The planner only deals in units per minute. In `G94` mode that's what `F` is, so it's passed
+//! straight on with [`Canon::SetFeedRate`]. In the other two modes the interpreter works out the
+//! rate each feed move needs and sends it just before the move:
+//!
+//! - `G93` inverse time: the move takes `1/F` minutes, so the rate is its length times `F`. An `F`
+//!   word is needed in every block that makes a `G1`, `G2` or `G3` move and is ignored in other
+//!   blocks. As section 2.1.2.5 of the RS274NGC spec describes, the length is along the linear
+//!   axes, or in degrees through the rotary axes if only they move, which is what wrapped 4th axis
+//!   toolpaths rely on. [`MachineAxes`](crate::axes::MachineAxes) can count a rotary axis as
+//!   linear instead. Rapids aren't affected and canned cycles can't be used.
+//! - `G95` units per revolution: the rate is `F` times the spindle speed, so the spindle must be
+//!   turning. With `G96` constant surface speed it's the speed at the start of the move. `G84`
+//!   tapping takes `F` as the pitch.
+//!
+//! Changing mode sets the feed rate to zero, so a program has to give a new `F` for the new mode.
+//! Feed moves with a zero feed rate are errors.
+
+use crate::arc::ArcFeed;
+use crate::block::BlockCommands;
+use crate::canon::Canon;
+use crate::lathe::spindle_rpm;
+use crate::parameters::ParameterStore;
+use crate::{Interpreter, InterpreterError};
+use common::{Axis, FeedRateMode, Number, Position, Spindle};
+use core::fmt;
+
+type M = libm::Libm<Number>;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FeedError {
+    /// A `G1` move in inverse time mode without an `F` word.
+    InverseTimeLine,
+
+    /// A `G2` or `G3` move in inverse time mode without an `F` word.
+    InverseTimeArc,
+
+    /// A feed move in units per revolution mode with the spindle stopped or at zero speed.
+    SpindleNotTurning,
+
+    /// A `G1` move in units per minute or units per revolution mode with a zero feed rate.
+    ZeroFeedLine,
+
+    /// A `G2` or `G3` move in units per minute or units per revolution mode with a zero feed rate.
+    ZeroFeedArc,
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InverseTimeLine => write!(f, "F word missing with inverse time G1 move"),
+            Self::InverseTimeArc => write!(f, "F word missing with inverse time arc move"),
+            Self::SpindleNotTurning => write!(f, "Spindle not turning in G95"),
+            Self::ZeroFeedLine => write!(f, "Cannot do G1 with zero feed rate"),
+            Self::ZeroFeedArc => write!(f, "Cannot make arc with zero feed rate"),
+        }
+    }
+}
+
+/// Length of a helical arc, along the helix.
+fn arc_length(start: &Position, arc: &ArcFeed) -> Number {
+    let (_, _, normal) = arc.plane.axes();
+
+    M::hypot(
+        arc.radius(start) * M::fabs(arc.sweep(start)),
+        arc.end[normal.index()] - start[normal.index()],
+    )
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Switch feed rate mode. The feed rate is reset when the mode changes.
+    pub(crate) fn feed_rate_mode(&mut self, mode: FeedRateMode) {
+        if mode != self.modal_groups.feed_rate_mode {
+            self.modal_groups.feed_rate_mode = mode;
+            self.feed_rate = 0.0;
+
+            if mode == FeedRateMode::UnitsPerMinute {
+                self.emit(Canon::SetFeedRate(0.0));
+            }
+        }
+    }
+
+    /// Set the feed rate before a feed move from the machine position `start`, unless it's already
+    /// in units per minute.
+    pub(crate) fn feed_for_move(
+        &mut self,
+        start: &Position,
+        canon: &Canon,
+        commands: &BlockCommands,
+    ) -> Result<(), InterpreterError> {
+        let rate = match (self.modal_groups.feed_rate_mode, canon) {
+            (FeedRateMode::InverseTime, Canon::StraightFeed { end }) => {
+                let feed = commands.value('F').ok_or(FeedError::InverseTimeLine)?;
+
+                self.axes.move_length(start, end) * feed
+            }
+            (FeedRateMode::InverseTime, Canon::ArcFeed(arc)) => {
+                let feed = commands.value('F').ok_or(FeedError::InverseTimeArc)?;
+
+                arc_length(start, arc) * feed
+            }
+            (_, Canon::StraightFeed { .. }) if self.feed_rate == 0.0 => {
+                return Err(FeedError::ZeroFeedLine.into())
+            }
+            (_, Canon::ArcFeed(_)) if self.feed_rate == 0.0 => {
+                return Err(FeedError::ZeroFeedArc.into())
+            }
+            (FeedRateMode::UnitsPerRevolution, Canon::StraightFeed { .. } | Canon::ArcFeed(_)) => {
+                self.feed_per_revolution()?
+            }
+            _ => return Ok(()),
+        };
+
+        self.emit(Canon::SetFeedRate(rate));
+
+        Ok(())
+    }
+
+    /// Units per minute feed rate for `G95` mode at the current position.
+    pub(crate) fn feed_per_revolution(&self) -> Result<Number, InterpreterError> {
+        let rpm = match self.spindle_speed_canon() {
+            Canon::ConstantSurfaceSpeed {
+                surface_speed,
+                max_rpm,
+                ..
+            } => spindle_rpm(surface_speed, self.position[Axis::X.index()], max_rpm),
+            _ => self.modal_groups.spindle_speed,
+        };
+
+        if self.modal_groups.spindle == Spindle::Stopped || rpm <= 0.0 {
+            return Err(FeedError::SpindleNotTurning.into());
+        }
+
+        Ok(self.feed_rate * rpm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canned_cycle::CannedCycleError;
+    use crate::test_utils::run;
+    use alloc::vec::Vec;
+    use common::consts::PI;
+
+    /// Feed rates set in the output.
+    fn rates(canon: &[Canon]) -> Vec<Number> {
+        canon
+            .iter()
+            .filter_map(|canon| match canon {
+                Canon::SetFeedRate(rate) => Some(*rate),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inverse_time() {
+        let mut interp = Interpreter::new();
+
+        let canon = run(&mut interp, "G93 G1 X3 Y4 F2\nF5\nG0 X0 Y0\nG1 A90 F1").unwrap();
+
+        assert_eq!(rates(&canon), [10.0, 90.0]);
+        assert_eq!(
+            canon[..2],
+            [
+                Canon::SetFeedRate(10.0),
+                Canon::StraightFeed {
+                    end: Position::from_column_slice(&[
+                        3.0, 4.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0
+                    ])
+                },
+            ]
+        );
+
+        // Rotary axes don't count when linear axes move too
+        let canon = run(&mut interp, "G1 X1 A180 F1").unwrap();
+
+        assert_eq!(rates(&canon), [1.0]);
+
+        // A half circle of radius 1
+        let canon = run(&mut interp, "G0 X0 Y0\nG2 X2 I1 F1").unwrap();
+
+        assert!((rates(&canon)[0] - PI).abs() < 0.0001);
+
+        assert_eq!(
+            run(&mut interp, "G1 X3"),
+            Err(FeedError::InverseTimeLine.into())
+        );
+        assert_eq!(
+            run(&mut interp, "G3 X0 I-1"),
+            Err(FeedError::InverseTimeArc.into())
+        );
+        assert_eq!(
+            run(&mut interp, "G81 X1 R1 Z-1"),
+            Err(CannedCycleError::InverseTime.into())
+        );
+
+        // Back to units per minute, needing a new feed rate
+        assert_eq!(run(&mut interp, "G94").unwrap(), [Canon::SetFeedRate(0.0)]);
+        assert_eq!(interp.feed_rate(), 0.0);
+        assert_eq!(
+            interp.modal_groups().feed_rate_mode(),
+            FeedRateMode::UnitsPerMinute
+        );
+    }
+
+    #[test]
+    fn zero_feed_rate() {
+        let mut interp = Interpreter::new();
+
+        assert_eq!(
+            run(&mut interp, "G1 X1"),
+            Err(FeedError::ZeroFeedLine.into())
+        );
+        assert_eq!(
+            run(&mut interp, "G2 X2 I1"),
+            Err(FeedError::ZeroFeedArc.into())
+        );
+
+        // Rapids don't need one
+        assert!(run(&mut interp, "G0 X1").is_ok());
+        assert!(run(&mut interp, "G1 X2 F100").is_ok());
+    }
+
+    #[test]
+    fn units_per_revolution() {
+        let mut interp = Interpreter::new();
+
+        assert_eq!(
+            run(&mut interp, "G95 G1 X1 F0.1"),
+            Err(FeedError::SpindleNotTurning.into())
+        );
+
+        let canon = run(&mut interp, "S1000 M3\nG95 G1 X2 F0.1\nF0.2 X3").unwrap();
+
+        assert_eq!(rates(&canon), [100.0, 200.0]);
+
+        // The pitch of a tapped hole is the feed per revolution
+        let canon = run(&mut interp, "G0 Z5\nF1.25 G84 X0 R1 Z-10").unwrap();
+
+        assert_eq!(rates(&canon), [1250.0]);
+        assert!(canon.contains(&Canon::StartSpeedFeedSync(1.25)));
+
+        // Constant surface speed is worked out at the start of the move
+        let mut interp = Interpreter::new();
+        interp.set_lathe(true);
+
+        let canon = run(&mut interp, "G0 X10 Z1\nG96 S100 M3\nG95 G1 Z0 F0.1").unwrap();
+        let rpm = spindle_rpm(100_000.0, 10.0, None);
+
+        assert_eq!(rates(&canon), [rpm * 0.1]);
+    }
+}
@@ -0,0 +1,30 @@
+//! Saving files without leaving a half written one behind.
+
+use alloc::string::String;
+use core::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Write the text made by `contents` to `path`.
+///
+/// The file is written next to the destination then renamed over it, so a crash while saving
+/// never leaves a truncated file behind.
+pub(crate) fn write_atomic(
+    path: &Path,
+    contents: impl FnOnce(&mut String) -> fmt::Result,
+) -> std::io::Result<()> {
+    let mut text = String::new();
+
+    // Writing to a `String` cannot fail
+    contents(&mut text).ok();
+
+    let mut tmp_path = std::ffi::OsString::from(path.as_os_str());
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = std::fs::File::create(&tmp_path)?;
+    file.write_all(text.as_bytes())?;
+    file.sync_all()?;
+
+    std::fs::rename(&tmp_path, path)
+}
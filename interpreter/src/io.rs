@@ -0,0 +1,447 @@
+//! Digital and analog IO, `M62` to `M68`.
+//!
+//! - `M62 P` and `M63 P` turn digital output `P` on and off as the next move starts. They're
+//!   output as [`Canon::SyncedOutput`] just before that move, so the planner can tie them to the
+//!   start of its segment.
+//! - `M64 P` and `M65 P` turn it on and off straight away, and `M67 E Q` and `M68 E Q` set analog
+//!   output `E` to `Q` with the next move or straight away.
+//! - `M66` waits for digital input `P` or analog input `E`, in the way given by `L`, for at most
+//!   `Q` seconds. `#5399` is then the value read, or `-1` if the wait timed out.
+//!
+//! Immediate outputs and inputs go through an [`IoBackend`] as the block is executed, as a tool
+//! change goes through the tool changer. Without one set, outputs are only output as [`Canon`]
+//! and `M66` is an error, unless [sync points](crate::sync) are on, when it waits for the machine
+//! to read the input instead. [`MockIo`] keeps inputs and outputs in memory for trying programs.
+
+use crate::block::{BlockCommands, IoCommand};
+use crate::canon::Canon;
+use crate::expression::{ExpressionError, ExpressionErrorKind};
+use crate::parameters::ParameterStore;
+use crate::sync::SyncPoint;
+use crate::{Interpreter, InterpreterError};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use common::{Number, Span};
+use core::fmt;
+
+/// `M66` result, the value read or `-1` on timeout.
+const INPUT_RESULT: usize = 5399;
+
+/// An output and the value to set it to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Output {
+    Digital { index: u32, on: bool },
+    Analog { index: u32, value: Number },
+}
+
+/// An input read by `M66`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Input {
+    Digital(u32),
+    Analog(u32),
+}
+
+/// What `M66` waits for, given by `L`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WaitMode {
+    /// `L0`: read the input without waiting.
+    Immediate,
+
+    /// `L1`: wait for the input to turn on.
+    Rise,
+
+    /// `L2`: wait for the input to turn off.
+    Fall,
+
+    /// `L3`: wait for the input to be on, which it may be already.
+    High,
+
+    /// `L4`: wait for the input to be off, which it may be already.
+    Low,
+}
+
+/// An `M66` wait.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InputWait {
+    pub input: Input,
+    pub mode: WaitMode,
+
+    /// Longest time to wait in seconds.
+    pub timeout: Number,
+}
+
+/// Sets outputs and reads inputs on the machine.
+pub trait IoBackend {
+    /// Set an output straight away.
+    fn set_output(&mut self, output: Output);
+
+    /// Wait for an input, returning its value (`0` or `1` for a digital input) or `None` if the
+    /// wait timed out.
+    fn wait_input(&mut self, wait: &InputWait) -> Option<Number>;
+}
+
+/// An [`IoBackend`] keeping outputs and inputs in memory, for trying out programs and testing.
+///
+/// Inputs only change when set from outside the interpreter, so a wait for one to rise or fall
+/// always times out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MockIo {
+    pub digital_outputs: BTreeMap<u32, bool>,
+    pub analog_outputs: BTreeMap<u32, Number>,
+    pub digital_inputs: BTreeMap<u32, bool>,
+    pub analog_inputs: BTreeMap<u32, Number>,
+}
+
+impl IoBackend for MockIo {
+    fn set_output(&mut self, output: Output) {
+        match output {
+            Output::Digital { index, on } => {
+                self.digital_outputs.insert(index, on);
+            }
+            Output::Analog { index, value } => {
+                self.analog_outputs.insert(index, value);
+            }
+        }
+    }
+
+    fn wait_input(&mut self, wait: &InputWait) -> Option<Number> {
+        let value = match wait.input {
+            Input::Digital(index) => match self.digital_inputs.get(&index) {
+                Some(true) => 1.0,
+                _ => 0.0,
+            },
+            Input::Analog(index) => self.analog_inputs.get(&index).copied().unwrap_or(0.0),
+        };
+
+        match wait.mode {
+            WaitMode::Immediate => Some(value),
+            WaitMode::High => (value != 0.0).then_some(value),
+            WaitMode::Low => (value == 0.0).then_some(value),
+            WaitMode::Rise | WaitMode::Fall => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum IoError {
+    /// `M66` without an input to read.
+    NoInput,
+
+    /// `M66` with both a digital and an analog input.
+    BothInputs,
+
+    /// `L` isn't one of the `M66` wait modes.
+    InvalidWaitMode(Number),
+
+    /// Analog inputs can only be read straight away.
+    AnalogWaitMode,
+
+    /// `M66` with no [`IoBackend`] set and sync points off, so nothing can read the input.
+    NoBackend,
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoInput => write!(f, "Need P or E word with M66"),
+            Self::BothInputs => write!(f, "Cannot have both P and E words with M66"),
+            Self::InvalidWaitMode(mode) => write!(f, "Invalid wait mode L{} with M66", mode),
+            Self::AnalogWaitMode => write!(f, "Analog input with M66 can only be read with L0"),
+            Self::NoBackend => write!(f, "No IO backend configured for M66"),
+        }
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Set the backend for immediate outputs and `M66` inputs.
+    pub fn set_io(&mut self, io: impl IoBackend + Send + 'static) {
+        self.io = Some(Box::new(io));
+    }
+
+    /// `M62` to `M68`.
+    pub(crate) fn io_command(
+        &mut self,
+        command: IoCommand,
+        commands: &BlockCommands,
+        span: Span,
+    ) -> Result<(), InterpreterError> {
+        let index = |letter| -> Result<u32, InterpreterError> {
+            let value = commands
+                .value(letter)
+                .ok_or(InterpreterError::MissingWord(letter))?;
+
+            if value < 0.0 {
+                return Err(InterpreterError::NegativeValue(letter));
+            }
+
+            Ok(value.round() as u32)
+        };
+
+        let (output, synced) = match command {
+            IoCommand::DigitalOutput { on, synced } => {
+                let index = index('P')?;
+
+                (Output::Digital { index, on }, synced)
+            }
+            IoCommand::AnalogOutput { synced } => {
+                let index = index('E')?;
+                let value = commands
+                    .value('Q')
+                    .ok_or(InterpreterError::MissingWord('Q'))?;
+
+                (Output::Analog { index, value }, synced)
+            }
+            IoCommand::WaitInput => {
+                let input = match (commands.value('P'), commands.value('E')) {
+                    (Some(_), None) => Input::Digital(index('P')?),
+                    (None, Some(_)) => Input::Analog(index('E')?),
+                    (None, None) => return Err(IoError::NoInput.into()),
+                    (Some(_), Some(_)) => return Err(IoError::BothInputs.into()),
+                };
+
+                return self.wait_input(input, commands, span);
+            }
+        };
+
+        if synced {
+            self.synced_outputs.push(output);
+        } else {
+            if let (Some(io), false) = (&mut self.io, self.dry_run) {
+                io.set_output(output);
+            }
+
+            self.emit(Canon::Output(output));
+        }
+
+        Ok(())
+    }
+
+    fn wait_input(
+        &mut self,
+        input: Input,
+        commands: &BlockCommands,
+        span: Span,
+    ) -> Result<(), InterpreterError> {
+        let mode = match commands.value('L').unwrap_or(0.0) {
+            0.0 => WaitMode::Immediate,
+            1.0 => WaitMode::Rise,
+            2.0 => WaitMode::Fall,
+            3.0 => WaitMode::High,
+            4.0 => WaitMode::Low,
+            mode => return Err(IoError::InvalidWaitMode(mode).into()),
+        };
+
+        if matches!(input, Input::Analog(_)) && mode != WaitMode::Immediate {
+            return Err(IoError::AnalogWaitMode.into());
+        }
+
+        let timeout = match (commands.value('Q'), mode) {
+            (Some(timeout), _) if timeout < 0.0 => {
+                return Err(InterpreterError::NegativeValue('Q'))
+            }
+            (Some(timeout), _) => timeout,
+            (None, WaitMode::Immediate) => 0.0,
+            (None, _) => return Err(InterpreterError::MissingWord('Q')),
+        };
+
+        let wait = InputWait {
+            input,
+            mode,
+            timeout,
+        };
+
+        // Nothing is read in a dry run, so `#5399` keeps its value
+        if self.dry_run {
+            return Ok(());
+        }
+
+        // The machine reads the input and gives the result at the end of the block
+        if self.io.is_none() && self.can_sync() {
+            self.pending_sync = Some(SyncPoint::Input(wait));
+
+            return Ok(());
+        }
+
+        let value = match &mut self.io {
+            Some(io) => io.wait_input(&wait),
+            None => return Err(IoError::NoBackend.into()),
+        };
+
+        self.input_result(value, span)
+    }
+
+    /// Set `#5399` to the value read by `M66`, or `-1` if it timed out.
+    pub(crate) fn input_result(
+        &mut self,
+        value: Option<Number>,
+        span: Span,
+    ) -> Result<(), InterpreterError> {
+        self.parameters
+            .set_numbered(INPUT_RESULT, value.map_or(-1.0, f64::from))
+            .map_err(|e| ExpressionError {
+                kind: ExpressionErrorKind::Parameter(e),
+                span,
+            })?;
+
+        Ok(())
+    }
+
+    /// Output the synced outputs waiting for the next move, just before it.
+    pub(crate) fn start_segment(&mut self) {
+        for output in core::mem::take(&mut self.synced_outputs) {
+            self.emit(Canon::SyncedOutput(output));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{Feedback, SyncPoint};
+    use crate::test_utils::run;
+    use alloc::sync::Arc;
+    use common::Position;
+    use std::sync::Mutex;
+
+    /// A [`MockIo`] the test can still look at once the interpreter has it.
+    struct Shared(Arc<Mutex<MockIo>>);
+
+    impl IoBackend for Shared {
+        fn set_output(&mut self, output: Output) {
+            self.0.lock().unwrap().set_output(output);
+        }
+
+        fn wait_input(&mut self, wait: &InputWait) -> Option<Number> {
+            self.0.lock().unwrap().wait_input(wait)
+        }
+    }
+
+    #[test]
+    fn synced_outputs() {
+        let mut interp = Interpreter::new();
+
+        let canon = run(&mut interp, "M62 P1\nM67 E2 Q3.5\nG1 X1 F100\nX2").unwrap();
+
+        let mut end = Position::zeros();
+        end[0] = 1.0;
+
+        assert_eq!(
+            canon[..4],
+            [
+                Canon::SetFeedRate(100.0),
+                Canon::SyncedOutput(Output::Digital { index: 1, on: true }),
+                Canon::SyncedOutput(Output::Analog {
+                    index: 2,
+                    value: 3.5
+                }),
+                Canon::StraightFeed { end },
+            ]
+        );
+
+        // Only the next move
+        assert!(matches!(canon[4..], [Canon::StraightFeed { .. }]));
+    }
+
+    #[test]
+    fn immediate_outputs() {
+        let io = Arc::new(Mutex::new(MockIo::default()));
+
+        let mut interp = Interpreter::new();
+        interp.set_io(Shared(io.clone()));
+
+        let canon = run(&mut interp, "M64 P3\nM65 P4\nM68 E1 Q-2").unwrap();
+
+        assert_eq!(
+            canon,
+            [
+                Canon::Output(Output::Digital { index: 3, on: true }),
+                Canon::Output(Output::Digital {
+                    index: 4,
+                    on: false
+                }),
+                Canon::Output(Output::Analog {
+                    index: 1,
+                    value: -2.0
+                }),
+            ]
+        );
+
+        let io = io.lock().unwrap();
+
+        assert_eq!(io.digital_outputs.get(&3), Some(&true));
+        assert_eq!(io.digital_outputs.get(&4), Some(&false));
+        assert_eq!(io.analog_outputs.get(&1), Some(&-2.0));
+    }
+
+    #[test]
+    fn wait_input() {
+        let mut io = MockIo::default();
+        io.digital_inputs.insert(1, true);
+        io.analog_inputs.insert(0, 2.5);
+
+        let mut interp = Interpreter::new();
+        interp.set_io(io);
+
+        for (program, result) in [
+            ("M66 P1 L3 Q1", 1.0),
+            ("M66 P1 L4 Q1", -1.0),
+            ("M66 P2 L1 Q1", -1.0),
+            ("M66 P2", 0.0),
+            ("M66 E0", 2.5),
+        ] {
+            run(&mut interp, program).unwrap();
+
+            assert_eq!(
+                interp.numbered_parameter(INPUT_RESULT),
+                Ok(result),
+                "{}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let mut interp = Interpreter::new();
+
+        for (program, error) in [
+            ("M62", InterpreterError::MissingWord('P')),
+            ("M65 P-1", InterpreterError::NegativeValue('P')),
+            ("M67 E1", InterpreterError::MissingWord('Q')),
+            ("M66 L1 Q1", IoError::NoInput.into()),
+            ("M66 P1 E1", IoError::BothInputs.into()),
+            ("M66 P1 L5 Q1", IoError::InvalidWaitMode(5.0).into()),
+            ("M66 E1 L3 Q1", IoError::AnalogWaitMode.into()),
+            ("M66 P1 L1", InterpreterError::MissingWord('Q')),
+            ("M66 P1 L1 Q-1", InterpreterError::NegativeValue('Q')),
+            ("M66 P1 L3 Q1", IoError::NoBackend.into()),
+        ] {
+            assert_eq!(run(&mut interp, program), Err(error), "{}", program);
+        }
+    }
+
+    #[test]
+    fn synced_wait() {
+        let mut interp = Interpreter::new();
+        interp.set_sync_points(true);
+
+        run(&mut interp, "M66 P1 L3 Q5").unwrap();
+
+        assert_eq!(
+            interp.sync_point(),
+            Some(&SyncPoint::Input(InputWait {
+                input: Input::Digital(1),
+                mode: WaitMode::High,
+                timeout: 5.0,
+            }))
+        );
+
+        interp.synchronise(Feedback::Input(Some(1.0))).unwrap();
+
+        assert_eq!(interp.numbered_parameter(INPUT_RESULT), Ok(1.0));
+    }
+}
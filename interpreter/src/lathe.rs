@@ -0,0 +1,594 @@
+//! Lathe support: diameter mode, constant surface speed and `G76` threading.
+//!
+//! [`Interpreter::set_lathe`] configures the interpreter for a lathe, which works in the XZ plane
+//! (`G18`) and treats `G76` as a threading cycle rather than the mill fine boring cycle.
+//!
+//! With `G7` X words are diameters, so the tool moves half as far from the spindle axis, until
+//! `G8` goes back to radii. As in LinuxCNC only X words change: arc centre offsets, `G76` depths
+//! and positions reported in parameters are still radii.
+//!
+//! `G96 S D` turns on constant surface speed. `S` is the cutting speed at the tool in feet per
+//! minute with `G20` or metres per minute with `G21`, and the spindle turns faster as the tool
+//! gets closer to the spindle axis, up to `D` rpm if given. `G97` goes back to `S` in rpm.
+//!
+//! `G76` cuts a thread from the current position, which is taken as the drive line, along Z to
+//! the Z word, in passes synchronised with the spindle:
+//!
+//! - `P`: thread pitch, the distance moved along Z per spindle revolution.
+//! - `I`: offset from the drive line to the thread peaks. Negative for outside threads, positive
+//!   for inside threads.
+//! - `J`: depth of the first cut, measured from the peaks.
+//! - `K`: full thread depth, measured from the peaks.
+//! - `R`: depth degression, at least `1` and `1` if left out. Pass `n` cuts to
+//!   `J × n^(1/R)`, so `1` keeps the depth of each cut the same and `2` the area.
+//! - `Q`: compound slide angle in degrees, `0` if left out. Each pass starts further along Z so
+//!   the tool only cuts on one flank.
+//! - `H`: number of spring passes at full depth after the first.
+//! - `E`: length along the drive line used to taper into and out of the thread.
+//! - `L`: which ends are tapered, `0` for neither, `1` the start, `2` the end, `3` both.
+//!
+//! Each pass moves in at rapid, feeds along the thread in step with the spindle and retracts at
+//! rapid, following LinuxCNC's threading cycle move for move. The tool ends back at the start.
+
+use crate::block::BlockCommands;
+use crate::canned_cycle::CannedCycleError;
+use crate::canon::Canon;
+use crate::parameters::ParameterStore;
+use crate::{Interpreter, InterpreterError};
+use common::consts::PI;
+use common::{
+    Axis, CannedCycle, DiameterMode, DistanceMode, Motion, Number, Plane, SpindleSpeedMode, Units,
+};
+use core::fmt;
+
+type M = libm::Libm<Number>;
+
+/// Spindle speed in rpm for a constant surface speed in length units per minute, with the tool
+/// `radius` from the spindle axis.
+pub fn spindle_rpm(surface_speed: Number, radius: Number, max_rpm: Option<Number>) -> Number {
+    let rpm = surface_speed / (2.0 * PI * M::fabs(radius));
+
+    match max_rpm {
+        Some(max_rpm) => rpm.min(max_rpm),
+        None => rpm,
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LatheError {
+    /// `G76` threading outside the XZ plane.
+    ThreadingPlane,
+
+    /// A `G76` word that must be positive isn't.
+    NotPositive(char),
+
+    /// `G76` depth degression is less than one.
+    BadDegression,
+
+    /// `G76` spring passes isn't a whole number.
+    BadSpringPasses,
+
+    /// `G76` taper ends isn't `0` to `3`.
+    BadTaperEnds,
+}
+
+impl fmt::Display for LatheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ThreadingPlane => write!(f, "G76 threading is only allowed in the XZ plane"),
+            Self::NotPositive(letter) => {
+                write!(f, "{} word must be greater than zero with G76", letter)
+            }
+            Self::BadDegression => write!(f, "R word must be at least 1 with G76"),
+            Self::BadSpringPasses => {
+                write!(f, "H word must be a non-negative integer with G76")
+            }
+            Self::BadTaperEnds => write!(f, "L word must be 0, 1, 2 or 3 with G76"),
+        }
+    }
+}
+
+/// A `G76` thread, in absolute program coordinates.
+struct Thread {
+    /// Inside thread, cut moving away from the spindle axis.
+    boring: bool,
+
+    /// X position of the drive line.
+    drive_line: Number,
+    start: Number,
+    end: Number,
+    pitch: Number,
+
+    /// Depth of the thread peaks from the drive line.
+    peaks: Number,
+
+    /// Depth of the thread itself.
+    depth: Number,
+
+    /// Tangent of the compound angle, towards the start of the thread.
+    compound: Number,
+
+    /// Length of the tapers, towards the end of the thread.
+    taper: Number,
+    taper_pitch: Number,
+    taper_start: bool,
+    taper_end: bool,
+}
+
+impl Thread {
+    /// X position `depth` from the drive line, towards the spindle axis for an outside thread.
+    fn x(&self, depth: Number) -> Number {
+        if self.boring {
+            self.drive_line + depth
+        } else {
+            self.drive_line - depth
+        }
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Configure the interpreter for a lathe or a mill. Selects the `G18` plane for a lathe, or
+    /// `G17` for a mill.
+    pub fn set_lathe(&mut self, lathe: bool) {
+        self.lathe = lathe;
+        self.modal_groups.plane = self.default_plane();
+    }
+
+    /// `G18` on a lathe, otherwise `G17`. Set at the end of a program.
+    pub(crate) fn default_plane(&self) -> Plane {
+        if self.lathe {
+            Plane::XZ
+        } else {
+            Plane::XY
+        }
+    }
+
+    pub fn is_lathe(&self) -> bool {
+        self.lathe
+    }
+
+    /// Adjust a block's commands for a lathe: X words are diameters with `G7`, and `G76` is
+    /// threading.
+    pub(crate) fn lathe_commands(&self, commands: &mut BlockCommands) {
+        // `G43.1` and `G43.2` offsets aren't positions
+        if self.modal_groups.diameter_mode == DiameterMode::Diameter
+            && !commands.axis_words_are_offsets()
+        {
+            commands.diameter_to_radius();
+        }
+
+        if self.lathe {
+            if let Some(Some(Motion::CannedCycle(cycle @ CannedCycle::FineBore))) =
+                &mut commands.motion
+            {
+                *cycle = CannedCycle::Threading;
+            }
+        }
+    }
+
+    /// `G96` or `G97`.
+    pub(crate) fn spindle_speed_mode(
+        &mut self,
+        mode: SpindleSpeedMode,
+        commands: &BlockCommands,
+    ) -> Result<(), InterpreterError> {
+        self.modal_groups.spindle_speed_mode = match mode {
+            SpindleSpeedMode::ConstantSurfaceSpeed { .. } => {
+                let max_rpm = commands.value('D');
+
+                if max_rpm.is_some_and(|max_rpm| max_rpm < 0.0) {
+                    return Err(InterpreterError::NegativeValue('D'));
+                }
+
+                SpindleSpeedMode::ConstantSurfaceSpeed { max_rpm }
+            }
+            mode => mode,
+        };
+
+        Ok(())
+    }
+
+    /// Spindle speed command for the current `S` word and spindle speed mode.
+    pub(crate) fn spindle_speed_canon(&self) -> Canon {
+        let speed = self.modal_groups.spindle_speed;
+
+        match self.modal_groups.spindle_speed_mode {
+            SpindleSpeedMode::Rpm => Canon::SetSpindleSpeed(speed),
+            SpindleSpeedMode::ConstantSurfaceSpeed { max_rpm } => Canon::ConstantSurfaceSpeed {
+                // From feet or metres per minute
+                surface_speed: match self.modal_groups.units {
+                    Units::Inch => speed * 12.0,
+                    Units::Mm => speed * 1000.0,
+                },
+                max_rpm,
+                center: self.program_offset()[Axis::X.index()],
+            },
+        }
+    }
+
+    /// `G76` threading cycle.
+    pub(crate) fn threading(&mut self, commands: &BlockCommands) -> Result<(), InterpreterError> {
+        if self.modal_groups.plane != Plane::XZ {
+            return Err(LatheError::ThreadingPlane.into());
+        }
+
+        if self.cutter_comp.is_some() {
+            return Err(CannedCycleError::CutterComp.into());
+        }
+
+        let word = |letter| {
+            commands
+                .value(letter)
+                .ok_or(InterpreterError::MissingWord(letter))
+        };
+
+        let pitch = word('P')?;
+        let end = word('Z')?;
+        let peaks = word('I')?;
+        let first_cut = word('J')?;
+        let depth = word('K')?;
+        let degression = commands.value('R').unwrap_or(1.0);
+        let angle = commands.value('Q').unwrap_or(0.0);
+        let spring_passes = commands.value('H').unwrap_or(0.0);
+        let taper = commands.value('E').unwrap_or(0.0).max(0.0);
+        let taper_ends = commands.value('L').unwrap_or(0.0);
+
+        for (letter, value) in [('P', pitch), ('J', first_cut), ('K', depth)] {
+            if value <= 0.0 {
+                return Err(LatheError::NotPositive(letter).into());
+            }
+        }
+
+        if degression < 1.0 {
+            return Err(LatheError::BadDegression.into());
+        }
+
+        if spring_passes < 0.0 || spring_passes != (spring_passes as u32) as Number {
+            return Err(LatheError::BadSpringPasses.into());
+        }
+
+        let taper_ends = match taper_ends as i32 {
+            ends @ 0..=3 if ends as Number == taper_ends => ends,
+            _ => return Err(LatheError::BadTaperEnds.into()),
+        };
+
+        let (x, z) = (Axis::X, Axis::Z);
+        let start = self.position;
+
+        let end = match self.modal_groups.distance_mode {
+            DistanceMode::Absolute => end,
+            DistanceMode::Incremental => start[z.index()] + end,
+        };
+
+        let boring = peaks > 0.0;
+        let peaks = M::fabs(peaks);
+        let taper_pitch = if taper > 0.0 {
+            pitch * M::hypot(taper, depth) / taper
+        } else {
+            pitch
+        };
+
+        // Threads cut away from the chuck are tapered and stepped over the other way
+        let direction = if end > start[z.index()] { -1.0 } else { 1.0 };
+
+        let thread = Thread {
+            boring,
+            drive_line: start[x.index()],
+            start: start[z.index()],
+            end,
+            pitch,
+            peaks,
+            depth,
+            compound: M::tan(angle.to_radians()) * direction,
+            taper: taper * direction,
+            taper_pitch,
+            taper_start: taper_ends == 1 || taper_ends == 3,
+            taper_end: taper_ends == 2 || taper_ends == 3,
+        };
+
+        let full_depth = peaks + depth;
+        let mut cut = peaks + first_cut;
+        let mut pass = 1;
+
+        while cut < full_depth {
+            self.thread_pass(&thread, cut);
+
+            pass += 1;
+            cut = peaks + first_cut * M::pow(pass as Number, 1.0 / degression);
+        }
+
+        for _ in 0..=spring_passes as u32 {
+            self.thread_pass(&thread, full_depth);
+        }
+
+        self.cycle_move(false, &[(x, thread.drive_line), (z, thread.start)]);
+
+        Ok(())
+    }
+
+    /// One `G76` pass, cutting `cut` deep from the drive line.
+    fn thread_pass(&mut self, thread: &Thread, cut: Number) {
+        let (x, z) = (Axis::X, Axis::Z);
+        let full_depth = thread.peaks + thread.depth;
+        let offset = (cut - thread.peaks) * thread.compound;
+        let start = thread.start - offset;
+        let end = thread.end - offset;
+
+        // Back to the start, as far from the cut as the bottom of the thread is from the drive line
+        self.cycle_move(false, &[(x, thread.x(cut - full_depth)), (z, start)]);
+
+        // A zero dwell makes sure the tool has stopped before synchronised motion starts
+        if thread.taper != 0.0 && thread.taper_start {
+            self.emit(Canon::Dwell(0.0));
+            self.emit(Canon::StartSpeedFeedSync(thread.taper_pitch));
+            self.cycle_move(true, &[(x, thread.x(cut - thread.depth))]);
+            self.cycle_move(true, &[(x, thread.x(cut)), (z, start - thread.taper)]);
+            self.emit(Canon::StartSpeedFeedSync(thread.pitch));
+        } else {
+            self.cycle_move(false, &[(x, thread.x(cut))]);
+            self.emit(Canon::Dwell(0.0));
+            self.emit(Canon::StartSpeedFeedSync(thread.pitch));
+        }
+
+        if thread.taper != 0.0 && thread.taper_end {
+            self.cycle_move(true, &[(z, end + thread.taper)]);
+            self.emit(Canon::StartSpeedFeedSync(thread.taper_pitch));
+            self.cycle_move(true, &[(x, thread.x(cut - thread.depth)), (z, end)]);
+        } else {
+            self.cycle_move(true, &[(z, end)]);
+        }
+
+        self.emit(Canon::StopSpeedFeedSync);
+        self.cycle_move(false, &[(x, thread.x(cut - full_depth))]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::run;
+    use crate::{cutter_comp::CutterCompError, tools::tool_file};
+    use alloc::vec::Vec;
+
+    /// Run a LinuxCNC example without the codes it uses that the interpreter doesn't have.
+    fn run_example(
+        interp: &mut Interpreter,
+        program: &str,
+    ) -> Result<Vec<Canon>, InterpreterError> {
+        let program = program
+            .lines()
+            .map(|line| line.replace("g64", "").replace("G64", ""))
+            .filter(|line| line.trim() != "%")
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        run(interp, &program)
+    }
+
+    /// Moves as `'R'` for rapid or `'F'` for feed followed by the rounded X and Z end point.
+    fn moves(canon: &[Canon]) -> Vec<(char, Number, Number)> {
+        let round = |value: Number| M::round(value * 10000.0) / 10000.0;
+
+        canon
+            .iter()
+            .filter_map(|canon| match canon {
+                Canon::StraightTraverse { end } => Some(('R', end)),
+                Canon::StraightFeed { end } => Some(('F', end)),
+                _ => None,
+            })
+            .map(|(kind, end)| (kind, round(end[0]), round(end[2])))
+            .collect()
+    }
+
+    fn lathe() -> Interpreter {
+        let mut interp = Interpreter::new();
+        interp.set_lathe(true);
+
+        interp
+    }
+
+    #[test]
+    fn diameter_mode() {
+        let mut interp = lathe();
+
+        assert_eq!(interp.modal_groups().plane(), Plane::XZ);
+
+        run(&mut interp, "G7 G0 X10 Z1").unwrap();
+        assert_eq!(interp.position()[0], 5.0);
+        assert_eq!(interp.named_parameter("_lathe_diameter_mode"), Some(1.0));
+
+        run(&mut interp, "G91 X2\nG90 G8 X10").unwrap();
+        assert_eq!(interp.position()[0], 10.0);
+        assert_eq!(interp.modal_groups().diameter_mode(), DiameterMode::Radius);
+    }
+
+    #[test]
+    fn constant_surface_speed() {
+        let mut interp = lathe();
+
+        let canon = run(&mut interp, "G21\nG96 S100 D2000 M3\nS120").unwrap();
+
+        assert_eq!(
+            canon[1..],
+            [
+                Canon::ConstantSurfaceSpeed {
+                    surface_speed: 100_000.0,
+                    max_rpm: Some(2000.0),
+                    center: 0.0,
+                },
+                Canon::Spindle(common::Spindle::Clockwise),
+                Canon::ConstantSurfaceSpeed {
+                    surface_speed: 120_000.0,
+                    max_rpm: Some(2000.0),
+                    center: 0.0,
+                },
+            ]
+        );
+        assert_eq!(interp.named_parameter("_spindle_css_mode"), Some(1.0));
+
+        assert_eq!(
+            run(&mut interp, "G97").unwrap(),
+            [Canon::SetSpindleSpeed(120.0)]
+        );
+        assert_eq!(
+            run(&mut interp, "G96 S100 D-1"),
+            Err(InterpreterError::NegativeValue('D'))
+        );
+
+        assert_eq!(M::round(spindle_rpm(100_000.0, 10.0, None)), 1592.0);
+        assert_eq!(spindle_rpm(100_000.0, -10.0, Some(1000.0)), 1000.0);
+    }
+
+    #[test]
+    fn threading() {
+        let mut interp = lathe();
+
+        let canon = run(&mut interp, "G0 X1 Z0\nG76 P0.1 Z-1 I-0.1 J0.05 K0.1").unwrap();
+
+        assert_eq!(
+            moves(&canon),
+            [
+                ('R', 1.0, 0.0),
+                // Two passes, 0.15 and 0.2 from the drive line
+                ('R', 1.05, 0.0),
+                ('R', 0.85, 0.0),
+                ('F', 0.85, -1.0),
+                ('R', 1.05, -1.0),
+                ('R', 1.0, 0.0),
+                ('R', 0.8, 0.0),
+                ('F', 0.8, -1.0),
+                ('R', 1.0, -1.0),
+                ('R', 1.0, 0.0),
+            ]
+        );
+        assert_eq!(
+            canon
+                .iter()
+                .filter(|canon| **canon == Canon::StartSpeedFeedSync(0.1))
+                .count(),
+            2
+        );
+
+        // A mill bores instead
+        let mut interp = Interpreter::new();
+
+        let canon = run(&mut interp, "S100 M3\nG76 X0 R1 Z-1 F10").unwrap();
+
+        assert!(canon.contains(&Canon::OrientSpindle(0.0)));
+    }
+
+    #[test]
+    fn threading_example() {
+        let mut interp = lathe();
+        interp.set_tool_table(tool_file::parse("T4 P4 X0.01 Z0.02\n").unwrap());
+
+        let example = include_str!("../../test_files/linuxcnc/lathe-g76.ngc");
+        let start = example.find("(thread)").unwrap();
+        let end = example.find("(part)").unwrap();
+
+        let canon = run_example(&mut interp, &example[start..end]).unwrap();
+        let moves = moves(&canon);
+
+        // 31 passes getting deeper, then the full depth pass and three spring passes
+        assert_eq!(
+            canon
+                .iter()
+                .filter(|canon| **canon == Canon::StopSpeedFeedSync)
+                .count(),
+            35
+        );
+        assert!(canon.contains(&Canon::Dwell(1.0)));
+
+        // Tapered out over the last 0.05, at the bottom of the thread for the final passes
+        let offset = (0.01, 0.02);
+        let stepped_over = 0.045 * M::tan(29.5f32.to_radians());
+        let bottom = M::round((0.2 - 0.12 + offset.0) * 10000.0) / 10000.0;
+        let exit = M::round((-0.5 - stepped_over + 0.05 + offset.1) * 10000.0) / 10000.0;
+
+        assert!(moves.contains(&('F', bottom, exit)));
+        assert_eq!(interp.position()[0], 0.5);
+        assert_eq!(interp.position()[2], 0.0);
+    }
+
+    #[test]
+    fn threading_errors() {
+        let mut interp = lathe();
+
+        let mut error = |program: &str| run(&mut interp, program).unwrap_err();
+
+        assert_eq!(
+            error("G76 P0.1 Z-1 I-0.1 J0.05"),
+            InterpreterError::MissingWord('K')
+        );
+        assert_eq!(
+            error("G76 P0.1 Z-1 I-0.1 J0 K0.1"),
+            LatheError::NotPositive('J').into()
+        );
+        assert_eq!(
+            error("G76 P0.1 Z-1 I-0.1 J0.05 K0.1 R0.5"),
+            LatheError::BadDegression.into()
+        );
+        assert_eq!(
+            error("G76 P0.1 Z-1 I-0.1 J0.05 K0.1 L4"),
+            LatheError::BadTaperEnds.into()
+        );
+        assert_eq!(
+            error("G17 G76 P0.1 Z-1 I-0.1 J0.05 K0.1"),
+            LatheError::ThreadingPlane.into()
+        );
+    }
+
+    #[test]
+    fn nose_radius_compensation() {
+        let mut interp = lathe();
+        interp.set_tool_table(tool_file::parse("T1 D0.2 Q3\n").unwrap());
+
+        // Turning towards the chuck with the tool outside the part
+        let canon = run(
+            &mut interp,
+            "T1 M6\nG0 X0.5 Z2\nG41 G1 Z1 F10\nZ-1\nG40 G0 X1",
+        )
+        .unwrap();
+
+        // The tip is 0.1 below and to the left of the nose centre
+        assert!(moves(&canon).contains(&('F', 0.5, -1.1)));
+        assert_eq!(interp.modal_groups().cutter_orientation(), 0);
+
+        let canon = run(
+            &mut interp,
+            "G0 X0.5 Z2\nG41.1 D0.2 L4 G1 Z1\nZ-1\nG40 G0 X1",
+        )
+        .unwrap();
+
+        // Below and to the right instead
+        assert!(moves(&canon).contains(&('F', 0.5, -0.9)));
+        assert_eq!(
+            run(&mut interp, "G41.1 D0.2 L10"),
+            Err(CutterCompError::BadOrientation.into())
+        );
+    }
+
+    #[test]
+    fn linuxcnc_examples() {
+        let mut interp = lathe();
+        interp.set_tool_table(
+            tool_file::parse("T2 P2 D0.03 Q2\nT7 P7 D0.03 Q3\nT9 P9 X0.1 Z0.2\n").unwrap(),
+        );
+
+        run_example(
+            &mut interp,
+            include_str!("../../test_files/linuxcnc/lathecomp.ngc"),
+        )
+        .unwrap();
+
+        let mut interp = lathe();
+
+        let canon = run_example(
+            &mut interp,
+            include_str!("../../test_files/linuxcnc/lathe_pawn.ngc"),
+        )
+        .unwrap();
+
+        assert_eq!(moves(&canon).last(), Some(&('R', 15.0, 10.0)));
+    }
+}
@@ -1,54 +1,1317 @@
 #![no_std]
 
 extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate std;
 
-use alloc::collections::VecDeque;
-use common::{Command, Motion};
+pub mod arc;
+pub mod axes;
+mod block;
+pub mod canned_cycle;
+pub mod canon;
+mod checkpoint;
+pub mod control_flow;
+pub mod cutter_comp;
+mod error;
+pub mod expression;
+pub mod feed;
+#[cfg(feature = "std")]
+mod file;
+pub mod io;
+pub mod lathe;
+pub mod mdi;
+pub mod overrides;
+pub mod parameters;
+mod predefined;
+pub mod probe;
+pub mod queue;
+pub mod remap;
+pub mod restart;
+pub mod snapshot;
+pub mod spindle_sync;
+pub mod state_change;
+mod stop;
+pub mod sync;
+mod system_parameters;
+#[cfg(test)]
+mod test_utils;
+pub mod tools;
+
+use crate::arc::ArcWords;
+use crate::axes::MachineAxes;
+use crate::block::{BlockCommands, NonModal};
+use crate::canned_cycle::{CannedCycleError, CycleWords};
+use crate::canon::Canon;
+use crate::checkpoint::Checkpoint;
+use crate::control_flow::ControlFlow;
+use crate::cutter_comp::CutterComp;
+use crate::expression::{evaluate, ExpressionError, ExpressionErrorKind, Target};
+use crate::io::{IoBackend, Output};
+use crate::mdi::ProgramState;
+use crate::overrides::OverrideSwitches;
+use crate::parameters::{
+    DefaultParameters, ParameterError, ParameterStore, COORDINATE_SYSTEM, READ_ONLY_NUMBERED,
+};
+use crate::probe::Probe;
+use crate::queue::{CommandQueue, Drain, QueueError, DEFAULT_CAPACITY, DEFAULT_OUTPUT_CAPACITY};
+use crate::remap::{Code, Remap, RemapCall, Step};
+use crate::snapshot::Snapshot;
+use crate::state_change::{Change, StateChanges};
+use crate::sync::{SyncPoint, Waiting};
+use crate::tools::{ToolChanger, ToolTable};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec;
+use alloc::vec::Vec;
+use common::{
+    Axis, Block, Command, Coolant, CoordinateSystem, CutterCompensation, DiameterMode,
+    DistanceMode, FeedRateMode, Motion, Number, Plane, Position, RetractMode, Span, Spindle,
+    SpindleSpeedMode, Units, Word,
+};
+pub use error::{ErrorLocation, InterpreterError};
 
-pub struct Interpreter {
-    queue: VecDeque<Command>,
+/// An RS274NGC interpreter storing parameters in `P`, with room to queue `N` commands.
+pub struct Interpreter<P = DefaultParameters, const N: usize = DEFAULT_CAPACITY> {
+    queue: CommandQueue<N>,
     modal_groups: ModalGroupState,
+    position: Position,
+    feed_rate: Number,
+    output: VecDeque<Canon>,
+
+    /// Number of canonical commands waiting to be read before blocks stop being run.
+    output_capacity: usize,
+    parameters: P,
+    control_flow: ControlFlow,
+    program_state: ProgramState,
+    tool_table: ToolTable,
+    tool_changer: Option<Box<dyn ToolChanger + Send>>,
+    probe: Option<Box<dyn Probe + Send>>,
+    io: Option<Box<dyn IoBackend + Send>>,
+
+    /// `M62`, `M63` and `M67` outputs waiting for the next move.
+    synced_outputs: Vec<Output>,
+    remaps: BTreeMap<Code, Remap<P, N>>,
+
+    /// Number of remap callbacks currently running.
+    remap_callbacks: usize,
+
+    /// Set while cutter radius compensation is on.
+    cutter_comp: Option<CutterComp>,
+
+    /// Configured for a lathe rather than a mill.
+    lathe: bool,
+
+    /// The axes the machine has.
+    axes: MachineAxes,
+
+    /// The optional stop switch, which makes `M1` pause the program.
+    optional_stop: bool,
+
+    /// Blocks are being run only for their effect on interpreter state, so the machine mustn't
+    /// be asked to do anything.
+    dry_run: bool,
+
+    /// MDI blocks waiting for a paused program to end.
+    mdi_queue: Vec<Block>,
+
+    /// Set while state changes are being recorded.
+    state_changes: Option<StateChanges>,
+
+    /// Stop at sync points for feedback from the machine.
+    sync_points: bool,
+
+    /// A sync point reached by the block being executed.
+    pending_sync: Option<SyncPoint>,
+
+    /// The sync point the interpreter is stopped at.
+    waiting: Option<Waiting>,
+
+    /// Program position when the machine last gave feedback.
+    synced_position: Option<Position>,
+
+    /// Taken when the program was last paused or aborted.
+    last_snapshot: Option<Snapshot>,
+    #[cfg(feature = "std")]
+    var_file: Option<std::path::PathBuf>,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        Self::with_parameters(DefaultParameters::default())
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Create an interpreter using the given parameter storage.
+    pub fn with_parameters(parameters: P) -> Self {
         Self {
-            queue: VecDeque::new(),
+            queue: CommandQueue::new(),
             modal_groups: ModalGroupState::default(),
+            position: Position::zeros(),
+            feed_rate: 0.0,
+            output: VecDeque::with_capacity(DEFAULT_OUTPUT_CAPACITY),
+            output_capacity: DEFAULT_OUTPUT_CAPACITY,
+            parameters,
+            control_flow: ControlFlow::default(),
+            program_state: ProgramState::Idle,
+            tool_table: ToolTable::new(),
+            tool_changer: None,
+            probe: None,
+            io: None,
+            synced_outputs: Vec::new(),
+            remaps: BTreeMap::new(),
+            remap_callbacks: 0,
+            cutter_comp: None,
+            lathe: false,
+            axes: MachineAxes::default(),
+            optional_stop: true,
+            dry_run: false,
+            mdi_queue: Vec::new(),
+            state_changes: None,
+            sync_points: false,
+            pending_sync: None,
+            waiting: None,
+            synced_position: None,
+            last_snapshot: None,
+            #[cfg(feature = "std")]
+            var_file: None,
+        }
+    }
+
+    /// Set the LinuxCNC `.var` file persistent parameters are loaded from at program start and
+    /// saved to at program end.
+    #[cfg(feature = "std")]
+    pub fn set_var_file(&mut self, path: impl Into<std::path::PathBuf>) {
+        self.var_file = Some(path.into());
+    }
+
+    /// Prepare to run a program, loading persistent parameters from the `.var` file if one is set.
+    ///
+    /// Modal state carries over from the previous program and any MDI commands.
+    pub fn start_program(&mut self) -> Result<(), InterpreterError> {
+        #[cfg(feature = "std")]
+        if let Some(path) = &self.var_file {
+            parameters::var_file::load_file(path, &mut self.parameters)?;
+        }
+
+        self.control_flow.clear_main_program();
+        self.program_state = ProgramState::Running;
+
+        Ok(())
+    }
+
+    /// Finish running a program, saving persistent parameters to the `.var` file if one is set,
+    /// then run any MDI commands queued while it was paused.
+    pub fn end_program(&mut self) -> Result<(), InterpreterError> {
+        self.program_state = ProgramState::Idle;
+
+        if let Some(comp) = &mut self.cutter_comp {
+            if !self.dry_run {
+                comp.finish(&mut self.output);
+            }
+        }
+
+        self.save_parameters()?;
+
+        self.run_queued_mdi()
+    }
+
+    /// Save persistent parameters to the `.var` file now, if one is set.
+    pub fn save_parameters(&self) -> Result<(), InterpreterError> {
+        #[cfg(feature = "std")]
+        if let Some(path) = &self.var_file {
+            parameters::var_file::save_file(path, &self.parameters)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add a command to the back of the queue, failing with [`QueueError::Full`] if there are
+    /// already `N` commands waiting.
+    pub fn queue_command(&mut self, command: Command) -> Result<(), QueueError> {
+        self.queue.push(command)
+    }
+
+    // Pop command at beginning of queue and update interpreter state. Nothing is popped while
+    // stopped at a sync point or paused, and nothing is popped with `InterpreterError::OutputFull`
+    // while the output is full. With an empty queue, blocks already received but held back by a
+    // full output, e.g. the rest of a loop, are run.
+    pub fn pop_command(&mut self) -> Result<(), InterpreterError> {
+        if self.waiting.is_some() || self.program_state == ProgramState::Paused {
+            return Ok(());
+        }
+
+        if self.output_full() {
+            return Err(InterpreterError::OutputFull);
+        }
+
+        match self.queue.pop() {
+            Some(command) => self.execute_command(command),
+            None => self.run(),
+        }
+    }
+
+    /// Look at the `n`th queued command without taking it, e.g. to look ahead at upcoming moves.
+    /// `peek_command(0)` is the command [`pop_command`](Self::pop_command) runs next.
+    pub fn peek_command(&self, n: usize) -> Option<&Command> {
+        self.queue.peek(n)
+    }
+
+    /// Number of commands waiting in the queue.
+    pub fn queued_commands(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Take every command out of the queue without running them, e.g. to abort a program.
+    pub fn drain_commands(&mut self) -> Drain<'_, N> {
+        self.queue.drain()
+    }
+
+    // Blocks are appended to the main program and executed along with any loop iterations or
+    // subroutine calls they complete. Errors raised by a block are `InterpreterError::Located`
+    // with the block and call stack.
+    pub(crate) fn execute_command(&mut self, command: Command) -> Result<(), InterpreterError> {
+        match command {
+            Command::Position { axis, value } => {
+                self.control_flow
+                    .push_block(Block::new(vec![Word::new(axis.letter(), value)]));
+                self.run()
+            }
+            Command::Motion(motion) => {
+                self.modal_groups.motion = Some(motion);
+
+                Ok(())
+            }
+            Command::Block(block) => {
+                self.control_flow.push_block(block);
+                self.run()
+            }
+        }
+    }
+
+    /// Output a canonical command, after any move held back by cutter compensation.
+    pub(crate) fn emit(&mut self, canon: Canon) {
+        if matches!(
+            canon,
+            Canon::StraightTraverse { .. }
+                | Canon::StraightFeed { .. }
+                | Canon::ArcFeed(_)
+                | Canon::StraightProbe { .. }
+        ) {
+            self.start_segment();
+        }
+
+        if self.dry_run {
+            return;
+        }
+
+        match &mut self.cutter_comp {
+            Some(comp) if comp.holding() => comp.hold(canon),
+            _ => self.output.push_back(canon),
+        }
+    }
+
+    /// Take the next canonical command produced by the interpreter.
+    pub fn next_canon(&mut self) -> Option<Canon> {
+        self.output.pop_front()
+    }
+
+    /// Set how many canonical commands can wait to be read before blocks stop being run.
+    pub fn set_output_capacity(&mut self, capacity: usize) {
+        self.output_capacity = capacity;
+        self.output
+            .reserve(capacity.saturating_sub(self.output.len()));
+    }
+
+    /// Whether the output has reached its capacity, so no more blocks are run until some of it
+    /// has been read.
+    pub fn output_full(&self) -> bool {
+        !self.dry_run && self.output.len() >= self.output_capacity
+    }
+
+    pub fn modal_groups(&self) -> &ModalGroupState {
+        &self.modal_groups
+    }
+
+    /// Current (programmed) position.
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    pub fn feed_rate(&self) -> Number {
+        self.feed_rate
+    }
+
+    /// Parameter storage, not including read-only system parameters.
+    pub fn parameters(&self) -> &P {
+        &self.parameters
+    }
+
+    /// Get a numbered parameter, e.g. `#5220`, including read-only system parameters.
+    pub fn numbered_parameter(&self, index: usize) -> Result<f64, ParameterError> {
+        match self.system_numbered(index) {
+            Some(value) => Ok(value),
+            None => self.parameters.numbered(index),
+        }
+    }
+
+    /// Set a numbered parameter as a program would. Read-only parameters cannot be set.
+    pub fn set_numbered_parameter(
+        &mut self,
+        index: usize,
+        value: f64,
+    ) -> Result<(), ParameterError> {
+        if READ_ONLY_NUMBERED.contains(&index) {
+            return Err(ParameterError::ReadOnly);
+        }
+
+        self.parameters.set_numbered(index, value)
+    }
+
+    /// Get a named parameter. Names starting with `_` are global, e.g. `_x` for `#<_x>`, otherwise
+    /// they're looked up in the current local scope.
+    ///
+    /// Returns `None` if the parameter has not been set.
+    pub fn named_parameter(&self, name: &str) -> Option<f64> {
+        if name.starts_with('_') {
+            self.system_named(name)
+                .or_else(|| self.parameters.global(name))
+        } else {
+            self.parameters.local(name)
+        }
+    }
+
+    /// Set a named parameter as a program would. Read-only system parameters cannot be set.
+    pub fn set_named_parameter(&mut self, name: &str, value: f64) -> Result<(), ParameterError> {
+        if name.starts_with('_') {
+            if self.system_named(name).is_some() {
+                return Err(ParameterError::ReadOnly);
+            }
+
+            self.parameters.set_global(name, value)
+        } else {
+            self.parameters.set_local(name, value)
+        }
+    }
+
+    /// Execute a block. If it fails, the state it changed is put back as it was before.
+    pub(crate) fn execute_block(&mut self, block: &Block) -> Result<(), InterpreterError> {
+        let mut checkpoint = self.checkpoint();
+
+        let result = self.apply_block(block, &mut checkpoint);
+
+        if result.is_err() {
+            self.roll_back(checkpoint);
+        }
+
+        result
+    }
+
+    fn apply_block(
+        &mut self,
+        block: &Block,
+        checkpoint: &mut Checkpoint,
+    ) -> Result<(), InterpreterError> {
+        // Every value in a block is read before any of its assignments take effect, so
+        // `#1 = #2 #2 = #1` swaps the two parameters.
+        let mut words = block
+            .words
+            .iter()
+            .map(|word| Ok((word.letter, evaluate(&word.value, self)? as Number)))
+            .collect::<Result<Vec<_>, ExpressionError>>()?;
+
+        let assignments = block
+            .assignments
+            .iter()
+            .map(|assignment| {
+                Ok((
+                    Target::new(&assignment.parameter, self)?,
+                    evaluate(&assignment.value, self)?,
+                    assignment.span,
+                ))
+            })
+            .collect::<Result<Vec<_>, ExpressionError>>()?;
+
+        for (target, value, span) in assignments {
+            let change = match target {
+                Target::Numbered(index) => {
+                    self.save_numbered(checkpoint, index);
+
+                    self.set_numbered_parameter(index, value)
+                        .map(|_| Change::NumberedParameter { index, value })
+                }
+                Target::Named(name) => {
+                    self.save_named(checkpoint, name);
+
+                    self.set_named_parameter(name, value)
+                        .map(|_| Change::NamedParameter {
+                            name: name.into(),
+                            value,
+                        })
+                }
+            }
+            .map_err(|e| ExpressionError {
+                kind: ExpressionErrorKind::Parameter(e),
+                span,
+            })?;
+
+            self.record_parameter(change, span);
+        }
+
+        let remaps = self.find_remaps(&mut words, block.span)?;
+
+        self.execute_words(&words, remaps, block.span)
+    }
+
+    /// Execute the evaluated words of a block, running remaps at their place among the built in
+    /// commands.
+    pub(crate) fn execute_words(
+        &mut self,
+        words: &[(char, Number)],
+        mut remaps: Vec<RemapCall>,
+        span: Span,
+    ) -> Result<(), InterpreterError> {
+        let mut commands = BlockCommands::from_words(words)?;
+
+        self.check_axis_words(words)?;
+
+        self.run_remaps(Step::FeedRateMode, &mut remaps)?;
+
+        if let Some(diameter_mode) = commands.diameter_mode {
+            self.modal_groups.diameter_mode = diameter_mode;
+        }
+
+        self.lathe_commands(&mut commands);
+
+        if let Some(mode) = commands.feed_rate_mode {
+            self.feed_rate_mode(mode);
+        }
+
+        self.run_remaps(Step::FeedRate, &mut remaps)?;
+
+        if let Some(feed_rate) = commands.value('F') {
+            self.feed_rate = feed_rate;
+
+            // Other modes set the rate for each move
+            if self.modal_groups.feed_rate_mode == FeedRateMode::UnitsPerMinute {
+                self.emit(Canon::SetFeedRate(feed_rate));
+            }
+        }
+
+        self.run_remaps(Step::SpindleSpeed, &mut remaps)?;
+
+        if let Some(mode) = commands.spindle_speed_mode {
+            self.spindle_speed_mode(mode, &commands)?;
+        }
+
+        if let Some(speed) = commands.value('S') {
+            if speed < 0.0 {
+                return Err(InterpreterError::NegativeValue('S'));
+            }
+
+            self.modal_groups.spindle_speed = speed;
+        }
+
+        if commands.spindle_speed_mode.is_some() || commands.value('S').is_some() {
+            let canon = self.spindle_speed_canon();
+
+            self.emit(canon);
+        }
+
+        self.run_remaps(Step::Tool, &mut remaps)?;
+
+        if let Some(tool) = commands.value('T') {
+            self.select_tool(tool)?;
+        }
+
+        self.run_remaps(Step::ToolChange, &mut remaps)?;
+
+        if let Some(command) = commands.tool_change {
+            self.tool_change(command, &commands)?;
+        }
+
+        self.run_remaps(Step::Spindle, &mut remaps)?;
+
+        if let Some(spindle) = commands.spindle {
+            self.modal_groups.spindle = spindle;
+            self.emit(Canon::Spindle(spindle));
+        }
+
+        self.run_remaps(Step::Coolant, &mut remaps)?;
+
+        if let Some(coolant) = commands.coolant {
+            match coolant {
+                Coolant::Mist => self.modal_groups.mist = true,
+                Coolant::Flood => self.modal_groups.flood = true,
+                Coolant::Off => {
+                    self.modal_groups.mist = false;
+                    self.modal_groups.flood = false;
+                }
+            }
+
+            self.emit(Canon::Coolant(coolant));
+        }
+
+        self.run_remaps(Step::Overrides, &mut remaps)?;
+
+        if let Some(command) = commands.overrides {
+            self.override_command(command, commands.value('P'));
+        }
+
+        self.run_remaps(Step::Io, &mut remaps)?;
+
+        if let Some(command) = commands.io {
+            self.io_command(command, &commands, span)?;
+        }
+
+        self.run_remaps(Step::Dwell, &mut remaps)?;
+
+        if let Some(NonModal::Dwell) = commands.non_modal {
+            let seconds = commands
+                .value('P')
+                .ok_or(InterpreterError::MissingWord('P'))?;
+
+            if seconds < 0.0 {
+                return Err(InterpreterError::NegativeValue('P'));
+            }
+
+            self.emit(Canon::Dwell(seconds));
+        }
+
+        self.run_remaps(Step::Plane, &mut remaps)?;
+
+        if let Some(plane) = commands.plane {
+            if self.cutter_comp.is_some() && plane != self.modal_groups.plane {
+                return Err(cutter_comp::CutterCompError::PlaneChange.into());
+            }
+
+            self.modal_groups.plane = plane;
+        }
+
+        self.run_remaps(Step::Units, &mut remaps)?;
+
+        if let Some(units) = commands.units {
+            self.modal_groups.units = units;
+            self.emit(Canon::SetUnits(units));
+        }
+
+        self.run_remaps(Step::CutterComp, &mut remaps)?;
+
+        if let Some(mode) = commands.cutter_compensation {
+            self.cutter_compensation(mode, &commands)?;
+        }
+
+        self.run_remaps(Step::DistanceMode, &mut remaps)?;
+
+        if let Some(distance_mode) = commands.distance_mode {
+            self.modal_groups.distance_mode = distance_mode;
+        }
+
+        if let Some(arc_distance_mode) = commands.arc_distance_mode {
+            self.modal_groups.arc_distance_mode = arc_distance_mode;
+        }
+
+        self.run_remaps(Step::ToolLengthOffset, &mut remaps)?;
+
+        if let Some(mode) = commands.tool_length_offset {
+            self.tool_length_offset(mode, &commands)?;
+        }
+
+        self.run_remaps(Step::CoordinateSystem, &mut remaps)?;
+
+        if let Some(coordinate_system) = commands.coordinate_system {
+            self.select_coordinate_system(coordinate_system, span)?;
+        }
+
+        self.run_remaps(Step::RetractMode, &mut remaps)?;
+
+        if let Some(retract_mode) = commands.retract_mode {
+            self.modal_groups.retract_mode = retract_mode;
+        }
+
+        self.run_remaps(Step::PredefinedPosition, &mut remaps)?;
+
+        match commands.non_modal {
+            Some(NonModal::GoToPredefined(predefined)) => {
+                self.go_to_predefined(predefined, &commands)?
+            }
+            Some(NonModal::SetPredefined(predefined)) => self.set_predefined(predefined, span)?,
+            _ => {}
+        }
+
+        self.run_remaps(Step::Motion, &mut remaps)?;
+
+        if let Some(motion) = commands.motion {
+            // The bottom of the hole is only kept while the same canned cycle stays active
+            if motion != self.modal_groups.motion {
+                self.modal_groups.cycle_words.bottom = None;
+            }
+
+            self.modal_groups.motion = motion;
+        }
+
+        self.motion(&commands, span)?;
+
+        self.run_remaps(Step::Stop, &mut remaps)?;
+
+        if let Some(stop) = commands.stop {
+            self.stop(stop, span)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn select_coordinate_system(
+        &mut self,
+        coordinate_system: CoordinateSystem,
+        span: Span,
+    ) -> Result<(), InterpreterError> {
+        let old_offset = self.work_offset();
+
+        self.modal_groups.coordinate_system = coordinate_system;
+        self.parameters
+            .set_numbered(COORDINATE_SYSTEM, coordinate_system.number().into())
+            .map_err(|e| ExpressionError {
+                kind: ExpressionErrorKind::Parameter(e),
+                span,
+            })?;
+        self.emit(Canon::SelectCoordinateSystem(coordinate_system));
+
+        // The machine hasn't moved, but its position in the new coordinate system is different
+        self.position += old_offset - self.work_offset();
+
+        Ok(())
+    }
+
+    fn motion(&mut self, commands: &BlockCommands, span: Span) -> Result<(), InterpreterError> {
+        if commands.axis_words_used() {
+            return Ok(());
+        }
+
+        let has_axes = Axis::ALL
+            .iter()
+            .any(|axis| commands.value(axis.letter()).is_some());
+
+        if commands.motion == Some(None) && has_axes {
+            return Err(CannedCycleError::AxesWithG80.into());
         }
+
+        let end = self.end_position(commands);
+
+        let offset = self.program_offset();
+
+        let canon = match self.modal_groups.motion {
+            Some(Motion::CannedCycle(cycle)) => return self.canned_cycle(cycle, commands),
+            Some(Motion::Probe(kind)) => return self.straight_probe(kind, end, commands, span),
+            Some(Motion::SpindleSync) if has_axes => {
+                return self.spindle_synced_feed(end, commands)
+            }
+            Some(Motion::RigidTap) if has_axes => return self.rigid_tap(end, commands),
+            Some(Motion::Rapid) if has_axes => Canon::StraightTraverse { end: end + offset },
+            Some(Motion::Feed) if has_axes => Canon::StraightFeed { end: end + offset },
+            Some(Motion::Arc(direction))
+                if has_axes
+                    || ['I', 'J', 'K', 'R']
+                        .iter()
+                        .any(|l| commands.value(*l).is_some()) =>
+            {
+                let (first, second, _) = self.modal_groups.plane.axes();
+
+                let words = ArcWords {
+                    offsets: [
+                        commands.value('I'),
+                        commands.value('J'),
+                        commands.value('K'),
+                    ],
+                    radius: commands.value('R'),
+                    turns: commands.value('P'),
+                    end_in_plane: commands.value(first.letter()).is_some()
+                        || commands.value(second.letter()).is_some(),
+                };
+
+                let mut arc = arc::arc_feed(
+                    &self.position,
+                    &end,
+                    direction,
+                    self.modal_groups.plane,
+                    &words,
+                    self.modal_groups.arc_distance_mode,
+                    self.modal_groups.units,
+                )?;
+
+                arc.end += offset;
+                arc.center.0 += offset[first.index()];
+                arc.center.1 += offset[second.index()];
+
+                Canon::ArcFeed(arc)
+            }
+            _ => return Ok(()),
+        };
+
+        self.feed_for_move(&(self.position + offset), &canon, commands)?;
+        self.start_segment();
+
+        let start = self.position + offset;
+
+        // Nothing is output during a dry run, though compensation still follows the path
+        let mut discard = VecDeque::new();
+        let output = if self.dry_run {
+            &mut discard
+        } else {
+            &mut self.output
+        };
+
+        match &mut self.cutter_comp {
+            Some(comp) => comp.motion(&start, canon, output)?,
+            None => output.push_back(canon),
+        }
+
+        self.position = end;
+
+        Ok(())
     }
 
-    pub fn queue_command(&mut self, command: Command) {
-        self.queue.push_front(command);
+    /// Offset of the active work coordinate system from machine zero, from parameters `5221` to
+    /// `5229` for `G54`, `5241` to `5249` for `G55` and so on.
+    pub(crate) fn work_offset(&self) -> Position {
+        let first = COORDINATE_SYSTEM
+            + 1
+            + 20 * (self.modal_groups.coordinate_system.number() as usize - 1);
+
+        Position::from_fn(|axis, _| self.parameters.numbered(first + axis).unwrap_or(0.0) as Number)
+    }
+
+    /// Offset from program to machine coordinates.
+    pub(crate) fn program_offset(&self) -> Position {
+        self.work_offset() + self.modal_groups.tool_length_offset
     }
 
-    // Pop command at beginning of queue and update interpreter state
-    pub fn pop_command(&mut self) {
-        if let Some(command) = self.queue.pop_back() {
-            match command {
-                Command::Position { axis, value } => todo!(),
-                Command::Motion(motion) => self.modal_groups.motion = Some(motion),
+    /// Absolute end position of a move given the axis words in the block.
+    fn end_position(&self, commands: &BlockCommands) -> Position {
+        let mut end = self.position;
+
+        for axis in Axis::ALL.iter() {
+            if let Some(value) = commands.value(axis.letter()) {
+                end[axis.index()] = match self.modal_groups.distance_mode {
+                    DistanceMode::Absolute => {
+                        self.axes
+                            .absolute_target(*axis, self.position[axis.index()], value)
+                    }
+                    DistanceMode::Incremental => self.position[axis.index()] + value,
+                };
             }
         }
+
+        end
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModalGroupState {
     motion: Option<Motion>,
+    plane: Plane,
+    distance_mode: DistanceMode,
+    arc_distance_mode: DistanceMode,
+    feed_rate_mode: FeedRateMode,
+    units: Units,
+    coordinate_system: CoordinateSystem,
+    cutter_compensation: CutterCompensation,
+    cutter_radius: Number,
+    cutter_orientation: u8,
+    retract_mode: RetractMode,
+    cycle_words: CycleWords,
+    spindle: Spindle,
+    spindle_speed: Number,
+    spindle_speed_mode: SpindleSpeedMode,
+    diameter_mode: DiameterMode,
+    mist: bool,
+    flood: bool,
+    tool: u32,
+    selected_tool: u32,
+    tool_length_offset: Position,
+    overrides: OverrideSwitches,
+}
+
+impl ModalGroupState {
+    pub fn motion(&self) -> Option<Motion> {
+        self.motion
+    }
+
+    pub fn plane(&self) -> Plane {
+        self.plane
+    }
+
+    pub fn distance_mode(&self) -> DistanceMode {
+        self.distance_mode
+    }
+
+    /// `G90.1`/`G91.1` arc centre distance mode.
+    pub fn arc_distance_mode(&self) -> DistanceMode {
+        self.arc_distance_mode
+    }
+
+    /// `G93`, `G94` or `G95`.
+    pub fn feed_rate_mode(&self) -> FeedRateMode {
+        self.feed_rate_mode
+    }
+
+    pub fn units(&self) -> Units {
+        self.units
+    }
+
+    pub fn coordinate_system(&self) -> CoordinateSystem {
+        self.coordinate_system
+    }
+
+    pub fn cutter_compensation(&self) -> CutterCompensation {
+        self.cutter_compensation
+    }
+
+    /// Tool radius used for cutter compensation, or zero when it's off.
+    pub fn cutter_radius(&self) -> Number {
+        self.cutter_radius
+    }
+
+    /// Orientation of the lathe tool used for cutter compensation, or zero when it's off.
+    pub fn cutter_orientation(&self) -> u8 {
+        self.cutter_orientation
+    }
+
+    /// `G98`/`G99` canned cycle retract mode.
+    pub fn retract_mode(&self) -> RetractMode {
+        self.retract_mode
+    }
+
+    /// Canned cycle words kept for later blocks.
+    pub fn cycle_words(&self) -> &CycleWords {
+        &self.cycle_words
+    }
+
+    pub fn spindle(&self) -> Spindle {
+        self.spindle
+    }
+
+    /// Last programmed `S` word, a surface speed with `G96`.
+    pub fn spindle_speed(&self) -> Number {
+        self.spindle_speed
+    }
+
+    /// `G96`/`G97` spindle speed mode.
+    pub fn spindle_speed_mode(&self) -> SpindleSpeedMode {
+        self.spindle_speed_mode
+    }
+
+    /// `G7`/`G8` lathe diameter mode.
+    pub fn diameter_mode(&self) -> DiameterMode {
+        self.diameter_mode
+    }
+
+    /// `M7` mist coolant.
+    pub fn mist(&self) -> bool {
+        self.mist
+    }
+
+    /// `M8` flood coolant.
+    pub fn flood(&self) -> bool {
+        self.flood
+    }
+
+    /// Tool in the spindle, or `0` for none.
+    pub fn tool(&self) -> u32 {
+        self.tool
+    }
+
+    /// Tool selected by the last `T` word, loaded by the next `M6`.
+    pub fn selected_tool(&self) -> u32 {
+        self.selected_tool
+    }
+
+    /// Offsets set by `G43`, `G43.1` or `G43.2` for each axis, or zero after `G49`.
+    pub fn tool_length_offset(&self) -> &Position {
+        &self.tool_length_offset
+    }
+
+    /// Overrides switched on or off by `M48` to `M53`.
+    pub fn overrides(&self) -> OverrideSwitches {
+        self.overrides
+    }
 }
 
 impl Default for ModalGroupState {
     fn default() -> Self {
-        Self { motion: None }
+        Self {
+            motion: None,
+            plane: Plane::XY,
+            distance_mode: DistanceMode::Absolute,
+            arc_distance_mode: DistanceMode::Incremental,
+            feed_rate_mode: FeedRateMode::UnitsPerMinute,
+            units: Units::Mm,
+            coordinate_system: CoordinateSystem::G54,
+            cutter_compensation: CutterCompensation::Off,
+            cutter_radius: 0.0,
+            cutter_orientation: 0,
+            retract_mode: RetractMode::OldZ,
+            cycle_words: CycleWords::default(),
+            spindle: Spindle::Stopped,
+            spindle_speed: 0.0,
+            spindle_speed_mode: SpindleSpeedMode::Rpm,
+            diameter_mode: DiameterMode::Radius,
+            mist: false,
+            flood: false,
+            tool: 0,
+            selected_tool: 0,
+            tool_length_offset: Position::zeros(),
+            overrides: OverrideSwitches::default(),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::arc::ArcError;
+    use crate::test_utils::run;
+    use alloc::string::ToString;
+
+    fn block(words: &[(char, Number)]) -> Command {
+        Command::Block(Block::new(
+            words
+                .iter()
+                .map(|(letter, value)| Word::new(*letter, *value))
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn arc_absolute_center() {
+        let mut interp = Interpreter::new();
+
+        let canon = run(&mut interp, "G0 X10 Y0\nG90.1 G3 X0 Y10 I0 J0 F100").unwrap();
+
+        assert_eq!(
+            canon.last(),
+            Some(&Canon::ArcFeed(arc::ArcFeed {
+                plane: Plane::XY,
+                end: Position::from([0.0, 10.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+                center: (0.0, 0.0),
+                rotation: 1,
+            }))
+        );
+        assert_eq!(interp.position()[1], 10.0);
+    }
+
+    #[test]
+    fn incremental_helical_arc() {
+        let mut interp = Interpreter::new();
+
+        let canon = run(&mut interp, "G0 X10\nG91 G2 Z-1 I-10 P2 F100").unwrap();
+
+        assert_eq!(
+            canon.last(),
+            Some(&Canon::ArcFeed(arc::ArcFeed {
+                plane: Plane::XY,
+                end: Position::from([10.0, 0.0, -1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+                center: (0.0, 0.0),
+                rotation: -2,
+            }))
+        );
+    }
+
+    #[test]
+    fn arc_error() {
+        let mut interp = Interpreter::new();
+
+        assert_eq!(
+            run(&mut interp, "G2 X10"),
+            Err(InterpreterError::Arc(ArcError::MissingRadiusAndCenter))
+        );
+    }
+
+    #[test]
+    fn system_parameters() {
+        let mut interp = Interpreter::new();
+
+        run(&mut interp, "G0 X1.5 Z-2").unwrap();
+
+        assert_eq!(interp.numbered_parameter(5420), Ok(1.5));
+        assert_eq!(interp.numbered_parameter(5422), Ok(-2.0));
+        assert_eq!(interp.named_parameter("_x"), Some(1.5));
+        assert_eq!(interp.named_parameter("_Z"), Some(-2.0));
+        assert_eq!(interp.named_parameter("_metric"), Some(1.0));
+        assert_eq!(interp.named_parameter("_motion_mode"), Some(0.0));
+
+        interp.tool_table_mut().insert(tools::Tool::new(3));
+
+        run(&mut interp, "G59.1 T3 M6 S500 M4 M7").unwrap();
+
+        assert_eq!(interp.numbered_parameter(5220), Ok(7.0));
+        assert_eq!(interp.named_parameter("_coord_system"), Some(591.0));
+        assert_eq!(interp.named_parameter("_current_tool"), Some(3.0));
+        assert_eq!(interp.named_parameter("_speed"), Some(500.0));
+        assert_eq!(interp.named_parameter("_spindle_on"), Some(1.0));
+        assert_eq!(interp.named_parameter("_spindle_cw"), Some(0.0));
+        assert_eq!(interp.named_parameter("_mist"), Some(1.0));
+        assert_eq!(interp.named_parameter("_flood"), Some(0.0));
+
+        assert_eq!(
+            interp.set_numbered_parameter(5420, 1.0),
+            Err(ParameterError::ReadOnly)
+        );
+        assert_eq!(
+            interp.set_named_parameter("_x", 1.0),
+            Err(ParameterError::ReadOnly)
+        );
+    }
+
+    #[test]
+    fn user_parameters() {
+        let mut interp = Interpreter::new();
+
+        interp.set_numbered_parameter(100, 1.0).unwrap();
+        interp.set_named_parameter("_global", 2.0).unwrap();
+        interp.set_named_parameter("local", 3.0).unwrap();
+
+        assert_eq!(interp.numbered_parameter(100), Ok(1.0));
+        assert_eq!(interp.named_parameter("_global"), Some(2.0));
+        assert_eq!(interp.named_parameter("local"), Some(3.0));
+        assert_eq!(interp.named_parameter("missing"), None);
+    }
+
+    #[test]
+    fn expressions() {
+        let mut interp = Interpreter::new();
+
+        let canon = run(
+            &mut interp,
+            "#<depth> = -1.5\nG1 X[2 * 3] Z#<depth> F[60 + SIN[30] * 2]\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            canon,
+            [
+                Canon::SetFeedRate(61.0),
+                Canon::StraightFeed {
+                    end: Position::from([6.0, 0.0, -1.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn assignments_read_old_values() {
+        let mut interp = Interpreter::new();
+
+        run(
+            &mut interp,
+            "#1 = 1 #2 = 2\n#1 = #2 #2 = #1 #3 = [#1 + 10]\nG0 X#1\n",
+        )
+        .unwrap();
+
+        assert_eq!(interp.numbered_parameter(1), Ok(2.0));
+        assert_eq!(interp.numbered_parameter(2), Ok(1.0));
+        assert_eq!(interp.numbered_parameter(3), Ok(11.0));
+        assert_eq!(interp.position()[0], 2.0);
+
+        // Words in the same block as an assignment see the old value
+        run(&mut interp, "#1 = 5 G0 X#1\n").unwrap();
+        assert_eq!(interp.position()[0], 2.0);
+
+        run(&mut interp, "#[#3 - 1] = 3\n#<_flag> = [#10 EQ 3.00001]\n").unwrap();
+        assert_eq!(interp.numbered_parameter(10), Ok(3.0));
+        assert_eq!(interp.named_parameter("_flag"), Some(1.0));
+    }
+
+    #[test]
+    fn expression_errors() {
+        let mut interp = Interpreter::new();
 
-    // #[test]
-    // fn it_works() {
-    //     let ass: VecDeque<i32> = VecDeque::new();
-    // }
+        let error = run(&mut interp, "G0 X1\nG1 X[1 / #5] F100\n").unwrap_err();
+
+        assert_eq!(
+            error,
+            InterpreterError::Expression(ExpressionError {
+                kind: ExpressionErrorKind::DivideByZero,
+                span: common::Span {
+                    offset: 10,
+                    len: 8,
+                    line: 2,
+                    column: 5
+                },
+            })
+        );
+        // Nothing in the failed block was executed
+        assert_eq!(interp.position()[0], 1.0);
+
+        assert_eq!(
+            run(&mut interp, "#5420 = 1\n").unwrap_err().to_string(),
+            "Cannot set read-only parameter at line 1, column 1"
+        );
+        assert_eq!(
+            run(&mut interp, "G0 X#<missing>\n")
+                .unwrap_err()
+                .to_string(),
+            "Named parameter #<missing> not defined at line 1, column 5"
+        );
+    }
+
+    #[test]
+    fn error_locations() {
+        let mut interp = Interpreter::new();
+
+        let error = interp.mdi("G0 X1\nG1 G2 X2 F100").unwrap_err();
+        let location = error.location().unwrap();
+
+        assert_eq!(*error.cause(), InterpreterError::ModalGroupConflict(2.0));
+        assert_eq!(location.block.line, 2);
+        assert_eq!(location.word.map(|word| word.column), Some(4));
+        assert!(location.call_stack.is_empty());
+        assert_eq!(
+            error.to_string(),
+            "Two G codes used from same modal group (G2) at line 2, column 4"
+        );
+
+        // The word with the duplicate letter is the second one
+        let error = interp.mdi("G0 X1 X2").unwrap_err();
+
+        assert_eq!(error.location().unwrap().word.unwrap().column, 7);
+
+        let error = interp
+            .mdi("o<deep> sub\n  G1 X[1 / 0] F100\no<deep> endsub\n\no<deep> call")
+            .unwrap_err();
+        let location = error.location().unwrap();
+
+        assert_eq!(location.block.line, 2);
+        assert_eq!(location.word.unwrap().column, 7);
+        assert_eq!(location.call_stack[0].line, 5);
+        assert_eq!(
+            error.to_string(),
+            "Attempt to divide by zero at line 2, column 7\n    in o<deep> called from line 5"
+        );
+    }
+
+    #[test]
+    fn bounded_queue() {
+        let mut interp = Interpreter::<DefaultParameters, 2>::with_parameters(Default::default());
+
+        interp
+            .queue_command(block(&[('G', 0.0), ('X', 1.0)]))
+            .unwrap();
+        interp.queue_command(block(&[('X', 2.0)])).unwrap();
+
+        assert_eq!(
+            interp.queue_command(block(&[('X', 3.0)])),
+            Err(QueueError::Full)
+        );
+        assert_eq!(interp.peek_command(1), Some(&block(&[('X', 2.0)])));
+        assert_eq!(interp.peek_command(2), None);
+
+        interp.pop_command().unwrap();
+
+        assert_eq!(interp.queued_commands(), 1);
+        assert_eq!(
+            interp.next_canon(),
+            Some(Canon::StraightTraverse {
+                end: Position::from([1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+            })
+        );
+
+        // Abort
+        assert_eq!(interp.drain_commands().count(), 1);
+
+        interp.pop_command().unwrap();
+
+        assert_eq!(interp.queued_commands(), 0);
+        assert_eq!(interp.next_canon(), None);
+    }
+
+    #[test]
+    fn send() {
+        fn assert_send<T: Send>() {}
+
+        // Handlers are boxed with `Send`, so the interpreter can run on its own thread
+        assert_send::<Interpreter>();
+    }
+
+    #[test]
+    fn bounded_output() {
+        let mut interp = Interpreter::new();
+        interp.set_output_capacity(3);
+
+        let program = "#1 = 0\no1 repeat [5]\nG0 X#1\n#1 = [#1 + 1]\no1 endrepeat\nG0 Y1";
+
+        for block in parser::parse_program(program).unwrap() {
+            interp.queue_command(Command::Block(block)).unwrap();
+        }
+
+        for _ in 0..5 {
+            interp.pop_command().unwrap();
+        }
+
+        // The loop stops once the output fills, leaving the last block queued
+        assert!(interp.output_full());
+        assert_eq!(interp.pop_command(), Err(InterpreterError::OutputFull));
+        assert_eq!(interp.queued_commands(), 1);
+
+        let mut canon = Vec::new();
+
+        while interp.queued_commands() > 0 || interp.output_full() {
+            canon.extend(core::iter::from_fn(|| interp.next_canon()));
+            interp.pop_command().unwrap();
+        }
+
+        canon.extend(core::iter::from_fn(|| interp.next_canon()));
+
+        assert_eq!(
+            canon
+                .iter()
+                .map(|canon| match canon {
+                    Canon::StraightTraverse { end } => (end[0], end[1]),
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>(),
+            [
+                (0.0, 0.0),
+                (1.0, 0.0),
+                (2.0, 0.0),
+                (3.0, 0.0),
+                (4.0, 0.0),
+                (4.0, 1.0)
+            ]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn var_file_program_start_end() {
+        let path = std::env::temp_dir().join(alloc::format!(
+            "interpreter-program-{}.var",
+            std::process::id()
+        ));
+
+        std::fs::write(&path, "5221\t12.500000\n5222\t-1.000000\n").unwrap();
+
+        let mut interp = Interpreter::new();
+        interp.set_var_file(&path);
+        interp.start_program().unwrap();
+
+        assert_eq!(interp.numbered_parameter(5221), Ok(12.5));
+
+        interp.set_numbered_parameter(5181, 7.0).unwrap();
+        interp.end_program().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+
+        assert!(contents.contains("5181\t7.000000\n"));
+        assert!(contents.contains("5221\t12.500000\n"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,196 @@
+//! Manual data input: blocks typed by the operator, run against the interpreter's current state.
+//!
+//! MDI shares modal state, parameters and subroutines with programs, so `G20` given by MDI is
+//! still in effect when the next program starts. Commands are refused while a program is running
+//! and queued while one is paused, to be run once it ends.
+
+use crate::parameters::ParameterStore;
+use crate::{Interpreter, InterpreterError};
+use common::{Command, Span};
+use core::fmt;
+
+/// Whether a program is being run.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProgramState {
+    /// No program has been started, or the last one has ended.
+    Idle,
+
+    /// Between [`Interpreter::start_program`] and [`Interpreter::end_program`].
+    Running,
+
+    /// A running program has been paused with [`Interpreter::pause`] or `M0`. No more blocks are
+    /// read until [`Interpreter::resume`].
+    Paused,
+}
+
+/// What happened to an MDI command.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MdiStatus {
+    /// The blocks were executed.
+    Executed,
+
+    /// A program is paused, so the blocks will be executed when it ends.
+    Queued,
+}
+
+/// An MDI command was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MdiError {
+    /// MDI can't be used while a program is running.
+    ProgramRunning,
+
+    /// The command isn't valid G code. Nothing was executed.
+    Parse(parser::ParseError),
+}
+
+impl fmt::Display for MdiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ProgramRunning => write!(f, "Cannot use MDI while a program is running"),
+            Self::Parse(e) => e.fmt(f),
+        }
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    pub fn program_state(&self) -> ProgramState {
+        self.program_state
+    }
+
+    /// Pause a running program, taking a [snapshot](crate::snapshot) to resume from.
+    pub fn pause(&mut self) {
+        if self.program_state == ProgramState::Running {
+            self.program_state = ProgramState::Paused;
+            self.last_snapshot = Some(self.snapshot());
+        }
+    }
+
+    /// Stop the program straight away, taking a [snapshot](crate::snapshot) first. Queued
+    /// commands, output that hasn't been read or is held back by cutter compensation, MDI
+    /// commands waiting for the program to end and the sync point it's stopped at are dropped,
+    /// and it leaves any subroutines and loops it's in. Modal state and parameters are kept.
+    pub fn abort(&mut self) -> Result<(), InterpreterError> {
+        self.last_snapshot = Some(self.snapshot());
+
+        self.queue.clear();
+        self.output.clear();
+        self.synced_outputs.clear();
+        self.reset_cutter_comp();
+        self.mdi_queue.clear();
+        self.waiting = None;
+        self.program_state = ProgramState::Idle;
+
+        self.end_control_flow(Span::default())
+    }
+
+    /// Carry on running a paused program, starting with any blocks it has already received, e.g.
+    /// the rest of a subroutine or loop it paused in. Queued commands are popped as usual.
+    pub fn resume(&mut self) -> Result<(), InterpreterError> {
+        if self.program_state == ProgramState::Paused {
+            self.program_state = ProgramState::Running;
+
+            self.run()?;
+        }
+
+        Ok(())
+    }
+
+    /// Parse and run one or more lines of MDI input.
+    ///
+    /// Every line is parsed before any are run, so a typo doesn't leave a command half done.
+    /// Output is available from [`next_canon`](Self::next_canon) as for a program.
+    pub fn mdi(&mut self, input: &str) -> Result<MdiStatus, InterpreterError> {
+        if self.program_state == ProgramState::Running {
+            return Err(MdiError::ProgramRunning.into());
+        }
+
+        let blocks = parser::parse_program(input).map_err(MdiError::Parse)?;
+
+        if self.program_state == ProgramState::Paused {
+            self.mdi_queue.extend(blocks);
+
+            return Ok(MdiStatus::Queued);
+        }
+
+        for block in blocks {
+            self.execute_command(Command::Block(block))?;
+        }
+
+        Ok(MdiStatus::Executed)
+    }
+
+    /// Run MDI commands queued while a program was paused.
+    pub(crate) fn run_queued_mdi(&mut self) -> Result<(), InterpreterError> {
+        for block in core::mem::take(&mut self.mdi_queue) {
+            self.execute_command(Command::Block(block))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canon::Canon;
+    use crate::test_utils::canon;
+    use common::{DistanceMode, Position, Units};
+
+    #[test]
+    fn modal_state_carries_into_program() {
+        let mut interp = Interpreter::new();
+
+        assert_eq!(interp.mdi("G20 G91\nG0 X1"), Ok(MdiStatus::Executed));
+        assert_eq!(
+            canon(&mut interp).last(),
+            Some(&Canon::StraightTraverse {
+                end: Position::from([1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+            })
+        );
+
+        interp.start_program().unwrap();
+
+        assert_eq!(interp.modal_groups().units(), Units::Inch);
+        assert_eq!(
+            interp.modal_groups().distance_mode(),
+            DistanceMode::Incremental
+        );
+
+        // Blocks of the program are counted from its start, not including MDI
+        for block in parser::parse_program("G0 X1\nG0 X1").unwrap() {
+            interp.queue_command(Command::Block(block)).unwrap();
+            interp.pop_command().unwrap();
+        }
+
+        assert_eq!(interp.snapshot().cursor().block, 2);
+        assert_eq!(interp.position()[0], 3.0);
+    }
+
+    #[test]
+    fn refused_while_running() {
+        let mut interp = Interpreter::new();
+
+        // Nothing runs if any line fails to parse
+        assert!(matches!(
+            interp.mdi("G0 X1\nG0 X!"),
+            Err(InterpreterError::Mdi(MdiError::Parse(_)))
+        ));
+        assert_eq!(interp.position()[0], 0.0);
+
+        interp.start_program().unwrap();
+
+        assert_eq!(interp.mdi("G0 X1"), Err(MdiError::ProgramRunning.into()));
+
+        interp.pause();
+
+        assert_eq!(interp.mdi("G0 X1"), Ok(MdiStatus::Queued));
+        assert_eq!(interp.position()[0], 0.0);
+
+        interp.resume().unwrap();
+        interp.end_program().unwrap();
+
+        assert_eq!(interp.program_state(), ProgramState::Idle);
+        assert_eq!(interp.position()[0], 1.0);
+    }
+}
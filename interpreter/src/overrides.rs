@@ -0,0 +1,225 @@
+//! Feed, spindle, rapid and maximum velocity overrides, and `M48` to `M53` to switch them.
+//!
+//! The operator's override settings change at any time, so they aren't part of the program: the
+//! planner reads them as live [`OverrideInputs`] while the machine moves. Which of them apply is
+//! up to the program, and changes at a point in it, so the interpreter keeps the
+//! [`OverrideSwitches`] as modal state and outputs them as [`Canon::Overrides`] in order with
+//! the moves they apply to. [`OverrideInputs::scales`] combines the two.
+//!
+//! - `M48` and `M49` turn the feed and spindle overrides on and off together.
+//! - `M50` turns the feed override on, or off with `P0`. Rapid moves follow it.
+//! - `M51` turns the spindle override on, or off with `P0`.
+//! - `M52` turns adaptive feed on, or off with `P0`.
+//! - `M53` turns the feed stop switch on, or off with `P0`.
+
+use crate::block::OverrideCommand;
+use crate::canon::Canon;
+use crate::parameters::ParameterStore;
+use crate::Interpreter;
+use common::Number;
+
+/// Which overrides apply, as set by the program.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OverrideSwitches {
+    /// `M50`: scale feed rates by the feed override, and rapids by the rapid override.
+    pub feed: bool,
+
+    /// `M51`: scale spindle speeds by the spindle override.
+    pub spindle: bool,
+
+    /// `M52`: scale feed rates by the adaptive feed input.
+    pub adaptive_feed: bool,
+
+    /// `M53`: stop feed moves while the feed stop input is on.
+    pub feed_stop: bool,
+}
+
+impl Default for OverrideSwitches {
+    fn default() -> Self {
+        Self {
+            feed: true,
+            spindle: true,
+            adaptive_feed: false,
+            feed_stop: true,
+        }
+    }
+}
+
+/// Override settings from the operator and the machine, read while moving.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct OverrideInputs {
+    /// Feed rate scale, `1.0` for 100%.
+    pub feed: Number,
+
+    /// Spindle speed scale.
+    pub spindle: Number,
+
+    /// Rapid rate scale, from `0.0` to `1.0`.
+    pub rapid: Number,
+
+    /// Speed limit for every move in units per minute, if any.
+    pub max_velocity: Option<Number>,
+
+    /// Adaptive feed input, from `0.0` to `1.0`.
+    pub adaptive_feed: Number,
+
+    /// The feed stop input is on.
+    pub feed_stop: bool,
+}
+
+impl Default for OverrideInputs {
+    fn default() -> Self {
+        Self {
+            feed: 1.0,
+            spindle: 1.0,
+            rapid: 1.0,
+            max_velocity: None,
+            adaptive_feed: 1.0,
+            feed_stop: false,
+        }
+    }
+}
+
+/// What the planner scales programmed rates and speeds by.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct OverrideScales {
+    pub feed: Number,
+    pub rapid: Number,
+    pub spindle: Number,
+    pub max_velocity: Option<Number>,
+}
+
+impl OverrideInputs {
+    /// Scales to apply with the given overrides switched on.
+    pub fn scales(&self, switches: &OverrideSwitches) -> OverrideScales {
+        let on = |switch: bool, value: Number| if switch { value } else { 1.0 };
+
+        let stopped = switches.feed_stop && self.feed_stop;
+
+        OverrideScales {
+            feed: if stopped {
+                0.0
+            } else {
+                on(switches.feed, self.feed) * on(switches.adaptive_feed, self.adaptive_feed)
+            },
+            rapid: on(switches.feed, self.rapid),
+            spindle: on(switches.spindle, self.spindle),
+            max_velocity: self.max_velocity,
+        }
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// `M48` to `M53`. `P` turns an override off when it's `0`, and on otherwise or if it's not
+    /// given.
+    pub(crate) fn override_command(&mut self, command: OverrideCommand, p: Option<Number>) {
+        let on = p != Some(0.0);
+        let switches = &mut self.modal_groups.overrides;
+
+        match command {
+            OverrideCommand::All(on) => {
+                switches.feed = on;
+                switches.spindle = on;
+            }
+            OverrideCommand::Feed => switches.feed = on,
+            OverrideCommand::Spindle => switches.spindle = on,
+            OverrideCommand::AdaptiveFeed => switches.adaptive_feed = on,
+            OverrideCommand::FeedStop => switches.feed_stop = on,
+        }
+
+        self.emit(Canon::Overrides(self.modal_groups.overrides));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::run;
+    use crate::InterpreterError;
+
+    #[test]
+    fn switches() {
+        let mut interp = Interpreter::new();
+
+        let off = OverrideSwitches {
+            feed: false,
+            spindle: false,
+            ..OverrideSwitches::default()
+        };
+
+        assert_eq!(run(&mut interp, "M49").unwrap(), [Canon::Overrides(off)]);
+
+        run(&mut interp, "M51\nM52\nM53 P0").unwrap();
+
+        assert_eq!(
+            interp.modal_groups().overrides(),
+            OverrideSwitches {
+                feed: false,
+                spindle: true,
+                adaptive_feed: true,
+                feed_stop: false,
+            }
+        );
+
+        assert_eq!(
+            run(&mut interp, "M50 M51"),
+            Err(InterpreterError::MModalGroupConflict(51.0))
+        );
+
+        run(&mut interp, "M50 P1\nM51 P0").unwrap();
+
+        assert!(interp.modal_groups().overrides().feed);
+        assert!(!interp.modal_groups().overrides().spindle);
+
+        // The end of the program turns feed and spindle overrides back on, as M48 does
+        assert!(run(&mut interp, "M2")
+            .unwrap()
+            .contains(&Canon::Overrides(OverrideSwitches {
+                feed: true,
+                spindle: true,
+                adaptive_feed: true,
+                feed_stop: false,
+            })));
+    }
+
+    #[test]
+    fn scales() {
+        let mut inputs = OverrideInputs {
+            feed: 1.5,
+            spindle: 0.8,
+            rapid: 0.5,
+            max_velocity: Some(1000.0),
+            adaptive_feed: 0.5,
+            feed_stop: false,
+        };
+
+        let mut switches = OverrideSwitches::default();
+
+        assert_eq!(
+            inputs.scales(&switches),
+            OverrideScales {
+                feed: 1.5,
+                rapid: 0.5,
+                spindle: 0.8,
+                max_velocity: Some(1000.0),
+            }
+        );
+
+        switches.adaptive_feed = true;
+        assert_eq!(inputs.scales(&switches).feed, 0.75);
+
+        switches.feed = false;
+        switches.spindle = false;
+
+        let scales = inputs.scales(&switches);
+
+        assert_eq!((scales.feed, scales.rapid, scales.spindle), (0.5, 1.0, 1.0));
+
+        inputs.feed_stop = true;
+        assert_eq!(inputs.scales(&switches).feed, 0.0);
+
+        switches.feed_stop = false;
+        assert_eq!(inputs.scales(&switches).feed, 0.5);
+    }
+}
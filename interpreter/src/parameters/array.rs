@@ -0,0 +1,260 @@
+//! Fixed size, allocation free parameter storage.
+
+use super::{check_name, numbered_offset, ParameterError, ParameterStore, MAX_NAME_LENGTH};
+
+/// Parameter storage in fixed size arrays.
+///
+/// `NAMED` is the total number of named parameters (global and local, across all scopes) that can
+/// be stored at once. `NUMBERED` is the number of numbered parameters that can be non-zero at
+/// once. Unset numbered parameters read as zero and setting one to zero frees its slot, so this
+/// only needs to cover the offsets, probe results and subroutine arguments a program actually
+/// uses.
+///
+/// The store is kept inline in the interpreter holding it, which has to fit on the stack or in a
+/// static. On a 64 bit target each named parameter takes 80 bytes and each numbered one 16, so
+/// `ArrayParameters<64, 128>` is about 7KB. 32 bit targets need a little less.
+pub struct ArrayParameters<const NAMED: usize, const NUMBERED: usize> {
+    numbered: [Option<NumberedEntry>; NUMBERED],
+    named: [Option<NamedEntry>; NAMED],
+    depth: usize,
+}
+
+#[derive(Copy, Clone)]
+struct NumberedEntry {
+    index: u16,
+    value: f64,
+}
+
+#[derive(Copy, Clone)]
+struct NamedEntry {
+    name: [u8; MAX_NAME_LENGTH],
+    len: usize,
+    /// `None` for globals, otherwise the local scope depth.
+    scope: Option<usize>,
+    value: f64,
+}
+
+impl NamedEntry {
+    fn matches(&self, name: &str, scope: Option<usize>) -> bool {
+        self.scope == scope && self.name[0..self.len].eq_ignore_ascii_case(name.as_bytes())
+    }
+}
+
+impl<const NAMED: usize, const NUMBERED: usize> ArrayParameters<NAMED, NUMBERED> {
+    pub fn new() -> Self {
+        Self {
+            numbered: [None; NUMBERED],
+            named: [None; NAMED],
+            depth: 0,
+        }
+    }
+
+    fn get(&self, name: &str, scope: Option<usize>) -> Option<f64> {
+        self.named
+            .iter()
+            .flatten()
+            .find(|entry| entry.matches(name, scope))
+            .map(|entry| entry.value)
+    }
+
+    fn set(&mut self, name: &str, scope: Option<usize>, value: f64) -> Result<(), ParameterError> {
+        check_name(name)?;
+
+        if let Some(entry) = self
+            .named
+            .iter_mut()
+            .flatten()
+            .find(|entry| entry.matches(name, scope))
+        {
+            entry.value = value;
+
+            return Ok(());
+        }
+
+        let slot = self
+            .named
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(ParameterError::Full)?;
+
+        let mut entry = NamedEntry {
+            name: [0; MAX_NAME_LENGTH],
+            len: name.len(),
+            scope,
+            value,
+        };
+
+        entry.name[0..name.len()].copy_from_slice(name.as_bytes());
+        entry.name.make_ascii_lowercase();
+
+        *slot = Some(entry);
+
+        Ok(())
+    }
+
+    fn remove(&mut self, name: &str, scope: Option<usize>) {
+        for slot in self.named.iter_mut() {
+            if matches!(slot, Some(entry) if entry.matches(name, scope)) {
+                *slot = None;
+            }
+        }
+    }
+
+    fn for_each(&self, scope: Option<usize>, f: &mut dyn FnMut(&str, f64)) {
+        for entry in self.named.iter().flatten() {
+            if entry.scope == scope {
+                // Names are copied from a `&str` so are always valid
+                f(
+                    core::str::from_utf8(&entry.name[0..entry.len]).unwrap_or_default(),
+                    entry.value,
+                );
+            }
+        }
+    }
+}
+
+impl<const NAMED: usize, const NUMBERED: usize> Default for ArrayParameters<NAMED, NUMBERED> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const NAMED: usize, const NUMBERED: usize> ParameterStore
+    for ArrayParameters<NAMED, NUMBERED>
+{
+    fn numbered(&self, index: usize) -> Result<f64, ParameterError> {
+        numbered_offset(index)?;
+
+        Ok(self
+            .numbered
+            .iter()
+            .flatten()
+            .find(|entry| usize::from(entry.index) == index)
+            .map_or(0.0, |entry| entry.value))
+    }
+
+    fn set_numbered(&mut self, index: usize, value: f64) -> Result<(), ParameterError> {
+        numbered_offset(index)?;
+
+        // In range, so it fits
+        let index = index as u16;
+
+        let slot = match self
+            .numbered
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(entry) if entry.index == index))
+        {
+            Some(slot) => slot,
+            None if value == 0.0 => return Ok(()),
+            None => self
+                .numbered
+                .iter_mut()
+                .find(|slot| slot.is_none())
+                .ok_or(ParameterError::Full)?,
+        };
+
+        *slot = if value == 0.0 {
+            None
+        } else {
+            Some(NumberedEntry { index, value })
+        };
+
+        Ok(())
+    }
+
+    fn global(&self, name: &str) -> Option<f64> {
+        self.get(name, None)
+    }
+
+    fn set_global(&mut self, name: &str, value: f64) -> Result<(), ParameterError> {
+        self.set(name, None, value)
+    }
+
+    fn local(&self, name: &str) -> Option<f64> {
+        self.get(name, Some(self.depth))
+    }
+
+    fn set_local(&mut self, name: &str, value: f64) -> Result<(), ParameterError> {
+        self.set(name, Some(self.depth), value)
+    }
+
+    fn remove_global(&mut self, name: &str) {
+        self.remove(name, None)
+    }
+
+    fn remove_local(&mut self, name: &str) {
+        self.remove(name, Some(self.depth))
+    }
+
+    fn push_scope(&mut self) -> Result<(), ParameterError> {
+        self.depth += 1;
+
+        Ok(())
+    }
+
+    fn pop_scope(&mut self) {
+        if self.depth == 0 {
+            return;
+        }
+
+        let depth = self.depth;
+
+        for slot in self.named.iter_mut() {
+            if matches!(slot, Some(entry) if entry.scope == Some(depth)) {
+                *slot = None;
+            }
+        }
+
+        self.depth -= 1;
+    }
+
+    fn scope_depth(&self) -> usize {
+        self.depth
+    }
+
+    fn for_each_global(&self, f: &mut dyn FnMut(&str, f64)) {
+        self.for_each(None, f)
+    }
+
+    fn for_each_local(&self, scope: usize, f: &mut dyn FnMut(&str, f64)) {
+        self.for_each(Some(scope), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_behaviour() {
+        super::super::tests::exercise(&mut ArrayParameters::<8, 4>::new());
+    }
+
+    #[test]
+    fn full() {
+        let mut params = ArrayParameters::<3, 2>::new();
+
+        params.set_global("_a", 1.0).unwrap();
+        params.set_local("b", 1.0).unwrap();
+        params.push_scope().unwrap();
+        params.set_local("c", 1.0).unwrap();
+
+        assert_eq!(params.set_local("d", 1.0), Err(ParameterError::Full));
+
+        // Popping a scope frees its entries
+        params.pop_scope();
+        params.push_scope().unwrap();
+        assert_eq!(params.set_local("d", 1.0), Ok(()));
+
+        params.set_numbered(1, 1.0).unwrap();
+        params.set_numbered(5221, 2.0).unwrap();
+        params.set_numbered(5221, 3.0).unwrap();
+        assert_eq!(params.set_numbered(2, 1.0), Err(ParameterError::Full));
+
+        // Zero is stored by freeing the slot
+        params.set_numbered(1, 0.0).unwrap();
+        assert_eq!(params.set_numbered(2, 1.0), Ok(()));
+        assert_eq!(params.numbered(1), Ok(0.0));
+        assert_eq!(params.numbered(5221), Ok(3.0));
+    }
+}
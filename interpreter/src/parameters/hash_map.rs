@@ -0,0 +1,117 @@
+//! Growable parameter storage using an allocator.
+
+use super::{check_name, numbered_offset, ParameterError, ParameterStore, MAX_NUMBERED};
+use alloc::{string::String, vec, vec::Vec};
+use hashbrown::HashMap;
+
+/// Parameter storage using hash maps.
+pub struct HashMapParameters {
+    numbered: Vec<f64>,
+    globals: HashMap<String, f64>,
+    locals: Vec<HashMap<String, f64>>,
+}
+
+impl HashMapParameters {
+    pub fn new() -> Self {
+        Self {
+            numbered: vec![0.0; MAX_NUMBERED],
+            globals: HashMap::new(),
+            locals: vec![HashMap::new()],
+        }
+    }
+}
+
+impl Default for HashMapParameters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn key(name: &str) -> Result<String, ParameterError> {
+    check_name(name)?;
+
+    Ok(name.to_ascii_lowercase())
+}
+
+impl ParameterStore for HashMapParameters {
+    fn numbered(&self, index: usize) -> Result<f64, ParameterError> {
+        Ok(self.numbered[numbered_offset(index)?])
+    }
+
+    fn set_numbered(&mut self, index: usize, value: f64) -> Result<(), ParameterError> {
+        self.numbered[numbered_offset(index)?] = value;
+
+        Ok(())
+    }
+
+    fn global(&self, name: &str) -> Option<f64> {
+        self.globals.get(&name.to_ascii_lowercase()).copied()
+    }
+
+    fn set_global(&mut self, name: &str, value: f64) -> Result<(), ParameterError> {
+        self.globals.insert(key(name)?, value);
+
+        Ok(())
+    }
+
+    fn local(&self, name: &str) -> Option<f64> {
+        self.locals.last()?.get(&name.to_ascii_lowercase()).copied()
+    }
+
+    fn set_local(&mut self, name: &str, value: f64) -> Result<(), ParameterError> {
+        let key = key(name)?;
+
+        // There is always a top level scope
+        self.locals.last_mut().unwrap().insert(key, value);
+
+        Ok(())
+    }
+
+    fn remove_global(&mut self, name: &str) {
+        self.globals.remove(&name.to_ascii_lowercase());
+    }
+
+    fn remove_local(&mut self, name: &str) {
+        if let Some(scope) = self.locals.last_mut() {
+            scope.remove(&name.to_ascii_lowercase());
+        }
+    }
+
+    fn push_scope(&mut self) -> Result<(), ParameterError> {
+        self.locals.push(HashMap::new());
+
+        Ok(())
+    }
+
+    fn pop_scope(&mut self) {
+        if self.locals.len() > 1 {
+            self.locals.pop();
+        }
+    }
+
+    fn scope_depth(&self) -> usize {
+        self.locals.len() - 1
+    }
+
+    fn for_each_global(&self, f: &mut dyn FnMut(&str, f64)) {
+        for (name, value) in self.globals.iter() {
+            f(name, *value);
+        }
+    }
+
+    fn for_each_local(&self, scope: usize, f: &mut dyn FnMut(&str, f64)) {
+        for (name, value) in self.locals.get(scope).into_iter().flatten() {
+            f(name, *value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_behaviour() {
+        super::super::tests::exercise(&mut HashMapParameters::new());
+    }
+}
@@ -0,0 +1,151 @@
+//! Parameter storage backed by `heapless` maps.
+
+use super::{
+    check_name, numbered_offset, ParameterError, ParameterStore, MAX_NAME_LENGTH, MAX_NUMBERED,
+};
+use heapless::{FnvIndexMap, String, Vec};
+
+type Name = String<MAX_NAME_LENGTH>;
+
+/// Parameter storage using `heapless` index maps.
+///
+/// `NAMED` is the capacity of each map and must be a power of two. There is one map for global
+/// names and one per local scope, with up to `SCOPES` scopes including the top level.
+pub struct HeaplessParameters<const NAMED: usize, const SCOPES: usize> {
+    numbered: [f64; MAX_NUMBERED],
+    globals: FnvIndexMap<Name, f64, NAMED>,
+    locals: Vec<FnvIndexMap<Name, f64, NAMED>, SCOPES>,
+}
+
+impl<const NAMED: usize, const SCOPES: usize> HeaplessParameters<NAMED, SCOPES> {
+    pub fn new() -> Self {
+        let mut locals = Vec::new();
+
+        // Top level scope
+        let _ = locals.push(FnvIndexMap::new());
+
+        Self {
+            numbered: [0.0; MAX_NUMBERED],
+            globals: FnvIndexMap::new(),
+            locals,
+        }
+    }
+}
+
+impl<const NAMED: usize, const SCOPES: usize> Default for HeaplessParameters<NAMED, SCOPES> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn key(name: &str) -> Result<Name, ParameterError> {
+    check_name(name)?;
+
+    let mut key = Name::new();
+
+    for c in name.chars() {
+        key.push(c.to_ascii_lowercase())
+            .map_err(|_| ParameterError::NameTooLong)?;
+    }
+
+    Ok(key)
+}
+
+fn insert<const NAMED: usize>(
+    map: &mut FnvIndexMap<Name, f64, NAMED>,
+    name: &str,
+    value: f64,
+) -> Result<(), ParameterError> {
+    map.insert(key(name)?, value)
+        .map(|_| ())
+        .map_err(|_| ParameterError::Full)
+}
+
+impl<const NAMED: usize, const SCOPES: usize> ParameterStore for HeaplessParameters<NAMED, SCOPES> {
+    fn numbered(&self, index: usize) -> Result<f64, ParameterError> {
+        Ok(self.numbered[numbered_offset(index)?])
+    }
+
+    fn set_numbered(&mut self, index: usize, value: f64) -> Result<(), ParameterError> {
+        self.numbered[numbered_offset(index)?] = value;
+
+        Ok(())
+    }
+
+    fn global(&self, name: &str) -> Option<f64> {
+        self.globals.get(&key(name).ok()?).copied()
+    }
+
+    fn set_global(&mut self, name: &str, value: f64) -> Result<(), ParameterError> {
+        insert(&mut self.globals, name, value)
+    }
+
+    fn local(&self, name: &str) -> Option<f64> {
+        self.locals.last()?.get(&key(name).ok()?).copied()
+    }
+
+    fn set_local(&mut self, name: &str, value: f64) -> Result<(), ParameterError> {
+        let scope = self.locals.last_mut().ok_or(ParameterError::Full)?;
+
+        insert(scope, name, value)
+    }
+
+    fn remove_global(&mut self, name: &str) {
+        if let Ok(key) = key(name) {
+            self.globals.remove(&key);
+        }
+    }
+
+    fn remove_local(&mut self, name: &str) {
+        if let (Ok(key), Some(scope)) = (key(name), self.locals.last_mut()) {
+            scope.remove(&key);
+        }
+    }
+
+    fn push_scope(&mut self) -> Result<(), ParameterError> {
+        self.locals
+            .push(FnvIndexMap::new())
+            .map_err(|_| ParameterError::Full)
+    }
+
+    fn pop_scope(&mut self) {
+        if self.locals.len() > 1 {
+            self.locals.pop();
+        }
+    }
+
+    fn scope_depth(&self) -> usize {
+        self.locals.len() - 1
+    }
+
+    fn for_each_global(&self, f: &mut dyn FnMut(&str, f64)) {
+        for (name, value) in self.globals.iter() {
+            f(name, *value);
+        }
+    }
+
+    fn for_each_local(&self, scope: usize, f: &mut dyn FnMut(&str, f64)) {
+        for (name, value) in self.locals.get(scope).into_iter().flatten() {
+            f(name, *value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    #[test]
+    fn shared_behaviour() {
+        super::super::tests::exercise(&mut *Box::new(HeaplessParameters::<8, 4>::new()));
+    }
+
+    #[test]
+    fn scope_limit() {
+        let mut params = HeaplessParameters::<8, 2>::new();
+
+        assert_eq!(params.push_scope(), Ok(()));
+        assert_eq!(params.push_scope(), Err(ParameterError::Full));
+    }
+}
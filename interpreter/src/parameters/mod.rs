@@ -0,0 +1,229 @@
+//! Storage for numbered (`#5220`), global named (`#<_name>`) and local named (`#<name>`)
+//! parameters.
+//!
+//! The interpreter is generic over a [`ParameterStore`] so it can run with or without an allocator.
+//! Three backends are provided, each behind a cargo feature:
+//!
+//! - `array`: [`ArrayParameters`], fixed size arrays sized with const generics. No dependencies.
+//! - `heapless`: [`HeaplessParameters`], `heapless` index maps with a fixed capacity.
+//! - `alloc`: [`HashMapParameters`], growable hash maps. Enabled by default.
+//!
+//! Persistent parameters can be loaded from and saved to LinuxCNC `.var` files with the
+//! [`var_file`] module.
+//!
+//! Named parameters are case insensitive. Local named parameters live in a scope which is pushed
+//! on subroutine call and popped on return; the main program runs in scope `0`.
+
+#[cfg(feature = "array")]
+mod array;
+#[cfg(feature = "alloc")]
+mod hash_map;
+#[cfg(feature = "heapless")]
+mod index_map;
+pub mod var_file;
+
+#[cfg(feature = "array")]
+pub use self::array::ArrayParameters;
+#[cfg(feature = "alloc")]
+pub use self::hash_map::HashMapParameters;
+#[cfg(feature = "heapless")]
+pub use self::index_map::HeaplessParameters;
+
+use core::fmt;
+use core::ops::RangeInclusive;
+
+/// Highest numbered parameter, as in LinuxCNC's `RS274NGC_MAX_PARAMETERS`.
+pub const MAX_NUMBERED: usize = 5602;
+
+/// Longest named parameter name, not including `#<` and `>`.
+pub const MAX_NAME_LENGTH: usize = 32;
+
+/// The active work coordinate system, `1` for `G54` to `9` for `G59.3`.
+pub const COORDINATE_SYSTEM: usize = 5220;
+
+/// Numbered parameters that can only be read by a program.
+///
+/// `5400`-`5413` hold the current tool and its offsets, `5420`-`5428` the current position.
+pub const READ_ONLY_NUMBERED: RangeInclusive<usize> = 5400..=5428;
+
+/// The parameter store used by the interpreter when none is given.
+#[cfg(feature = "alloc")]
+pub type DefaultParameters = HashMapParameters;
+
+/// The parameter store used by the interpreter when none is given, with a scope for the main
+/// program and each of [`MAX_CALL_DEPTH`](crate::control_flow::MAX_CALL_DEPTH) nested calls.
+#[cfg(all(not(feature = "alloc"), feature = "heapless"))]
+pub type DefaultParameters = HeaplessParameters<64, 11>;
+
+/// The parameter store used by the interpreter when none is given.
+#[cfg(all(not(feature = "alloc"), not(feature = "heapless"), feature = "array"))]
+pub type DefaultParameters = ArrayParameters<64, 128>;
+
+#[cfg(not(any(feature = "alloc", feature = "heapless", feature = "array")))]
+compile_error!("At least one of the `alloc`, `heapless` or `array` features must be enabled");
+
+/// Storage backend for program parameters.
+///
+/// Values are stored as `f64` regardless of [`common::Number`], as LinuxCNC does.
+pub trait ParameterStore {
+    /// Get a numbered parameter. Unset parameters are `0.0`.
+    fn numbered(&self, index: usize) -> Result<f64, ParameterError>;
+
+    /// Set a numbered parameter.
+    fn set_numbered(&mut self, index: usize, value: f64) -> Result<(), ParameterError>;
+
+    /// Get a global named parameter, or `None` if it has never been set.
+    fn global(&self, name: &str) -> Option<f64>;
+
+    /// Set a global named parameter.
+    fn set_global(&mut self, name: &str, value: f64) -> Result<(), ParameterError>;
+
+    /// Get a local named parameter from the current scope, or `None` if it isn't set in this
+    /// scope.
+    fn local(&self, name: &str) -> Option<f64>;
+
+    /// Set a local named parameter in the current scope.
+    fn set_local(&mut self, name: &str, value: f64) -> Result<(), ParameterError>;
+
+    /// Remove a global named parameter, so it reads as never set.
+    fn remove_global(&mut self, name: &str);
+
+    /// Remove a local named parameter from the current scope.
+    fn remove_local(&mut self, name: &str);
+
+    /// Start a new local scope, e.g. when calling a subroutine.
+    fn push_scope(&mut self) -> Result<(), ParameterError>;
+
+    /// Discard the current local scope and all parameters set in it. The top level scope is never
+    /// removed.
+    fn pop_scope(&mut self);
+
+    /// Current scope depth. The main program is at depth `0`.
+    fn scope_depth(&self) -> usize;
+
+    /// Call `f` with the name and value of every global named parameter, in any order.
+    fn for_each_global(&self, f: &mut dyn FnMut(&str, f64));
+
+    /// Call `f` with the name and value of every local named parameter in a scope, in any order.
+    fn for_each_local(&self, scope: usize, f: &mut dyn FnMut(&str, f64));
+}
+
+/// Check a numbered parameter index is within `1..=MAX_NUMBERED`, returning its offset into an
+/// array of parameters.
+pub(crate) fn numbered_offset(index: usize) -> Result<usize, ParameterError> {
+    if (1..=MAX_NUMBERED).contains(&index) {
+        Ok(index - 1)
+    } else {
+        Err(ParameterError::OutOfRange(index))
+    }
+}
+
+/// Check a name isn't too long to store.
+pub(crate) fn check_name(name: &str) -> Result<(), ParameterError> {
+    if name.len() > MAX_NAME_LENGTH {
+        Err(ParameterError::NameTooLong)
+    } else {
+        Ok(())
+    }
+}
+
+/// Errors from reading or writing parameters.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ParameterError {
+    /// Numbered parameter outside `1..=MAX_NUMBERED`.
+    OutOfRange(usize),
+
+    /// A program tried to write to a read-only system parameter.
+    ReadOnly,
+
+    /// A fixed capacity store has no room for another parameter or scope.
+    Full,
+
+    /// Parameter name is longer than [`MAX_NAME_LENGTH`].
+    NameTooLong,
+}
+
+impl fmt::Display for ParameterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfRange(index) => write!(f, "Parameter number out of range: #{}", index),
+            Self::ReadOnly => f.write_str("Cannot set read-only parameter"),
+            Self::Full => f.write_str("Parameter storage full"),
+            Self::NameTooLong => write!(
+                f,
+                "Parameter name longer than {} characters",
+                MAX_NAME_LENGTH
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Behaviour every backend must share.
+    pub(crate) fn exercise(params: &mut impl ParameterStore) {
+        assert_eq!(params.numbered(5220), Ok(0.0));
+        params.set_numbered(5220, 2.0).unwrap();
+        assert_eq!(params.numbered(5220), Ok(2.0));
+        assert_eq!(params.numbered(1), Ok(0.0));
+        assert_eq!(params.numbered(MAX_NUMBERED), Ok(0.0));
+        assert_eq!(params.numbered(0), Err(ParameterError::OutOfRange(0)));
+        assert_eq!(
+            params.set_numbered(MAX_NUMBERED + 1, 1.0),
+            Err(ParameterError::OutOfRange(MAX_NUMBERED + 1))
+        );
+
+        assert_eq!(params.global("_feed_speed"), None);
+        params.set_global("_feed_speed", 100.0).unwrap();
+        assert_eq!(params.global("_FEED_Speed"), Some(100.0));
+
+        params.set_global("_gone", 1.0).unwrap();
+        params.remove_global("_Gone");
+        assert_eq!(params.global("_gone"), None);
+
+        params.set_local("depth", 1.0).unwrap();
+        assert_eq!(params.scope_depth(), 0);
+
+        params.push_scope().unwrap();
+        assert_eq!(params.scope_depth(), 1);
+        assert_eq!(params.local("depth"), None);
+        assert_eq!(params.global("_feed_speed"), Some(100.0));
+
+        params.set_local("Depth", 2.0).unwrap();
+        assert_eq!(params.local("depth"), Some(2.0));
+
+        // Removing only touches the current scope
+        params.set_local("gone", 1.0).unwrap();
+        params.remove_local("GONE");
+        params.remove_local("missing");
+        assert_eq!(params.local("gone"), None);
+
+        let mut named = alloc::vec::Vec::new();
+        params.for_each_local(0, &mut |name, value| named.push((name.into(), value)));
+        params.for_each_local(1, &mut |name, value| named.push((name.into(), value)));
+        params.for_each_global(&mut |name, value| named.push((name.into(), value)));
+        assert_eq!(
+            named,
+            [
+                (alloc::string::String::from("depth"), 1.0),
+                ("depth".into(), 2.0),
+                ("_feed_speed".into(), 100.0)
+            ]
+        );
+
+        params.pop_scope();
+        assert_eq!(params.scope_depth(), 0);
+        assert_eq!(params.local("depth"), Some(1.0));
+
+        // The top level scope is never removed
+        params.pop_scope();
+        assert_eq!(params.local("depth"), Some(1.0));
+
+        assert_eq!(
+            params.set_global("_abcdefghijklmnopqrstuvwxyz_0123456789", 1.0),
+            Err(ParameterError::NameTooLong)
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! LinuxCNC `.var` parameter files.
+//!
+//! Each line holds a parameter number and its value separated by whitespace, sorted by parameter
+//! number, e.g. `5221 10.000000`. Only the persistent parameter ranges are written.
+
+use super::{ParameterError, ParameterStore, MAX_NUMBERED};
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::RangeInclusive;
+
+/// Parameters that persist between runs.
+pub const PERSISTENT: [RangeInclusive<usize>; 14] = [
+    // Probe results
+    5061..=5070,
+    // G28 and G30 positions
+    5161..=5169,
+    5181..=5189,
+    // G92 enabled flag and offsets
+    5210..=5219,
+    // Current coordinate system
+    5220..=5220,
+    // G54 to G59.3 offsets and XY rotation
+    5221..=5230,
+    5241..=5250,
+    5261..=5270,
+    5281..=5290,
+    5301..=5310,
+    5321..=5330,
+    5341..=5350,
+    5361..=5370,
+    5381..=5390,
+];
+
+/// Whether a numbered parameter is stored in `.var` files.
+pub fn is_persistent(index: usize) -> bool {
+    PERSISTENT.iter().any(|range| range.contains(&index))
+}
+
+/// Parse the contents of a `.var` file into `(number, value)` pairs.
+pub fn parse(input: &str) -> Result<Vec<(usize, f64)>, VarFileError> {
+    let mut values: Vec<(usize, f64)> = Vec::new();
+
+    for (line_number, line) in input.lines().enumerate() {
+        let line_number = line_number + 1;
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+
+        let (index, value) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(index), Some(value), None) => (index, value),
+            _ => return Err(VarFileError::Malformed { line: line_number }),
+        };
+
+        let index: usize = index
+            .parse()
+            .map_err(|_| VarFileError::Malformed { line: line_number })?;
+        let value: f64 = value
+            .parse()
+            .map_err(|_| VarFileError::Malformed { line: line_number })?;
+
+        if index == 0 || index > MAX_NUMBERED {
+            return Err(VarFileError::Parameter(ParameterError::OutOfRange(index)));
+        }
+
+        if matches!(values.last(), Some((previous, _)) if *previous >= index) {
+            return Err(VarFileError::OutOfOrder { line: line_number });
+        }
+
+        values.push((index, value));
+    }
+
+    Ok(values)
+}
+
+/// Load the contents of a `.var` file into a parameter store.
+pub fn load(input: &str, parameters: &mut impl ParameterStore) -> Result<(), VarFileError> {
+    for (index, value) in parse(input)? {
+        parameters.set_numbered(index, value)?;
+    }
+
+    Ok(())
+}
+
+/// Write the persistent parameters in a store in `.var` format.
+pub fn write(parameters: &impl ParameterStore, out: &mut impl fmt::Write) -> fmt::Result {
+    for index in (1..=MAX_NUMBERED).filter(|index| is_persistent(*index)) {
+        // Indices are all in range
+        let value = parameters.numbered(index).unwrap_or(0.0);
+
+        writeln!(out, "{}\t{:.6}", index, value)?;
+    }
+
+    Ok(())
+}
+
+/// Read a `.var` file from disk into a parameter store.
+///
+/// A missing file is treated as empty so a machine can start from a blank state.
+#[cfg(feature = "std")]
+pub fn load_file(
+    path: &std::path::Path,
+    parameters: &mut impl ParameterStore,
+) -> Result<(), VarFileError> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => load(&contents, parameters),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Save the persistent parameters in a store to a `.var` file.
+///
+/// The file is written next to the destination then renamed over it, so a crash while saving
+/// never leaves a truncated file behind.
+#[cfg(feature = "std")]
+pub fn save_file(
+    path: &std::path::Path,
+    parameters: &impl ParameterStore,
+) -> Result<(), VarFileError> {
+    crate::file::write_atomic(path, |out| write(parameters, out))?;
+
+    Ok(())
+}
+
+/// Errors from reading or writing `.var` files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VarFileError {
+    /// A line isn't a parameter number followed by a value.
+    Malformed { line: usize },
+
+    /// Parameter numbers aren't in ascending order.
+    OutOfOrder { line: usize },
+
+    /// A parameter couldn't be stored.
+    Parameter(ParameterError),
+
+    /// The file couldn't be read or written.
+    #[cfg(feature = "std")]
+    Io(std::io::ErrorKind),
+}
+
+impl From<ParameterError> for VarFileError {
+    fn from(e: ParameterError) -> Self {
+        Self::Parameter(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for VarFileError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e.kind())
+    }
+}
+
+impl fmt::Display for VarFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed { line } => write!(f, "Bad format for parameter file on line {}", line),
+            Self::OutOfOrder { line } => write!(f, "Parameter file out of order on line {}", line),
+            Self::Parameter(e) => e.fmt(f),
+            #[cfg(feature = "std")]
+            Self::Io(kind) => write!(f, "Unable to access parameter file: {:?}", kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters::DefaultParameters;
+    use alloc::string::String;
+
+    #[test]
+    fn round_trip() {
+        let mut params = DefaultParameters::default();
+
+        params.set_numbered(5221, 10.5).unwrap();
+        params.set_numbered(5063, -1.25).unwrap();
+        // Not persistent
+        params.set_numbered(100, 1.0).unwrap();
+
+        let mut out = String::new();
+        write(&params, &mut out).unwrap();
+
+        assert!(out.starts_with("5061\t0.000000\n5062\t0.000000\n5063\t-1.250000\n"));
+        assert!(out.contains("\n5221\t10.500000\n"));
+        assert!(!out.contains("\n100\t"));
+        assert!(out.ends_with("5390\t0.000000\n"));
+
+        let mut loaded = DefaultParameters::default();
+        load(&out, &mut loaded).unwrap();
+
+        assert_eq!(loaded.numbered(5221), Ok(10.5));
+        assert_eq!(loaded.numbered(5063), Ok(-1.25));
+        assert_eq!(loaded.numbered(100), Ok(0.0));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse("5161 0.0\n5162\n"),
+            Err(VarFileError::Malformed { line: 2 })
+        );
+        assert_eq!(
+            parse("5161 zero\n"),
+            Err(VarFileError::Malformed { line: 1 })
+        );
+        assert_eq!(
+            parse("5161 0.0 1.0\n"),
+            Err(VarFileError::Malformed { line: 1 })
+        );
+        assert_eq!(
+            parse("5162 0.0\n\n5161 0.0\n"),
+            Err(VarFileError::OutOfOrder { line: 3 })
+        );
+        assert_eq!(
+            parse("9999 0.0\n"),
+            Err(VarFileError::Parameter(ParameterError::OutOfRange(9999)))
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn save_and_load_file() {
+        let path = std::env::temp_dir().join(alloc::format!(
+            "interpreter-var-file-{}.var",
+            std::process::id()
+        ));
+
+        let mut params = DefaultParameters::default();
+        params.set_numbered(5181, 3.0).unwrap();
+
+        save_file(&path, &params).unwrap();
+
+        let mut loaded = DefaultParameters::default();
+        load_file(&path, &mut loaded).unwrap();
+
+        assert_eq!(loaded.numbered(5181), Ok(3.0));
+
+        std::fs::remove_file(&path).unwrap();
+
+        // Missing files are treated as empty
+        assert_eq!(load_file(&path, &mut loaded), Ok(()));
+    }
+}
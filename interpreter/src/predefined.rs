@@ -0,0 +1,174 @@
+//! `G28` and `G30` predefined positions, and `G28.1` and `G30.1` to store them.
+//!
+//! The positions are kept in machine coordinates in parameters `5161` to `5169` for `G28` and
+//! `5181` to `5189` for `G30`, so they don't move with work or tool length offsets. With axis
+//! words, `G28` and `G30` first rapid to the point they give, as any move would with the current
+//! offsets and distance mode, and then only those axes go on to the stored position. Without any,
+//! every axis goes there.
+
+use crate::block::{BlockCommands, PredefinedPosition};
+use crate::canon::Canon;
+use crate::cutter_comp::CutterCompError;
+use crate::expression::{ExpressionError, ExpressionErrorKind};
+use crate::parameters::ParameterStore;
+use crate::{Interpreter, InterpreterError};
+use common::{Axis, Number, Span};
+
+impl PredefinedPosition {
+    /// Parameter holding the X position, followed by the other axes.
+    fn first_parameter(self) -> usize {
+        match self {
+            PredefinedPosition::G28 => 5161,
+            PredefinedPosition::G30 => 5181,
+        }
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// `G28` or `G30`.
+    pub(crate) fn go_to_predefined(
+        &mut self,
+        predefined: PredefinedPosition,
+        commands: &BlockCommands,
+    ) -> Result<(), InterpreterError> {
+        if self.cutter_comp.is_some() {
+            return Err(CutterCompError::PredefinedPosition.into());
+        }
+
+        let offset = self.program_offset();
+        let given = Axis::ALL.map(|axis| commands.value(axis.letter()).is_some());
+        let any_given = given.contains(&true);
+
+        if any_given {
+            let intermediate = self.end_position(commands);
+
+            self.emit(Canon::StraightTraverse {
+                end: intermediate + offset,
+            });
+            self.position = intermediate;
+        }
+
+        let first = predefined.first_parameter();
+        let mut end = self.position;
+
+        for (axis, given) in given.iter().enumerate() {
+            if *given || !any_given {
+                let stored = self.parameters.numbered(first + axis).unwrap_or(0.0) as Number;
+
+                end[axis] = stored - offset[axis];
+            }
+        }
+
+        self.emit(Canon::StraightTraverse { end: end + offset });
+        self.position = end;
+
+        Ok(())
+    }
+
+    /// `G28.1` or `G30.1`.
+    pub(crate) fn set_predefined(
+        &mut self,
+        predefined: PredefinedPosition,
+        span: Span,
+    ) -> Result<(), InterpreterError> {
+        let machine = self.position + self.program_offset();
+        let first = predefined.first_parameter();
+
+        for (axis, value) in machine.iter().enumerate() {
+            self.parameters
+                .set_numbered(first + axis, f64::from(*value))
+                .map_err(|e| ExpressionError {
+                    kind: ExpressionErrorKind::Parameter(e),
+                    span,
+                })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::run;
+    use common::Position;
+
+    fn at(x: Number, y: Number, z: Number) -> Position {
+        Position::from([x, y, z, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+    }
+
+    fn traverse(x: Number, y: Number, z: Number) -> Canon {
+        Canon::StraightTraverse { end: at(x, y, z) }
+    }
+
+    #[test]
+    fn all_axes() {
+        let mut interp = Interpreter::new();
+        interp.set_numbered_parameter(5161, 10.0).unwrap();
+        interp.set_numbered_parameter(5163, 5.0).unwrap();
+
+        // G54 X offset
+        interp.set_numbered_parameter(5221, 1.0).unwrap();
+
+        assert_eq!(
+            run(&mut interp, "G0 X3 Y4 Z6\nG28").unwrap(),
+            [traverse(4.0, 4.0, 6.0), traverse(10.0, 0.0, 5.0)]
+        );
+
+        // The stored position is in machine coordinates
+        assert_eq!(*interp.position(), at(9.0, 0.0, 5.0));
+    }
+
+    #[test]
+    fn intermediate_point() {
+        let mut interp = Interpreter::new();
+        interp.set_numbered_parameter(5181, 10.0).unwrap();
+        interp.set_numbered_parameter(5183, 20.0).unwrap();
+
+        run(&mut interp, "G0 X1 Y2 Z3").unwrap();
+
+        // Only Z moves on to the stored position, from Z4 with G91
+        assert_eq!(
+            run(&mut interp, "G91 G30 Z1").unwrap(),
+            [traverse(1.0, 2.0, 4.0), traverse(1.0, 2.0, 20.0)]
+        );
+
+        // The motion mode stays, but the block's axis words don't also make a move
+        assert_eq!(interp.modal_groups().motion(), Some(common::Motion::Rapid));
+    }
+
+    #[test]
+    fn store_position() {
+        let mut interp = Interpreter::new();
+
+        // Work offset and tool length offset both apply
+        interp.set_numbered_parameter(5221, 1.0).unwrap();
+        run(&mut interp, "G43.1 Z2\nG0 X3 Y4 Z5\nG28.1\nG0 X0 Y0 Z0").unwrap();
+
+        assert_eq!(interp.numbered_parameter(5161), Ok(4.0));
+        assert_eq!(interp.numbered_parameter(5162), Ok(4.0));
+        assert_eq!(interp.numbered_parameter(5163), Ok(7.0));
+
+        // Back to where it was stored, in a different coordinate system
+        interp.set_numbered_parameter(5241, -1.0).unwrap();
+
+        assert_eq!(
+            run(&mut interp, "G55 G28").unwrap(),
+            [
+                Canon::SelectCoordinateSystem(common::CoordinateSystem::new(2).unwrap()),
+                traverse(4.0, 4.0, 7.0)
+            ]
+        );
+        assert_eq!(*interp.position(), at(5.0, 4.0, 5.0));
+    }
+
+    #[test]
+    fn cutter_comp() {
+        let mut interp = Interpreter::new();
+
+        assert_eq!(
+            run(&mut interp, "G41.1 D1 G1 X10 F100\nG28"),
+            Err(CutterCompError::PredefinedPosition.into())
+        );
+    }
+}
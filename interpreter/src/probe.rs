@@ -0,0 +1,669 @@
+//! Straight probing, `G38.2` to `G38.5`.
+//!
+//! A probe move feeds towards the programmed point and stops when the probe input changes: on
+//! making contact for `G38.2` and `G38.3`, or on losing it for `G38.4` and `G38.5`. `G38.2` and
+//! `G38.4` are errors if the move ends without the input changing, or if the probe already is in
+//! the state the move looks for. The other two just stop there: at the end of the move, or where
+//! they started. This follows LinuxCNC, which allows rotary axes and very short moves.
+//!
+//! The interpreter needs the result before it can carry on, so like `M6` it hands the move to a
+//! [`Probe`] and waits for it. On a machine that means running the move and reading back where the
+//! input tripped; [`SimulatedProbe`] works it out from solids at known machine coordinates so
+//! probing programs can be tried without one. Without a probe set a probe move is an error, unless
+//! [sync points](crate::sync) are on, when the interpreter stops at the move until the machine
+//! gives the result.
+//!
+//! Afterwards the program is where the move stopped, and parameters `5061` to `5069` hold that
+//! position in the program coordinates at the time. `5070` is `1` if the input tripped or `0` if
+//! it didn't. A [`Canon::StraightProbe`] records the move for the planner.
+
+use crate::block::BlockCommands;
+use crate::canon::Canon;
+use crate::expression::{ExpressionError, ExpressionErrorKind};
+use crate::parameters::ParameterStore;
+use crate::sync::SyncPoint;
+use crate::{Interpreter, InterpreterError};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use common::{Axis, FeedRateMode, Number, Position, ProbeKind, Span};
+use core::fmt;
+
+/// Probed position, `#5061` to `#5069` for X to W.
+const PROBE_POSITION: usize = 5061;
+
+/// `1` if the last probe move tripped, otherwise `0`.
+const PROBE_TRIPPED: usize = 5070;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProbeError {
+    /// A probe move without any axis words.
+    MissingAxes,
+
+    /// Probing with cutter radius compensation on.
+    CutterComp,
+
+    /// Probing in `G93` inverse time mode.
+    InverseTime,
+
+    /// Probing in `G95` units per revolution mode.
+    UnitsPerRevolution,
+
+    /// Probing with a zero feed rate.
+    ZeroFeed,
+
+    /// `G38.2` or `G38.3` started with the probe already in contact. Only an error for `G38.2`.
+    AlreadyTripped,
+
+    /// `G38.4` or `G38.5` started with the probe already clear. Only an error for `G38.4`.
+    AlreadyClear,
+
+    /// `G38.2` or `G38.4` reached the programmed point without the probe changing state.
+    NotTripped(ProbeKind),
+
+    /// A probe move with no [`Probe`] set and sync points off, so nothing can run it.
+    NoProbe,
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingAxes => write!(
+                f,
+                "X, Y, Z, A, B, C, U, V and W words all missing with G38.x"
+            ),
+            Self::CutterComp => write!(f, "Cannot probe with cutter radius compensation on"),
+            Self::InverseTime => write!(f, "Cannot probe in inverse time feed mode"),
+            Self::UnitsPerRevolution => write!(f, "Cannot probe with feed per rev mode"),
+            Self::ZeroFeed => write!(f, "Cannot probe with zero feed rate"),
+            Self::AlreadyTripped => {
+                write!(
+                    f,
+                    "Probe is already tripped when starting G38.2 or G38.3 move"
+                )
+            }
+            Self::AlreadyClear => {
+                write!(
+                    f,
+                    "Probe is already clear when starting G38.4 or G38.5 move"
+                )
+            }
+            Self::NotTripped(kind) if kind.towards() => {
+                write!(f, "G{} move finished without making contact", kind.code())
+            }
+            Self::NotTripped(kind) => {
+                write!(f, "G{} move finished without breaking contact", kind.code())
+            }
+            Self::NoProbe => write!(f, "No probe configured for G38.x move"),
+        }
+    }
+}
+
+/// A straight probe move, in machine coordinates.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProbeMove {
+    pub kind: ProbeKind,
+    pub start: Position,
+    pub end: Position,
+
+    /// Feed rate in units per minute.
+    pub feed_rate: Number,
+}
+
+/// Runs probe moves, e.g. by driving the machine and watching its probe input.
+pub trait Probe {
+    /// Move towards `probe.end` until the probe input changes, returning where it did or `None`
+    /// if it reached the end first.
+    ///
+    /// If the probe already is in the state the move looks for, a move that
+    /// [must trip](ProbeKind::must_trip) returns [`ProbeError::AlreadyTripped`] or
+    /// [`ProbeError::AlreadyClear`], and others stop at the start.
+    fn probe(&mut self, probe: &ProbeMove) -> Result<Option<Position>, ProbeError>;
+}
+
+impl<F> Probe for F
+where
+    F: FnMut(&ProbeMove) -> Result<Option<Position>, ProbeError>,
+{
+    fn probe(&mut self, probe: &ProbeMove) -> Result<Option<Position>, ProbeError> {
+        self(probe)
+    }
+}
+
+/// A solid the [`SimulatedProbe`] touches, in machine X, Y and Z. Sliding along the surface
+/// doesn't count as contact.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Solid {
+    /// A box aligned to the axes, between two opposite corners.
+    Box { min: [Number; 3], max: [Number; 3] },
+
+    /// Everything on the side of a plane through `point` opposite its `normal`, e.g. a table top
+    /// with a normal of `[0.0, 0.0, 1.0]`.
+    Plane {
+        point: [Number; 3],
+        normal: [Number; 3],
+    },
+}
+
+impl Solid {
+    /// The part of the line `start + t * (end - start)` inside the solid, as a range of `t`.
+    fn interval(&self, start: &Position, end: &Position) -> Option<(Number, Number)> {
+        let delta = |axis: usize| end[axis] - start[axis];
+
+        match self {
+            Solid::Box { min, max } => {
+                let mut interval = (Number::NEG_INFINITY, Number::INFINITY);
+
+                for axis in 0..3 {
+                    let (low, high) = (min[axis].min(max[axis]), min[axis].max(max[axis]));
+
+                    if delta(axis) == 0.0 {
+                        if start[axis] <= low || start[axis] >= high {
+                            return None;
+                        }
+                    } else {
+                        let a = (low - start[axis]) / delta(axis);
+                        let b = (high - start[axis]) / delta(axis);
+
+                        interval.0 = interval.0.max(a.min(b));
+                        interval.1 = interval.1.min(a.max(b));
+                    }
+                }
+
+                (interval.0 <= interval.1).then_some(interval)
+            }
+            Solid::Plane { point, normal } => {
+                // Signed distance along the normal at the start, and how fast it changes
+                let height: Number = (0..3).map(|i| (start[i] - point[i]) * normal[i]).sum();
+                let rate: Number = (0..3).map(|i| delta(i) * normal[i]).sum();
+
+                if rate == 0.0 {
+                    (height < 0.0).then_some((Number::NEG_INFINITY, Number::INFINITY))
+                } else if rate > 0.0 {
+                    Some((Number::NEG_INFINITY, -height / rate))
+                } else {
+                    Some((-height / rate, Number::INFINITY))
+                }
+            }
+        }
+    }
+}
+
+/// A [`Probe`] that touches [`Solid`]s at known machine coordinates, stopping exactly where the
+/// probe tip meets or leaves them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimulatedProbe {
+    pub solids: Vec<Solid>,
+}
+
+impl SimulatedProbe {
+    pub fn new(solids: impl IntoIterator<Item = Solid>) -> Self {
+        Self {
+            solids: solids.into_iter().collect(),
+        }
+    }
+}
+
+impl Probe for SimulatedProbe {
+    fn probe(&mut self, probe: &ProbeMove) -> Result<Option<Position>, ProbeError> {
+        let intervals = self
+            .solids
+            .iter()
+            .filter_map(|solid| solid.interval(&probe.start, &probe.end))
+            .filter(|(enter, leave)| *enter <= 1.0 && *leave >= 0.0)
+            .collect::<Vec<_>>();
+
+        let inside = intervals
+            .iter()
+            .any(|(enter, leave)| *enter < 0.0 && *leave > 0.0);
+        let outside = intervals
+            .iter()
+            .all(|(enter, leave)| *enter > 0.0 || *leave < 0.0);
+
+        // Starting on a surface, the probe is touching it or just clear of it, whichever the move
+        // is looking for, e.g. after `G38.2` stops at the surface or `G38.5` where it leaves
+        if !inside && !outside {
+            return Ok(Some(probe.start));
+        }
+
+        let tripped_at = match (probe.kind.towards(), inside) {
+            (true, true) | (false, false) if probe.kind.must_trip() => {
+                return Err(if inside {
+                    ProbeError::AlreadyTripped
+                } else {
+                    ProbeError::AlreadyClear
+                });
+            }
+            (true, true) | (false, false) => Some(0.0),
+            (true, false) => intervals
+                .iter()
+                .map(|(enter, _)| *enter)
+                .min_by(|a, b| a.total_cmp(b)),
+            (false, true) => {
+                // Follow overlapping solids until the probe comes out of the last one
+                let mut t: Number = 0.0;
+
+                while let Some(leave) = intervals
+                    .iter()
+                    .filter(|(enter, leave)| *enter <= t && *leave > t)
+                    .map(|(_, leave)| *leave)
+                    .max_by(|a, b| a.total_cmp(b))
+                {
+                    t = leave;
+                }
+
+                (t < 1.0).then_some(t)
+            }
+        };
+
+        Ok(tripped_at.map(|t| probe.start + (probe.end - probe.start) * t))
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Set the probe asked to carry out `G38.2` to `G38.5` moves.
+    pub fn set_probe(&mut self, probe: impl Probe + Send + 'static) {
+        self.probe = Some(Box::new(probe));
+    }
+
+    /// A `G38.2` to `G38.5` move to the program position `end`.
+    pub(crate) fn straight_probe(
+        &mut self,
+        kind: ProbeKind,
+        end: Position,
+        commands: &BlockCommands,
+        span: Span,
+    ) -> Result<(), InterpreterError> {
+        if Axis::ALL
+            .iter()
+            .all(|axis| commands.value(axis.letter()).is_none())
+        {
+            // The mode stays active, but only a block that starts it must move
+            return match commands.motion {
+                Some(_) => Err(ProbeError::MissingAxes.into()),
+                None => Ok(()),
+            };
+        }
+
+        if self.cutter_comp.is_some() {
+            return Err(ProbeError::CutterComp.into());
+        }
+
+        match self.modal_groups.feed_rate_mode {
+            FeedRateMode::InverseTime => return Err(ProbeError::InverseTime.into()),
+            FeedRateMode::UnitsPerRevolution => return Err(ProbeError::UnitsPerRevolution.into()),
+            FeedRateMode::UnitsPerMinute => {}
+        }
+
+        if self.feed_rate == 0.0 {
+            return Err(ProbeError::ZeroFeed.into());
+        }
+
+        let offset = self.program_offset();
+
+        let probe = ProbeMove {
+            kind,
+            start: self.position + offset,
+            end: end + offset,
+            feed_rate: self.feed_rate,
+        };
+
+        // Nothing moves in a dry run, so carry on as if the probe reached the end
+        if self.dry_run {
+            self.position = end;
+
+            return Ok(());
+        }
+
+        // Outputs synced with the move are set as it starts, whoever carries it out
+        self.start_segment();
+
+        // The machine carries out the move and gives the result at the end of the block
+        if self.probe.is_none() && self.can_sync() {
+            self.pending_sync = Some(SyncPoint::Probe(probe));
+
+            return Ok(());
+        }
+
+        let tripped_at = match &mut self.probe {
+            Some(probe_input) => probe_input.probe(&probe)?,
+            None => return Err(ProbeError::NoProbe.into()),
+        };
+
+        self.probe_result(&probe, tripped_at, span)
+    }
+
+    /// Finish a probe move given where the input tripped, if it did: set the program position
+    /// and the probe result parameters.
+    pub(crate) fn probe_result(
+        &mut self,
+        probe: &ProbeMove,
+        tripped_at: Option<Position>,
+        span: Span,
+    ) -> Result<(), InterpreterError> {
+        let kind = probe.kind;
+
+        if tripped_at.is_none() && kind.must_trip() {
+            return Err(ProbeError::NotTripped(kind).into());
+        }
+
+        let offset = self.program_offset();
+
+        let stopped = tripped_at.unwrap_or(probe.end);
+
+        self.emit(Canon::StraightProbe {
+            kind,
+            end: probe.end,
+            stopped,
+        });
+
+        self.position = stopped - offset;
+
+        let results = self
+            .position
+            .iter()
+            .enumerate()
+            .map(|(axis, value)| (PROBE_POSITION + axis, f64::from(*value)))
+            .chain([(PROBE_TRIPPED, if tripped_at.is_some() { 1.0 } else { 0.0 })]);
+
+        for (index, value) in results {
+            self.parameters
+                .set_numbered(index, value)
+                .map_err(|e| ExpressionError {
+                    kind: ExpressionErrorKind::Parameter(e),
+                    span,
+                })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::run;
+    use alloc::format;
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    fn table() -> Solid {
+        Solid::Plane {
+            point: [0.0, 0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
+        }
+    }
+
+    fn at(x: Number, y: Number, z: Number) -> Position {
+        let mut position = Position::zeros();
+        position[0] = x;
+        position[1] = y;
+        position[2] = z;
+
+        position
+    }
+
+    fn probe_move(kind: ProbeKind, start: Position, end: Position) -> ProbeMove {
+        ProbeMove {
+            kind,
+            start,
+            end,
+            feed_rate: 10.0,
+        }
+    }
+
+    fn parameter(interp: &Interpreter, index: usize) -> f64 {
+        interp.numbered_parameter(index).unwrap()
+    }
+
+    #[test]
+    fn simulated_probe() {
+        let mut probe = SimulatedProbe::new([
+            table(),
+            Solid::Box {
+                min: [1.0, -1.0, -1.0],
+                max: [2.0, 1.0, 1.0],
+            },
+        ]);
+
+        let mut run = |kind, start, end| probe.probe(&probe_move(kind, start, end));
+
+        assert_eq!(
+            run(ProbeKind::Contact, at(0.0, 0.0, 2.0), at(0.0, 0.0, -2.0)),
+            Ok(Some(at(0.0, 0.0, 0.0)))
+        );
+        assert_eq!(
+            run(ProbeKind::Contact, at(0.0, 0.0, 0.5), at(3.0, 0.0, 0.5)),
+            Ok(Some(at(1.0, 0.0, 0.5)))
+        );
+        assert_eq!(
+            run(
+                ProbeKind::ContactNoError,
+                at(0.0, 0.0, 2.0),
+                at(3.0, 0.0, 2.0)
+            ),
+            Ok(None)
+        );
+
+        // Out of the box and through the table
+        assert_eq!(
+            run(
+                ProbeKind::LossOfContact,
+                at(1.5, 0.0, 0.0),
+                at(1.5, 0.0, 3.0)
+            ),
+            Ok(Some(at(1.5, 0.0, 1.0)))
+        );
+        assert_eq!(
+            run(
+                ProbeKind::LossOfContact,
+                at(1.5, 0.0, 0.5),
+                at(1.5, 0.0, -3.0)
+            ),
+            Ok(None)
+        );
+
+        // Already in the state the move looks for
+        assert_eq!(
+            run(ProbeKind::Contact, at(1.5, 0.0, 0.5), at(1.5, 0.0, 3.0)),
+            Err(ProbeError::AlreadyTripped)
+        );
+        assert_eq!(
+            run(
+                ProbeKind::LossOfContact,
+                at(0.0, 0.0, 1.0),
+                at(0.0, 0.0, 2.0)
+            ),
+            Err(ProbeError::AlreadyClear)
+        );
+        assert_eq!(
+            run(
+                ProbeKind::LossOfContactNoError,
+                at(0.0, 0.0, 1.0),
+                at(0.0, 0.0, 2.0)
+            ),
+            Ok(Some(at(0.0, 0.0, 1.0)))
+        );
+
+        // Back off the table after touching it, touch it again or slide along it
+        assert_eq!(
+            run(
+                ProbeKind::LossOfContact,
+                at(0.0, 0.0, 0.0),
+                at(0.0, 0.0, 1.0)
+            ),
+            Ok(Some(at(0.0, 0.0, 0.0)))
+        );
+        assert_eq!(
+            run(ProbeKind::Contact, at(0.0, 0.0, 0.0), at(0.0, 0.0, -1.0)),
+            Ok(Some(at(0.0, 0.0, 0.0)))
+        );
+        assert_eq!(
+            run(
+                ProbeKind::ContactNoError,
+                at(0.0, 0.0, 0.0),
+                at(0.5, 0.0, 0.0)
+            ),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn straight_probe() {
+        let mut interp = Interpreter::new();
+        interp.set_probe(SimulatedProbe::new([table()]));
+
+        // Machine Z is 2 below program Z
+        interp.set_numbered_parameter(5223, -2.0).unwrap();
+
+        let canon = run(&mut interp, "G0 X1 Z5\nF10 G91 G38.2 Z-5").unwrap();
+
+        assert_eq!(
+            canon.last(),
+            Some(&Canon::StraightProbe {
+                kind: ProbeKind::Contact,
+                end: at(1.0, 0.0, -2.0),
+                stopped: at(1.0, 0.0, 0.0),
+            })
+        );
+        assert_eq!(interp.position()[2], 2.0);
+        assert_eq!(parameter(&interp, 5061), 1.0);
+        assert_eq!(parameter(&interp, 5063), 2.0);
+        assert_eq!(parameter(&interp, 5070), 1.0);
+        assert_eq!(interp.named_parameter("_motion_mode"), Some(382.0));
+
+        // Already clear as soon as it moves up, then along the table without touching it
+        run(&mut interp, "G90 G38.5 Z4").unwrap();
+
+        assert_eq!(interp.position()[2], 2.0);
+        assert_eq!(parameter(&interp, 5070), 1.0);
+
+        run(&mut interp, "G38.3 X3").unwrap();
+
+        assert_eq!(parameter(&interp, 5061), 3.0);
+        assert_eq!(parameter(&interp, 5063), 2.0);
+        assert_eq!(parameter(&interp, 5070), 0.0);
+        assert_eq!(interp.position()[0], 3.0);
+    }
+
+    #[test]
+    fn errors() {
+        let mut interp = Interpreter::new();
+
+        assert_eq!(
+            run(&mut interp, "G38.2 Z-1 F10"),
+            Err(ProbeError::NoProbe.into())
+        );
+
+        interp.set_probe(SimulatedProbe::default());
+
+        let mut error = |program: &str| run(&mut interp, program).unwrap_err();
+
+        assert_eq!(error("F0 G38.2 Z-1"), ProbeError::ZeroFeed.into());
+        assert_eq!(error("F10 G38.2"), ProbeError::MissingAxes.into());
+        assert_eq!(
+            error("F10 G38.2 Z-1"),
+            ProbeError::NotTripped(ProbeKind::Contact).into()
+        );
+        assert_eq!(error("F10 G38.4 Z-1"), ProbeError::AlreadyClear.into());
+        assert_eq!(error("G93 G38.2 Z-1"), ProbeError::InverseTime.into());
+        assert_eq!(
+            error("G95 F10 G38.2 Z-1"),
+            ProbeError::UnitsPerRevolution.into()
+        );
+        assert_eq!(
+            error("G94 F10 G41.1 D1 G38.2 Z-1"),
+            ProbeError::CutterComp.into()
+        );
+
+        assert_eq!(
+            ProbeError::NotTripped(ProbeKind::LossOfContact).to_string(),
+            "G38.4 move finished without breaking contact"
+        );
+    }
+
+    #[test]
+    fn grid_probe() {
+        let mut interp = Interpreter::new();
+        interp.set_probe(SimulatedProbe::new([
+            table(),
+            Solid::Box {
+                min: [0.5, 0.4, -1.0],
+                max: [1.5, 0.75, 0.05],
+            },
+        ]));
+
+        let canon = run(
+            &mut interp,
+            include_str!("../../test_files/linuxcnc/gridprobe.ngc"),
+        )
+        .unwrap();
+
+        let heights = canon
+            .iter()
+            .filter_map(|canon| match canon {
+                Canon::StraightProbe { stopped, .. } => Some((stopped[0], stopped[1], stopped[2])),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(heights.len(), 13 * 5);
+        assert!(heights.contains(&(0.0, 0.0, 0.0)));
+        assert!(heights.contains(&(1.0, 0.5, 0.05)));
+    }
+
+    #[test]
+    fn smart_probe() {
+        let mut interp = Interpreter::new();
+        interp.set_probe(SimulatedProbe::new([table()]));
+
+        let canon = run(
+            &mut interp,
+            include_str!("../../test_files/linuxcnc/smartprobe.ngc"),
+        )
+        .unwrap();
+
+        let touches = canon
+            .iter()
+            .filter(|canon| match canon {
+                Canon::StraightProbe {
+                    kind: ProbeKind::Contact,
+                    stopped,
+                    ..
+                } => stopped[2] == 0.0,
+                _ => false,
+            })
+            .count();
+
+        assert_eq!(touches, 51 * 51);
+    }
+
+    #[test]
+    fn probe_hole() {
+        let mut interp = Interpreter::new();
+
+        // A 0.6 square hole centred on X0.2 Y0.1
+        let wall = |min_x, max_x, min_y, max_y| Solid::Box {
+            min: [min_x, min_y, -1.0],
+            max: [max_x, max_y, 1.0],
+        };
+
+        interp.set_probe(SimulatedProbe::new([
+            wall(0.5, 2.0, -2.0, 2.0),
+            wall(-2.0, -0.1, -2.0, 2.0),
+            wall(-2.0, 2.0, 0.4, 2.0),
+            wall(-2.0, 2.0, -2.0, -0.2),
+        ]));
+
+        let program = format!(
+            "G0 X0.3 Y0.05 Z0\n{}\nO<probe-hole> call [1]",
+            include_str!("../../test_files/linuxcnc/probe-hole.ngc")
+        );
+
+        run(&mut interp, &program).unwrap();
+
+        assert!((parameter(&interp, 1001) - 0.2).abs() < 1e-5);
+        assert!((parameter(&interp, 1002) - 0.1).abs() < 1e-5);
+        assert!((interp.position()[0] - 0.2).abs() < 1e-5);
+    }
+}
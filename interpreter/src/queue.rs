@@ -0,0 +1,245 @@
+//! Fixed capacity queue of commands waiting to be executed.
+//!
+//! The queue is a ring buffer sized with const generics so it never allocates. When it's full
+//! [`CommandQueue::push`] fails with [`QueueError::Full`] and the sender has to wait for commands
+//! to be executed, giving backpressure to whatever is feeding the interpreter.
+//!
+//! The canonical commands the interpreter outputs are bounded the same way. Once
+//! [`DEFAULT_OUTPUT_CAPACITY`], or the capacity set with
+//! [`Interpreter::set_output_capacity`](crate::Interpreter::set_output_capacity), are waiting to
+//! be read, no more blocks are run and
+//! [`Interpreter::pop_command`](crate::Interpreter::pop_command) fails with
+//! [`InterpreterError::OutputFull`](crate::InterpreterError::OutputFull) until some have been
+//! taken. A block that has started is always finished, so the output can go over its
+//! capacity by what one block produces, e.g. the repeats of a canned cycle.
+
+use common::Command;
+use core::fmt;
+
+/// Number of commands an [`Interpreter`](crate::Interpreter) can queue when no capacity is given.
+pub const DEFAULT_CAPACITY: usize = 16;
+
+/// Number of canonical commands an [`Interpreter`](crate::Interpreter) holds before it stops
+/// running blocks, when no other capacity is set.
+pub const DEFAULT_OUTPUT_CAPACITY: usize = 64;
+
+/// A first in, first out queue of up to `N` commands.
+pub struct CommandQueue<const N: usize> {
+    commands: [Option<Command>; N],
+    /// Index of the oldest command.
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> CommandQueue<N> {
+    pub fn new() -> Self {
+        Self {
+            commands: core::array::from_fn(|_| None),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Add a command to the back of the queue.
+    pub fn push(&mut self, command: Command) -> Result<(), QueueError> {
+        if self.is_full() {
+            return Err(QueueError::Full);
+        }
+
+        self.commands[(self.head + self.len) % N] = Some(command);
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Take the command at the front of the queue.
+    pub fn pop(&mut self) -> Option<Command> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let command = self.commands[self.head].take();
+
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+
+        command
+    }
+
+    /// Look at the `n`th command from the front of the queue without taking it. `peek(0)` is the
+    /// command [`pop`](Self::pop) would return.
+    pub fn peek(&self, n: usize) -> Option<&Command> {
+        if n >= self.len {
+            return None;
+        }
+
+        self.commands[(self.head + n) % N].as_ref()
+    }
+
+    /// Take every command out of the queue, front first.
+    ///
+    /// Commands not taken from the iterator are dropped along with it, so the queue is always
+    /// empty afterwards.
+    pub fn drain(&mut self) -> Drain<'_, N> {
+        Drain { queue: self }
+    }
+
+    /// Drop every command in the queue.
+    pub fn clear(&mut self) {
+        self.drain().for_each(drop);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<const N: usize> Default for CommandQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Iterator over the commands taken out of a queue by [`CommandQueue::drain`].
+pub struct Drain<'a, const N: usize> {
+    queue: &'a mut CommandQueue<N>,
+}
+
+impl<const N: usize> Iterator for Drain<'_, N> {
+    type Item = Command;
+
+    fn next(&mut self) -> Option<Command> {
+        self.queue.pop()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.queue.len, Some(self.queue.len))
+    }
+}
+
+impl<const N: usize> ExactSizeIterator for Drain<'_, N> {}
+
+impl<const N: usize> Drop for Drain<'_, N> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
+
+/// A command couldn't be queued.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum QueueError {
+    /// The queue is at capacity. Execute some commands and try again.
+    Full,
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full => write!(f, "Command queue is full"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use common::{Axis, Motion};
+
+    fn position(value: common::Number) -> Command {
+        Command::Position {
+            axis: Axis::X,
+            value,
+        }
+    }
+
+    #[test]
+    fn first_in_first_out() {
+        let mut queue = CommandQueue::<3>::new();
+
+        // Go round the end of the buffer
+        for round in 0..3 {
+            let base = round as common::Number * 10.0;
+
+            queue.push(position(base)).unwrap();
+            queue.push(position(base + 1.0)).unwrap();
+
+            assert_eq!(queue.len(), 2);
+            assert_eq!(queue.pop(), Some(position(base)));
+            assert_eq!(queue.pop(), Some(position(base + 1.0)));
+            assert_eq!(queue.pop(), None);
+        }
+    }
+
+    #[test]
+    fn full() {
+        let mut queue = CommandQueue::<2>::new();
+
+        queue.push(position(1.0)).unwrap();
+        queue.push(Command::Motion(Motion::Feed)).unwrap();
+
+        assert!(queue.is_full());
+        assert_eq!(queue.push(position(2.0)), Err(QueueError::Full));
+
+        queue.pop();
+
+        assert_eq!(queue.push(position(2.0)), Ok(()));
+        assert_eq!(queue.len(), queue.capacity());
+    }
+
+    #[test]
+    fn peek() {
+        let mut queue = CommandQueue::<4>::new();
+
+        queue.push(position(1.0)).unwrap();
+        queue.pop();
+
+        for value in 2..=5 {
+            queue.push(position(value as common::Number)).unwrap();
+        }
+
+        assert_eq!(queue.peek(0), Some(&position(2.0)));
+        assert_eq!(queue.peek(3), Some(&position(5.0)));
+        assert_eq!(queue.peek(4), None);
+        assert_eq!(queue.len(), 4);
+    }
+
+    #[test]
+    fn drain() {
+        let mut queue = CommandQueue::<4>::new();
+
+        for value in 1..=3 {
+            queue.push(position(value as common::Number)).unwrap();
+        }
+
+        let mut drain = queue.drain();
+
+        assert_eq!(drain.len(), 3);
+        assert_eq!(drain.next(), Some(position(1.0)));
+
+        drop(drain);
+
+        assert!(queue.is_empty());
+
+        queue.push(position(4.0)).unwrap();
+        queue.push(position(5.0)).unwrap();
+
+        assert_eq!(
+            queue.drain().collect::<Vec<_>>(),
+            [position(4.0), position(5.0)]
+        );
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -0,0 +1,914 @@
+//! User defined and remapped codes.
+//!
+//! As with LinuxCNC's `REMAP`, a `G` or `M` code, or the `T`, `S` or `F` word, can be handed to
+//! user code instead of the interpreter's built in behaviour. This is also how new codes are
+//! added, e.g. the user `M` codes `M100` to `M199`, or a `G88.1` cycle. A [`Remap`] either:
+//!
+//! - calls a [`RemapHandler`] with the interpreter, which can inspect and change its state, or
+//! - calls an NGC subroutine, loaded like any other by name, optionally with a prolog run just
+//!   after the call and an epilog run at `endsub` while its locals are still in scope.
+//!
+//! The words a remap takes are listed like LinuxCNC's argspec: an upper case letter is required,
+//! a lower case one optional. They're taken out of the block and given to the remap, to a
+//! subroutine as the named locals `#<x>`, `#<p>` and so on. A remap runs at its code's place in
+//! the block's order of execution, so in `T1 M6` a remapped `T` has finished before the `M6`. The
+//! subroutine is run to its end before the rest of the block. New `M` codes run with the user
+//! `M` codes after coolant, and new `G` codes with motion.
+//!
+//! `#<_remap_level>` is the number of remaps running. Inside a remap its own code has its built in
+//! meaning, so a remapped `M6` can finish with an `M6` of its own, and any handler can return
+//! [`Remapped::BuiltIn`] to fall back to it instead.
+
+use crate::control_flow::SUBROUTINE_PARAMETERS;
+use crate::expression::{ExpressionError, ExpressionErrorKind};
+use crate::parameters::{ParameterError, ParameterStore};
+use crate::queue::DEFAULT_CAPACITY;
+use crate::{Interpreter, InterpreterError};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use common::{Block, Number, OName, Span};
+use core::fmt;
+
+/// A code that can be remapped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Code {
+    /// A `G` code in tenths, so `G88.1` is `881`.
+    G(u32),
+
+    /// An `M` code.
+    M(u32),
+
+    /// Tool selection.
+    T,
+
+    /// Spindle speed.
+    S,
+
+    /// Feed rate.
+    F,
+}
+
+impl Code {
+    /// A `G` code, e.g. `Code::g(88.1)`.
+    pub fn g(code: Number) -> Self {
+        Self::G((code * 10.0).round() as u32)
+    }
+
+    /// An `M` code, e.g. `Code::m(6.0)`.
+    pub fn m(code: Number) -> Self {
+        Self::M(code.round() as u32)
+    }
+
+    /// The code a word in a block gives, if it's one that can be remapped.
+    fn from_word(letter: char, value: Number) -> Option<Self> {
+        match letter {
+            'G' => Some(Self::g(value)),
+            'M' => Some(Self::m(value)),
+            'T' => Some(Self::T),
+            'S' => Some(Self::S),
+            'F' => Some(Self::F),
+            _ => None,
+        }
+    }
+
+    /// Where the code runs in a block.
+    fn step(&self) -> Step {
+        match self {
+            Self::F => Step::FeedRate,
+            Self::S => Step::SpindleSpeed,
+            Self::T => Step::Tool,
+            Self::M(6 | 61) => Step::ToolChange,
+            Self::M(3..=5) => Step::Spindle,
+            Self::M(7..=9) => Step::Coolant,
+            Self::M(48..=53) => Step::Overrides,
+            Self::M(62..=68) => Step::Io,
+            Self::M(0 | 1 | 2 | 30 | 60) => Step::Stop,
+            Self::M(_) => Step::UserM,
+            Self::G(70 | 80 | 930 | 940 | 950) => Step::FeedRateMode,
+            Self::G(960 | 970) => Step::SpindleSpeed,
+            Self::G(40) => Step::Dwell,
+            Self::G(170 | 180 | 190) => Step::Plane,
+            Self::G(200 | 210) => Step::Units,
+            Self::G(400..=421) => Step::CutterComp,
+            Self::G(900..=911) => Step::DistanceMode,
+            Self::G(430..=432 | 490) => Step::ToolLengthOffset,
+            Self::G(540..=593) => Step::CoordinateSystem,
+            Self::G(980 | 990) => Step::RetractMode,
+            Self::G(280 | 281 | 300 | 301) => Step::PredefinedPosition,
+            Self::G(_) => Step::Motion,
+        }
+    }
+
+    /// The word that gives the code in a block, or `None` for `T`, `S` and `F`, which are among
+    /// the remap's words.
+    fn word(&self) -> Option<(char, Number)> {
+        match self {
+            Self::G(tenths) => Some(('G', *tenths as Number / 10.0)),
+            Self::M(code) => Some(('M', *code as Number)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::G(tenths) if tenths % 10 == 0 => write!(f, "G{}", tenths / 10),
+            Self::G(tenths) => write!(f, "G{}.{}", tenths / 10, tenths % 10),
+            Self::M(code) => write!(f, "M{}", code),
+            Self::T => write!(f, "T"),
+            Self::S => write!(f, "S"),
+            Self::F => write!(f, "F"),
+        }
+    }
+}
+
+/// The order commands in a block are executed in, following LinuxCNC.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Step {
+    FeedRateMode,
+    FeedRate,
+    SpindleSpeed,
+    Tool,
+    ToolChange,
+    Spindle,
+    Coolant,
+    Overrides,
+    Io,
+    UserM,
+    Dwell,
+    Plane,
+    Units,
+    CutterComp,
+    DistanceMode,
+    ToolLengthOffset,
+    CoordinateSystem,
+    RetractMode,
+    PredefinedPosition,
+    Motion,
+    Stop,
+}
+
+/// A remapped code used in a block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemapCall {
+    pub code: Code,
+
+    /// The evaluated words taken by the remap, in block order.
+    pub words: Vec<(char, Number)>,
+
+    /// The block the code was used in.
+    pub span: Span,
+}
+
+impl RemapCall {
+    /// Get the value of a word taken by the remap.
+    pub fn value(&self, letter: char) -> Option<Number> {
+        self.words
+            .iter()
+            .find(|(word, _)| *word == letter)
+            .map(|(_, value)| *value)
+    }
+
+    /// An error from setting a parameter, e.g. a local for the subroutine, in the remapped block.
+    pub fn parameter_error(&self, e: ParameterError) -> InterpreterError {
+        ExpressionError {
+            kind: ExpressionErrorKind::Parameter(e),
+            span: self.span,
+        }
+        .into()
+    }
+}
+
+/// What to do after a remap handler has run.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Remapped {
+    /// The code has been dealt with.
+    Done,
+
+    /// Carry out the code's built in behaviour with the remap's words. From a subroutine's prolog
+    /// this is instead of calling the subroutine.
+    BuiltIn,
+}
+
+/// User code run for a remapped code, or as the prolog or epilog of a subroutine.
+pub trait RemapHandler<P, const N: usize = DEFAULT_CAPACITY> {
+    fn remap(
+        &mut self,
+        interp: &mut Interpreter<P, N>,
+        call: &RemapCall,
+    ) -> Result<Remapped, InterpreterError>;
+}
+
+impl<P, F, const N: usize> RemapHandler<P, N> for F
+where
+    F: FnMut(&mut Interpreter<P, N>, &RemapCall) -> Result<Remapped, InterpreterError>,
+{
+    fn remap(
+        &mut self,
+        interp: &mut Interpreter<P, N>,
+        call: &RemapCall,
+    ) -> Result<Remapped, InterpreterError> {
+        self(interp, call)
+    }
+}
+
+enum Target<P, const N: usize> {
+    Callback(Box<dyn RemapHandler<P, N> + Send>),
+    Subroutine(OName),
+}
+
+/// What a remapped code does instead of its built in behaviour.
+pub struct Remap<P, const N: usize = DEFAULT_CAPACITY> {
+    target: Target<P, N>,
+
+    /// Letters of the words taken, upper case if required.
+    words: String,
+    prolog: Option<Box<dyn RemapHandler<P, N> + Send>>,
+    epilog: Option<Box<dyn RemapHandler<P, N> + Send>>,
+}
+
+impl<P, const N: usize> Remap<P, N> {
+    /// Call a handler. Callbacks aren't run during the dry run of a restart.
+    pub fn callback(handler: impl RemapHandler<P, N> + Send + 'static) -> Self {
+        Self::new(Target::Callback(Box::new(handler)))
+    }
+
+    /// Call the subroutine `o<name>`.
+    pub fn subroutine(name: &str) -> Self {
+        Self::new(Target::Subroutine(OName::Named(name.to_ascii_lowercase())))
+    }
+
+    fn new(target: Target<P, N>) -> Self {
+        Self {
+            target,
+            words: String::new(),
+            prolog: None,
+            epilog: None,
+        }
+    }
+
+    /// Set the words taken from the block, e.g. `"XYZpq"` for required `X`, `Y` and `Z` and
+    /// optional `P` and `Q` words.
+    pub fn words(mut self, words: &str) -> Self {
+        self.words = words.into();
+        self
+    }
+
+    /// Run a handler after calling the subroutine, before its first block.
+    pub fn prolog(mut self, handler: impl RemapHandler<P, N> + Send + 'static) -> Self {
+        self.prolog = Some(Box::new(handler));
+        self
+    }
+
+    /// Run a handler at the end of the subroutine, after `#<_value>` is set from `endsub` or
+    /// `return`.
+    pub fn epilog(mut self, handler: impl RemapHandler<P, N> + Send + 'static) -> Self {
+        self.epilog = Some(Box::new(handler));
+        self
+    }
+
+    fn takes(&self, letter: char) -> bool {
+        self.words
+            .chars()
+            .any(|word| word.eq_ignore_ascii_case(&letter))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemapError {
+    /// A remap failed, e.g. its subroutine returned a negative value. Raised by handlers.
+    Failed(Code, f64),
+}
+
+impl fmt::Display for RemapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failed(code, value) => write!(f, "{} failed ({})", code, value),
+        }
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Remap a code, replacing any previous remap of it.
+    pub fn remap(&mut self, code: Code, remap: Remap<P, N>) {
+        self.remaps.insert(code, remap);
+    }
+
+    /// Remove a remap, restoring the built in behaviour of its code.
+    pub fn remove_remap(&mut self, code: Code) -> Option<Remap<P, N>> {
+        self.remaps.remove(&code)
+    }
+
+    /// Execute a block straight away, e.g. from a remap handler. O-word statements are ignored.
+    pub fn execute(&mut self, block: &Block) -> Result<(), InterpreterError> {
+        self.execute_block(block)
+    }
+
+    /// Number of remaps running, `#<_remap_level>`.
+    pub(crate) fn remap_level(&self) -> usize {
+        self.control_flow.remap_depth() + self.remap_callbacks
+    }
+
+    /// Take remapped codes and their words out of a block's evaluated words, returning them in
+    /// the order they run.
+    pub(crate) fn find_remaps(
+        &self,
+        words: &mut Vec<(char, Number)>,
+        span: Span,
+    ) -> Result<Vec<RemapCall>, InterpreterError> {
+        let mut calls = Vec::new();
+
+        for code in words
+            .iter()
+            .filter_map(|&(letter, value)| Code::from_word(letter, value))
+        {
+            if !self.remaps.contains_key(&code) || self.control_flow.remapping(code) {
+                continue;
+            }
+
+            let remap = &self.remaps[&code];
+
+            let taken = words
+                .iter()
+                .copied()
+                .filter(|&(letter, value)| match letter {
+                    'G' | 'M' => false,
+                    _ => Code::from_word(letter, value) == Some(code) || remap.takes(letter),
+                })
+                .collect::<Vec<_>>();
+
+            if let Some(letter) = remap
+                .words
+                .chars()
+                .filter(|letter| letter.is_ascii_uppercase())
+                .find(|letter| taken.iter().all(|(word, _)| word != letter))
+            {
+                return Err(InterpreterError::MissingWord(letter));
+            }
+
+            calls.push(RemapCall {
+                code,
+                words: taken,
+                span,
+            });
+        }
+
+        words.retain(|&(letter, value)| {
+            calls.iter().all(|call| {
+                Code::from_word(letter, value) != Some(call.code)
+                    && call.words.iter().all(|(word, _)| *word != letter)
+            })
+        });
+
+        // Stable, so remaps at the same step run in block order
+        calls.sort_by_key(|call| call.code.step());
+
+        Ok(calls)
+    }
+
+    /// Run the remaps that come before or at `step` in a block.
+    pub(crate) fn run_remaps(
+        &mut self,
+        step: Step,
+        calls: &mut Vec<RemapCall>,
+    ) -> Result<(), InterpreterError> {
+        let count = calls
+            .iter()
+            .take_while(|call| call.code.step() <= step)
+            .count();
+
+        for call in calls.drain(..count).collect::<Vec<_>>() {
+            self.run_remap(call)?;
+        }
+
+        Ok(())
+    }
+
+    fn run_remap(&mut self, call: RemapCall) -> Result<(), InterpreterError> {
+        let mut remap = match self.remaps.remove(&call.code) {
+            Some(remap) => remap,
+            None => return Ok(()),
+        };
+
+        // Changes made by the block so far aren't made by the subroutine's blocks
+        self.record_changes(call.span);
+
+        // The remap is out of the table while its handlers run, so they see the built in code
+        let result = self.start_remap(&mut remap, &call);
+
+        self.remaps.entry(call.code).or_insert(remap);
+
+        match result? {
+            Remapped::Done => (),
+            Remapped::BuiltIn => return self.built_in(&call),
+        }
+
+        // The subroutine's epilog runs when it returns
+        if self.control_flow.remapping(call.code) {
+            self.run_subroutine()?;
+        }
+
+        Ok(())
+    }
+
+    fn start_remap(
+        &mut self,
+        remap: &mut Remap<P, N>,
+        call: &RemapCall,
+    ) -> Result<Remapped, InterpreterError> {
+        let name = match &mut remap.target {
+            Target::Callback(_) if self.dry_run => return Ok(Remapped::Done),
+            Target::Callback(handler) => {
+                self.remap_callbacks += 1;
+                let result = handler.remap(self, call);
+                self.remap_callbacks -= 1;
+
+                return result;
+            }
+            Target::Subroutine(name) => name,
+        };
+
+        self.call_subroutine(
+            name,
+            &[0.0; SUBROUTINE_PARAMETERS],
+            call.span,
+            Some(call.clone()),
+        )?;
+
+        for &(letter, value) in call.words.iter() {
+            self.parameters
+                .set_local(
+                    letter.to_ascii_lowercase().encode_utf8(&mut [0; 4]),
+                    value.into(),
+                )
+                .map_err(|e| call.parameter_error(e))?;
+        }
+
+        let action = match &mut remap.prolog {
+            Some(prolog) => prolog.remap(self, call)?,
+            None => Remapped::Done,
+        };
+
+        if action == Remapped::BuiltIn {
+            self.pop_frame(call.span)?;
+        }
+
+        Ok(action)
+    }
+
+    /// Run the epilog of a remap whose subroutine is returning.
+    pub(crate) fn remap_epilog(&mut self, call: &RemapCall) -> Result<Remapped, InterpreterError> {
+        let mut remap = match self.remaps.remove(&call.code) {
+            Some(remap) => remap,
+            None => return Ok(Remapped::Done),
+        };
+
+        let result = match &mut remap.epilog {
+            Some(epilog) => epilog.remap(self, call),
+            None => Ok(Remapped::Done),
+        };
+
+        self.remaps.entry(call.code).or_insert(remap);
+
+        result
+    }
+
+    /// Carry out the built in behaviour of a remapped code.
+    pub(crate) fn built_in(&mut self, call: &RemapCall) -> Result<(), InterpreterError> {
+        let words = call
+            .code
+            .word()
+            .into_iter()
+            .chain(call.words.iter().copied())
+            .collect::<Vec<_>>();
+
+        self.execute_words(&words, Vec::new(), call.span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canon::Canon;
+    use crate::test_utils::run;
+    use crate::tools::Tool;
+    use alloc::string::ToString;
+    use alloc::sync::Arc;
+    use common::Word;
+    use std::sync::Mutex;
+
+    fn add_example(interp: &mut Interpreter, name: &str, program: &str) {
+        interp.add_program(name, parser::parse_program(program).unwrap());
+    }
+
+    #[test]
+    fn codes() {
+        assert_eq!(Code::g(88.1), Code::G(881));
+        assert_eq!(Code::g(88.1).to_string(), "G88.1");
+        assert_eq!(Code::g(76.0).to_string(), "G76");
+        assert_eq!(Code::m(101.0).to_string(), "M101");
+        assert_eq!(Code::G(881).word(), Some(('G', 88.1)));
+        assert_eq!(Code::T.word(), None);
+    }
+
+    #[test]
+    fn user_m_code() {
+        let mut interp = Interpreter::new();
+
+        assert_eq!(
+            run(&mut interp, "M101 P2"),
+            Err(InterpreterError::UnknownMCode(101.0))
+        );
+
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let seen = levels.clone();
+
+        // Dwell for `P` seconds
+        interp.remap(
+            Code::m(101.0),
+            Remap::callback(move |interp: &mut Interpreter, call: &RemapCall| {
+                seen.lock()
+                    .unwrap()
+                    .push(interp.named_parameter("_remap_level").unwrap());
+
+                interp.execute(&Block::new(alloc::vec![
+                    Word::new('G', 4.0),
+                    Word::new('P', call.value('P').unwrap_or(1.0)),
+                ]))?;
+
+                Ok(Remapped::Done)
+            })
+            .words("p"),
+        );
+
+        // User M codes run after coolant and before motion
+        assert_eq!(
+            run(&mut interp, "G0 X1 M101 P2 M8\nM101").unwrap(),
+            [
+                Canon::Coolant(common::Coolant::Flood),
+                Canon::Dwell(2.0),
+                Canon::StraightTraverse {
+                    end: common::Position::from([1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+                },
+                Canon::Dwell(1.0),
+            ]
+        );
+        assert_eq!(*levels.lock().unwrap(), [1.0, 1.0]);
+        assert_eq!(interp.named_parameter("_remap_level"), Some(0.0));
+
+        // Required words
+        interp.remap(
+            Code::m(102.0),
+            Remap::callback(|interp: &mut Interpreter, _: &RemapCall| {
+                interp.execute(&Block::new(alloc::vec![
+                    Word::new('G', 0.0),
+                    Word::new('X', 0.0)
+                ]))?;
+
+                Ok(Remapped::Done)
+            })
+            .words("Q"),
+        );
+
+        assert_eq!(
+            run(&mut interp, "M102 P1"),
+            Err(InterpreterError::MissingWord('Q'))
+        );
+
+        // Remaps in the same group run in block order, before motion
+        assert_eq!(
+            run(&mut interp, "M102 Q1 M101 P3 G1 X2 F100").unwrap()[1..],
+            [
+                Canon::StraightTraverse {
+                    end: common::Position::zeros()
+                },
+                Canon::Dwell(3.0),
+                Canon::StraightFeed {
+                    end: common::Position::from([2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+                },
+            ]
+        );
+
+        assert!(interp.remove_remap(Code::m(102.0)).is_some());
+        assert_eq!(
+            run(&mut interp, "M102 Q1"),
+            Err(InterpreterError::UnknownMCode(102.0))
+        );
+    }
+
+    #[test]
+    fn built_in() {
+        let mut interp = Interpreter::new();
+
+        // Only feed rates up to 1000 are remapped
+        interp.remap(
+            Code::F,
+            Remap::callback(
+                |interp: &mut Interpreter, call: &RemapCall| match call.value('F') {
+                    Some(feed) if feed > 1000.0 => Ok(Remapped::BuiltIn),
+                    _ => {
+                        interp
+                            .set_named_parameter("_slow", 1.0)
+                            .map_err(|e| call.parameter_error(e))?;
+
+                        Ok(Remapped::Done)
+                    }
+                },
+            ),
+        );
+
+        assert_eq!(run(&mut interp, "F500").unwrap(), []);
+        assert_eq!(interp.named_parameter("_slow"), Some(1.0));
+        assert_eq!(
+            run(&mut interp, "F2000").unwrap(),
+            [Canon::SetFeedRate(2000.0)]
+        );
+        assert_eq!(interp.feed_rate(), 2000.0);
+    }
+
+    #[test]
+    fn subroutine() {
+        let mut interp = Interpreter::new();
+        interp.tool_table_mut().insert(Tool::new(1));
+
+        add_example(
+            &mut interp,
+            "m6remap.ngc",
+            include_str!("../../test_files/linuxcnc/m6remap.ngc"),
+        );
+
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let seen = levels.clone();
+
+        // `m6remap` uses `M69`, and finishes with the built in `M6`
+        interp.remap(
+            Code::m(6.0),
+            Remap::subroutine("M6Remap")
+                .epilog(|_: &mut Interpreter, _: &RemapCall| Ok(Remapped::BuiltIn)),
+        );
+        interp.remap(
+            Code::m(69.0),
+            Remap::callback(move |interp: &mut Interpreter, _: &RemapCall| {
+                seen.lock()
+                    .unwrap()
+                    .push(interp.named_parameter("_remap_level").unwrap());
+
+                Ok(Remapped::Done)
+            }),
+        );
+
+        assert_eq!(
+            run(&mut interp, "T1 M6\nG0 X1").unwrap()[..2],
+            [Canon::SelectTool(1), Canon::ChangeTool(1)]
+        );
+        assert_eq!(*levels.lock().unwrap(), [2.0]);
+        assert_eq!(interp.modal_groups().tool(), 1);
+        assert_eq!(interp.call_stack().count(), 0);
+    }
+
+    #[test]
+    fn word_parameters() {
+        let mut interp = Interpreter::new();
+
+        add_example(
+            &mut interp,
+            "m250.ngc",
+            include_str!("../../test_files/linuxcnc/m250.ngc"),
+        );
+
+        let words = Arc::new(Mutex::new(Vec::new()));
+        let seen = words.clone();
+
+        interp.remap(
+            Code::m(250.0),
+            Remap::subroutine("m250").words("xyzpqr").epilog(
+                move |interp: &mut Interpreter, _: &RemapCall| {
+                    seen.lock().unwrap().extend(
+                        ["x", "y", "p", "r"]
+                            .iter()
+                            .map(|name| interp.named_parameter(name)),
+                    );
+
+                    Ok(Remapped::Done)
+                },
+            ),
+        );
+
+        let canon = run(&mut interp, "G0 A3 M250 X1 P2").unwrap();
+
+        // `X` is taken by the remap so only `A` moves
+        assert_eq!(
+            canon,
+            [Canon::StraightTraverse {
+                end: common::Position::from([0.0, 0.0, 0.0, 3.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+            }]
+        );
+        assert_eq!(*words.lock().unwrap(), [Some(1.0), None, Some(2.0), None]);
+        assert_eq!(interp.named_parameter("x"), None);
+    }
+
+    #[test]
+    fn nested_remaps() {
+        let mut interp = Interpreter::new();
+
+        add_example(
+            &mut interp,
+            "g882.ngc",
+            include_str!("../../test_files/linuxcnc/g882.ngc"),
+        );
+        add_example(
+            &mut interp,
+            "m75.ngc",
+            include_str!("../../test_files/linuxcnc/m75.ngc"),
+        );
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let epilog = seen.clone();
+
+        interp.remap(Code::g(88.2), Remap::subroutine("g882").words("XYZr"));
+        interp.remap(
+            Code::m(75.0),
+            Remap::subroutine("m75").words("pq").epilog(
+                move |interp: &mut Interpreter, call: &RemapCall| {
+                    epilog.lock().unwrap().push((
+                        interp.named_parameter("_remap_level"),
+                        interp.named_parameter("_call_level"),
+                        call.value('Q'),
+                    ));
+
+                    Ok(Remapped::Done)
+                },
+            ),
+        );
+
+        assert_eq!(
+            run(&mut interp, "G88.2 X1 Y2"),
+            Err(InterpreterError::MissingWord('Z'))
+        );
+
+        run(&mut interp, "G88.2 X1 Y2 Z3").unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), [(Some(2.0), Some(2.0), Some(47.0))]);
+        assert_eq!(interp.named_parameter("_value"), Some(1.0));
+    }
+
+    #[test]
+    fn remapped_cycle() {
+        let mut interp = Interpreter::new();
+
+        add_example(
+            &mut interp,
+            "g881.ngc",
+            include_str!("../../test_files/linuxcnc/g881.ngc"),
+        );
+
+        let words = Arc::new(Mutex::new(Vec::new()));
+        let seen = words.clone();
+
+        interp.remap(
+            Code::g(88.1),
+            Remap::subroutine("g881").words("xyzpqr").epilog(
+                move |interp: &mut Interpreter, _: &RemapCall| {
+                    seen.lock().unwrap().extend(
+                        ["x", "y", "z", "p", "q", "r"]
+                            .iter()
+                            .map(|name| interp.named_parameter(name)),
+                    );
+
+                    Ok(Remapped::Done)
+                },
+            ),
+        );
+
+        let at = |x: Number, y: Number, z: Number| {
+            common::Position::from([x, y, z, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+        };
+
+        run(&mut interp, "G0 Z5\nG98 G81 X1 Y1 R2 Z-1 F100").unwrap();
+
+        // The subroutine only reads its words, so the remapped cycle doesn't move or drill
+        assert_eq!(run(&mut interp, "G88.1 X2 Y3 Z-2 R1").unwrap(), []);
+        assert_eq!(
+            *words.lock().unwrap(),
+            [Some(2.0), Some(3.0), Some(-2.0), None, None, Some(1.0)]
+        );
+        assert_eq!(interp.named_parameter("_value"), Some(1.0));
+        assert_eq!(*interp.position(), at(1.0, 1.0, 5.0));
+
+        // The built in cycle is still in effect with its own R and Z
+        assert_eq!(
+            run(&mut interp, "X4 Y4").unwrap(),
+            [
+                Canon::StraightTraverse {
+                    end: at(4.0, 4.0, 5.0)
+                },
+                Canon::StraightTraverse {
+                    end: at(4.0, 4.0, 2.0)
+                },
+                Canon::StraightFeed {
+                    end: at(4.0, 4.0, -1.0)
+                },
+                Canon::StraightTraverse {
+                    end: at(4.0, 4.0, 5.0)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn prolog_and_epilog() {
+        let mut interp = Interpreter::new();
+
+        for tool in 1..=3 {
+            interp.tool_table_mut().insert(Tool::new(tool));
+        }
+
+        add_example(
+            &mut interp,
+            "prepare.ngc",
+            include_str!("../../test_files/linuxcnc/remap-subroutines/prepare.ngc"),
+        );
+        add_example(
+            &mut interp,
+            "change.ngc",
+            include_str!("../../test_files/linuxcnc/remap-subroutines/change.ngc"),
+        );
+
+        // A negative return value aborts the change, otherwise carry on with the built in code
+        let check =
+            |interp: &mut Interpreter, call: &RemapCall| match interp.named_parameter("_value") {
+                Some(value) if value < 0.0 => Err(RemapError::Failed(call.code, value).into()),
+                _ => Ok(Remapped::BuiltIn),
+            };
+
+        interp.remap(
+            Code::T,
+            Remap::subroutine("prepare")
+                .words("T")
+                .prolog(|interp: &mut Interpreter, call: &RemapCall| {
+                    let tool = call.value('T').unwrap_or(0.0);
+                    let pocket = interp
+                        .tool_table()
+                        .get(tool as u32)
+                        .map_or(0, |tool| tool.pocket);
+
+                    interp
+                        .set_named_parameter("tool", tool.into())
+                        .and_then(|_| interp.set_named_parameter("pocket", pocket.into()))
+                        .map_err(|e| call.parameter_error(e))?;
+
+                    Ok(Remapped::Done)
+                })
+                .epilog(check),
+        );
+        interp.remap(
+            Code::m(6.0),
+            Remap::subroutine("change")
+                .prolog(|interp: &mut Interpreter, call: &RemapCall| {
+                    let tool = interp.modal_groups().tool();
+                    let selected = interp.modal_groups().selected_tool();
+                    let pocket = interp.tool_table().get(selected).map_or(0, |t| t.pocket);
+
+                    // Nothing to do if the tool is already in the spindle
+                    if tool == selected {
+                        return Ok(Remapped::BuiltIn);
+                    }
+
+                    interp
+                        .set_named_parameter("tool_in_spindle", tool.into())
+                        .and_then(|_| interp.set_named_parameter("pocket", pocket.into()))
+                        .map_err(|e| call.parameter_error(e))?;
+
+                    Ok(Remapped::Done)
+                })
+                .epilog(check),
+        );
+
+        assert_eq!(
+            run(&mut interp, "T1 M6").unwrap(),
+            [Canon::SelectTool(1), Canon::ChangeTool(1)]
+        );
+        assert_eq!(run(&mut interp, "M6").unwrap(), [Canon::ChangeTool(1)]);
+        assert_eq!(interp.call_stack().count(), 0);
+
+        let error = interp.mdi("T2").unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "T failed (-1) at line 6, column 1\n    in o<prepare> called from line 1"
+        );
+        assert_eq!(interp.modal_groups().selected_tool(), 1);
+
+        // Clear the failed call
+        interp.pop_frame(Span::default()).unwrap();
+
+        assert_eq!(run(&mut interp, "T3").unwrap(), [Canon::SelectTool(3)]);
+        assert_eq!(
+            run(&mut interp, "M6"),
+            Err(RemapError::Failed(Code::M(6), -1.0).into())
+        );
+        assert_eq!(interp.modal_groups().tool(), 1);
+    }
+}
@@ -0,0 +1,412 @@
+//! Restart a program part way through, e.g. to carry on after replacing a broken tool.
+//!
+//! [`Interpreter::run_to_line`] executes every block before the restart line without producing
+//! any output, following subroutine calls, branches and loops as a normal run would, so modal
+//! state, parameters and the programmed position are what they'd be at that line. It returns a
+//! [`Preamble`] to bring the machine into that state, which can be shown to the operator (its
+//! `Display` impl gives the equivalent G code) before [`Interpreter::run_preamble`] queues it.
+//! The tool is left as it was on the machine until the preamble changes it through the tool
+//! changer, or at a sync point, as an `M6` would.
+//! The rest of the program is then queued from the restart line as usual. If cutter compensation
+//! is on at the restart line, the first move in the plane after it is an entry move.
+
+use crate::canon::Canon;
+use crate::feed::FeedError;
+use crate::parameters::ParameterStore;
+use crate::{Interpreter, InterpreterError};
+use alloc::vec;
+use alloc::vec::Vec;
+use common::{
+    Axis, Block, Command, Coolant, CoordinateSystem, Number, Position, Spindle, Units, Word,
+};
+use core::fmt;
+
+/// Machine state and moves needed before restarting a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preamble {
+    pub units: Units,
+    pub coordinate_system: CoordinateSystem,
+
+    /// Tool to load, or `0` to leave the spindle empty.
+    pub tool: u32,
+
+    /// Offset set by `G43`, `G43.1` or `G43.2`.
+    pub tool_length_offset: Position,
+
+    /// Offset of the coordinate system from machine zero.
+    pub work_offset: Position,
+    pub spindle: Spindle,
+    pub spindle_speed: Number,
+    pub mist: bool,
+    pub flood: bool,
+
+    /// Z height to rapid at before plunging to the start position, in program coordinates.
+    pub clearance: Number,
+
+    /// Position at the restart line, in program coordinates.
+    pub start: Position,
+
+    /// Feed rate for the plunge, and of the program at the restart line.
+    pub feed_rate: Number,
+}
+
+impl Preamble {
+    /// Canonical commands for the preamble, moving from the machine's `current` position.
+    pub fn canon(&self, current: &Position) -> Vec<Canon> {
+        let mut canon = vec![
+            Canon::SetUnits(self.units),
+            Canon::SelectCoordinateSystem(self.coordinate_system),
+        ];
+
+        if self.tool != 0 {
+            canon.push(Canon::SelectTool(self.tool));
+            canon.push(Canon::ChangeTool(self.tool));
+        }
+
+        canon.push(Canon::UseToolLengthOffset(self.tool_length_offset));
+
+        canon.push(Canon::SetSpindleSpeed(self.spindle_speed));
+        canon.push(Canon::Spindle(self.spindle));
+
+        if self.mist {
+            canon.push(Canon::Coolant(Coolant::Mist));
+        }
+
+        if self.flood {
+            canon.push(Canon::Coolant(Coolant::Flood));
+        }
+
+        if !self.mist && !self.flood {
+            canon.push(Canon::Coolant(Coolant::Off));
+        }
+
+        let offset = self.work_offset + self.tool_length_offset;
+        let z = Axis::Z.index();
+
+        let mut retract = *current;
+        retract[z] = self.clearance + offset[z];
+
+        let mut above = self.start;
+        above[z] = self.clearance;
+
+        canon.extend([
+            Canon::StraightTraverse { end: retract },
+            Canon::StraightTraverse {
+                end: above + offset,
+            },
+            Canon::SetFeedRate(self.feed_rate),
+            Canon::StraightFeed {
+                end: self.start + offset,
+            },
+        ]);
+
+        canon
+    }
+}
+
+impl fmt::Display for Preamble {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.units {
+            Units::Inch => writeln!(f, "G20")?,
+            Units::Mm => writeln!(f, "G21")?,
+        }
+
+        writeln!(f, "{}", self.coordinate_system)?;
+
+        if self.tool != 0 {
+            writeln!(f, "T{} M6", self.tool)?;
+        }
+
+        if self.tool_length_offset.iter().all(|offset| *offset == 0.0) {
+            writeln!(f, "G49")?;
+        } else {
+            write!(f, "G43.1")?;
+
+            for axis in Axis::ALL.iter() {
+                let offset = self.tool_length_offset[axis.index()];
+
+                if offset != 0.0 {
+                    write!(f, " {}{}", axis.letter(), offset)?;
+                }
+            }
+
+            writeln!(f)?;
+        }
+
+        match self.spindle {
+            Spindle::Clockwise => writeln!(f, "S{} M3", self.spindle_speed)?,
+            Spindle::CounterClockwise => writeln!(f, "S{} M4", self.spindle_speed)?,
+            Spindle::Stopped => writeln!(f, "S{} M5", self.spindle_speed)?,
+        }
+
+        match (self.mist, self.flood) {
+            (true, true) => writeln!(f, "M7\nM8")?,
+            (true, false) => writeln!(f, "M7")?,
+            (false, true) => writeln!(f, "M8")?,
+            (false, false) => writeln!(f, "M9")?,
+        }
+
+        writeln!(f, "G0 Z{}", self.clearance)?;
+
+        write!(f, "G0")?;
+
+        for axis in Axis::ALL.iter().filter(|axis| **axis != Axis::Z) {
+            let value = self.start[axis.index()];
+
+            if matches!(axis, Axis::X | Axis::Y) || value != 0.0 {
+                write!(f, " {}{}", axis.letter(), value)?;
+            }
+        }
+
+        writeln!(
+            f,
+            "\nG1 Z{} F{}",
+            self.start[Axis::Z.index()],
+            self.feed_rate
+        )
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Execute the blocks of a program before `line` (counting from `1`, one block per line)
+    /// without producing any output, then work out the preamble needed to restart it at that
+    /// line with the tool rapiding in at a `clearance` height.
+    pub fn run_to_line(
+        &mut self,
+        blocks: &[Block],
+        line: usize,
+        clearance: Number,
+    ) -> Result<Preamble, InterpreterError> {
+        if line == 0 || line > blocks.len() {
+            return Err(InterpreterError::NoSuchLine(line));
+        }
+
+        let loaded = self.modal_groups.tool;
+        self.dry_run = true;
+
+        let result = blocks[..line - 1]
+            .iter()
+            .try_for_each(|block| self.execute_command(Command::Block(block.clone())));
+
+        self.dry_run = false;
+
+        // Nothing was output during the run, so the next move has to enter compensation afresh
+        self.reset_cutter_comp();
+
+        let tool = self.modal_groups.tool;
+
+        // The machine still has the tool it had before, until the preamble changes it
+        self.modal_groups.tool = loaded;
+
+        result?;
+
+        let modal = &self.modal_groups;
+
+        Ok(Preamble {
+            units: modal.units,
+            coordinate_system: modal.coordinate_system,
+            tool,
+            tool_length_offset: modal.tool_length_offset,
+            work_offset: self.work_offset(),
+            spindle: modal.spindle,
+            spindle_speed: modal.spindle_speed,
+            mist: modal.mist,
+            flood: modal.flood,
+            clearance,
+            start: self.position,
+            feed_rate: self.feed_rate,
+        })
+    }
+
+    /// Queue the preamble's canonical commands once the operator has confirmed it, moving from
+    /// the machine's `current` position. The tool change goes through the tool changer, or stops
+    /// at a sync point, as an `M6` in the program would.
+    pub fn run_preamble(
+        &mut self,
+        preamble: &Preamble,
+        current: &Position,
+    ) -> Result<(), InterpreterError> {
+        if preamble.feed_rate == 0.0 {
+            return Err(FeedError::ZeroFeedLine.into());
+        }
+
+        for canon in preamble.canon(current) {
+            match canon {
+                Canon::SelectTool(tool) => self.select_tool(tool as Number)?,
+                Canon::ChangeTool(_) => self.change_tool()?,
+                canon => self.output.push_back(canon),
+            }
+        }
+
+        if let Some(point) = self.pending_sync.take() {
+            let block = Block::new(vec![
+                Word::new('T', preamble.tool as Number),
+                Word::new('M', 6.0),
+            ]);
+
+            self.sync_after(point, &block);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{Feedback, SyncPoint};
+    use crate::test_utils::run_blocks;
+    use crate::tools::{tool_file, ToolChange, ToolChangeError};
+    use alloc::string::ToString;
+    use alloc::sync::Arc;
+    use std::sync::Mutex;
+
+    const PROGRAM: &str = "G21 G55
+        T2 M6 G43
+        S1000 M3 M8
+        G0 X1 Y1 Z1
+        #1 = 0
+        o1 repeat [3]
+            G1 X[#1 * 10] Z-1 F200
+            #1 = [#1 + 1]
+            G1 Y[#1 * 10]
+        o1 endrepeat
+        M5 M9";
+
+    fn interpreter() -> Interpreter {
+        let mut interp = Interpreter::new();
+        interp.set_tool_table(tool_file::parse("T2 Z+3").unwrap());
+        interp.set_numbered_parameter(5241, 100.0).unwrap();
+
+        interp
+    }
+
+    #[test]
+    fn restart_in_loop() {
+        let blocks = parser::parse_program(PROGRAM).unwrap();
+
+        // Restart part way through the first pass of the loop
+        let mut uninterrupted = interpreter();
+        let skipped = run_blocks(&mut uninterrupted, &blocks[..8]).unwrap().len();
+        let rest = run_blocks(&mut uninterrupted, &blocks[8..]).unwrap();
+
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&changes);
+
+        let mut interp = interpreter();
+        interp.set_tool_changer(move |change: &ToolChange| -> Result<(), ToolChangeError> {
+            seen.lock().unwrap().push(*change);
+
+            Ok(())
+        });
+
+        let preamble = interp.run_to_line(&blocks, 9, 5.0).unwrap();
+
+        // Nothing is changed while skipping to the restart line
+        assert_eq!(interp.next_canon(), None);
+        assert!(changes.lock().unwrap().is_empty());
+        assert_eq!(interp.modal_groups().tool(), 0);
+        assert_eq!(interp.numbered_parameter(1), Ok(1.0));
+        assert_eq!(
+            preamble.start,
+            Position::from([0.0, 1.0, -1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+        );
+        assert_eq!(
+            preamble.to_string(),
+            "G21\nG55\nT2 M6\nG43.1 Z3\nS1000 M3\nM8\nG0 Z5\nG0 X0 Y1\nG1 Z-1 F200\n"
+        );
+
+        let current = Position::from([50.0, 50.0, -2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        interp.run_preamble(&preamble, &current).unwrap();
+
+        assert_eq!(
+            changes.lock().unwrap()[..],
+            [ToolChange {
+                current: 0,
+                selected: 2,
+                pocket: 2,
+            }]
+        );
+        assert_eq!(interp.modal_groups().tool(), 2);
+
+        let canon = run_blocks(&mut interp, &blocks[8..]).unwrap();
+        let (preamble_canon, resumed) = canon.split_at(canon.len() - rest.len());
+
+        assert!(skipped > 0);
+        assert_eq!(resumed, rest);
+        assert_eq!(
+            preamble_canon[preamble_canon.len() - 4..],
+            [
+                Canon::StraightTraverse {
+                    end: Position::from([50.0, 50.0, 8.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+                },
+                Canon::StraightTraverse {
+                    end: Position::from([100.0, 1.0, 8.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+                },
+                Canon::SetFeedRate(200.0),
+                Canon::StraightFeed {
+                    end: Position::from([100.0, 1.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn preamble_sync_point() {
+        let blocks = parser::parse_program(PROGRAM).unwrap();
+
+        let mut interp = interpreter();
+        interp.set_sync_points(true);
+
+        let mut preamble = interp.run_to_line(&blocks, 9, 5.0).unwrap();
+
+        interp.run_preamble(&preamble, &Position::zeros()).unwrap();
+
+        // The machine changes the tool, and the old one stays in if it fails
+        assert!(matches!(
+            interp.sync_point(),
+            Some(SyncPoint::ToolChange(ToolChange {
+                current: 0,
+                selected: 2,
+                ..
+            }))
+        ));
+        assert!(interp
+            .synchronise(Feedback::ToolChange(Err(ToolChangeError(-1))))
+            .is_err());
+        assert_eq!(interp.modal_groups().tool(), 0);
+
+        preamble.feed_rate = 0.0;
+
+        assert_eq!(
+            interp.run_preamble(&preamble, &Position::zeros()),
+            Err(FeedError::ZeroFeedLine.into())
+        );
+    }
+
+    #[test]
+    fn compensated_skip() {
+        let blocks =
+            parser::parse_program("G0 X0 Y0\nG42.1 D1\nG1 X10 F100\nY10\nG40 G0 X20\nG0 Y20")
+                .unwrap();
+
+        let mut interp = Interpreter::new();
+        interp.set_output_capacity(1);
+
+        // Moves entering and leaving compensation aren't output either
+        interp.run_to_line(&blocks, 6, 5.0).unwrap();
+
+        assert_eq!(interp.next_canon(), None);
+        assert!(!interp.output_full());
+    }
+
+    #[test]
+    fn no_such_line() {
+        let blocks = parser::parse_program("G0 X1\nG0 X2").unwrap();
+
+        assert_eq!(
+            Interpreter::new().run_to_line(&blocks, 3, 5.0),
+            Err(InterpreterError::NoSuchLine(3))
+        );
+    }
+}
@@ -0,0 +1,419 @@
+//! Snapshots of interpreter state for pausing, resuming and crash recovery.
+//!
+//! A [`Snapshot`] holds everything needed to carry on executing a program from the block after
+//! the last one executed: modal state, parameters, the tool table, the call stack and loops, the
+//! program cursor, the current position, any move held back by cutter compensation, outputs
+//! waiting for the next move and the sync point the interpreter is stopped at. It doesn't hold
+//! the program itself. After [`Interpreter::restore`],
+//! queue the main program again from its first block; blocks before the cursor are stored without
+//! being executed. Subroutine files are found by name, so they must be added again with
+//! [`Interpreter::add_program`] or be on the subroutine search path.
+//!
+//! A snapshot is taken whenever the program is paused, by [`Interpreter::pause`] or `M0`, and
+//! when it's aborted with [`Interpreter::abort`]. The last one is kept until the next, for
+//! [`Interpreter::last_snapshot`] to save somewhere it will survive a crash.
+//!
+//! With the `serde` feature snapshots can be serialised. Named parameters are sorted so the same
+//! state always serialises to the same output.
+
+use crate::control_flow::{ControlFlowSnapshot, ProgramCursor};
+use crate::cutter_comp::CutterComp;
+use crate::io::Output;
+use crate::mdi::ProgramState;
+use crate::parameters::{ParameterError, ParameterStore, MAX_NUMBERED};
+use crate::sync::{SyncPoint, Waiting};
+use crate::tools::ToolTable;
+use crate::{Interpreter, InterpreterError, ModalGroupState};
+use alloc::string::String;
+use alloc::vec::Vec;
+use common::{Block, Number, Position, Span};
+use core::fmt;
+
+/// Version of the snapshot format. Snapshots with a different version can't be restored.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    /// [`SNAPSHOT_VERSION`] when the snapshot was taken.
+    pub version: u32,
+    pub modal_groups: ModalGroupState,
+    pub feed_rate: Number,
+    pub position: [Number; 9],
+    pub parameters: ParameterSnapshot,
+    pub control_flow: ControlFlowSnapshot,
+    pub program_state: ProgramState,
+    pub tool_table: ToolTable,
+
+    /// `M62`, `M63` and `M67` outputs waiting for the next move.
+    pub synced_outputs: Vec<Output>,
+
+    /// The sync point the interpreter is stopped at, with the span of the block that reached it.
+    pub waiting: Option<(SyncPoint, Span)>,
+
+    /// Program position when the machine last gave feedback.
+    pub synced_position: Option<[Number; 9]>,
+
+    /// Cutter compensation, with the move it's holding back until the next one arrives.
+    cutter_comp: Option<CutterComp>,
+}
+
+impl Snapshot {
+    /// The next block to execute.
+    pub fn cursor(&self) -> &ProgramCursor {
+        &self.control_flow.cursor
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParameterSnapshot {
+    /// Non-zero numbered parameters, in ascending order.
+    pub numbered: Vec<(usize, f64)>,
+
+    /// Global named parameters, sorted by name.
+    pub globals: Vec<(String, f64)>,
+
+    /// Local named parameters sorted by name, for each scope from the main program down.
+    pub locals: Vec<Vec<(String, f64)>>,
+}
+
+fn sorted(mut named: Vec<(String, f64)>) -> Vec<(String, f64)> {
+    named.sort_by(|a, b| a.0.cmp(&b.0));
+
+    named
+}
+
+impl ParameterSnapshot {
+    fn take(parameters: &impl ParameterStore) -> Self {
+        let numbered = (1..=MAX_NUMBERED)
+            .filter_map(|index| match parameters.numbered(index) {
+                Ok(value) if value != 0.0 => Some((index, value)),
+                _ => None,
+            })
+            .collect();
+
+        let mut globals = Vec::new();
+        parameters.for_each_global(&mut |name, value| globals.push((name.into(), value)));
+
+        let locals = (0..=parameters.scope_depth())
+            .map(|scope| {
+                let mut locals = Vec::new();
+                parameters
+                    .for_each_local(scope, &mut |name, value| locals.push((name.into(), value)));
+
+                sorted(locals)
+            })
+            .collect();
+
+        Self {
+            numbered,
+            globals: sorted(globals),
+            locals,
+        }
+    }
+
+    fn restore(&self, parameters: &mut impl ParameterStore) -> Result<(), ParameterError> {
+        for (index, value) in self.numbered.iter() {
+            parameters.set_numbered(*index, *value)?;
+        }
+
+        for (name, value) in self.globals.iter() {
+            parameters.set_global(name, *value)?;
+        }
+
+        for (scope, locals) in self.locals.iter().enumerate() {
+            if scope > 0 {
+                parameters.push_scope()?;
+            }
+
+            for (name, value) in locals.iter() {
+                parameters.set_local(name, *value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Capture the interpreter's state, e.g. when a program is paused or aborted.
+    ///
+    /// Queued commands and canonical commands that haven't been taken yet aren't included.
+    pub fn snapshot(&self) -> Snapshot {
+        let array = |position: &Position| {
+            let mut array = [0.0; 9];
+            array.copy_from_slice(position.as_slice());
+
+            array
+        };
+
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            modal_groups: self.modal_groups.clone(),
+            feed_rate: self.feed_rate,
+            position: array(&self.position),
+            parameters: ParameterSnapshot::take(&self.parameters),
+            control_flow: self.control_flow.snapshot(),
+            program_state: self.program_state,
+            tool_table: self.tool_table.clone(),
+            synced_outputs: self.synced_outputs.clone(),
+            waiting: self
+                .waiting
+                .as_ref()
+                .map(|waiting| (waiting.point.clone(), waiting.block.span)),
+            synced_position: self.synced_position.as_ref().map(array),
+            cutter_comp: self.cutter_comp.clone(),
+        }
+    }
+
+    /// The snapshot taken when the program was last paused or aborted.
+    pub fn last_snapshot(&self) -> Option<&Snapshot> {
+        self.last_snapshot.as_ref()
+    }
+
+    /// Replace the interpreter's state with a snapshot, discarding queued commands and pending
+    /// output. The main program must then be queued again from its first block.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), InterpreterError>
+    where
+        P: Default,
+    {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version).into());
+        }
+
+        let mut parameters = P::default();
+
+        snapshot
+            .parameters
+            .restore(&mut parameters)
+            .map_err(SnapshotError::Parameter)?;
+
+        self.control_flow.restore(snapshot.control_flow)?;
+
+        self.parameters = parameters;
+        self.modal_groups = snapshot.modal_groups;
+        self.feed_rate = snapshot.feed_rate;
+        self.position = Position::from(snapshot.position);
+        self.program_state = snapshot.program_state;
+        self.tool_table = snapshot.tool_table;
+        self.synced_outputs = snapshot.synced_outputs;
+        self.waiting = snapshot.waiting.map(|(point, span)| Waiting {
+            point,
+            block: Block {
+                span,
+                ..Block::default()
+            },
+        });
+        self.synced_position = snapshot.synced_position.map(Position::from);
+        self.cutter_comp = snapshot.cutter_comp;
+        self.queue.clear();
+        self.output.clear();
+
+        Ok(())
+    }
+}
+
+/// A snapshot couldn't be restored.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    /// The snapshot was taken by an interpreter with a different [`SNAPSHOT_VERSION`].
+    UnsupportedVersion(u32),
+
+    /// A program in the call stack hasn't been added and isn't on the search path.
+    UnknownProgram(String),
+
+    /// Parameters couldn't be stored, e.g. because a fixed capacity store is too small.
+    Parameter(ParameterError),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Snapshot version {} is not supported, expected {}",
+                version, SNAPSHOT_VERSION
+            ),
+            Self::UnknownProgram(name) => write!(f, "Unable to find program {}", name),
+            Self::Parameter(e) => e.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Feedback;
+    use crate::test_utils::run_blocks;
+    use crate::tools::tool_file;
+
+    const PROGRAM: &str = "#<_scale> = 2
+        #<offset> = 1
+        #1 = 0
+        o1 while [#1 LT 4]
+            o<lib> call [#1]
+            G1 X[#<_value> + #<offset>] F100
+            #1 = [#1 + 1]
+        o1 endwhile
+        G0 X0";
+
+    const LIB: &str = "o<lib> sub
+        #<local> = [#1 * #<_scale>]
+        o<lib> return [#<local>]
+        o<lib> endsub";
+
+    fn interpreter() -> Interpreter {
+        let mut interp = Interpreter::new();
+        interp.add_program("lib.ngc", parser::parse_program(LIB).unwrap());
+
+        interp
+    }
+
+    #[test]
+    fn resume_matches_uninterrupted_run() {
+        let blocks = parser::parse_program(PROGRAM).unwrap();
+
+        let uninterrupted = run_blocks(&mut interpreter(), &blocks).unwrap();
+
+        // Stop inside the loop, before its end has been seen
+        let mut first = interpreter();
+        let mut canon = run_blocks(&mut first, &blocks[..6]).unwrap();
+        let snapshot = first.snapshot();
+
+        assert_eq!(snapshot.cursor().block, 6);
+        assert_eq!(snapshot.parameters.locals, [[("offset".into(), 1.0)]]);
+
+        let mut resumed = interpreter();
+        resumed.restore(snapshot.clone()).unwrap();
+
+        assert_eq!(resumed.snapshot(), snapshot);
+
+        canon.extend(run_blocks(&mut resumed, &blocks).unwrap());
+
+        assert_eq!(canon, uninterrupted);
+    }
+
+    #[test]
+    fn held_move_and_sync_point() {
+        let blocks = parser::parse_program(
+            "G41.1 D2 G1 X10 F100
+            G1 X20
+            M62 P1
+            T1 M6
+            G1 Y10
+            G40 G1 X30",
+        )
+        .unwrap();
+
+        let interpreter = || {
+            let mut interp = interpreter();
+            interp.set_tool_table(tool_file::parse("T1 P1\n").unwrap());
+            interp.set_sync_points(true);
+
+            interp
+        };
+
+        let mut uninterrupted = interpreter();
+        let mut expected = run_blocks(&mut uninterrupted, &blocks[..4]).unwrap();
+        uninterrupted
+            .synchronise(Feedback::ToolChange(Ok(())))
+            .unwrap();
+        expected.extend(run_blocks(&mut uninterrupted, &blocks[4..]).unwrap());
+
+        // Stopped for the tool change, with `X20` held back until the move after it
+        let mut first = interpreter();
+        let mut canon = run_blocks(&mut first, &blocks[..4]).unwrap();
+        let snapshot = first.snapshot();
+
+        assert!(matches!(
+            snapshot.waiting,
+            Some((SyncPoint::ToolChange(_), _))
+        ));
+        assert_eq!(snapshot.synced_outputs.len(), 1);
+        assert!(snapshot.tool_table.get(1).is_some());
+
+        let mut resumed = interpreter();
+        resumed.restore(snapshot.clone()).unwrap();
+
+        assert_eq!(resumed.snapshot(), snapshot);
+
+        resumed.synchronise(Feedback::ToolChange(Ok(()))).unwrap();
+        canon.extend(run_blocks(&mut resumed, &blocks).unwrap());
+
+        assert_eq!(canon, expected);
+    }
+
+    #[test]
+    fn pause_and_abort() {
+        let mut interp = interpreter();
+        interp.add_program(
+            "pause.ngc",
+            parser::parse_program("o<pause> sub\nG0 X2\nM0\nG0 X3\no<pause> endsub").unwrap(),
+        );
+        interp.start_program().unwrap();
+
+        assert_eq!(interp.last_snapshot(), None);
+
+        run_blocks(
+            &mut interp,
+            &parser::parse_program("G0 X1\no<pause> call\nG0 X4").unwrap(),
+        )
+        .unwrap();
+
+        // Paused in the subroutine
+        let paused = interp.last_snapshot().unwrap().clone();
+
+        assert_eq!(paused.program_state, ProgramState::Paused);
+        assert_eq!(paused.cursor().program, "pause.ngc");
+        assert_eq!(paused.position[0], 2.0);
+
+        interp.abort().unwrap();
+
+        assert_eq!(interp.program_state(), ProgramState::Idle);
+        assert_eq!(interp.queued_commands(), 0);
+        assert_eq!(interp.call_stack().count(), 0);
+        assert_eq!(interp.last_snapshot(), Some(&paused));
+    }
+
+    #[test]
+    fn errors() {
+        let mut snapshot = interpreter().snapshot();
+        snapshot.version = 0;
+
+        assert_eq!(
+            Interpreter::new().restore(snapshot),
+            Err(SnapshotError::UnsupportedVersion(0).into())
+        );
+
+        // Subroutines are found by program name
+        let mut first = interpreter();
+        run_blocks(&mut first, &parser::parse_program(PROGRAM).unwrap()[..6]).unwrap();
+
+        assert_eq!(
+            Interpreter::new().restore(first.snapshot()),
+            Err(SnapshotError::UnknownProgram("lib.ngc".into()).into())
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize() {
+        let blocks = parser::parse_program(PROGRAM).unwrap();
+
+        let mut first = interpreter();
+        run_blocks(&mut first, &blocks[..6]).unwrap();
+
+        let json = serde_json::to_string(&first.snapshot()).unwrap();
+
+        // Hash map ordering doesn't leak into the output
+        assert_eq!(json, serde_json::to_string(&first.snapshot()).unwrap());
+
+        let mut resumed = interpreter();
+        resumed
+            .restore(serde_json::from_str(&json).unwrap())
+            .unwrap();
+
+        assert_eq!(serde_json::to_string(&resumed.snapshot()).unwrap(), json);
+    }
+}
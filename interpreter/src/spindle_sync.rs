@@ -0,0 +1,170 @@
+//! Spindle synchronised motion, `G33` and `G33.1`.
+//!
+//! `G33 X Z K` feeds in a straight line, moving `K` along it for each revolution of the spindle,
+//! e.g. to cut a thread in several passes. It's output as the move between
+//! [`Canon::StartSpeedFeedSync`] and [`Canon::StopSpeedFeedSync`]. `G33.1 X Z K` is rigid
+//! tapping: it feeds to the end in step with the spindle, which then reverses to bring the tap
+//! back out to the start. It's output as a single [`Canon::RigidTap`]. Both need the spindle to be
+//! turning and can't be used with cutter radius compensation.
+//!
+//! The planner keeps the axes in step on these moves by following the spindle's encoder.
+
+use crate::block::BlockCommands;
+use crate::canon::Canon;
+use crate::parameters::ParameterStore;
+use crate::{Interpreter, InterpreterError};
+use common::{Number, Position, Spindle};
+use core::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpindleSyncError {
+    /// `G33` or `G33.1` with the spindle stopped or at zero speed.
+    SpindleNotTurning,
+
+    /// `G33` or `G33.1` with cutter radius compensation on.
+    CutterComp,
+
+    /// `K` isn't greater than zero.
+    BadPitch,
+}
+
+impl fmt::Display for SpindleSyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SpindleNotTurning => write!(f, "Spindle not turning in G33 or G33.1"),
+            Self::CutterComp => write!(f, "Cannot use G33 or G33.1 with cutter radius comp"),
+            Self::BadPitch => write!(f, "K word must be greater than zero with G33 or G33.1"),
+        }
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// `G33` to the program position `end`.
+    pub(crate) fn spindle_synced_feed(
+        &mut self,
+        end: Position,
+        commands: &BlockCommands,
+    ) -> Result<(), InterpreterError> {
+        let pitch = self.spindle_sync_pitch(commands)?;
+        let offset = self.program_offset();
+
+        self.emit(Canon::StartSpeedFeedSync(pitch));
+        self.emit(Canon::StraightFeed { end: end + offset });
+        self.emit(Canon::StopSpeedFeedSync);
+
+        self.position = end;
+
+        Ok(())
+    }
+
+    /// `G33.1` to the program position `end` and back.
+    pub(crate) fn rigid_tap(
+        &mut self,
+        end: Position,
+        commands: &BlockCommands,
+    ) -> Result<(), InterpreterError> {
+        let pitch = self.spindle_sync_pitch(commands)?;
+
+        self.emit(Canon::RigidTap {
+            end: end + self.program_offset(),
+            pitch,
+        });
+
+        Ok(())
+    }
+
+    /// Check a `G33` or `G33.1` block can be run and get its pitch.
+    fn spindle_sync_pitch(&self, commands: &BlockCommands) -> Result<Number, InterpreterError> {
+        if self.cutter_comp.is_some() {
+            return Err(SpindleSyncError::CutterComp.into());
+        }
+
+        if self.modal_groups.spindle == Spindle::Stopped || self.modal_groups.spindle_speed <= 0.0 {
+            return Err(SpindleSyncError::SpindleNotTurning.into());
+        }
+
+        match commands.value('K') {
+            Some(pitch) if pitch > 0.0 => Ok(pitch),
+            Some(_) => Err(SpindleSyncError::BadPitch.into()),
+            None => Err(InterpreterError::MissingWord('K')),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::run;
+
+    fn at(x: Number, z: Number) -> Position {
+        Position::from([x, 0.0, z, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+    }
+
+    #[test]
+    fn threading() {
+        let mut interp = Interpreter::new();
+        run(&mut interp, "G0 X1 Z1\nM3 S500").unwrap();
+
+        assert_eq!(
+            run(&mut interp, "G33 Z-10 K1.5").unwrap(),
+            [
+                Canon::StartSpeedFeedSync(1.5),
+                Canon::StraightFeed {
+                    end: at(1.0, -10.0)
+                },
+                Canon::StopSpeedFeedSync,
+            ]
+        );
+        assert_eq!(*interp.position(), at(1.0, -10.0));
+        assert_eq!(
+            interp.modal_groups().motion(),
+            Some(common::Motion::SpindleSync)
+        );
+        assert_eq!(interp.named_parameter("_motion_mode"), Some(330.0));
+    }
+
+    #[test]
+    fn rigid_tap() {
+        let mut interp = Interpreter::new();
+
+        // G54 Z offset
+        interp.set_numbered_parameter(5223, 2.0).unwrap();
+        run(&mut interp, "G0 X1 Z1\nM3 S200").unwrap();
+
+        assert_eq!(
+            run(&mut interp, "G33.1 Z-5 K1").unwrap(),
+            [Canon::RigidTap {
+                end: at(1.0, -3.0),
+                pitch: 1.0
+            }]
+        );
+
+        // The tap comes back out to where it started
+        assert_eq!(*interp.position(), at(1.0, 1.0));
+    }
+
+    #[test]
+    fn errors() {
+        let mut interp = Interpreter::new();
+
+        assert_eq!(
+            run(&mut interp, "G33 Z-10 K1"),
+            Err(SpindleSyncError::SpindleNotTurning.into())
+        );
+
+        run(&mut interp, "M3 S500").unwrap();
+
+        assert_eq!(
+            run(&mut interp, "G33.1 Z-10"),
+            Err(InterpreterError::MissingWord('K'))
+        );
+        assert_eq!(
+            run(&mut interp, "G33 Z-10 K-1"),
+            Err(SpindleSyncError::BadPitch.into())
+        );
+        assert_eq!(
+            run(&mut interp, "G41.1 D1 G1 X10 F100\nG33 Z-10 K1"),
+            Err(SpindleSyncError::CutterComp.into())
+        );
+    }
+}
@@ -0,0 +1,402 @@
+//! A stream of the changes each block makes to the interpreter's state, for UIs and logs that
+//! want to follow modal state without diffing it themselves.
+//!
+//! Recording is off until [`Interpreter::record_state_changes`] is called. Changes are then
+//! collected like canonical commands and taken with [`Interpreter::next_state_change`], each with
+//! the span of the block (or parameter assignment) that made it.
+
+use crate::overrides::OverrideSwitches;
+use crate::parameters::ParameterStore;
+use crate::{Interpreter, ModalGroupState};
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use common::{
+    CoordinateSystem, CutterCompensation, DiameterMode, DistanceMode, FeedRateMode, Motion, Number,
+    Plane, Position, RetractMode, Span, Spindle, SpindleSpeedMode, Units,
+};
+
+/// A change made by a block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateChange {
+    pub change: Change,
+
+    /// The block that made the change, or the assignment for a parameter write.
+    pub span: Span,
+}
+
+/// A piece of interpreter state and its new value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Motion mode, `None` after `G80`.
+    Motion(Option<Motion>),
+    Plane(Plane),
+    DistanceMode(DistanceMode),
+    ArcDistanceMode(DistanceMode),
+    FeedRateMode(FeedRateMode),
+    FeedRate(Number),
+    Units(Units),
+    CoordinateSystem(CoordinateSystem),
+    CutterCompensation {
+        side: CutterCompensation,
+        radius: Number,
+        orientation: u8,
+    },
+    RetractMode(RetractMode),
+    Spindle(Spindle),
+    SpindleSpeed(Number),
+    SpindleSpeedMode(SpindleSpeedMode),
+    DiameterMode(DiameterMode),
+    Coolant {
+        mist: bool,
+        flood: bool,
+    },
+
+    /// Tool in the spindle, after `M6` or `M61`.
+    Tool(u32),
+
+    /// Tool selected by a `T` word.
+    SelectedTool(u32),
+
+    /// Tool length offsets for each axis.
+    ToolLengthOffset(Position),
+
+    /// Overrides switched on or off by `M48` to `M53`.
+    Overrides(OverrideSwitches),
+
+    /// Offset of the active work coordinate system, after selecting another one or setting its
+    /// parameters.
+    WorkOffset(Position),
+
+    /// A numbered parameter set by the program, e.g. `#100 = 1`.
+    NumberedParameter {
+        index: usize,
+        value: f64,
+    },
+
+    /// A named parameter set by the program, e.g. `#<depth> = 1`.
+    NamedParameter {
+        name: String,
+        value: f64,
+    },
+}
+
+/// Changes waiting to be taken, and the state they were last worked out from.
+pub(crate) struct StateChanges {
+    last: TrackedState,
+    changes: VecDeque<StateChange>,
+}
+
+/// How far recording had got, to go back to if a block fails.
+pub(crate) struct StateChangeMark {
+    last: TrackedState,
+    len: usize,
+}
+
+#[derive(Clone, PartialEq)]
+struct TrackedState {
+    modal: ModalGroupState,
+    feed_rate: Number,
+    work_offset: Position,
+}
+
+impl TrackedState {
+    /// Add the changes from `self` to `new`.
+    fn diff(&self, new: &Self, span: Span, changes: &mut VecDeque<StateChange>) {
+        let (old_modal, modal) = (&self.modal, &new.modal);
+
+        let mut push = |change| changes.push_back(StateChange { change, span });
+
+        if modal.motion != old_modal.motion {
+            push(Change::Motion(modal.motion));
+        }
+
+        if modal.plane != old_modal.plane {
+            push(Change::Plane(modal.plane));
+        }
+
+        if modal.distance_mode != old_modal.distance_mode {
+            push(Change::DistanceMode(modal.distance_mode));
+        }
+
+        if modal.arc_distance_mode != old_modal.arc_distance_mode {
+            push(Change::ArcDistanceMode(modal.arc_distance_mode));
+        }
+
+        if modal.feed_rate_mode != old_modal.feed_rate_mode {
+            push(Change::FeedRateMode(modal.feed_rate_mode));
+        }
+
+        if new.feed_rate != self.feed_rate {
+            push(Change::FeedRate(new.feed_rate));
+        }
+
+        if modal.units != old_modal.units {
+            push(Change::Units(modal.units));
+        }
+
+        if modal.coordinate_system != old_modal.coordinate_system {
+            push(Change::CoordinateSystem(modal.coordinate_system));
+        }
+
+        if modal.cutter_compensation != old_modal.cutter_compensation
+            || modal.cutter_radius != old_modal.cutter_radius
+            || modal.cutter_orientation != old_modal.cutter_orientation
+        {
+            push(Change::CutterCompensation {
+                side: modal.cutter_compensation,
+                radius: modal.cutter_radius,
+                orientation: modal.cutter_orientation,
+            });
+        }
+
+        if modal.retract_mode != old_modal.retract_mode {
+            push(Change::RetractMode(modal.retract_mode));
+        }
+
+        if modal.spindle != old_modal.spindle {
+            push(Change::Spindle(modal.spindle));
+        }
+
+        if modal.spindle_speed != old_modal.spindle_speed {
+            push(Change::SpindleSpeed(modal.spindle_speed));
+        }
+
+        if modal.spindle_speed_mode != old_modal.spindle_speed_mode {
+            push(Change::SpindleSpeedMode(modal.spindle_speed_mode));
+        }
+
+        if modal.diameter_mode != old_modal.diameter_mode {
+            push(Change::DiameterMode(modal.diameter_mode));
+        }
+
+        if modal.mist != old_modal.mist || modal.flood != old_modal.flood {
+            push(Change::Coolant {
+                mist: modal.mist,
+                flood: modal.flood,
+            });
+        }
+
+        if modal.tool != old_modal.tool {
+            push(Change::Tool(modal.tool));
+        }
+
+        if modal.selected_tool != old_modal.selected_tool {
+            push(Change::SelectedTool(modal.selected_tool));
+        }
+
+        if modal.tool_length_offset != old_modal.tool_length_offset {
+            push(Change::ToolLengthOffset(modal.tool_length_offset));
+        }
+
+        if modal.overrides != old_modal.overrides {
+            push(Change::Overrides(modal.overrides));
+        }
+
+        if new.work_offset != self.work_offset {
+            push(Change::WorkOffset(new.work_offset));
+        }
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Start or stop recording state changes. Changes not yet taken are dropped when recording
+    /// stops.
+    pub fn record_state_changes(&mut self, record: bool) {
+        self.state_changes = if record {
+            Some(StateChanges {
+                last: self.tracked_state(),
+                changes: VecDeque::new(),
+            })
+        } else {
+            None
+        };
+    }
+
+    /// Take the next recorded state change.
+    pub fn next_state_change(&mut self) -> Option<StateChange> {
+        self.state_changes.as_mut()?.changes.pop_front()
+    }
+
+    fn tracked_state(&self) -> TrackedState {
+        TrackedState {
+            modal: self.modal_groups.clone(),
+            feed_rate: self.feed_rate,
+            work_offset: self.work_offset(),
+        }
+    }
+
+    /// Record the changes made since the last time, as made by `span`.
+    ///
+    /// Nothing is recorded during a dry run, so the changes it makes are recorded with the first
+    /// block after it.
+    pub(crate) fn record_changes(&mut self, span: Span) {
+        if self.dry_run || self.state_changes.is_none() {
+            return;
+        }
+
+        let state = self.tracked_state();
+
+        if let Some(recorded) = &mut self.state_changes {
+            if recorded.last != state {
+                recorded.last.diff(&state, span, &mut recorded.changes);
+                recorded.last = state;
+            }
+        }
+    }
+
+    /// Mark how far recording has got.
+    pub(crate) fn state_change_mark(&self) -> Option<StateChangeMark> {
+        self.state_changes.as_ref().map(|recorded| StateChangeMark {
+            last: recorded.last.clone(),
+            len: recorded.changes.len(),
+        })
+    }
+
+    /// Drop the changes recorded since `mark` was taken.
+    pub(crate) fn reset_state_changes(&mut self, mark: Option<StateChangeMark>) {
+        if let (Some(recorded), Some(mark)) = (&mut self.state_changes, mark) {
+            recorded.changes.truncate(mark.len);
+            recorded.last = mark.last;
+        }
+    }
+
+    /// Record a parameter set by the program.
+    pub(crate) fn record_parameter(&mut self, change: Change, span: Span) {
+        if self.dry_run {
+            return;
+        }
+
+        if let Some(recorded) = &mut self.state_changes {
+            recorded.changes.push_back(StateChange { change, span });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remap::{Code, Remap, Remapped};
+    use crate::tools::tool_file;
+    use alloc::vec::Vec;
+    use common::Command;
+
+    fn run(interp: &mut Interpreter, program: &str) -> Vec<StateChange> {
+        for block in parser::parse_program(program).unwrap() {
+            interp.queue_command(Command::Block(block)).unwrap();
+            interp.pop_command().unwrap();
+        }
+
+        core::iter::from_fn(|| interp.next_state_change()).collect()
+    }
+
+    fn changes(changes: &[StateChange]) -> Vec<(u32, Change)> {
+        changes
+            .iter()
+            .map(|change| (change.span.line, change.change.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn not_recorded_by_default() {
+        let mut interp = Interpreter::new();
+
+        assert_eq!(run(&mut interp, "G20"), []);
+    }
+
+    #[test]
+    fn block_changes() {
+        let mut interp = Interpreter::new();
+        interp.record_state_changes(true);
+        interp.set_numbered_parameter(5241, 10.0).unwrap();
+        interp.set_tool_table(tool_file::parse("T2 P2 D1\n").unwrap());
+
+        let recorded = run(
+            &mut interp,
+            "G0 X1\n#100 = 2 #<depth> = 3 G1 X2 F200\nX3\nG55 M8 T2 M6",
+        );
+
+        let mut work_offset = Position::zeros();
+        work_offset[0] = 10.0;
+
+        assert_eq!(
+            changes(&recorded),
+            [
+                (1, Change::Motion(Some(Motion::Rapid))),
+                (
+                    2,
+                    Change::NumberedParameter {
+                        index: 100,
+                        value: 2.0
+                    }
+                ),
+                (
+                    2,
+                    Change::NamedParameter {
+                        name: "depth".into(),
+                        value: 3.0
+                    }
+                ),
+                (2, Change::Motion(Some(Motion::Feed))),
+                (2, Change::FeedRate(200.0)),
+                (
+                    4,
+                    Change::CoordinateSystem(CoordinateSystem::new(2).unwrap())
+                ),
+                (
+                    4,
+                    Change::Coolant {
+                        mist: false,
+                        flood: true
+                    }
+                ),
+                (4, Change::Tool(2)),
+                (4, Change::SelectedTool(2)),
+                (4, Change::WorkOffset(work_offset)),
+            ]
+        );
+
+        // The assignment, not the whole block
+        assert_eq!(recorded[1].span.column, 1);
+        assert_eq!(recorded[2].span.column, 10);
+
+        interp.record_state_changes(false);
+
+        assert_eq!(run(&mut interp, "G21"), []);
+    }
+
+    #[test]
+    fn remap_changes() {
+        let mut interp = Interpreter::new();
+        interp.record_state_changes(true);
+
+        interp.add_program(
+            "flood",
+            parser::parse_program("o<flood> sub\n\n\nM8\no<flood> endsub").unwrap(),
+        );
+        interp.remap(Code::m(100.0), Remap::subroutine("flood"));
+        interp.remap(
+            Code::m(101.0),
+            Remap::callback(|interp: &mut Interpreter, _: &_| {
+                interp.execute(&parser::parse_program("G20").unwrap()[0])?;
+
+                Ok(Remapped::Done)
+            }),
+        );
+
+        assert_eq!(
+            changes(&run(&mut interp, "\nG91 M100\nM101")),
+            [
+                // User M codes come before the distance mode
+                (
+                    4,
+                    Change::Coolant {
+                        mist: false,
+                        flood: true
+                    }
+                ),
+                (2, Change::DistanceMode(DistanceMode::Incremental)),
+                (3, Change::Units(Units::Inch)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! `M0`, `M1` and `M60` program pauses and `M2`/`M30` program end.
+//!
+//! Each is output as its own [`Canon`] for the controller to stop on. A pause also pauses the
+//! program as [`Interpreter::pause`] does, so no more blocks are read until
+//! [`Interpreter::resume`] and MDI commands are queued until it ends. The end of the program
+//! leaves any subroutines and loops it's in, and the rest of the program isn't run.
+
+use crate::block::{BlockCommands, CutterCompensationMode, OverrideCommand, Stop};
+use crate::canon::Canon;
+use crate::parameters::ParameterStore;
+use crate::{Interpreter, InterpreterError};
+use common::{Coolant, CoordinateSystem, DistanceMode, FeedRateMode, Motion, Span, Spindle};
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Turn the optional stop switch on or off. `M1` only pauses the program when it's on, as it
+    /// is by default.
+    pub fn set_optional_stop(&mut self, on: bool) {
+        self.optional_stop = on;
+    }
+
+    pub fn optional_stop(&self) -> bool {
+        self.optional_stop
+    }
+
+    pub(crate) fn stop(&mut self, stop: Stop, span: Span) -> Result<(), InterpreterError> {
+        match stop {
+            Stop::Pause => self.program_stop(Canon::ProgramStop),
+            Stop::OptionalPause if self.optional_stop => {
+                self.program_stop(Canon::OptionalProgramStop)
+            }
+            Stop::OptionalPause => {}
+            Stop::PalletShuttle => {
+                self.emit(Canon::PalletShuttle);
+                self.program_stop(Canon::ProgramStop);
+            }
+            Stop::End => return self.program_end(false, span),
+            Stop::EndRewind => return self.program_end(true, span),
+        }
+
+        Ok(())
+    }
+
+    /// Emit `canon` and pause, as `M0` does.
+    pub(crate) fn program_stop(&mut self, canon: Canon) {
+        self.emit(canon);
+
+        if !self.dry_run {
+            self.pause();
+        }
+    }
+
+    /// Put the machine in the end of program state, then end the program as
+    /// [`end_program`](Self::end_program) does. Units, tool length offsets and the tool in the
+    /// spindle are kept, and the feed and spindle overrides are turned on as by `M48`.
+    fn program_end(&mut self, rewind: bool, span: Span) -> Result<(), InterpreterError> {
+        self.cutter_compensation(CutterCompensationMode::Off, &BlockCommands::default())?;
+        self.select_coordinate_system(CoordinateSystem::G54, span)?;
+
+        self.modal_groups.plane = self.default_plane();
+        self.modal_groups.distance_mode = DistanceMode::Absolute;
+        self.feed_rate_mode(FeedRateMode::UnitsPerMinute);
+
+        self.modal_groups.spindle = Spindle::Stopped;
+        self.emit(Canon::Spindle(Spindle::Stopped));
+
+        self.modal_groups.motion = Some(Motion::Feed);
+        self.modal_groups.cycle_words.bottom = None;
+
+        self.modal_groups.mist = false;
+        self.modal_groups.flood = false;
+        self.emit(Canon::Coolant(Coolant::Off));
+
+        let overrides = self.modal_groups.overrides;
+
+        if !(overrides.feed && overrides.spindle) {
+            self.override_command(OverrideCommand::All(true), None);
+        }
+
+        if rewind {
+            self.emit(Canon::PalletShuttle);
+        }
+
+        self.emit(Canon::ProgramEnd { rewind });
+
+        // Nothing after the end of the program is run, including the rest of any subroutine or
+        // loop it ended in
+        self.queue.clear();
+        self.end_control_flow(span)?;
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        self.end_program()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mdi::{MdiStatus, ProgramState};
+    use crate::test_utils::{run, stream};
+    use alloc::vec::Vec;
+    use common::{Command, Number, Plane, Position};
+
+    #[test]
+    fn pauses() {
+        let mut interp = Interpreter::new();
+        interp.start_program().unwrap();
+
+        assert_eq!(run(&mut interp, "M0").unwrap(), [Canon::ProgramStop]);
+        assert_eq!(interp.program_state(), ProgramState::Paused);
+
+        interp.resume().unwrap();
+
+        assert_eq!(
+            run(&mut interp, "M1").unwrap(),
+            [Canon::OptionalProgramStop]
+        );
+        assert_eq!(interp.program_state(), ProgramState::Paused);
+
+        interp.resume().unwrap();
+        interp.set_optional_stop(false);
+
+        assert_eq!(run(&mut interp, "M1").unwrap(), []);
+        assert_eq!(interp.program_state(), ProgramState::Running);
+
+        assert_eq!(
+            run(&mut interp, "G0 X1 M60").unwrap(),
+            [
+                Canon::StraightTraverse {
+                    end: Position::from([1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+                },
+                Canon::PalletShuttle,
+                Canon::ProgramStop
+            ]
+        );
+        assert_eq!(interp.program_state(), ProgramState::Paused);
+
+        interp.resume().unwrap();
+
+        assert_eq!(
+            run(&mut interp, "M0 M2").unwrap_err(),
+            InterpreterError::MModalGroupConflict(2.0)
+        );
+    }
+
+    #[test]
+    fn program_end() {
+        let mut interp = Interpreter::new();
+        interp.set_numbered_parameter(5241, 10.0).unwrap();
+
+        run(
+            &mut interp,
+            "G20 G55 G18 G91 G93 G0 X1 M3 S1000 M8\nG1 X1 F10",
+        )
+        .unwrap();
+
+        let canon = run(&mut interp, "M2").unwrap();
+
+        assert_eq!(
+            canon,
+            [
+                Canon::SelectCoordinateSystem(CoordinateSystem::G54),
+                Canon::SetFeedRate(0.0),
+                Canon::Spindle(Spindle::Stopped),
+                Canon::Coolant(Coolant::Off),
+                Canon::ProgramEnd { rewind: false }
+            ]
+        );
+
+        let modal = interp.modal_groups();
+
+        assert_eq!(modal.coordinate_system(), CoordinateSystem::G54);
+        assert_eq!(modal.plane(), Plane::XY);
+        assert_eq!(modal.distance_mode(), DistanceMode::Absolute);
+        assert_eq!(modal.feed_rate_mode(), FeedRateMode::UnitsPerMinute);
+        assert_eq!(modal.spindle(), Spindle::Stopped);
+        assert_eq!(modal.motion(), Some(Motion::Feed));
+        assert!(!modal.flood());
+
+        // Kept
+        assert_eq!(modal.units(), common::Units::Inch);
+
+        // The machine is at X2, which was X-8 in G55
+        assert_eq!(interp.position()[0], 2.0);
+
+        interp.set_lathe(true);
+        run(&mut interp, "G17").unwrap();
+
+        assert!(run(&mut interp, "M30")
+            .unwrap()
+            .ends_with(&[Canon::PalletShuttle, Canon::ProgramEnd { rewind: true }]));
+        assert_eq!(interp.modal_groups().plane(), Plane::XZ);
+    }
+
+    #[test]
+    fn nothing_runs_after_end() {
+        let mut interp = Interpreter::new();
+        interp.start_program().unwrap();
+
+        for line in ["G0 X1", "M2", "G0 X2"] {
+            let block = parser::parse_program(line).unwrap().remove(0);
+
+            interp.queue_command(Command::Block(block)).unwrap();
+        }
+
+        interp.pop_command().unwrap();
+        interp.pop_command().unwrap();
+
+        assert_eq!(interp.queued_commands(), 0);
+        assert_eq!(interp.program_state(), ProgramState::Idle);
+        assert_eq!(interp.mdi("G0 X3"), Ok(MdiStatus::Executed));
+    }
+
+    fn traverses(canon: &[Canon]) -> Vec<Number> {
+        canon
+            .iter()
+            .filter_map(|canon| match canon {
+                Canon::StraightTraverse { end } => Some(end[0]),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn end_in_subroutine() {
+        let mut interp = Interpreter::new();
+        interp.set_numbered_parameter(1, 5.0).unwrap();
+        interp.start_program().unwrap();
+
+        let canon = stream(
+            &mut interp,
+            "o100 sub
+                G0 X1
+                M2
+                G0 X2
+            o100 endsub
+            o100 call [7]
+            G0 X3",
+        )
+        .unwrap();
+
+        assert_eq!(traverses(&canon), [1.0]);
+        assert_eq!(interp.call_stack().count(), 0);
+        assert_eq!(interp.program_state(), ProgramState::Idle);
+
+        // The caller's parameters are back
+        assert_eq!(interp.numbered_parameter(1), Ok(5.0));
+    }
+
+    #[test]
+    fn end_in_loop() {
+        let mut interp = Interpreter::new();
+        interp.start_program().unwrap();
+
+        // The end is only reached when the loop body is replayed
+        let canon = stream(
+            &mut interp,
+            "#1 = 0
+            o1 while [#1 LT 3]
+                G0 X#1
+                o2 if [#1 EQ 1]
+                    M30
+                o2 endif
+                #1 = [#1 + 1]
+            o1 endwhile
+            G0 X9",
+        )
+        .unwrap();
+
+        assert_eq!(traverses(&canon), [0.0, 1.0]);
+        assert_eq!(interp.numbered_parameter(1), Ok(1.0));
+        assert_eq!(interp.program_state(), ProgramState::Idle);
+
+        // A new program starts afresh
+        interp.start_program().unwrap();
+
+        assert_eq!(traverses(&stream(&mut interp, "G0 X4").unwrap()), [4.0]);
+        assert_eq!(interp.snapshot().cursor().block, 1);
+    }
+
+    #[test]
+    fn pause_stops_reading() {
+        let mut interp = Interpreter::new();
+        interp.start_program().unwrap();
+
+        assert_eq!(
+            traverses(&stream(&mut interp, "G0 X1\nM0\nG0 X2").unwrap()),
+            [1.0]
+        );
+        assert_eq!(interp.queued_commands(), 1);
+
+        interp.pop_command().unwrap();
+        assert_eq!(interp.queued_commands(), 1);
+
+        interp.resume().unwrap();
+        interp.pop_command().unwrap();
+
+        assert_eq!(interp.position()[0], 2.0);
+    }
+
+    #[test]
+    fn pause_in_subroutine() {
+        let mut interp = Interpreter::new();
+        interp.start_program().unwrap();
+
+        let canon = stream(
+            &mut interp,
+            "o100 sub
+                G0 X1
+                M1
+                G0 X2
+            o100 endsub
+            o100 call
+            G0 X3",
+        )
+        .unwrap();
+
+        assert_eq!(traverses(&canon), [1.0]);
+        assert_eq!(interp.call_stack().count(), 1);
+
+        // The rest of the subroutine runs on resuming, then the queue carries on
+        interp.resume().unwrap();
+
+        assert_eq!(interp.position()[0], 2.0);
+        assert_eq!(interp.call_stack().count(), 0);
+
+        interp.pop_command().unwrap();
+
+        assert_eq!(interp.position()[0], 3.0);
+    }
+
+    #[test]
+    fn cutter_comp_finished_at_end() {
+        let mut interp = Interpreter::new();
+
+        let canon = run(&mut interp, "G41.1 D2 G1 X10 F100\nX20\nM2").unwrap();
+
+        assert_eq!(interp.modal_groups().cutter_radius(), 0.0);
+        assert_eq!(
+            canon
+                .iter()
+                .filter(|canon| matches!(canon, Canon::StraightFeed { .. }))
+                .count(),
+            2
+        );
+        assert_eq!(canon.last(), Some(&Canon::ProgramEnd { rewind: false }));
+    }
+}
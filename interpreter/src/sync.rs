@@ -0,0 +1,430 @@
+//! Sync points: blocks the interpreter can't read past until the machine has caught up.
+//!
+//! A probe move's result, the outcome of an `M6`, an input read by `M66` and the live position
+//! read by `#5420` to `#5428` or `#<_x>` to `#<_w>` all come from the machine. With sync points
+//! on, the interpreter stops reading ahead at such a block and reports a [`SyncPoint`]. Once the
+//! canonical commands before it have been carried out, the executor passes back a [`Feedback`]
+//! with what happened and the interpreter carries on with the right parameter values.
+//!
+//! - A probe move, `M6` or `M66` stops the interpreter after its block, unless a
+//!   [`Probe`](crate::probe::Probe), [`ToolChanger`](crate::tools::ToolChanger) or
+//!   [`IoBackend`](crate::io::IoBackend) is set to give the result straight away.
+//! - A block reading the live position stops the interpreter before it's executed, if the
+//!   program has moved since the last sync. Only parameters given as literals are spotted, so
+//!   `#[5420 + #1]` reads the interpreter's own idea of the position.
+//!
+//! Remapped codes run their subroutines to the end within their block, so sync points in them
+//! are passed without stopping, as they are with sync points off.
+
+use crate::io::InputWait;
+use crate::parameters::ParameterStore;
+use crate::probe::ProbeMove;
+use crate::system_parameters::CURRENT_POSITION;
+use crate::tools::{ToolChange, ToolChangeError};
+use crate::{Interpreter, InterpreterError};
+use common::{Axis, Block, Expression, Number, Parameter, Position, Statement};
+use core::fmt;
+
+/// Why the interpreter has stopped reading ahead.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SyncPoint {
+    /// A `G38.2` to `G38.5` probe move, in machine coordinates. Carry it out and feed back
+    /// [`Feedback::Probe`].
+    Probe(ProbeMove),
+
+    /// An `M6`, output as [`Canon::ChangeTool`](crate::canon::Canon::ChangeTool). Feed back
+    /// [`Feedback::ToolChange`] once it's done.
+    ToolChange(ToolChange),
+
+    /// An `M66` wait for an input. Feed back [`Feedback::Input`].
+    Input(InputWait),
+
+    /// The next block reads the machine position. Feed back [`Feedback::Position`].
+    Position,
+}
+
+/// What happened at a sync point, from the executor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Feedback {
+    /// Where the probe input changed, in machine coordinates, or `None` if the move reached its
+    /// end first.
+    Probe(Option<Position>),
+
+    /// Whether the tool change worked. A failed change leaves the old tool in the spindle.
+    ToolChange(Result<(), ToolChangeError>),
+
+    /// The value read from the input, or `None` if the wait timed out.
+    Input(Option<Number>),
+
+    /// The machine position.
+    Position(Position),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SyncError {
+    /// Feedback was given while the interpreter wasn't stopped at a sync point.
+    NotWaiting,
+
+    /// The feedback is for a different kind of sync point.
+    WrongFeedback,
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotWaiting => write!(f, "Interpreter is not waiting for the machine"),
+            Self::WrongFeedback => write!(f, "Feedback does not match the sync point"),
+        }
+    }
+}
+
+/// The sync point the interpreter is stopped at, with the block that stopped it.
+pub(crate) struct Waiting {
+    pub(crate) point: SyncPoint,
+    pub(crate) block: Block,
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Turn sync points on or off. They're off by default, so results the machine would give
+    /// come from the probe and tool changer, or are assumed if they aren't set.
+    pub fn set_sync_points(&mut self, on: bool) {
+        self.sync_points = on;
+    }
+
+    /// The sync point the interpreter is stopped at, if any. Commands stay queued until
+    /// [`synchronise`](Self::synchronise) is called.
+    pub fn sync_point(&self) -> Option<&SyncPoint> {
+        self.waiting.as_ref().map(|waiting| &waiting.point)
+    }
+
+    /// Carry on from a sync point with what happened on the machine, executing the rest of any
+    /// blocks already read.
+    pub fn synchronise(&mut self, feedback: Feedback) -> Result<(), InterpreterError> {
+        let Waiting { point, block } = self.waiting.take().ok_or(SyncError::NotWaiting)?;
+
+        let result = match (&point, feedback) {
+            (SyncPoint::Probe(probe), Feedback::Probe(tripped_at)) => {
+                self.probe_result(probe, tripped_at, block.span)
+            }
+            (SyncPoint::ToolChange(change), Feedback::ToolChange(result)) => result.map_err(|e| {
+                self.modal_groups.tool = change.current;
+
+                e.into()
+            }),
+            (SyncPoint::Input(_), Feedback::Input(value)) => self.input_result(value, block.span),
+            (SyncPoint::Position, Feedback::Position(position)) => {
+                self.position = position - self.program_offset();
+
+                Ok(())
+            }
+            _ => {
+                self.waiting = Some(Waiting { point, block });
+
+                return Err(SyncError::WrongFeedback.into());
+            }
+        };
+
+        self.synced_position = Some(self.position);
+
+        result.map_err(|e| self.locate(e, Some(&block)))?;
+
+        self.run()
+    }
+
+    /// Whether a sync point would stop the interpreter now. Blocks run by a remap can't be
+    /// stopped in, as the rest of the remapped block is still to come.
+    pub(crate) fn can_sync(&self) -> bool {
+        self.sync_points
+            && !self.dry_run
+            && self.remap_callbacks == 0
+            && self.control_flow.remap_depth() == 0
+    }
+
+    /// Stop after the block being executed.
+    pub(crate) fn sync_after(&mut self, point: SyncPoint, block: &Block) {
+        self.waiting = Some(Waiting {
+            point,
+            block: block.clone(),
+        });
+    }
+
+    /// Stop before executing a block that reads the live position, unless it's known.
+    pub(crate) fn sync_before(&mut self, block: &Block) -> bool {
+        if !self.can_sync()
+            || self.control_flow.skipping()
+            || self.synced_position == Some(self.position)
+            || !reads_position(block)
+        {
+            return false;
+        }
+
+        self.sync_after(SyncPoint::Position, block);
+
+        true
+    }
+}
+
+/// Whether a block reads `#5420` to `#5428` or `#<_x>` to `#<_w>`.
+fn reads_position(block: &Block) -> bool {
+    let words = block.words.iter().map(|word| &word.value.item);
+
+    let assignments = block.assignments.iter().flat_map(|assignment| {
+        let index = match &assignment.parameter {
+            Parameter::Numbered(index) => Some(&index.item),
+            _ => None,
+        };
+
+        index.into_iter().chain([&assignment.value.item])
+    });
+
+    let o_word = block.o_word.iter().flat_map(|o_word| {
+        let (arguments, value) = match &o_word.statement {
+            Statement::Call(arguments) => (arguments.as_slice(), None),
+            Statement::EndSub(value) | Statement::Return(value) => (&[][..], value.as_ref()),
+            Statement::If(value)
+            | Statement::ElseIf(value)
+            | Statement::While(value)
+            | Statement::Repeat(value) => (&[][..], Some(value)),
+            _ => (&[][..], None),
+        };
+
+        arguments.iter().chain(value).map(|value| &value.item)
+    });
+
+    words
+        .chain(assignments)
+        .chain(o_word)
+        .any(expression_reads_position)
+}
+
+fn expression_reads_position(expression: &Expression) -> bool {
+    match expression {
+        Expression::Literal(_) | Expression::Exists(_) => false,
+        Expression::Parameter(Parameter::Numbered(index)) => match index.item {
+            Expression::Literal(index) => {
+                (CURRENT_POSITION as f64..CURRENT_POSITION as f64 + 9.0).contains(&index)
+            }
+            ref index => expression_reads_position(index),
+        },
+        Expression::Parameter(Parameter::Global(name)) => {
+            let mut letters = name.chars();
+
+            matches!(
+                (
+                    letters.next(),
+                    letters.next().and_then(Axis::from_letter),
+                    letters.next()
+                ),
+                (Some('_'), Some(_), None)
+            )
+        }
+        Expression::Parameter(Parameter::Local(_)) => false,
+        Expression::Unary { argument, .. } => expression_reads_position(&argument.item),
+        Expression::Binary { lhs, rhs, .. } => {
+            expression_reads_position(&lhs.item) || expression_reads_position(&rhs.item)
+        }
+        Expression::Atan { y, x } => {
+            expression_reads_position(&y.item) || expression_reads_position(&x.item)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canon::Canon;
+    use crate::probe::SimulatedProbe;
+    use crate::test_utils::{canon, stream as run};
+    use crate::tools::tool_file;
+    use alloc::string::ToString;
+    use common::ProbeKind;
+
+    fn interpreter() -> Interpreter {
+        let mut interp = Interpreter::new();
+        interp.set_tool_table(tool_file::parse("T1 P1\nT2 P2\n").unwrap());
+
+        interp
+    }
+
+    fn at(x: Number, z: Number) -> Position {
+        let mut position = Position::zeros();
+        position[0] = x;
+        position[2] = z;
+
+        position
+    }
+
+    #[test]
+    fn probe() {
+        let mut interp = interpreter();
+        interp.set_sync_points(true);
+
+        assert_eq!(
+            run(&mut interp, "G38.2 Z-10 F100\nG0 X1").unwrap(),
+            [Canon::SetFeedRate(100.0)]
+        );
+        assert_eq!(
+            interp.sync_point(),
+            Some(&SyncPoint::Probe(ProbeMove {
+                kind: ProbeKind::Contact,
+                start: Position::zeros(),
+                end: at(0.0, -10.0),
+                feed_rate: 100.0,
+            }))
+        );
+
+        // The rest of the program waits
+        interp.pop_command().unwrap();
+        assert_eq!(interp.queued_commands(), 1);
+
+        interp
+            .synchronise(Feedback::Probe(Some(at(0.0, -4.0))))
+            .unwrap();
+
+        assert_eq!(interp.sync_point(), None);
+        assert_eq!(interp.numbered_parameter(5063), Ok(-4.0));
+        assert_eq!(interp.numbered_parameter(5070), Ok(1.0));
+
+        interp.pop_command().unwrap();
+
+        assert_eq!(
+            canon(&mut interp),
+            [
+                Canon::StraightProbe {
+                    kind: ProbeKind::Contact,
+                    end: at(0.0, -10.0),
+                    stopped: at(0.0, -4.0),
+                },
+                Canon::StraightTraverse { end: at(1.0, -4.0) }
+            ]
+        );
+    }
+
+    #[test]
+    fn probe_not_tripped() {
+        let mut interp = interpreter();
+        interp.set_sync_points(true);
+
+        run(&mut interp, "G38.2 Z-10 F100").unwrap();
+
+        assert_eq!(
+            interp
+                .synchronise(Feedback::Probe(None))
+                .unwrap_err()
+                .into_cause()
+                .to_string(),
+            "G38.2 move finished without making contact"
+        );
+    }
+
+    #[test]
+    fn tool_change() {
+        let mut interp = interpreter();
+        interp.set_sync_points(true);
+
+        run(&mut interp, "T1 M6\nT2 M6\nG0 X1").unwrap();
+
+        assert!(matches!(
+            interp.sync_point(),
+            Some(SyncPoint::ToolChange(ToolChange { selected: 1, .. }))
+        ));
+
+        interp.synchronise(Feedback::ToolChange(Ok(()))).unwrap();
+        interp.pop_command().unwrap();
+
+        assert!(matches!(
+            interp.sync_point(),
+            Some(SyncPoint::ToolChange(ToolChange {
+                current: 1,
+                selected: 2,
+                ..
+            }))
+        ));
+        assert_eq!(interp.modal_groups().tool(), 2);
+
+        let error = interp
+            .synchronise(Feedback::ToolChange(Err(ToolChangeError(-2))))
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "M6 failed (-2) at line 2, column 1");
+        assert_eq!(interp.modal_groups().tool(), 1);
+    }
+
+    #[test]
+    fn tool_changer_does_not_wait() {
+        let mut interp = interpreter();
+        interp.set_sync_points(true);
+        interp.set_tool_changer(|_: &ToolChange| Ok(()));
+
+        run(&mut interp, "T1 M6\nG0 X1").unwrap();
+
+        assert_eq!(interp.sync_point(), None);
+        assert_eq!(interp.queued_commands(), 0);
+    }
+
+    #[test]
+    fn position() {
+        let mut interp = interpreter();
+        interp.set_sync_points(true);
+
+        let canon = run(&mut interp, "G0 X1\n#1 = #5420").unwrap();
+
+        // Stopped before the block reading the position
+        assert_eq!(canon.len(), 1);
+        assert_eq!(interp.sync_point(), Some(&SyncPoint::Position));
+        assert_eq!(interp.numbered_parameter(1), Ok(0.0));
+
+        interp
+            .synchronise(Feedback::Position(at(0.9, 0.0)))
+            .unwrap();
+
+        assert_eq!(interp.numbered_parameter(1), Ok(0.9f32 as f64));
+
+        // The position hasn't changed since, so there's no need to wait again
+        run(&mut interp, "#2 = #<_x>").unwrap();
+
+        assert_eq!(interp.sync_point(), None);
+        assert_eq!(interp.numbered_parameter(2), Ok(0.9f32 as f64));
+
+        // Only the global axis letters are the position
+        run(
+            &mut interp,
+            "#<x> = 1 #<_xy> = 2\nG0 X2\n#3 = [#<x> + #<_xy>]",
+        )
+        .unwrap();
+
+        assert_eq!(interp.sync_point(), None);
+        assert_eq!(interp.numbered_parameter(3), Ok(3.0));
+    }
+
+    #[test]
+    fn off_by_default() {
+        let mut interp = interpreter();
+        interp.set_probe(SimulatedProbe::default());
+
+        run(&mut interp, "G0 X1\nG38.3 Z-10 F100\n#1 = #5420\nT1 M6").unwrap();
+
+        assert_eq!(interp.sync_point(), None);
+        assert_eq!(interp.numbered_parameter(1), Ok(1.0));
+    }
+
+    #[test]
+    fn feedback_errors() {
+        let mut interp = interpreter();
+        interp.set_sync_points(true);
+
+        assert_eq!(
+            interp.synchronise(Feedback::Position(Position::zeros())),
+            Err(SyncError::NotWaiting.into())
+        );
+
+        run(&mut interp, "T1 M6").unwrap();
+
+        assert_eq!(
+            interp.synchronise(Feedback::Position(Position::zeros())),
+            Err(SyncError::WrongFeedback.into())
+        );
+        assert!(interp.sync_point().is_some());
+    }
+}
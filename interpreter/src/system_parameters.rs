@@ -0,0 +1,121 @@
+//! Read-only system parameters derived from interpreter state, e.g. `#5420` or `#<_x>`.
+
+use crate::parameters::ParameterStore;
+use crate::Interpreter;
+use common::{
+    ArcDirection, Axis, DiameterMode, DistanceMode, Motion, Plane, RetractMode, Spindle,
+    SpindleSpeedMode, Units,
+};
+
+/// Current tool number, followed by its offsets in `#5401`-`#5409`, diameter, front angle, back
+/// angle and orientation.
+const CURRENT_TOOL: usize = 5400;
+
+/// First of the current position parameters, `#5420`-`#5428` for X through W.
+pub(crate) const CURRENT_POSITION: usize = 5420;
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    /// Get a read-only numbered system parameter, or `None` if `index` isn't one the interpreter
+    /// computes.
+    pub(crate) fn system_numbered(&self, index: usize) -> Option<f64> {
+        let tool = self.current_tool();
+
+        match index {
+            CURRENT_TOOL => Some(self.modal_groups.tool.into()),
+            5401..=5409 => Some(tool.map_or(0.0, |tool| tool.offset[index - 5401].into())),
+            5410 => Some(tool.map_or(0.0, |tool| tool.diameter.into())),
+            5411 => Some(tool.map_or(0.0, |tool| tool.front_angle.into())),
+            5412 => Some(tool.map_or(0.0, |tool| tool.back_angle.into())),
+            5413 => Some(tool.map_or(0.0, |tool| tool.orientation.into())),
+            CURRENT_POSITION..=5428 => {
+                Some(self.position[Axis::ALL[index - CURRENT_POSITION].index()].into())
+            }
+            _ => None,
+        }
+    }
+
+    /// Get a read-only named system parameter, e.g. `_x` or `_metric`.
+    pub(crate) fn system_named(&self, name: &str) -> Option<f64> {
+        let flag = |set: bool| Some(if set { 1.0 } else { 0.0 });
+
+        let name = name.strip_prefix('_')?;
+
+        if let Some(axis) = (name.len() == 1)
+            .then(|| name.chars().next().and_then(Axis::from_letter))
+            .flatten()
+        {
+            return Some(self.position[axis.index()].into());
+        }
+
+        let modal = &self.modal_groups;
+        let is = |other: &str| name.eq_ignore_ascii_case(other);
+
+        if is("feed") {
+            Some(self.feed_rate.into())
+        } else if is("metric") {
+            flag(modal.units == Units::Mm)
+        } else if is("imperial") {
+            flag(modal.units == Units::Inch)
+        } else if is("absolute") {
+            flag(modal.distance_mode == DistanceMode::Absolute)
+        } else if is("incremental") {
+            flag(modal.distance_mode == DistanceMode::Incremental)
+        } else if is("ijk_absolute_mode") {
+            flag(modal.arc_distance_mode == DistanceMode::Absolute)
+        } else if is("plane") {
+            Some(match modal.plane {
+                Plane::XY => 170.0,
+                Plane::XZ => 180.0,
+                Plane::YZ => 190.0,
+            })
+        } else if is("coord_system") {
+            // `540` for `G54` to `593` for `G59.3`
+            Some(match modal.coordinate_system.number() {
+                number @ 1..=6 => 530.0 + f64::from(number) * 10.0,
+                number => 584.0 + f64::from(number),
+            })
+        } else if is("speed") {
+            Some(modal.spindle_speed.into())
+        } else if is("spindle_rpm_mode") {
+            flag(modal.spindle_speed_mode == SpindleSpeedMode::Rpm)
+        } else if is("spindle_css_mode") {
+            flag(modal.spindle_speed_mode != SpindleSpeedMode::Rpm)
+        } else if is("lathe_diameter_mode") {
+            flag(modal.diameter_mode == DiameterMode::Diameter)
+        } else if is("spindle_on") {
+            flag(modal.spindle != Spindle::Stopped)
+        } else if is("spindle_cw") {
+            flag(modal.spindle == Spindle::Clockwise)
+        } else if is("mist") {
+            flag(modal.mist)
+        } else if is("flood") {
+            flag(modal.flood)
+        } else if is("current_tool") {
+            Some(modal.tool.into())
+        } else if is("selected_tool") {
+            Some(modal.selected_tool.into())
+        } else if is("call_level") {
+            Some(self.control_flow.depth() as f64)
+        } else if is("remap_level") {
+            Some(self.remap_level() as f64)
+        } else if is("motion_mode") {
+            Some(match modal.motion {
+                None => 800.0,
+                Some(Motion::Rapid) => 0.0,
+                Some(Motion::Feed) => 10.0,
+                Some(Motion::Arc(ArcDirection::Clockwise)) => 20.0,
+                Some(Motion::Arc(ArcDirection::CounterClockwise)) => 30.0,
+                Some(Motion::CannedCycle(cycle)) => f64::from(cycle.code()) * 10.0,
+                Some(Motion::Probe(kind)) => (f64::from(kind.code()) * 10.0).round(),
+                Some(Motion::SpindleSync) => 330.0,
+                Some(Motion::RigidTap) => 331.0,
+            })
+        } else if is("retract_r_plane") {
+            flag(modal.retract_mode == RetractMode::RPlane)
+        } else if is("retract_old_z") {
+            flag(modal.retract_mode == RetractMode::OldZ)
+        } else {
+            None
+        }
+    }
+}
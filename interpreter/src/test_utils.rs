@@ -0,0 +1,84 @@
+//! Helpers shared by the interpreter's tests.
+
+use crate::canon::Canon;
+use crate::parameters::ParameterStore;
+use crate::{Interpreter, InterpreterError};
+use alloc::vec::Vec;
+use common::{Block, Command};
+
+/// Parse `program` and run it a block at a time, returning the output or the first error without
+/// its location.
+pub(crate) fn run<P: ParameterStore, const N: usize>(
+    interp: &mut Interpreter<P, N>,
+    program: &str,
+) -> Result<Vec<Canon>, InterpreterError> {
+    run_located(interp, program).map_err(InterpreterError::into_cause)
+}
+
+/// As [`run`], keeping where the error happened.
+pub(crate) fn run_located<P: ParameterStore, const N: usize>(
+    interp: &mut Interpreter<P, N>,
+    program: &str,
+) -> Result<Vec<Canon>, InterpreterError> {
+    run_blocks(interp, &parser::parse_program(program).unwrap())
+}
+
+/// Run already parsed blocks one at a time.
+pub(crate) fn run_blocks<P: ParameterStore, const N: usize>(
+    interp: &mut Interpreter<P, N>,
+    blocks: &[Block],
+) -> Result<Vec<Canon>, InterpreterError> {
+    let mut output = Vec::new();
+
+    for block in blocks {
+        interp.queue_command(Command::Block(block.clone())).unwrap();
+        pop(interp, &mut output)?;
+    }
+
+    output.extend(canon(interp));
+
+    Ok(output)
+}
+
+/// Queue a whole program before popping any of it, as a controller streaming it would.
+pub(crate) fn stream<P: ParameterStore, const N: usize>(
+    interp: &mut Interpreter<P, N>,
+    program: &str,
+) -> Result<Vec<Canon>, InterpreterError> {
+    for block in parser::parse_program(program).unwrap() {
+        interp.queue_command(Command::Block(block)).unwrap();
+    }
+
+    let mut output = Vec::new();
+
+    for _ in 0..interp.queued_commands() {
+        pop(interp, &mut output).map_err(InterpreterError::into_cause)?;
+    }
+
+    output.extend(canon(interp));
+
+    Ok(output)
+}
+
+/// Pop a command, reading the output into `output` whenever it fills until the blocks held back
+/// by it have run.
+fn pop<P: ParameterStore, const N: usize>(
+    interp: &mut Interpreter<P, N>,
+    output: &mut Vec<Canon>,
+) -> Result<(), InterpreterError> {
+    interp.pop_command()?;
+
+    while interp.output_full() {
+        output.extend(canon(interp));
+        interp.pop_command()?;
+    }
+
+    Ok(())
+}
+
+/// Take all the output waiting to be read.
+pub(crate) fn canon<P: ParameterStore, const N: usize>(
+    interp: &mut Interpreter<P, N>,
+) -> Vec<Canon> {
+    core::iter::from_fn(|| interp.next_canon()).collect()
+}
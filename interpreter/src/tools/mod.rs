@@ -0,0 +1,501 @@
+//! Tool table, tool changes and tool length offsets.
+//!
+//! `T` selects a tool from the [`ToolTable`] and `M6` loads it, asking a [`ToolChanger`] to do
+//! the change if one is set. `M61 Q` tells the interpreter which tool is already in the spindle
+//! without changing anything. Tool tables can be loaded from and saved to LinuxCNC `tool.tbl`
+//! files with the [`tool_file`] module.
+
+pub mod tool_file;
+
+use crate::block::{BlockCommands, ToolCommand, ToolLengthOffset};
+use crate::canon::Canon;
+use crate::parameters::ParameterStore;
+use crate::sync::SyncPoint;
+use crate::{Interpreter, InterpreterError};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use common::{Axis, Number, Position};
+use core::fmt;
+
+/// A tool table entry.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tool {
+    /// `T` number used to select the tool.
+    pub number: u32,
+
+    /// Pocket the tool is stored in.
+    pub pocket: u32,
+
+    /// Offsets for each axis, X through W. Z is the tool length on a mill, X and Z are the tool
+    /// offsets on a lathe.
+    pub offset: Position,
+
+    pub diameter: Number,
+
+    /// Lathe tool front angle.
+    pub front_angle: Number,
+
+    /// Lathe tool back angle.
+    pub back_angle: Number,
+
+    /// Lathe tool orientation, `0` to `9`.
+    pub orientation: u8,
+
+    pub comment: String,
+}
+
+impl Tool {
+    /// A tool with zero offsets, stored in the pocket with the same number.
+    pub fn new(number: u32) -> Self {
+        Self {
+            number,
+            pocket: number,
+            offset: Position::zeros(),
+            diameter: 0.0,
+            front_angle: 0.0,
+            back_angle: 0.0,
+            orientation: 0,
+            comment: String::new(),
+        }
+    }
+}
+
+/// Tools known to the machine, by tool number.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ToolTable {
+    tools: BTreeMap<u32, Tool>,
+}
+
+impl ToolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, number: u32) -> Option<&Tool> {
+        self.tools.get(&number)
+    }
+
+    pub fn get_mut(&mut self, number: u32) -> Option<&mut Tool> {
+        self.tools.get_mut(&number)
+    }
+
+    /// Add a tool, returning the entry it replaced.
+    pub fn insert(&mut self, tool: Tool) -> Option<Tool> {
+        self.tools.insert(tool.number, tool)
+    }
+
+    pub fn remove(&mut self, number: u32) -> Option<Tool> {
+        self.tools.remove(&number)
+    }
+
+    /// Tools in ascending tool number order.
+    pub fn iter(&self) -> impl Iterator<Item = &Tool> {
+        self.tools.values()
+    }
+}
+
+/// An `M6` tool change, with the same values LinuxCNC passes to a remapped `M6` as `#1`-`#3`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ToolChange {
+    /// Tool in the spindle, or `0` for none.
+    pub current: u32,
+
+    /// Tool selected by the last `T` word.
+    pub selected: u32,
+
+    /// Pocket of the selected tool.
+    pub pocket: u32,
+}
+
+/// Performs tool changes, e.g. by driving an automatic tool changer or prompting the operator.
+pub trait ToolChanger {
+    /// Change tools. Returning an error fails the change and leaves the current tool as it was.
+    fn change_tool(&mut self, change: &ToolChange) -> Result<(), ToolChangeError>;
+}
+
+impl<F> ToolChanger for F
+where
+    F: FnMut(&ToolChange) -> Result<(), ToolChangeError>,
+{
+    fn change_tool(&mut self, change: &ToolChange) -> Result<(), ToolChangeError> {
+        self(change)
+    }
+}
+
+/// A tool change failed, with a code reported by the tool changer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToolChangeError(pub i32);
+
+impl fmt::Display for ToolChangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "M6 failed ({})", self.0)
+    }
+}
+
+impl<P: ParameterStore, const N: usize> Interpreter<P, N> {
+    pub fn tool_table(&self) -> &ToolTable {
+        &self.tool_table
+    }
+
+    pub fn tool_table_mut(&mut self) -> &mut ToolTable {
+        &mut self.tool_table
+    }
+
+    pub fn set_tool_table(&mut self, table: ToolTable) {
+        self.tool_table = table;
+    }
+
+    /// Set the tool changer asked to carry out `M6`. Without one, tool changes always succeed.
+    pub fn set_tool_changer(&mut self, changer: impl ToolChanger + Send + 'static) {
+        self.tool_changer = Some(Box::new(changer));
+    }
+
+    /// The tool table entry of the tool in the spindle, if there is one.
+    pub fn current_tool(&self) -> Option<&Tool> {
+        self.tool_table.get(self.modal_groups.tool)
+    }
+
+    /// Get a tool number from a word value. `0` means no tool and is always allowed.
+    pub(crate) fn tool_number(&self, letter: char, value: Number) -> Result<u32, InterpreterError> {
+        if value < 0.0 {
+            return Err(InterpreterError::NegativeValue(letter));
+        }
+
+        let number = value.round() as u32;
+
+        if number != 0 && self.tool_table.get(number).is_none() {
+            return Err(InterpreterError::UnknownTool(number));
+        }
+
+        Ok(number)
+    }
+
+    /// `T`: prepare a tool for the next `M6`.
+    pub(crate) fn select_tool(&mut self, value: Number) -> Result<(), InterpreterError> {
+        self.modal_groups.selected_tool = self.tool_number('T', value)?;
+        self.emit(Canon::SelectTool(self.modal_groups.selected_tool));
+
+        Ok(())
+    }
+
+    /// `M6` or `M61`.
+    pub(crate) fn tool_change(
+        &mut self,
+        command: ToolCommand,
+        commands: &BlockCommands,
+    ) -> Result<(), InterpreterError> {
+        match command {
+            ToolCommand::Change => self.change_tool()?,
+            ToolCommand::SetCurrent => {
+                let value = commands
+                    .value('Q')
+                    .ok_or(InterpreterError::MissingWord('Q'))?;
+
+                self.modal_groups.tool = self.tool_number('Q', value)?;
+                self.emit(Canon::SetToolNumber(self.modal_groups.tool));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `M6`: change to the selected tool through the tool changer, or stop for the machine to
+    /// change it at a sync point.
+    pub(crate) fn change_tool(&mut self) -> Result<(), InterpreterError> {
+        let selected = self.modal_groups.selected_tool;
+
+        let change = ToolChange {
+            current: self.modal_groups.tool,
+            selected,
+            pocket: self.tool_table.get(selected).map_or(0, |tool| tool.pocket),
+        };
+
+        if let (Some(changer), false) = (&mut self.tool_changer, self.dry_run) {
+            changer.change_tool(&change)?;
+        }
+
+        self.modal_groups.tool = selected;
+        self.emit(Canon::ChangeTool(selected));
+
+        // The change is assumed to work until the machine says otherwise
+        if self.can_sync() && self.tool_changer.is_none() {
+            self.pending_sync = Some(SyncPoint::ToolChange(change));
+        }
+
+        Ok(())
+    }
+
+    /// `G43`, `G43.1`, `G43.2` or `G49`.
+    pub(crate) fn tool_length_offset(
+        &mut self,
+        mode: ToolLengthOffset,
+        commands: &BlockCommands,
+    ) -> Result<(), InterpreterError> {
+        let old_offset = self.modal_groups.tool_length_offset;
+
+        let tool_offset = |interp: &Self, value: Option<Number>| -> Result<_, InterpreterError> {
+            let number = match value {
+                Some(value) => interp.tool_number('H', value)?,
+                None => interp.modal_groups.tool,
+            };
+
+            Ok(interp
+                .tool_table
+                .get(number)
+                .map_or_else(Position::zeros, |tool| tool.offset))
+        };
+
+        let axis_offsets =
+            || Position::from_fn(|axis, _| commands.value(Axis::ALL[axis].letter()).unwrap_or(0.0));
+
+        let offset = match mode {
+            ToolLengthOffset::Tool => tool_offset(self, commands.value('H'))?,
+            ToolLengthOffset::Dynamic => axis_offsets(),
+            ToolLengthOffset::Add => {
+                let added = match commands.value('H') {
+                    Some(value) => tool_offset(self, Some(value))?,
+                    None => axis_offsets(),
+                };
+
+                old_offset + added
+            }
+            ToolLengthOffset::Cancel => Position::zeros(),
+        };
+
+        self.modal_groups.tool_length_offset = offset;
+        self.emit(Canon::UseToolLengthOffset(offset));
+
+        // As for a change of coordinate system, the machine stays where it is
+        self.position += old_offset - offset;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::MockIo;
+    use crate::remap::{Code, Remap, RemapCall, RemapError, Remapped};
+    use crate::test_utils::run;
+    use alloc::string::ToString;
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use common::Span;
+    use std::sync::Mutex;
+
+    fn table() -> ToolTable {
+        tool_file::parse("T1 P3 Z+10\nT2 P4 Z+20 X1 ;two\n").unwrap()
+    }
+
+    #[test]
+    fn tool_changes() {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let seen = changes.clone();
+
+        let mut interp = Interpreter::new();
+        interp.set_tool_table(table());
+        interp.set_tool_changer(move |change: &ToolChange| {
+            seen.lock().unwrap().push(*change);
+
+            match change.selected {
+                2 => Err(ToolChangeError(-1)),
+                _ => Ok(()),
+            }
+        });
+
+        let canon = run(&mut interp, "T1 M6\nT2").unwrap();
+
+        assert_eq!(
+            canon,
+            [
+                Canon::SelectTool(1),
+                Canon::ChangeTool(1),
+                Canon::SelectTool(2)
+            ]
+        );
+        assert_eq!(interp.modal_groups().tool(), 1);
+        assert_eq!(interp.modal_groups().selected_tool(), 2);
+
+        assert_eq!(
+            run(&mut interp, "M6").unwrap_err().to_string(),
+            "M6 failed (-1)"
+        );
+        assert_eq!(interp.modal_groups().tool(), 1);
+        assert_eq!(
+            *changes.lock().unwrap(),
+            [
+                ToolChange {
+                    current: 0,
+                    selected: 1,
+                    pocket: 3
+                },
+                ToolChange {
+                    current: 1,
+                    selected: 2,
+                    pocket: 4
+                }
+            ]
+        );
+
+        // M61 only changes the tool number
+        assert_eq!(
+            run(&mut interp, "M61 Q2").unwrap(),
+            [Canon::SetToolNumber(2)]
+        );
+        assert_eq!(changes.lock().unwrap().len(), 2);
+        assert_eq!(interp.current_tool().unwrap().comment, "two");
+        assert_eq!(interp.numbered_parameter(5400), Ok(2.0));
+        assert_eq!(interp.numbered_parameter(5403), Ok(20.0));
+
+        assert_eq!(
+            run(&mut interp, "T3"),
+            Err(InterpreterError::UnknownTool(3))
+        );
+        assert_eq!(
+            run(&mut interp, "M61"),
+            Err(InterpreterError::MissingWord('Q'))
+        );
+    }
+
+    #[test]
+    fn tool_length_offsets() {
+        let mut interp = Interpreter::new();
+        interp.set_tool_table(table());
+
+        let end = |canon: Vec<Canon>| match canon.last() {
+            Some(Canon::StraightTraverse { end }) => (end[0], end[2]),
+            other => panic!("Expected a rapid, got {:?}", other),
+        };
+
+        assert_eq!(
+            end(run(&mut interp, "T1 M6 G43\nG0 Z0").unwrap()),
+            (0.0, 10.0)
+        );
+
+        // The machine doesn't move when the offset changes
+        run(&mut interp, "G43 H2").unwrap();
+        assert_eq!(interp.position()[2], -10.0);
+        assert_eq!(end(run(&mut interp, "G0 X0 Z0").unwrap()), (1.0, 20.0));
+
+        assert_eq!(
+            end(run(&mut interp, "G43.2 H1\nG0 Z0").unwrap()),
+            (1.0, 30.0)
+        );
+        assert_eq!(
+            end(run(&mut interp, "G43.1 Z5\nG0 Z0").unwrap()),
+            (1.0, 5.0)
+        );
+        assert_eq!(end(run(&mut interp, "G49\nG0 Z0").unwrap()), (1.0, 0.0));
+
+        // Work offsets are applied along with tool length offsets
+        interp.set_numbered_parameter(5243, 100.0).unwrap();
+        assert_eq!(
+            end(run(&mut interp, "G55 G43 H1\nG0 X0 Z0").unwrap()),
+            (0.0, 110.0)
+        );
+        assert_eq!(interp.named_parameter("_z"), Some(0.0));
+    }
+
+    /// An interpreter with `M6` and `M61` remapped to the LinuxCNC demo subroutines, which fail
+    /// the change when digital input 2 is on.
+    fn remapped(fail: bool) -> Interpreter {
+        let mut interp = Interpreter::new();
+        interp.set_tool_table(table());
+
+        let mut io = MockIo::default();
+        io.digital_inputs.insert(1, true);
+        io.digital_inputs.insert(2, fail);
+        interp.set_io(io);
+
+        for (name, program) in [
+            (
+                "m6demo.ngc",
+                include_str!("../../../test_files/linuxcnc/m6demo.ngc"),
+            ),
+            (
+                "m61demo.ngc",
+                include_str!("../../../test_files/linuxcnc/m61demo.ngc"),
+            ),
+        ] {
+            interp.add_program(name, parser::parse_program(program).unwrap());
+        }
+
+        // A negative return value fails the change, otherwise the built in code makes it
+        let check =
+            |interp: &mut Interpreter, call: &RemapCall| match interp.named_parameter("_value") {
+                Some(value) if value < 0.0 => Err(RemapError::Failed(call.code, value).into()),
+                _ => Ok(Remapped::BuiltIn),
+            };
+
+        // The subroutines take their arguments as `#1`, `#2` and `#3`
+        interp.remap(
+            Code::m(6.0),
+            Remap::subroutine("m6demo")
+                .prolog(|interp: &mut Interpreter, call: &RemapCall| {
+                    let tool = interp.modal_groups().tool();
+                    let selected = interp.modal_groups().selected_tool();
+                    let pocket = interp.tool_table().get(selected).map_or(0, |t| t.pocket);
+
+                    interp
+                        .set_numbered_parameter(1, tool.into())
+                        .and_then(|_| interp.set_numbered_parameter(2, selected.into()))
+                        .and_then(|_| interp.set_numbered_parameter(3, pocket.into()))
+                        .map_err(|e| call.parameter_error(e))?;
+
+                    Ok(Remapped::Done)
+                })
+                .epilog(check),
+        );
+        interp.remap(
+            Code::m(61.0),
+            Remap::subroutine("m61demo")
+                .words("Q")
+                .prolog(|interp: &mut Interpreter, call: &RemapCall| {
+                    interp
+                        .set_numbered_parameter(1, call.value('Q').unwrap_or(0.0).into())
+                        .map_err(|e| call.parameter_error(e))?;
+
+                    Ok(Remapped::Done)
+                })
+                .epilog(check),
+        );
+
+        interp
+    }
+
+    #[test]
+    fn linuxcnc_demos() {
+        let mut interp = remapped(false);
+
+        let canon = run(&mut interp, "T2 M6").unwrap();
+
+        assert_eq!(canon.first(), Some(&Canon::SelectTool(2)));
+        assert_eq!(canon.last(), Some(&Canon::ChangeTool(2)));
+        assert_eq!(interp.modal_groups().tool(), 2);
+
+        run(&mut interp, "M61 Q1").unwrap();
+        assert_eq!(interp.modal_groups().tool(), 1);
+
+        // Input 2 fails both
+        let mut interp = remapped(true);
+
+        assert_eq!(
+            run(&mut interp, "T2 M6"),
+            Err(RemapError::Failed(Code::M(6), -1.0).into())
+        );
+
+        // Clear the failed call
+        interp.pop_frame(Span::default()).unwrap();
+
+        assert_eq!(
+            run(&mut interp, "M61 Q1"),
+            Err(RemapError::Failed(Code::M(61), -1.0).into())
+        );
+        assert_eq!(interp.modal_groups().tool(), 0);
+    }
+}
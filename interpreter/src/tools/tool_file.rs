@@ -0,0 +1,233 @@
+//! LinuxCNC `tool.tbl` tool table files.
+//!
+//! Each line describes one tool as a list of words followed by an optional `;` comment, e.g.
+//! `T1 P1 D0.125 Z+0.511 ;1/8 end mill`. `T` is the tool number and `P` its pocket, `X` to `W`
+//! are offsets, `D` the diameter, `I` and `J` the front and back angles and `Q` the lathe tool
+//! orientation. Words other than `T` can be left out and default to zero, or the tool number for
+//! `P`.
+
+use super::{Tool, ToolTable};
+use alloc::string::String;
+use common::{Axis, Number};
+use core::fmt;
+
+/// Parse the contents of a `tool.tbl` file.
+pub fn parse(input: &str) -> Result<ToolTable, ToolFileError> {
+    let mut table = ToolTable::new();
+
+    for (line_number, line) in input.lines().enumerate() {
+        let line_number = line_number + 1;
+        let malformed = ToolFileError::Malformed { line: line_number };
+
+        let (words, comment) = match line.find(';') {
+            Some(start) => (&line[..start], line[start + 1..].trim()),
+            None => (line, ""),
+        };
+
+        if words.trim().is_empty() {
+            continue;
+        }
+
+        let mut number = None;
+        let mut tool = Tool::new(0);
+        let mut pocket = None;
+
+        for word in words.split_whitespace() {
+            let mut chars = word.chars();
+            let letter = chars.next().map(|c| c.to_ascii_uppercase());
+            let value = chars.as_str();
+
+            let integer = || value.parse::<u32>().map_err(|_| malformed.clone());
+            let real = || value.parse::<Number>().map_err(|_| malformed.clone());
+
+            match letter {
+                Some('T') => number = Some(integer()?),
+                Some('P') => pocket = Some(integer()?),
+                Some('D') => tool.diameter = real()?,
+                Some('I') => tool.front_angle = real()?,
+                Some('J') => tool.back_angle = real()?,
+                Some('Q') => {
+                    tool.orientation = value.parse::<u8>().map_err(|_| malformed.clone())?
+                }
+                Some(letter) => match Axis::from_letter(letter) {
+                    Some(axis) => tool.offset[axis.index()] = real()?,
+                    None => return Err(malformed),
+                },
+                None => return Err(malformed),
+            }
+        }
+
+        tool.number = number.ok_or(malformed)?;
+        tool.pocket = pocket.unwrap_or(tool.number);
+        tool.comment = String::from(comment);
+
+        if table.get(tool.number).is_some() {
+            return Err(ToolFileError::Duplicate { line: line_number });
+        }
+
+        table.insert(tool);
+    }
+
+    Ok(table)
+}
+
+/// Write a tool table in `tool.tbl` format. Zero offsets and angles are left out.
+pub fn write(table: &ToolTable, out: &mut impl fmt::Write) -> fmt::Result {
+    for tool in table.iter() {
+        write!(out, "T{} P{}", tool.number, tool.pocket)?;
+
+        for axis in Axis::ALL.iter() {
+            let offset = tool.offset[axis.index()];
+
+            if offset != 0.0 {
+                write!(out, " {}{:+.6}", axis.letter(), offset)?;
+            }
+        }
+
+        if tool.diameter != 0.0 {
+            write!(out, " D{:.6}", tool.diameter)?;
+        }
+
+        if tool.front_angle != 0.0 {
+            write!(out, " I{:+.6}", tool.front_angle)?;
+        }
+
+        if tool.back_angle != 0.0 {
+            write!(out, " J{:+.6}", tool.back_angle)?;
+        }
+
+        if tool.orientation != 0 {
+            write!(out, " Q{}", tool.orientation)?;
+        }
+
+        writeln!(out, " ;{}", tool.comment)?;
+    }
+
+    Ok(())
+}
+
+/// Read a `tool.tbl` file from disk.
+#[cfg(feature = "std")]
+pub fn load_file(path: &std::path::Path) -> Result<ToolTable, ToolFileError> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+/// Save a tool table to a `tool.tbl` file.
+///
+/// As with `.var` files, the table is written next to the destination then renamed over it.
+#[cfg(feature = "std")]
+pub fn save_file(path: &std::path::Path, table: &ToolTable) -> Result<(), ToolFileError> {
+    crate::file::write_atomic(path, |out| write(table, out))?;
+
+    Ok(())
+}
+
+/// Errors from reading or writing tool table files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolFileError {
+    /// A line has an unknown word, a bad value or no `T` word.
+    Malformed { line: usize },
+
+    /// A tool number appears more than once.
+    Duplicate { line: usize },
+
+    /// The file couldn't be read or written.
+    #[cfg(feature = "std")]
+    Io(std::io::ErrorKind),
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for ToolFileError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e.kind())
+    }
+}
+
+impl fmt::Display for ToolFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed { line } => write!(f, "Bad format for tool table on line {}", line),
+            Self::Duplicate { line } => write!(f, "Duplicate tool in tool table on line {}", line),
+            #[cfg(feature = "std")]
+            Self::Io(kind) => write!(f, "Unable to access tool table: {:?}", kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let table = parse(
+            "T1 P1 D0.125 Z+0.511 ;1/8 end mill\n\
+             ; Lathe tool\n\
+             t2 p5 x-0.1 z+1.5 d0.4 i95 j155 q3\n\
+             \n\
+             T3 Z+0.25;\n",
+        )
+        .unwrap();
+
+        let tool = table.get(2).unwrap();
+
+        assert_eq!(tool.pocket, 5);
+        assert_eq!(tool.offset[Axis::X.index()], -0.1);
+        assert_eq!(tool.offset[Axis::Z.index()], 1.5);
+        assert_eq!(tool.diameter, 0.4);
+        assert_eq!((tool.front_angle, tool.back_angle), (95.0, 155.0));
+        assert_eq!(tool.orientation, 3);
+        assert_eq!(table.get(1).unwrap().comment, "1/8 end mill");
+        assert_eq!(table.get(3).unwrap().pocket, 3);
+
+        let mut out = String::new();
+        write(&table, &mut out).unwrap();
+
+        assert_eq!(
+            out,
+            "T1 P1 Z+0.511000 D0.125000 ;1/8 end mill\n\
+             T2 P5 X-0.100000 Z+1.500000 D0.400000 I+95.000000 J+155.000000 Q3 ;\n\
+             T3 P3 Z+0.250000 ;\n"
+        );
+        assert_eq!(parse(&out), Ok(table));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse("T1 P1\nP2 Z1\n"),
+            Err(ToolFileError::Malformed { line: 2 })
+        );
+        assert_eq!(parse("T1 R2\n"), Err(ToolFileError::Malformed { line: 1 }));
+        assert_eq!(
+            parse("T1 Zabc\n"),
+            Err(ToolFileError::Malformed { line: 1 })
+        );
+        assert_eq!(
+            parse("T1\nT1 Z1\n"),
+            Err(ToolFileError::Duplicate { line: 2 })
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn save_and_load_file() {
+        let path = std::env::temp_dir().join(alloc::format!(
+            "interpreter-tool-table-{}.tbl",
+            std::process::id()
+        ));
+
+        let table = parse("T4 P1 Z+1.5 D6 ;drill\n").unwrap();
+
+        save_file(&path, &table).unwrap();
+
+        assert_eq!(load_file(&path), Ok(table));
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            load_file(&path),
+            Err(ToolFileError::Io(std::io::ErrorKind::NotFound))
+        );
+    }
+}
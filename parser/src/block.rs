@@ -0,0 +1,273 @@
+//! Blocks, words, assignments and comments.
+
+use crate::{
+    expression::{parameter, real_value},
+    o_word::o_word,
+    spanned, to_span, ParseError, Span,
+};
+use alloc::{string::String, vec::Vec};
+use common::{Assignment, Block, Spanned, Word};
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, take_till},
+    character::complete::{char, digit1, line_ending, satisfy, space0},
+    combinator::{eof, map, map_res, opt},
+    multi::many0,
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+use nom_locate::position;
+
+enum Item {
+    Word(Word),
+    Assignment(Assignment),
+    Comment(Spanned<String>),
+}
+
+/// `(comment)` or `; comment to end of line`.
+fn comment(i: Span) -> IResult<Span, Spanned<String>> {
+    spanned(alt((
+        map(
+            delimited(char('('), opt(is_not(")\r\n")), char(')')),
+            |text: Option<Span>| {
+                text.map(|t| String::from(*t.fragment()))
+                    .unwrap_or_default()
+            },
+        ),
+        map(
+            preceded(char(';'), take_till(|c| c == '\r' || c == '\n')),
+            |text: Span| String::from(*text.fragment()),
+        ),
+    )))(i)
+}
+
+/// `#5 = 10`, `#<depth> = [#1 * 2]`.
+fn assignment(i: Span) -> IResult<Span, Assignment> {
+    map(
+        spanned(pair(
+            terminated(parameter, tuple((space0, char('='), space0))),
+            real_value,
+        )),
+        |assignment| Assignment {
+            parameter: assignment.item.0,
+            value: assignment.item.1,
+            span: assignment.span,
+        },
+    )(i)
+}
+
+/// A letter followed by a value, e.g. `X10` or `F [#1 * 2]`. `N` and `O` words are handled
+/// separately.
+fn word(i: Span) -> IResult<Span, Word> {
+    map(
+        spanned(pair(
+            terminated(
+                satisfy(|c| c.is_ascii_alphabetic() && !matches!(c, 'N' | 'n' | 'O' | 'o')),
+                space0,
+            ),
+            real_value,
+        )),
+        |word| Word {
+            letter: word.item.0.to_ascii_uppercase(),
+            value: word.item.1,
+            span: word.span,
+        },
+    )(i)
+}
+
+fn item(i: Span) -> IResult<Span, Item> {
+    alt((
+        map(comment, Item::Comment),
+        map(assignment, Item::Assignment),
+        map(word, Item::Word),
+    ))(i)
+}
+
+/// Parse one line, not including its line ending.
+fn block(i: Span) -> IResult<Span, Block> {
+    let (i, start) = position(i)?;
+
+    let (i, block_delete) = map(opt(preceded(space0, char('/'))), |c| c.is_some())(i)?;
+
+    let (i, line_number) = opt(preceded(
+        pair(space0, satisfy(|c| c == 'N' || c == 'n')),
+        preceded(
+            space0,
+            map_res(digit1, |number: Span| number.fragment().parse::<u32>()),
+        ),
+    ))(i)?;
+
+    let (i, o_word) = opt(preceded(space0, o_word))(i)?;
+
+    let (i, items) = many0(preceded(space0, item))(i)?;
+
+    let (i, _) = space0(i)?;
+
+    let (i, end) = position(i)?;
+
+    let mut block = Block {
+        block_delete,
+        line_number,
+        o_word,
+        span: to_span(&start, &end),
+        ..Block::default()
+    };
+
+    for item in items {
+        match item {
+            Item::Word(word) => block.words.push(word),
+            Item::Assignment(assignment) => block.assignments.push(assignment),
+            Item::Comment(comment) => block.comments.push(comment),
+        }
+    }
+
+    Ok((i, block))
+}
+
+/// `%` marks the start and end of a program and is otherwise ignored.
+fn percent(i: Span) -> IResult<Span, Block> {
+    let (i, start) = position(i)?;
+
+    let (i, _) = tuple((space0, char('%'), space0))(i)?;
+
+    let (i, end) = position(i)?;
+
+    Ok((
+        i,
+        Block {
+            span: to_span(&start, &end),
+            ..Block::default()
+        },
+    ))
+}
+
+/// Parse a single block. The input must not contain more than one line.
+pub fn parse_block(input: &str) -> Result<Block, ParseError> {
+    let input = Span::new(input);
+
+    match terminated(block, pair(opt(line_ending), eof))(input) {
+        Ok((_, block)) => Ok(block),
+        Err(_) => Err(ParseError::at(&stopped_at(input))),
+    }
+}
+
+/// Parse a whole program into one block per line.
+///
+/// Empty lines produce empty blocks so that block indices match line numbers. `%` lines produce
+/// empty blocks.
+pub fn parse_program(input: &str) -> Result<Vec<Block>, ParseError> {
+    let mut i = Span::new(input);
+    let mut blocks = Vec::new();
+
+    while !i.fragment().is_empty() {
+        let (rest, block) = terminated(alt((percent, block)), alt((line_ending, eof)))(i)
+            .map_err(|_| ParseError::at(&stopped_at(i)))?;
+
+        blocks.push(block);
+
+        i = rest;
+    }
+
+    Ok(blocks)
+}
+
+/// Find where the block parser gave up on a line that failed to parse.
+fn stopped_at(i: Span) -> Span {
+    match block(i) {
+        Ok((rest, _)) => rest,
+        Err(_) => i,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{Expression, Parameter};
+
+    fn literal(word: &Word) -> f64 {
+        match word.value.item {
+            Expression::Literal(value) => value,
+            ref other => panic!("Expected literal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn words() {
+        let block = parse_block("N10 g1 X 10 y-2.5 F[100]").unwrap();
+
+        assert_eq!(block.line_number, Some(10));
+        assert!(!block.block_delete);
+
+        let letters: Vec<char> = block.words.iter().map(|w| w.letter).collect();
+        assert_eq!(letters, ['G', 'X', 'Y', 'F']);
+
+        assert_eq!(literal(&block.words[0]), 1.0);
+        assert_eq!(literal(&block.words[1]), 10.0);
+        assert_eq!(literal(&block.words[2]), -2.5);
+
+        assert_eq!(block.words[1].span.offset, 7);
+        assert_eq!(block.words[1].span.len, 4);
+        assert_eq!(block.words[1].span.column, 8);
+    }
+
+    #[test]
+    fn comments_and_block_delete() {
+        let block = parse_block("/G0 (rapid) X1 ; end of line").unwrap();
+
+        assert!(block.block_delete);
+        assert_eq!(block.words.len(), 2);
+        assert_eq!(block.comments.len(), 2);
+        assert_eq!(block.comments[0].item, "rapid");
+        assert_eq!(block.comments[1].item, " end of line");
+    }
+
+    #[test]
+    fn assignments() {
+        let block = parse_block("#1 = 2 #<_depth>=[#1 + 1] G0 X#1").unwrap();
+
+        assert_eq!(block.assignments.len(), 2);
+        assert_eq!(
+            block.assignments[1].parameter,
+            Parameter::Global("_depth".into())
+        );
+        assert_eq!(block.words.len(), 2);
+    }
+
+    #[test]
+    fn o_words() {
+        let block = parse_block("N20 o<loop> while [#1 LT 10] (count up)").unwrap();
+
+        assert_eq!(block.line_number, Some(20));
+        assert!(block.words.is_empty());
+        assert_eq!(block.comments.len(), 1);
+
+        let o_word = block.o_word.unwrap();
+        assert_eq!(o_word.name, common::OName::Named("loop".into()));
+        assert_eq!(o_word.span.offset, 4);
+        assert!(matches!(o_word.statement, common::Statement::While(_)));
+    }
+
+    #[test]
+    fn program() {
+        let program = "%\nG0 X0\n\r\nG1 X1 F100\r\n%\n";
+
+        let blocks = parse_program(program).unwrap();
+
+        assert_eq!(blocks.len(), 5);
+        assert_eq!(blocks[1].words.len(), 2);
+        assert!(blocks[2].words.is_empty());
+        assert_eq!(blocks[3].span.line, 4);
+        assert_eq!(blocks[3].words[2].span.column, 7);
+    }
+
+    #[test]
+    fn errors() {
+        let error = parse_program("G0 X0\nG1 X[1 + ] Y2\n").unwrap_err();
+
+        assert_eq!(error.span.line, 2);
+        assert_eq!(error.span.column, 4);
+
+        assert!(parse_block("G0 X").is_err());
+        assert!(parse_block("G0\nX1").is_err());
+    }
+}
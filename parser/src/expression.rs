@@ -0,0 +1,382 @@
+//! Values, parameters and bracketed expressions.
+
+use crate::{spanned, Span};
+use alloc::{boxed::Box, string::String};
+use common::{BinaryOperator, Expression, Parameter, Spanned, UnaryFunction};
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, tag_no_case, take_until},
+    character::complete::{char, digit0, digit1, one_of, space0},
+    combinator::{map, map_res, not, opt, recognize, value},
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+
+/// A real value: a number, parameter, bracketed expression or function call, optionally negated.
+pub(crate) fn real_value(i: Span) -> IResult<Span, Spanned<Expression>> {
+    spanned(alt((
+        map(number, Expression::Literal),
+        map(preceded(pair(char('-'), space0), real_value), |argument| {
+            Expression::Unary {
+                function: UnaryFunction::Negate,
+                argument: Box::new(argument),
+            }
+        }),
+        map(preceded(pair(char('+'), space0), real_value), |value| {
+            value.item
+        }),
+        map(parameter, Expression::Parameter),
+        map(bracketed, |expression| expression.item),
+        function,
+    )))(i)
+}
+
+/// `10`, `-1.5`, `.25` or `3.`.
+fn number(i: Span) -> IResult<Span, f64> {
+    map_res(
+        recognize(pair(
+            opt(one_of("+-")),
+            alt((
+                recognize(pair(digit1, opt(pair(char('.'), digit0)))),
+                recognize(pair(char('.'), digit1)),
+            )),
+        )),
+        |number: Span| number.fragment().parse::<f64>(),
+    )(i)
+}
+
+/// `#5220`, `#<_global>`, `#<local>` or `#[#1 + 1]`.
+pub(crate) fn parameter(i: Span) -> IResult<Span, Parameter> {
+    preceded(
+        pair(char('#'), space0),
+        alt((
+            map(
+                delimited(char('<'), take_until(">"), char('>')),
+                |name: Span| named_parameter(name.fragment()),
+            ),
+            map(real_value, |index| Parameter::Numbered(Box::new(index))),
+        )),
+    )(i)
+}
+
+/// Names are case insensitive and may contain spaces, which are ignored.
+pub(crate) fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn named_parameter(name: &str) -> Parameter {
+    let name = normalize_name(name);
+
+    if name.starts_with('_') {
+        Parameter::Global(name)
+    } else {
+        Parameter::Local(name)
+    }
+}
+
+/// `[ ... ]`.
+pub(crate) fn bracketed(i: Span) -> IResult<Span, Spanned<Expression>> {
+    delimited(pair(char('['), space0), logical, pair(space0, char(']')))(i)
+}
+
+fn function(i: Span) -> IResult<Span, Expression> {
+    alt((
+        map(
+            tuple((
+                tag_no_case("ATAN"),
+                space0,
+                bracketed,
+                space0,
+                char('/'),
+                space0,
+                bracketed,
+            )),
+            |(_, _, y, _, _, _, x)| Expression::Atan {
+                y: Box::new(y),
+                x: Box::new(x),
+            },
+        ),
+        map(
+            preceded(
+                pair(tag_no_case("EXISTS"), space0),
+                delimited(pair(char('['), space0), parameter, pair(space0, char(']'))),
+            ),
+            Expression::Exists,
+        ),
+        map(
+            pair(unary_function, preceded(space0, bracketed)),
+            |(function, argument)| Expression::Unary {
+                function,
+                argument: Box::new(argument),
+            },
+        ),
+    ))(i)
+}
+
+fn unary_function(i: Span) -> IResult<Span, UnaryFunction> {
+    alt((
+        value(UnaryFunction::Abs, tag_no_case("ABS")),
+        value(UnaryFunction::Acos, tag_no_case("ACOS")),
+        value(UnaryFunction::Asin, tag_no_case("ASIN")),
+        value(UnaryFunction::Cos, tag_no_case("COS")),
+        value(UnaryFunction::Exp, tag_no_case("EXP")),
+        value(UnaryFunction::Fix, tag_no_case("FIX")),
+        value(UnaryFunction::Fup, tag_no_case("FUP")),
+        value(UnaryFunction::Ln, tag_no_case("LN")),
+        value(UnaryFunction::Round, tag_no_case("ROUND")),
+        value(UnaryFunction::Sin, tag_no_case("SIN")),
+        value(UnaryFunction::Sqrt, tag_no_case("SQRT")),
+        value(UnaryFunction::Tan, tag_no_case("TAN")),
+    ))(i)
+}
+
+/// Parse a left associative chain of operands at one precedence level.
+fn binary_level<'a>(
+    i: Span<'a>,
+    operand: fn(Span<'a>) -> IResult<Span<'a>, Spanned<Expression>>,
+    operator: fn(Span<'a>) -> IResult<Span<'a>, BinaryOperator>,
+) -> IResult<Span<'a>, Spanned<Expression>> {
+    let (mut i, mut lhs) = operand(i)?;
+
+    loop {
+        match preceded(space0, operator)(i) {
+            Ok((rest, operator)) => {
+                let (rest, rhs) = preceded(space0, operand)(rest)?;
+
+                let span = common::Span {
+                    len: rhs.span.offset + rhs.span.len - lhs.span.offset,
+                    ..lhs.span
+                };
+
+                lhs = Spanned::new(
+                    Expression::Binary {
+                        operator,
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(rhs),
+                    },
+                    span,
+                );
+
+                i = rest;
+            }
+            Err(nom::Err::Error(_)) => return Ok((i, lhs)),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn logical(i: Span) -> IResult<Span, Spanned<Expression>> {
+    binary_level(i, comparison, |i| {
+        alt((
+            value(BinaryOperator::And, tag_no_case("AND")),
+            value(BinaryOperator::Or, tag_no_case("OR")),
+            value(BinaryOperator::Xor, tag_no_case("XOR")),
+        ))(i)
+    })
+}
+
+fn comparison(i: Span) -> IResult<Span, Spanned<Expression>> {
+    binary_level(i, additive, |i| {
+        alt((
+            value(BinaryOperator::Eq, tag_no_case("EQ")),
+            value(BinaryOperator::Ne, tag_no_case("NE")),
+            value(BinaryOperator::Ge, tag_no_case("GE")),
+            value(BinaryOperator::Gt, tag_no_case("GT")),
+            value(BinaryOperator::Le, tag_no_case("LE")),
+            value(BinaryOperator::Lt, tag_no_case("LT")),
+        ))(i)
+    })
+}
+
+fn additive(i: Span) -> IResult<Span, Spanned<Expression>> {
+    binary_level(i, multiplicative, |i| {
+        alt((
+            value(BinaryOperator::Add, char('+')),
+            value(BinaryOperator::Subtract, char('-')),
+        ))(i)
+    })
+}
+
+fn multiplicative(i: Span) -> IResult<Span, Spanned<Expression>> {
+    binary_level(i, power, |i| {
+        alt((
+            value(
+                BinaryOperator::Multiply,
+                terminated(char('*'), not(char('*'))),
+            ),
+            value(BinaryOperator::Divide, char('/')),
+            value(BinaryOperator::Modulo, tag_no_case("MOD")),
+        ))(i)
+    })
+}
+
+fn power(i: Span) -> IResult<Span, Spanned<Expression>> {
+    binary_level(i, real_value, |i| {
+        value(BinaryOperator::Power, tag("**"))(i)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn parse(input: &str) -> Expression {
+        let (rest, value) = real_value(Span::new(input)).unwrap();
+
+        assert!(rest.fragment().is_empty(), "Remaining input: {}", rest);
+
+        value.item
+    }
+
+    fn literal(value: f64) -> Box<Spanned<Expression>> {
+        Box::new(Spanned::unspanned(Expression::Literal(value)))
+    }
+
+    /// Remove spans so trees can be compared structurally.
+    fn strip(expression: Expression) -> Expression {
+        let strip_box = |e: Box<Spanned<Expression>>| Box::new(Spanned::unspanned(strip(e.item)));
+        let strip_param = |p: Parameter| match p {
+            Parameter::Numbered(index) => Parameter::Numbered(strip_box(index)),
+            other => other,
+        };
+
+        match expression {
+            Expression::Parameter(p) => Expression::Parameter(strip_param(p)),
+            Expression::Unary { function, argument } => Expression::Unary {
+                function,
+                argument: strip_box(argument),
+            },
+            Expression::Binary { operator, lhs, rhs } => Expression::Binary {
+                operator,
+                lhs: strip_box(lhs),
+                rhs: strip_box(rhs),
+            },
+            Expression::Atan { y, x } => Expression::Atan {
+                y: strip_box(y),
+                x: strip_box(x),
+            },
+            Expression::Exists(p) => Expression::Exists(strip_param(p)),
+            literal => literal,
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse("10"), Expression::Literal(10.0));
+        assert_eq!(parse("-1.5"), Expression::Literal(-1.5));
+        assert_eq!(parse(".25"), Expression::Literal(0.25));
+        assert_eq!(parse("3."), Expression::Literal(3.0));
+    }
+
+    #[test]
+    fn parameters() {
+        assert_eq!(
+            strip(parse("#5220")),
+            Expression::Parameter(Parameter::Numbered(literal(5220.0)))
+        );
+        assert_eq!(
+            parse("#<_Global Name>"),
+            Expression::Parameter(Parameter::Global("_globalname".to_string()))
+        );
+        assert_eq!(
+            parse("#<depth>"),
+            Expression::Parameter(Parameter::Local("depth".to_string()))
+        );
+        assert_eq!(
+            strip(parse("##1")),
+            Expression::Parameter(Parameter::Numbered(Box::new(Spanned::unspanned(
+                Expression::Parameter(Parameter::Numbered(literal(1.0)))
+            ))))
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        // 1 + 2 * 3 ** 2 - 4
+        assert_eq!(
+            strip(parse("[1 + 2 * 3 ** 2 - 4]")),
+            Expression::Binary {
+                operator: BinaryOperator::Subtract,
+                lhs: Box::new(Spanned::unspanned(Expression::Binary {
+                    operator: BinaryOperator::Add,
+                    lhs: literal(1.0),
+                    rhs: Box::new(Spanned::unspanned(Expression::Binary {
+                        operator: BinaryOperator::Multiply,
+                        lhs: literal(2.0),
+                        rhs: Box::new(Spanned::unspanned(Expression::Binary {
+                            operator: BinaryOperator::Power,
+                            lhs: literal(3.0),
+                            rhs: literal(2.0),
+                        })),
+                    })),
+                })),
+                rhs: literal(4.0),
+            }
+        );
+
+        assert_eq!(
+            strip(parse("[1 LT 2 AND 3 EQ 3]")),
+            Expression::Binary {
+                operator: BinaryOperator::And,
+                lhs: Box::new(Spanned::unspanned(Expression::Binary {
+                    operator: BinaryOperator::Lt,
+                    lhs: literal(1.0),
+                    rhs: literal(2.0),
+                })),
+                rhs: Box::new(Spanned::unspanned(Expression::Binary {
+                    operator: BinaryOperator::Eq,
+                    lhs: literal(3.0),
+                    rhs: literal(3.0),
+                })),
+            }
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(
+            strip(parse("sin[30]")),
+            Expression::Unary {
+                function: UnaryFunction::Sin,
+                argument: literal(30.0)
+            }
+        );
+        assert_eq!(
+            strip(parse("ATAN[1]/[2]")),
+            Expression::Atan {
+                y: literal(1.0),
+                x: literal(2.0)
+            }
+        );
+        assert_eq!(
+            parse("EXISTS[#<x>]"),
+            Expression::Exists(Parameter::Local("x".to_string()))
+        );
+        assert_eq!(
+            strip(parse("-#1")),
+            Expression::Unary {
+                function: UnaryFunction::Negate,
+                argument: Box::new(Spanned::unspanned(Expression::Parameter(
+                    Parameter::Numbered(literal(1.0))
+                )))
+            }
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let (_, value) = real_value(Span::new("[1 / [2 - 2]]")).unwrap();
+
+        match value.item {
+            Expression::Binary { rhs, .. } => {
+                assert_eq!(rhs.span.offset, 5);
+                assert_eq!(rhs.span.len, 7);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+}
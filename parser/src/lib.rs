@@ -1,7 +1,81 @@
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+//! `no_std` Nom-based parser for RS274NGC programs.
+//!
+//! Parses blocks (lines) into the [`common::Block`] structure consumed by the interpreter. Every
+//! word, assignment, comment and expression carries its position in the input as a
+//! [`common::Span`].
+
+#![no_std]
+
+extern crate alloc;
+
+mod block;
+mod expression;
+mod o_word;
+
+pub use block::{parse_block, parse_program};
+
+use core::fmt;
+use nom::IResult;
+use nom_locate::{position, LocatedSpan};
+
+pub type Span<'a> = LocatedSpan<&'a str>;
+
+/// Convert the input between two positions into a [`common::Span`].
+pub(crate) fn to_span(start: &Span, end: &Span) -> common::Span {
+    common::Span {
+        offset: start.location_offset(),
+        len: end.location_offset() - start.location_offset(),
+        line: start.location_line(),
+        column: start.get_utf8_column(),
+    }
+}
+
+/// Wrap a parser's output with its position in the input.
+pub(crate) fn spanned<'a, T>(
+    mut inner: impl FnMut(Span<'a>) -> IResult<Span<'a>, T>,
+) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, common::Spanned<T>> {
+    move |i: Span<'a>| {
+        let (i, start) = position(i)?;
+
+        let (i, item) = inner(i)?;
+
+        let (i, end) = position(i)?;
+
+        Ok((i, common::Spanned::new(item, to_span(&start, &end))))
+    }
+}
+
+/// A block couldn't be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Where parsing stopped.
+    pub span: common::Span,
+}
+
+impl ParseError {
+    fn at(i: &Span) -> Self {
+        let len = i
+            .fragment()
+            .find(['\r', '\n'])
+            .unwrap_or_else(|| i.fragment().len());
+
+        Self {
+            span: common::Span {
+                offset: i.location_offset(),
+                len,
+                line: i.location_line(),
+                column: i.get_utf8_column(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unable to parse block at line {}, column {}",
+            self.span.line, self.span.column
+        )
     }
 }
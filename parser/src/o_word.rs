@@ -0,0 +1,157 @@
+//! O-word control flow statements, e.g. `o100 sub` or `o<loop> while [#1 LT 10]`.
+
+use crate::{
+    expression::{normalize_name, real_value},
+    spanned, Span,
+};
+use common::{OName, OWord, Statement};
+use nom::{
+    branch::alt,
+    bytes::complete::{tag_no_case, take_until},
+    character::complete::{char, digit1, satisfy, space0},
+    combinator::{map, map_res, opt, value},
+    multi::many0,
+    sequence::{delimited, pair, preceded},
+    IResult,
+};
+
+/// `100` or `<name>`.
+fn name(i: Span) -> IResult<Span, OName> {
+    alt((
+        map_res(digit1, |number: Span| {
+            number.fragment().parse::<u32>().map(OName::Number)
+        }),
+        map(
+            delimited(char('<'), take_until(">"), char('>')),
+            |name: Span| OName::Named(normalize_name(name.fragment())),
+        ),
+    ))(i)
+}
+
+fn statement(i: Span) -> IResult<Span, Statement> {
+    let argument = || preceded(space0, real_value);
+
+    alt((
+        map(
+            preceded(tag_no_case("endsub"), opt(argument())),
+            Statement::EndSub,
+        ),
+        value(Statement::EndIf, tag_no_case("endif")),
+        value(Statement::EndWhile, tag_no_case("endwhile")),
+        value(Statement::EndRepeat, tag_no_case("endrepeat")),
+        map(
+            preceded(tag_no_case("elseif"), argument()),
+            Statement::ElseIf,
+        ),
+        value(Statement::Else, tag_no_case("else")),
+        value(Statement::Sub, tag_no_case("sub")),
+        map(
+            preceded(tag_no_case("call"), many0(argument())),
+            Statement::Call,
+        ),
+        map(
+            preceded(tag_no_case("return"), opt(argument())),
+            Statement::Return,
+        ),
+        map(preceded(tag_no_case("if"), argument()), Statement::If),
+        value(Statement::Do, tag_no_case("do")),
+        map(preceded(tag_no_case("while"), argument()), Statement::While),
+        map(
+            preceded(tag_no_case("repeat"), argument()),
+            Statement::Repeat,
+        ),
+        value(Statement::Break, tag_no_case("break")),
+        value(Statement::Continue, tag_no_case("continue")),
+    ))(i)
+}
+
+pub(crate) fn o_word(i: Span) -> IResult<Span, OWord> {
+    map(
+        spanned(pair(
+            preceded(satisfy(|c| c == 'O' || c == 'o'), preceded(space0, name)),
+            preceded(space0, statement),
+        )),
+        |o_word| OWord {
+            name: o_word.item.0,
+            statement: o_word.item.1,
+            span: o_word.span,
+        },
+    )(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::ToString, vec::Vec};
+    use common::Expression;
+
+    fn parse(input: &str) -> OWord {
+        let (rest, o_word) = o_word(Span::new(input)).unwrap();
+
+        assert!(rest.fragment().is_empty(), "Remaining input: {}", rest);
+
+        o_word
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(parse("o100 sub").name, OName::Number(100));
+        assert_eq!(
+            parse("O<My Sub> endsub").name,
+            OName::Named("mysub".to_string())
+        );
+        assert_eq!(parse("o<x> endsub").name.to_string(), "o<x>");
+    }
+
+    #[test]
+    fn statements() {
+        assert_eq!(parse("o1 sub").statement, Statement::Sub);
+        assert_eq!(parse("o1 ENDSUB").statement, Statement::EndSub(None));
+        assert_eq!(parse("o1 return").statement, Statement::Return(None));
+        assert_eq!(parse("o1 else").statement, Statement::Else);
+        assert_eq!(parse("o1 endif").statement, Statement::EndIf);
+        assert_eq!(parse("o1 do").statement, Statement::Do);
+        assert_eq!(parse("o1 endwhile").statement, Statement::EndWhile);
+        assert_eq!(parse("o1 endrepeat").statement, Statement::EndRepeat);
+        assert_eq!(parse("o1 break").statement, Statement::Break);
+        assert_eq!(parse("o1 continue").statement, Statement::Continue);
+
+        assert!(matches!(
+            parse("o1 endsub [4712]").statement,
+            Statement::EndSub(Some(value)) if value.item == Expression::Literal(4712.0)
+        ));
+        assert!(matches!(
+            parse("o<factorial> \t return  [#<_value> * #1]").statement,
+            Statement::Return(Some(_))
+        ));
+        assert!(matches!(
+            parse("o1 if [#1 GT 0]").statement,
+            Statement::If(_)
+        ));
+        assert!(matches!(
+            parse("o1 elseif [#1 LT 0]").statement,
+            Statement::ElseIf(_)
+        ));
+        assert!(matches!(
+            parse("o1 while [1]").statement,
+            Statement::While(_)
+        ));
+        assert!(matches!(
+            parse("o1 repeat [3]").statement,
+            Statement::Repeat(_)
+        ));
+    }
+
+    #[test]
+    fn call_arguments() {
+        match parse("o<simp> call [.6] [0.4][#1 + 1]").statement {
+            Statement::Call(arguments) => {
+                assert_eq!(arguments.len(), 3);
+                assert_eq!(arguments[1].item, Expression::Literal(0.4));
+            }
+            other => panic!("Expected call, got {:?}", other),
+        }
+
+        assert_eq!(parse("o100 call").statement, Statement::Call(Vec::new()));
+    }
+}
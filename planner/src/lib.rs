@@ -1,5 +1,7 @@
 pub mod one_d;
 pub mod one_d_jerk;
+pub mod spindle_sync;
+pub mod trajectory;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,189 @@
+//! Following the spindle on `G33` and `G33.1` moves.
+//!
+//! The axes keep in step with the spindle by following a [`SpindleEncoder`] rather than the clock.
+//! A [`SpindleSyncedMove`] gives the position on a move for the spindle position it reads,
+//! starting from a whole revolution so that each pass of a thread starts at the same spindle
+//! angle. [`SimulatedEncoder`] stands in for the spindle when testing how closely a move follows
+//! it.
+
+use common::{Number, Position};
+
+/// Reads the position of the spindle.
+pub trait SpindleEncoder {
+    /// Spindle position in revolutions, counting up as it turns forwards.
+    fn revolutions(&mut self) -> Number;
+}
+
+/// A spindle turning at a set speed, read through an encoder with a whole number of counts per
+/// revolution.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SimulatedEncoder {
+    /// Spindle speed, negative when turning backwards.
+    pub rpm: Number,
+
+    pub counts_per_revolution: u32,
+
+    /// Exact spindle position in revolutions.
+    position: f64,
+}
+
+impl SimulatedEncoder {
+    pub fn new(rpm: Number, counts_per_revolution: u32) -> Self {
+        Self {
+            rpm,
+            counts_per_revolution,
+            position: 0.0,
+        }
+    }
+
+    /// Turn the spindle for a number of seconds at its current speed.
+    pub fn advance(&mut self, seconds: Number) {
+        self.position += f64::from(self.rpm) * f64::from(seconds) / 60.0;
+    }
+
+    /// Where the spindle really is, which the encoder only reads to the count.
+    pub fn true_revolutions(&self) -> Number {
+        self.position as Number
+    }
+}
+
+impl SpindleEncoder for SimulatedEncoder {
+    fn revolutions(&mut self) -> Number {
+        let counts = f64::from(self.counts_per_revolution);
+
+        ((self.position * counts).floor() / counts) as Number
+    }
+}
+
+/// A move locked to the spindle, from `start` to `end` in machine coordinates.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpindleSyncedMove {
+    pub start: Position,
+    pub end: Position,
+
+    /// Distance along the move per revolution.
+    pub pitch: Number,
+
+    /// Spindle position the move starts from.
+    index: Number,
+}
+
+impl SpindleSyncedMove {
+    /// Start a move with the spindle at `revolutions`. The axes wait for the next whole
+    /// revolution, the spindle's index pulse, before moving.
+    pub fn new(start: Position, end: Position, pitch: Number, revolutions: Number) -> Self {
+        Self {
+            start,
+            end,
+            pitch,
+            index: revolutions.ceil(),
+        }
+    }
+
+    /// Where the move has got to with the spindle at `revolutions`. It stays at the start until
+    /// the index and at the end once it's reached, and goes back along the move if the spindle
+    /// turns backwards, as it does to take a rigid tap out.
+    pub fn position(&self, revolutions: Number) -> Position {
+        let length = (self.end - self.start).norm();
+
+        if length == 0.0 {
+            return self.end;
+        }
+
+        let travelled = ((revolutions - self.index) * self.pitch).clamp(0.0, length);
+
+        self.start + (self.end - self.start) * (travelled / length)
+    }
+
+    /// Whether the move has reached its end with the spindle at `revolutions`.
+    pub fn finished(&self, revolutions: Number) -> bool {
+        (revolutions - self.index) * self.pitch >= (self.end - self.start).norm()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: Number, z: Number) -> Position {
+        Position::from([x, 0.0, z, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+    }
+
+    #[test]
+    fn follow_encoder() {
+        let pitch = 1.25;
+        let counts = 100;
+        let mut encoder = SimulatedEncoder::new(600.0, counts);
+
+        // Part way through a revolution, so the move waits for the index
+        encoder.advance(0.025);
+
+        let motion =
+            SpindleSyncedMove::new(at(0.0, 0.0), at(0.0, -10.0), pitch, encoder.revolutions());
+        let mut max_error: Number = 0.0;
+
+        while !motion.finished(encoder.revolutions()) {
+            encoder.advance(0.001);
+
+            let followed = motion.position(encoder.revolutions());
+            let exact = motion.position(encoder.true_revolutions());
+
+            max_error = max_error.max((followed - exact).norm());
+        }
+
+        assert!(max_error <= pitch / counts as Number + 1.0e-4);
+        assert_eq!(motion.position(encoder.revolutions()), at(0.0, -10.0));
+    }
+
+    #[test]
+    fn passes_start_at_the_same_angle() {
+        let mut encoder = SimulatedEncoder::new(300.0, 1000);
+        let mut starts = Vec::new();
+
+        for pass in 0..3 {
+            encoder.advance(0.37 * (pass + 1) as Number);
+
+            let motion =
+                SpindleSyncedMove::new(at(0.0, 0.0), at(0.0, -5.0), 1.0, encoder.revolutions());
+
+            while motion.position(encoder.revolutions()) == at(0.0, 0.0) {
+                encoder.advance(0.0001);
+            }
+
+            let revolutions = encoder.revolutions();
+
+            starts.push(revolutions - revolutions.floor());
+        }
+
+        for start in starts {
+            assert!(
+                start < 0.002,
+                "started {} revolutions past the index",
+                start
+            );
+        }
+    }
+
+    #[test]
+    fn rigid_tap_reversal() {
+        let mut encoder = SimulatedEncoder::new(200.0, 500);
+        let motion =
+            SpindleSyncedMove::new(at(0.0, 0.0), at(0.0, -3.0), 1.0, encoder.revolutions());
+
+        while !motion.finished(encoder.revolutions()) {
+            encoder.advance(0.001);
+        }
+
+        // The spindle reverses and the tap follows it back out
+        encoder.rpm = -200.0;
+
+        let bottom = motion.position(encoder.revolutions());
+        encoder.advance(0.3);
+        let mid = motion.position(encoder.revolutions());
+
+        assert!(mid[2] > bottom[2] && mid[2] < 0.0);
+
+        encoder.advance(1.0);
+        assert_eq!(motion.position(encoder.revolutions()), at(0.0, 0.0));
+    }
+}
@@ -0,0 +1,525 @@
+//! Planning the interpreter's output.
+//!
+//! [`Planner`] reads canonical commands in order and plans each straight move and arc as a
+//! [`Move`], with a trapezoidal [`Segment`] along the length of its path that starts and ends at
+//! rest. Rapids run at the velocity limit. Feed moves run at the feed rate set before them, which
+//! the interpreter always gives in units per minute, working out a rate for each move in inverse
+//! time (`G93`) and units per revolution (`G95`) mode. Each move so has its own velocity and
+//! duration.
+//!
+//! Outputs synced with motion by `M62`, `M63`, `M67` and the like come just before the move they
+//! go with, and are attached to it as [`Move::outputs`] to be set as its segment starts.
+//!
+//! Overrides apply while moves run rather than when they're planned, as the operator changes them
+//! at any time. Each move keeps the [`OverrideSwitches`] the program had on for it, and
+//! [`Move::time_scale`] combines them with the live [`OverrideInputs`] into how fast to step
+//! through its segment. Stepping faster than planned raises the acceleration by the square of the
+//! scale, so the limits should leave room for the highest feed override.
+//!
+//! `G33` and `G33.1` moves follow the spindle instead of the clock, through
+//! [`Move::spindle_synced`]. Their segments are planned at the pitch times the last spindle speed
+//! set, which is how long they take if the spindle holds that speed. A rigid tap's path is the
+//! way in, and it comes back out along it as the spindle reverses.
+//!
+//! Only the axes the machine has, as set with [`Planner::set_axes`], are planned and moved.
+//! Straight moves are measured with [`MachineAxes::move_length`], as the interpreter measures them
+//! for inverse time feed, so a move of only rotary axes runs at the feed rate in degrees.
+
+use crate::one_d::{Limits, Segment, Vertex};
+use crate::spindle_sync::SpindleSyncedMove;
+use common::{Number, Position};
+use interpreter::arc::ArcFeed;
+use interpreter::axes::MachineAxes;
+use interpreter::canon::Canon;
+use interpreter::io::Output;
+use interpreter::overrides::{OverrideInputs, OverrideSwitches};
+use std::collections::VecDeque;
+
+/// The path a move follows.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Path {
+    Line { start: Position, end: Position },
+    Arc { start: Position, arc: ArcFeed },
+}
+
+impl Path {
+    pub fn start(&self) -> &Position {
+        match self {
+            Self::Line { start, .. } | Self::Arc { start, .. } => start,
+        }
+    }
+
+    pub fn end(&self) -> &Position {
+        match self {
+            Self::Line { end, .. } => end,
+            Self::Arc { arc, .. } => &arc.end,
+        }
+    }
+
+    /// Length along the path as moved by `axes`, along the helix for a helical arc.
+    pub fn length(&self, axes: &MachineAxes) -> Number {
+        match self {
+            Self::Line { start, end } => axes.move_length(start, end),
+            Self::Arc { start, arc } => {
+                let (_, _, normal) = arc.plane.axes();
+
+                (arc.radius(start) * arc.sweep(start).abs())
+                    .hypot(arc.end[normal.index()] - start[normal.index()])
+            }
+        }
+    }
+
+    /// The point `fraction` of the way along the path, moving only `axes`.
+    pub fn point(&self, fraction: Number, axes: &MachineAxes) -> Position {
+        let start = self.start();
+        let mut point = *start;
+
+        // Straight along the path, or alongside the arc for axes off its plane
+        for axis in axes.present() {
+            let index = axis.index();
+
+            point[index] = start[index] + (self.end()[index] - start[index]) * fraction;
+        }
+
+        match self {
+            Self::Line { .. } => point,
+            Self::Arc { arc, .. } => {
+                let (first, second, _) = arc.plane.axes();
+                let (first, second) = (first.index(), second.index());
+
+                let start_angle = (start[second] - arc.center.1).atan2(start[first] - arc.center.0);
+                let angle = start_angle + arc.sweep(start) * fraction;
+                let radius =
+                    arc.radius(start) + (arc.radius(&arc.end) - arc.radius(start)) * fraction;
+
+                point[first] = arc.center.0 + radius * angle.cos();
+                point[second] = arc.center.1 + radius * angle.sin();
+
+                point
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MoveKind {
+    Rapid,
+    Feed,
+
+    /// `G33`, moving `pitch` along the path per spindle revolution.
+    SpindleSynced {
+        pitch: Number,
+    },
+
+    /// `G33.1`, in and back out in step with the spindle.
+    RigidTap {
+        pitch: Number,
+    },
+}
+
+/// A planned move.
+#[derive(Debug, Clone)]
+pub struct Move {
+    pub kind: MoveKind,
+    pub path: Path,
+
+    /// Velocity along the path the move was planned for, in units per second.
+    pub velocity: Number,
+
+    /// Distance along the path over time.
+    pub segment: Segment,
+
+    /// Outputs to set as the move starts.
+    pub outputs: Vec<Output>,
+
+    /// The overrides the program has on for the move.
+    pub overrides: OverrideSwitches,
+
+    /// The axes the move was planned for.
+    pub axes: MachineAxes,
+}
+
+impl Move {
+    /// Time the move takes in seconds.
+    pub fn duration(&self) -> Number {
+        self.segment.duration()
+    }
+
+    /// Length of the move's path.
+    pub fn length(&self) -> Number {
+        self.path.length(&self.axes)
+    }
+
+    /// Position `t` seconds into the move.
+    pub fn position(&self, t: Number) -> Position {
+        let length = self.length();
+
+        if length == 0.0 {
+            return *self.path.end();
+        }
+
+        let distance = self.segment.position(t.clamp(0.0, self.duration()));
+
+        self.path.point(distance / length, &self.axes)
+    }
+
+    /// How fast to step through the move compared to the time it was planned to take, with the
+    /// override inputs as they are now.
+    pub fn time_scale(&self, inputs: &OverrideInputs) -> Number {
+        let scales = inputs.scales(&self.overrides);
+
+        let scale = match self.kind {
+            MoveKind::Rapid => scales.rapid,
+            MoveKind::Feed => scales.feed,
+            // The spindle sets the pace
+            MoveKind::SpindleSynced { .. } | MoveKind::RigidTap { .. } => return 1.0,
+        };
+
+        match scales.max_velocity {
+            Some(max) if self.velocity > 0.0 => scale.min(max / 60.0 / self.velocity),
+            _ => scale,
+        }
+    }
+
+    /// Start following the spindle on a spindle synced move, with it now at `revolutions`.
+    pub fn spindle_synced(&self, revolutions: Number) -> Option<SpindleSyncedMove> {
+        match self.kind {
+            MoveKind::SpindleSynced { pitch } | MoveKind::RigidTap { pitch } => Some(
+                SpindleSyncedMove::new(*self.path.start(), *self.path.end(), pitch, revolutions),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Plans moves from canonical commands.
+#[derive(Debug)]
+pub struct Planner {
+    limits: Limits,
+    axes: MachineAxes,
+
+    /// Where the last move planned ends.
+    position: Position,
+
+    /// Feed rate in units per minute.
+    feed_rate: Number,
+
+    /// Spindle speed in RPM.
+    spindle_speed: Number,
+
+    /// Pitch of `G33` moves, between the commands starting and stopping it.
+    speed_feed_sync: Option<Number>,
+
+    /// Outputs waiting for the next move.
+    synced_outputs: Vec<Output>,
+
+    overrides: OverrideSwitches,
+
+    moves: VecDeque<Move>,
+}
+
+impl Planner {
+    /// Plan moves starting at the machine position `start`, with limits in units per second.
+    pub fn new(start: Position, limits: Limits) -> Self {
+        Self {
+            limits,
+            axes: MachineAxes::default(),
+            position: start,
+            feed_rate: 0.0,
+            spindle_speed: 0.0,
+            speed_feed_sync: None,
+            synced_outputs: Vec::new(),
+            overrides: OverrideSwitches::default(),
+            moves: VecDeque::new(),
+        }
+    }
+
+    /// Plan for the axes the machine has, as the interpreter is configured.
+    pub fn set_axes(&mut self, axes: MachineAxes) {
+        self.axes = axes;
+    }
+
+    pub fn axes(&self) -> &MachineAxes {
+        &self.axes
+    }
+
+    /// Plan the interpreter's next canonical command.
+    pub fn push(&mut self, canon: &Canon) {
+        let start = self.position;
+
+        match canon {
+            Canon::SetFeedRate(rate) => self.feed_rate = *rate,
+            Canon::SetSpindleSpeed(speed) => self.spindle_speed = *speed,
+            Canon::StartSpeedFeedSync(pitch) => self.speed_feed_sync = Some(*pitch),
+            Canon::StopSpeedFeedSync => self.speed_feed_sync = None,
+            Canon::StraightTraverse { end } => {
+                self.plan(MoveKind::Rapid, Path::Line { start, end: *end })
+            }
+            Canon::StraightFeed { end } => {
+                let kind = match self.speed_feed_sync {
+                    Some(pitch) => MoveKind::SpindleSynced { pitch },
+                    None => MoveKind::Feed,
+                };
+
+                self.plan(kind, Path::Line { start, end: *end })
+            }
+            Canon::RigidTap { end, pitch } => {
+                self.plan(
+                    MoveKind::RigidTap { pitch: *pitch },
+                    Path::Line { start, end: *end },
+                );
+
+                // The tap comes back out to where it started
+                self.position = start;
+            }
+            Canon::ArcFeed(arc) => self.plan(
+                MoveKind::Feed,
+                Path::Arc {
+                    start,
+                    arc: arc.clone(),
+                },
+            ),
+            Canon::SyncedOutput(output) => self.synced_outputs.push(*output),
+            Canon::Overrides(switches) => self.overrides = *switches,
+            // The probe has already moved the machine
+            Canon::StraightProbe { stopped, .. } => self.position = *stopped,
+            _ => (),
+        }
+    }
+
+    /// Take the next planned move.
+    pub fn next_move(&mut self) -> Option<Move> {
+        self.moves.pop_front()
+    }
+
+    fn plan(&mut self, kind: MoveKind, path: Path) {
+        let velocity = match kind {
+            MoveKind::Rapid => self.limits.velocity,
+            MoveKind::Feed => (self.feed_rate / 60.0).min(self.limits.velocity),
+            MoveKind::SpindleSynced { pitch } | MoveKind::RigidTap { pitch } => {
+                pitch * self.spindle_speed / 60.0
+            }
+        };
+
+        let segment = Segment::new(
+            Vertex::default(),
+            Vertex {
+                position: path.length(&self.axes),
+                velocity: 0.0,
+            },
+            &Limits {
+                velocity,
+                ..self.limits
+            },
+        );
+
+        self.position = *path.end();
+
+        self.moves.push_back(Move {
+            kind,
+            path,
+            velocity,
+            segment,
+            outputs: std::mem::take(&mut self.synced_outputs),
+            overrides: self.overrides,
+            axes: self.axes.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spindle_sync::{SimulatedEncoder, SpindleEncoder};
+    use common::consts::PI;
+    use common::Axis;
+    use interpreter::Interpreter;
+
+    const LIMITS: Limits = Limits {
+        acceleration: 1000.0,
+        velocity: 100.0,
+    };
+
+    fn plan(program: &str) -> Vec<Move> {
+        plan_with(MachineAxes::default(), program)
+    }
+
+    fn plan_with(axes: MachineAxes, program: &str) -> Vec<Move> {
+        let mut interp = Interpreter::new();
+        interp.set_axes(axes.clone());
+        interp.mdi(program).unwrap();
+
+        let mut planner = Planner::new(Position::zeros(), LIMITS);
+        planner.set_axes(axes);
+
+        while let Some(canon) = interp.next_canon() {
+            planner.push(&canon);
+        }
+
+        std::iter::from_fn(|| planner.next_move()).collect()
+    }
+
+    fn assert_close(value: Number, expected: Number) {
+        assert!(
+            (value - expected).abs() < 0.01,
+            "{} is not close to {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn feed_rate_modes() {
+        let moves = plan("G1 X10 F120\nG0 X0");
+
+        assert_eq!(moves[0].kind, MoveKind::Feed);
+        assert_close(moves[0].velocity, 2.0);
+        assert_close(moves[0].duration(), 5.0);
+        assert_eq!(moves[1].kind, MoveKind::Rapid);
+        assert_close(moves[1].velocity, LIMITS.velocity);
+
+        // Inverse time moves take 1/F minutes each, however far they go
+        let moves = plan("G93 G1 X10 F2\nX30 F0.5");
+
+        assert_close(moves[0].duration(), 30.0);
+        assert_close(moves[1].velocity, 20.0 * 0.5 / 60.0);
+        assert_close(moves[1].duration(), 120.0);
+
+        // 0.1 per revolution at 600 RPM
+        let moves = plan("S600 M3 G95 G1 X10 F0.1");
+
+        assert_close(moves[0].velocity, 1.0);
+        assert_close(moves[0].duration(), 10.0);
+    }
+
+    #[test]
+    fn follows_the_path() {
+        let moves = plan("G1 X10 F600\nG3 X-10 I-10");
+        let arc = &moves[1];
+
+        assert_close(arc.length(), 10.0 * PI);
+
+        let middle = arc.position(arc.duration() / 2.0);
+
+        assert_close(middle[0], 0.0);
+        assert_close(middle[1], 10.0);
+        assert_close((arc.position(arc.duration()) - arc.path.end()).norm(), 0.0);
+        assert_eq!(moves[0].position(0.0), Position::zeros());
+    }
+
+    #[test]
+    fn synced_outputs() {
+        let moves = plan("G0 X1\nM62 P1\nM67 E2 Q5\nG1 X10 F100\nM64 P3\nX20");
+
+        assert!(moves[0].outputs.is_empty());
+        assert_eq!(
+            moves[1].outputs,
+            [
+                Output::Digital { index: 1, on: true },
+                Output::Analog {
+                    index: 2,
+                    value: 5.0
+                },
+            ]
+        );
+
+        // Immediate outputs aren't tied to a move
+        assert!(moves[2].outputs.is_empty());
+    }
+
+    #[test]
+    fn live_overrides() {
+        let moves = plan("G1 X10 F600\nM50 P0\nX20\nG0 X0");
+        let mut inputs = OverrideInputs {
+            feed: 0.5,
+            rapid: 0.25,
+            ..OverrideInputs::default()
+        };
+
+        assert_close(moves[0].time_scale(&inputs), 0.5);
+
+        // M50 P0 turns the feed and rapid overrides off
+        assert_close(moves[1].time_scale(&inputs), 1.0);
+        assert_close(moves[2].time_scale(&inputs), 1.0);
+
+        // 2 units per second, where the move was planned for 10
+        inputs.max_velocity = Some(120.0);
+        assert_close(moves[0].time_scale(&inputs), 0.2);
+
+        // The operator halves the feed rate part way through the move
+        let feed = &moves[0];
+        let mut inputs = OverrideInputs::default();
+        let (mut t, mut elapsed) = (0.0, 0.0);
+
+        while t < feed.duration() {
+            if t >= feed.duration() / 2.0 {
+                inputs.feed = 0.5;
+            }
+
+            t += 0.001 * feed.time_scale(&inputs);
+            elapsed += 0.001;
+        }
+
+        assert_close(elapsed, feed.duration() * 1.5);
+    }
+
+    #[test]
+    fn follows_the_spindle() {
+        let moves = plan("G0 X1 Z1\nM3 S600\nG33 Z-10 K1.25\nG0 X2\nG33.1 Z-5 K1\nG0 X3");
+
+        assert_eq!(moves[1].kind, MoveKind::SpindleSynced { pitch: 1.25 });
+        assert_close(moves[1].velocity, 12.5);
+        assert_eq!(moves[3].kind, MoveKind::RigidTap { pitch: 1.0 });
+
+        // The tap comes back out before the next move
+        assert_eq!(moves[4].path.start(), moves[3].path.start());
+
+        let mut encoder = SimulatedEncoder::new(600.0, 100);
+        encoder.advance(0.01);
+
+        let thread = moves[1].spindle_synced(encoder.revolutions()).unwrap();
+
+        while !thread.finished(encoder.revolutions()) {
+            encoder.advance(0.001);
+        }
+
+        assert_eq!(thread.position(encoder.revolutions()), *moves[1].path.end());
+        assert!(moves[0].spindle_synced(0.0).is_none());
+
+        // Overrides don't change the spindle's pace
+        let inputs = OverrideInputs {
+            feed: 0.5,
+            ..OverrideInputs::default()
+        };
+
+        assert_close(moves[1].time_scale(&inputs), 1.0);
+    }
+
+    #[test]
+    fn present_axes() {
+        let axes = MachineAxes::new(&[Axis::X, Axis::Z, Axis::A]);
+
+        // Rotary only moves are measured in degrees, otherwise only the linear axes count
+        let moves = plan_with(axes.clone(), "G93 G1 A90 F2\nX10 A0 F1");
+
+        assert_close(moves[0].length(), 90.0);
+        assert_close(moves[0].duration(), 30.0);
+        assert_close(moves[1].length(), 10.0);
+        assert_close(moves[1].duration(), 60.0);
+
+        // Axes the machine doesn't have aren't moved
+        let mut planner = Planner::new(Position::zeros(), LIMITS);
+        planner.set_axes(axes);
+        planner.push(&Canon::StraightTraverse {
+            end: Position::from([3.0, 4.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+        });
+
+        let traverse = planner.next_move().unwrap();
+
+        assert_close(traverse.length(), 3.0);
+        let end = traverse.position(traverse.duration());
+
+        assert_close(end[0], 3.0);
+        assert_eq!(end[1], 0.0);
+    }
+}